@@ -1,9 +1,10 @@
 #[tokio::main]
 async fn main() -> std::io::Result<()> {
-    tracing_subscriber::fmt::init();
     if std::env::var("IS_SYSTEMD_SERVICE").is_err() {
         dotenv::dotenv().ok();
     }
+    listen_engine::logging::init_logging(listen_engine::logging::LogFormat::from_env())
+        .expect("Failed to install the log subscriber");
     listen_engine::metrics::init_metrics();
 
     tracing::info!("Starting listen-engine...");
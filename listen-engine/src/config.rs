@@ -0,0 +1,206 @@
+use std::str::FromStr;
+
+use serde::{Serialize, Serializer};
+
+use crate::engine::constants::{
+    DEFAULT_DLQ_MAX_LEN, DEFAULT_EVAL_INTERVAL_MS, DEFAULT_MAX_SLIPPAGE_BPS,
+    DEFAULT_MAX_STEP_DEPTH, DEFAULT_PRICE_CACHE_TTL_MS, DEFAULT_PYTH_MAX_PRICE_AGE_SECS,
+    DEFAULT_TELEGRAM_API_URL, MIN_EVAL_INTERVAL_MS,
+};
+use crate::logging::LogFormat;
+use crate::server::{
+    bind_addrs, metrics_bind_addrs, DEFAULT_ACTION_TIMEOUT_MS, DEFAULT_MAX_BATCH_SIZE,
+    DEFAULT_MAX_PAYLOAD_BYTES, DEFAULT_RATE_LIMIT_PER_MINUTE, DEFAULT_REQUEST_TIMEOUT_MS,
+    DEFAULT_SHUTDOWN_GRACE_PERIOD_SECS,
+};
+
+const DEFAULT_REDIS_URL: &str = "redis://127.0.0.1/";
+
+#[derive(Debug, thiserror::Error)]
+pub enum ConfigError {
+    #[error("[Config] Invalid listen address: {0}")]
+    InvalidListenAddrError(String),
+
+    #[error("[Config] Invalid metrics address: {0}")]
+    InvalidMetricsAddrError(String),
+}
+
+/// A value that is serialized as `***`, or `null` when unset
+#[derive(Clone, Default)]
+pub struct Secret(Option<String>);
+
+impl Secret {
+    pub fn expose(&self) -> Option<&str> {
+        self.0.as_deref()
+    }
+}
+
+impl std::fmt::Debug for Secret {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(if self.0.is_some() { "***" } else { "None" })
+    }
+}
+
+impl Serialize for Secret {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match self.0 {
+            Some(_) => serializer.serialize_str("***"),
+            None => serializer.serialize_none(),
+        }
+    }
+}
+
+/// The runtime configuration of the engine and its server, with the
+/// defaults applied, as served by `/api/admin/config`
+#[derive(Debug, Clone, Serialize)]
+pub struct Config {
+    #[serde(serialize_with = "serialize_addrs")]
+    pub bind_addrs: Vec<(String, u16)>,
+    pub max_batch_size: usize,
+    pub max_payload_bytes: usize,
+    pub request_timeout_ms: u64,
+    /// How long a request running actions, a forced trigger or a dead letter
+    /// retry, waits for them
+    pub action_timeout_ms: u64,
+    /// Pipelines each API key may create per minute, 0 for no limit
+    pub rate_limit_per_minute: u32,
+    pub max_slippage_bps: u64,
+    pub dlq_max_len: usize,
+    pub max_step_depth: usize,
+    pub eval_interval_ms: u64,
+    pub swap_actions_enabled: bool,
+    /// `POST /api/pipeline/{id}/trigger` runs actions, swaps included, so
+    /// it is off unless asked for
+    pub force_trigger_enabled: bool,
+    /// Lets webhooks post to localhost and private networks, for
+    /// development, as pipelines could otherwise reach internal services
+    pub webhook_allow_private_hosts: bool,
+    /// Names the instance as the owner of its pipeline leases, the host
+    /// name unless `ENGINE_INSTANCE_ID` is set
+    pub instance_id: String,
+    /// How long an instance owns a pipeline before renewing its lease, 0
+    /// for no leases. Set when more than one engine shares the Redis.
+    pub pipeline_lease_ttl_ms: u64,
+    pub shutdown_grace_period_secs: u64,
+    pub swap_service_url: Option<String>,
+    /// Bot API that `Telegram` actions send their messages to
+    pub telegram_api_url: String,
+    pub allowed_origins: Option<String>,
+    pub metrics_global_labels: Option<String>,
+    /// `/metrics` is served unless `METRICS_ENABLED=false`
+    pub metrics_enabled: bool,
+    /// Serves `/metrics` on these addresses instead of the API's, from the
+    /// comma-separated `host:port` of `METRICS_BIND_ADDR`
+    #[serde(serialize_with = "serialize_addrs")]
+    pub metrics_bind_addrs: Vec<(String, u16)>,
+    /// `LOG_FORMAT=json` writes logs and access logs as JSON lines
+    pub log_format: LogFormat,
+    pub privy_app_id: Option<String>,
+    pub clickhouse_url: Option<String>,
+    pub clickhouse_user: Option<String>,
+    pub clickhouse_database: Option<String>,
+    pub pyth_price_accounts: Option<String>,
+    pub pyth_max_price_age_secs: u64,
+    pub price_cache_ttl_ms: u64,
+    pub redis_url: Secret,
+    pub solana_rpc_url: Secret,
+    pub swap_confirmation_webhook: Secret,
+    pub privy_app_secret: Secret,
+    pub clickhouse_password: Secret,
+    pub api_keys: Secret,
+    pub admin_token: Secret,
+}
+
+impl Config {
+    pub fn from_env() -> Result<Self, ConfigError> {
+        Self::from_vars(|name| std::env::var(name).ok())
+    }
+
+    /// Reads each setting through `var`, unparseable numbers fall back to
+    /// their defaults
+    pub fn from_vars(var: impl Fn(&str) -> Option<String>) -> Result<Self, ConfigError> {
+        Ok(Self {
+            bind_addrs: bind_addrs(
+                var("LISTEN_BIND_ADDR").as_deref(),
+                var("LISTEN_PORT").as_deref(),
+            )
+            .map_err(ConfigError::InvalidListenAddrError)?,
+            max_batch_size: parse_or(var("PIPELINE_BATCH_MAX_SIZE"), DEFAULT_MAX_BATCH_SIZE),
+            max_payload_bytes: parse_or(
+                var("PIPELINE_MAX_PAYLOAD_BYTES"),
+                DEFAULT_MAX_PAYLOAD_BYTES,
+            ),
+            request_timeout_ms: parse_or(
+                var("PIPELINE_REQUEST_TIMEOUT_MS"),
+                DEFAULT_REQUEST_TIMEOUT_MS,
+            ),
+            action_timeout_ms: parse_or(
+                var("ACTION_REQUEST_TIMEOUT_MS"),
+                DEFAULT_ACTION_TIMEOUT_MS,
+            ),
+            rate_limit_per_minute: parse_or(
+                var("RATE_LIMIT_PER_MINUTE"),
+                DEFAULT_RATE_LIMIT_PER_MINUTE,
+            ),
+            max_slippage_bps: parse_or(var("MAX_SLIPPAGE_BPS"), DEFAULT_MAX_SLIPPAGE_BPS),
+            dlq_max_len: parse_or(var("DLQ_MAX_LEN"), DEFAULT_DLQ_MAX_LEN),
+            max_step_depth: parse_or(var("MAX_STEP_DEPTH"), DEFAULT_MAX_STEP_DEPTH),
+            eval_interval_ms: parse_or(var("EVAL_INTERVAL_MS"), DEFAULT_EVAL_INTERVAL_MS)
+                .max(MIN_EVAL_INTERVAL_MS),
+            swap_actions_enabled: parse_or(var("SWAP_ACTIONS_ENABLED"), false),
+            force_trigger_enabled: parse_or(var("FORCE_TRIGGER_ENABLED"), false),
+            webhook_allow_private_hosts: parse_or(var("WEBHOOK_ALLOW_PRIVATE_HOSTS"), false),
+            instance_id: var("ENGINE_INSTANCE_ID")
+                .or_else(|| var("HOSTNAME"))
+                .unwrap_or_else(|| uuid::Uuid::new_v4().to_string()),
+            pipeline_lease_ttl_ms: parse_or(var("PIPELINE_LEASE_TTL_MS"), 0),
+            shutdown_grace_period_secs: parse_or(
+                var("SHUTDOWN_GRACE_PERIOD"),
+                DEFAULT_SHUTDOWN_GRACE_PERIOD_SECS,
+            ),
+            swap_service_url: var("SWAP_SERVICE_URL"),
+            telegram_api_url: var("TELEGRAM_API_URL")
+                .unwrap_or_else(|| DEFAULT_TELEGRAM_API_URL.to_string()),
+            allowed_origins: var("ALLOWED_ORIGINS"),
+            metrics_global_labels: var("METRICS_GLOBAL_LABELS"),
+            metrics_enabled: parse_or(var("METRICS_ENABLED"), true),
+            metrics_bind_addrs: metrics_bind_addrs(var("METRICS_BIND_ADDR").as_deref())
+                .map_err(ConfigError::InvalidMetricsAddrError)?,
+            log_format: parse_or(var("LOG_FORMAT"), LogFormat::Text),
+            privy_app_id: var("PRIVY_APP_ID"),
+            clickhouse_url: var("CLICKHOUSE_URL"),
+            clickhouse_user: var("CLICKHOUSE_USER"),
+            clickhouse_database: var("CLICKHOUSE_DATABASE"),
+            pyth_price_accounts: var("PYTH_PRICE_ACCOUNTS"),
+            pyth_max_price_age_secs: parse_or(
+                var("PYTH_MAX_PRICE_AGE_SECS"),
+                DEFAULT_PYTH_MAX_PRICE_AGE_SECS,
+            ),
+            price_cache_ttl_ms: parse_or(var("PRICE_CACHE_TTL_MS"), DEFAULT_PRICE_CACHE_TTL_MS),
+            redis_url: Secret(Some(
+                var("REDIS_URL").unwrap_or_else(|| DEFAULT_REDIS_URL.to_string()),
+            )),
+            solana_rpc_url: Secret(var("SOLANA_RPC_URL")),
+            swap_confirmation_webhook: Secret(var("SWAP_CONFIRMATION_WEBHOOK")),
+            privy_app_secret: Secret(var("PRIVY_APP_SECRET")),
+            clickhouse_password: Secret(var("CLICKHOUSE_PASSWORD")),
+            api_keys: Secret(var("API_KEYS")),
+            admin_token: Secret(var("ADMIN_TOKEN")),
+        })
+    }
+}
+
+fn parse_or<T: FromStr>(value: Option<String>, default: T) -> T {
+    value.and_then(|v| v.parse().ok()).unwrap_or(default)
+}
+
+fn serialize_addrs<S: Serializer>(
+    addrs: &[(String, u16)],
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    serializer.collect_seq(
+        addrs
+            .iter()
+            .map(|(addr, port)| format!("{}:{}", addr, port)),
+    )
+}
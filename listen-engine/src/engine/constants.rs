@@ -1,2 +1,92 @@
 pub const TEST_ADDRESS_EVM: &str = "0xCCC48877a33a2C14e40c82da843Cf4c607ABF770";
 pub const TEST_ADDRESS_SOL: &str = "6fp9frQ16W3kTRGiBVvpMS2NzoixE4Y1MWqYrW9SvTAj";
+
+/// Number of recent price updates kept per asset for volatility
+pub const PRICE_HISTORY_LEN: usize = 60;
+pub const DEFAULT_MAX_SLIPPAGE_BPS: u64 = 1_000;
+
+/// Upper bound for the page size when listing pipelines
+pub const MAX_PAGE_SIZE: usize = 100;
+
+/// Dead letters kept per user, oldest are dropped first
+pub const DEFAULT_DLQ_MAX_LEN: usize = 1_000;
+
+/// Execution events kept per pipeline, oldest are dropped first
+pub const EXECUTION_LOG_MAX_LEN: usize = 100;
+
+/// Swap results buffered for `SWAP_CONFIRMATION_WEBHOOK`, and how delivery
+/// is retried
+pub const WEBHOOK_QUEUE_LEN: usize = 1_000;
+pub const WEBHOOK_MAX_ATTEMPTS: u32 = 5;
+pub const WEBHOOK_BACKOFF_MS: u64 = 500;
+
+/// Bound for each `Action::Webhook` request and how often it is attempted
+pub const ACTION_WEBHOOK_TIMEOUT_MS: u64 = 5_000;
+pub const ACTION_WEBHOOK_MAX_ATTEMPTS: u32 = 3;
+
+/// Connection pool of the shared HTTP client: idle connections kept open
+/// per host, enough for bursts of concurrent actions to one webhook, and
+/// how long an unused one is kept
+pub const HTTP_POOL_MAX_IDLE_PER_HOST: usize = 64;
+pub const HTTP_POOL_IDLE_TIMEOUT_SECS: u64 = 90;
+
+/// `Action::Telegram` messages are retried once on a transient failure
+pub const TELEGRAM_MAX_ATTEMPTS: u32 = 2;
+pub const DEFAULT_TELEGRAM_API_URL: &str = "https://api.telegram.org";
+
+/// `Action::Discord` waits out a rate limit this long at most before
+/// retrying once, longer ones fail the attempt. Discord truncates nothing
+/// itself, longer content is rejected.
+pub const DISCORD_MAX_RETRY_AFTER_MS: u64 = 5_000;
+pub const DISCORD_MAX_CONTENT_CHARS: usize = 2_000;
+
+/// Bound for each dependency checked by `/api/healthz`
+pub const HEALTH_CHECK_TIMEOUT_MS: u64 = 1_500;
+
+/// Bound for each price looked up in the `PriceSource`
+pub const PRICE_SOURCE_TIMEOUT_MS: u64 = 2_000;
+
+/// How long a price looked up in the `PriceSource` is reused
+pub const DEFAULT_PRICE_CACHE_TTL_MS: u64 = 1_000;
+
+/// Oldest a Pyth price may be, in seconds, for `PythPriceSource` to use it
+pub const DEFAULT_PYTH_MAX_PRICE_AGE_SECS: u64 = 60;
+
+/// Longest chain of steps a pipeline may have
+pub const DEFAULT_MAX_STEP_DEPTH: usize = 64;
+
+/// Swaps older than this are dropped from the per-pool activity, bounding
+/// the window of `PoolTxCountAbove`
+pub const MAX_SWAP_ACTIVITY_WINDOW_SECS: u64 = 60 * 60;
+
+/// Pipeline events buffered per subscriber before it starts missing them
+pub const PIPELINE_EVENTS_CAPACITY: usize = 1_024;
+
+/// Finished admin jobs are kept this long for polling
+pub const JOB_RETENTION_SECS: i64 = 60 * 60;
+
+/// How often the engine loop ticks to evaluate pipelines with an action due
+/// to be retried, `EVAL_INTERVAL_MS`. Shorter intervals are raised to the
+/// minimum so that a typo can't turn the loop into a busy wait.
+pub const DEFAULT_EVAL_INTERVAL_MS: u64 = 250;
+pub const MIN_EVAL_INTERVAL_MS: u64 = 50;
+
+/// Times a pipeline save is attempted when other writes keep getting in
+/// first
+pub const PIPELINE_SAVE_ATTEMPTS: usize = 3;
+
+/// Deepest nesting of `And`/`Or` conditions, counting the outermost
+pub const MAX_CONDITION_DEPTH: usize = 8;
+
+/// Most steps a pipeline may have
+pub const MAX_STEPS_PER_PIPELINE: usize = 100;
+
+/// Most conditions a step may have, counting those nested in `And`/`Or`
+pub const MAX_CONDITIONS_PER_STEP: usize = 32;
+
+/// Longest cooldown a condition may have, a year
+pub const MAX_COOLDOWN_SECS: u64 = 365 * 24 * 60 * 60;
+
+/// `COUNT` hint of the `SCAN` for pipelines created by other instances,
+/// run every `PIPELINE_LEASE_TTL_MS`
+pub const PIPELINE_SCAN_COUNT: usize = 1_000;
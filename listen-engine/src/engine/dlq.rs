@@ -0,0 +1,38 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use super::pipeline::{Action, PipelineStep};
+
+/// Action that failed for good, kept with enough context to inspect and
+/// re-attempt it later
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DeadLetter {
+    pub id: Uuid,
+    pub user_id: String,
+    pub pipeline_id: Uuid,
+    pub step_id: Uuid,
+    pub action: Action,
+    pub error: String,
+    pub attempts: u32,
+    pub failed_at: DateTime<Utc>,
+}
+
+impl DeadLetter {
+    pub fn new(user_id: &str, pipeline_id: Uuid, step: &PipelineStep, error: String) -> Self {
+        Self {
+            id: Uuid::new_v4(),
+            user_id: user_id.to_string(),
+            pipeline_id,
+            step_id: step.id,
+            action: step.action.clone(),
+            error,
+            attempts: 1,
+            failed_at: Utc::now(),
+        }
+    }
+
+    pub fn key(user_id: &str) -> String {
+        format!("dlq:{}", user_id)
+    }
+}
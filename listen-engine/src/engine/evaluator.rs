@@ -1,9 +1,33 @@
-use super::pipeline::{Condition, ConditionType};
+use super::constants::MAX_CONDITION_DEPTH;
+use super::pipeline::{Bound, Condition, ConditionType, Direction, Pipeline, Status};
+use super::swap::SwapResult;
 use crate::engine::EngineError;
-use std::collections::HashMap;
+use chrono::{NaiveTime, Utc};
+use serde::Serialize;
+use std::collections::{HashMap, VecDeque};
+use uuid::Uuid;
 
 pub struct Evaluator;
 
+/// The pipeline's pending steps evaluated with `asset` at `price`, other
+/// assets at their current price
+#[derive(Debug, Clone, Serialize)]
+pub struct WhatIf {
+    pub pipeline_id: Uuid,
+    pub asset: String,
+    pub price: f64,
+    pub steps: HashMap<Uuid, WhatIfStep>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct WhatIfStep {
+    pub would_trigger: bool,
+    pub conditions: Vec<bool>, // per condition, in order
+}
+
+/// Timestamps of recent swaps per pool, oldest first, in unix seconds
+pub type SwapActivity = HashMap<String, VecDeque<u64>>;
+
 #[derive(Debug, thiserror::Error)]
 pub enum EvaluatorError {
     #[error("[Evaluator] Failed to evaluate conditions: {0}")]
@@ -15,6 +39,9 @@ pub enum EvaluatorError {
     #[error("[Evaluator] Missing price data for asset: {0}")]
     MissingPriceData(String),
 
+    #[error("[Evaluator] Missing volume data for asset: {0}")]
+    MissingVolumeData(String),
+
     #[error("[Evaluator] Invalid condition type: {0}")]
     InvalidConditionType(String),
 }
@@ -26,37 +53,237 @@ impl From<EvaluatorError> for EngineError {
 }
 
 impl Evaluator {
+    /// Evaluates the conditions, recording `triggered` and `last_evaluated`
+    /// on each one evaluated, and `last_triggered` on those that fired.
+    /// Settled one-shot conditions are skipped.
+    /// `pipelines` holds the statuses of the pipelines the conditions refer to,
+    /// `last_swap` is the latest swap of the pipeline being evaluated.
     pub fn evaluate_conditions(
-        conditions: &[Condition],
+        conditions: &mut [Condition],
         prices: &HashMap<String, f64>,
+        pipelines: &HashMap<Uuid, Status>,
+        swaps: &SwapActivity,
+        last_swap: Option<&SwapResult>,
     ) -> Result<bool, EvaluatorError> {
-        conditions.iter().try_fold(true, |acc, c| {
-            Ok(acc && Self::evaluate_condition(c, prices)?)
+        conditions.iter_mut().try_fold(true, |acc, c| {
+            if !acc {
+                return Ok(false);
+            }
+            if c.is_settled() {
+                return Ok(true);
+            }
+            let condition_type = c.condition_type.kind();
+            let start = std::time::Instant::now();
+            metrics::counter!("condition_evaluations", 1, "condition_type" => condition_type);
+            let result = Self::update_condition(c, prices, pipelines, swaps, last_swap);
+            metrics::histogram!("condition_evaluation_duration", start.elapsed(), "condition_type" => condition_type);
+            if result.is_err() {
+                metrics::counter!("condition_evaluation_errors", 1, "condition_type" => condition_type);
+            }
+            result
         })
     }
 
+    /// Evaluates the pipeline's pending steps against `prices` with `asset`
+    /// replaced by `price`, on copies of the conditions so the pipeline is
+    /// left as is. A condition that can't be evaluated, e.g. for lack of a
+    /// price, wouldn't trigger.
+    pub fn what_if(
+        pipeline: &Pipeline,
+        asset: &str,
+        price: f64,
+        prices: &HashMap<String, f64>,
+        pipelines: &HashMap<Uuid, Status>,
+        swaps: &SwapActivity,
+    ) -> WhatIf {
+        let mut prices = prices.clone();
+        prices.insert(asset.to_string(), price);
+        let last_swap = pipeline.swap_history.last();
+        let steps = pipeline
+            .steps
+            .iter()
+            .filter(|(_, step)| matches!(step.status, Status::Pending))
+            .map(|(id, step)| {
+                let conditions: Vec<bool> = step
+                    .conditions
+                    .iter()
+                    .map(|c| {
+                        Self::update_condition(&mut c.clone(), &prices, pipelines, swaps, last_swap)
+                            .unwrap_or(false)
+                    })
+                    .collect();
+                let step = WhatIfStep {
+                    would_trigger: conditions.iter().all(|triggered| *triggered),
+                    conditions,
+                };
+                (*id, step)
+            })
+            .collect();
+        WhatIf {
+            pipeline_id: pipeline.id,
+            asset: asset.to_string(),
+            price,
+            steps,
+        }
+    }
+
+    fn update_condition(
+        condition: &mut Condition,
+        prices: &HashMap<String, f64>,
+        pipelines: &HashMap<Uuid, Status>,
+        swaps: &SwapActivity,
+        last_swap: Option<&SwapResult>,
+    ) -> Result<bool, EvaluatorError> {
+        if condition.is_settled() {
+            return Ok(true);
+        }
+        let mut triggered =
+            Self::evaluate_condition(condition, prices, pipelines, swaps, last_swap, 1)?;
+        let now = Utc::now();
+        // held back if it would trigger anew during its cooldown, staying
+        // triggered isn't triggering again
+        if triggered && !condition.triggered {
+            if condition.in_cooldown(now) {
+                metrics::counter!("condition_cooldown_suppressed", 1);
+                triggered = false;
+            } else {
+                condition.last_triggered = Some(now);
+            }
+        }
+        condition.triggered = triggered;
+        condition.last_evaluated = Some(now);
+        Ok(triggered)
+    }
+
+    /// `depth` is the nesting of the condition within `And`/`Or`, the
+    /// outermost being 1
     fn evaluate_condition(
-        condition: &Condition,
+        condition: &mut Condition,
         prices: &HashMap<String, f64>,
+        pipelines: &HashMap<Uuid, Status>,
+        swaps: &SwapActivity,
+        last_swap: Option<&SwapResult>,
+        depth: usize,
     ) -> Result<bool, EvaluatorError> {
-        match &condition.condition_type {
+        if depth > MAX_CONDITION_DEPTH {
+            return Err(EvaluatorError::InvalidConditionType(format!(
+                "Conditions nested deeper than {}",
+                MAX_CONDITION_DEPTH
+            )));
+        }
+        match &mut condition.condition_type {
             ConditionType::PriceAbove { asset, threshold } => {
                 let price = prices
-                    .get(asset)
+                    .get(asset.as_str())
                     .ok_or_else(|| EvaluatorError::MissingPriceData(asset.clone()))?;
-                Ok(price >= threshold)
+                Ok(*price >= *threshold)
             }
             ConditionType::PriceBelow { asset, threshold } => {
                 let price = prices
-                    .get(asset)
+                    .get(asset.as_str())
+                    .ok_or_else(|| EvaluatorError::MissingPriceData(asset.clone()))?;
+                Ok(*price <= *threshold)
+            }
+            ConditionType::PriceBand {
+                asset,
+                upper,
+                lower,
+                crossed,
+            } => {
+                let price = prices
+                    .get(asset.as_str())
+                    .ok_or_else(|| EvaluatorError::MissingPriceData(asset.clone()))?;
+                *crossed = if *price >= *upper {
+                    Some(Bound::Upper)
+                } else if *price <= *lower {
+                    Some(Bound::Lower)
+                } else {
+                    None
+                };
+                Ok(crossed.is_some())
+            }
+            ConditionType::TrailingStop {
+                asset,
+                trail_percent,
+                peak,
+            } => {
+                let price = *prices
+                    .get(asset.as_str())
                     .ok_or_else(|| EvaluatorError::MissingPriceData(asset.clone()))?;
-                Ok(price <= threshold)
+                // ratchets up only, the stop never moves down
+                let peak = peak.get_or_insert(price);
+                *peak = peak.max(price);
+                Ok(price <= *peak * (1.0 - *trail_percent / 100.0))
             }
-            ConditionType::And(sub) => sub.iter().try_fold(true, |acc, c| {
-                Ok(acc && Self::evaluate_condition(c, prices)?)
+            ConditionType::PercentChange {
+                asset,
+                reference_price,
+                percent,
+                direction,
+            } => {
+                let price = *prices
+                    .get(asset.as_str())
+                    .ok_or_else(|| EvaluatorError::MissingPriceData(asset.clone()))?;
+                // no price was known at creation, the move counts from here
+                let reference_price = *reference_price.get_or_insert(price);
+                if reference_price <= 0.0 {
+                    return Err(EvaluatorError::PriceEvaluationError(format!(
+                        "Reference price of {} must be positive, got {}",
+                        asset, reference_price
+                    )));
+                }
+                let change = (price - reference_price) * 100.0 / reference_price;
+                Ok(match direction {
+                    Direction::Up => change >= *percent,
+                    Direction::Down => change <= -*percent,
+                })
+            }
+            ConditionType::TimeAfter { timestamp } => Ok(Utc::now() >= *timestamp),
+            ConditionType::TimeWindow { start, end } => {
+                Ok(in_time_window(*start, *end, Utc::now().time()))
+            }
+            ConditionType::PoolTxCountAbove {
+                amm_pool,
+                threshold,
+                window_seconds,
+            } => {
+                let since = (Utc::now().timestamp() as u64).saturating_sub(*window_seconds);
+                let count = swaps
+                    .get(amm_pool.as_str())
+                    .map(|timestamps| timestamps.iter().filter(|t| **t >= since).count())
+                    .unwrap_or(0);
+                Ok(count as u64 > *threshold)
+            }
+            ConditionType::VolumeAbove {
+                asset,
+                threshold,
+                volume,
+                ..
+            } => {
+                let volume =
+                    volume.ok_or_else(|| EvaluatorError::MissingVolumeData(asset.clone()))?;
+                Ok(volume >= *threshold)
+            }
+            ConditionType::SwapFilled { min_amount } => {
+                Ok(last_swap
+                    .is_some_and(|swap| swap.is_confirmed() && swap.out_amount >= *min_amount))
+            }
+            ConditionType::PipelineCompleted {
+                pipeline_id,
+                status,
+            } => {
+                // a deleted pipeline never completes
+                *status = pipelines.get(pipeline_id).cloned();
+                Ok(matches!(status, Some(Status::Completed)))
+            }
+            // both stop evaluating once the outcome is known
+            ConditionType::And(sub) => sub.iter_mut().try_fold(true, |acc, c| {
+                Ok(acc
+                    && Self::evaluate_condition(c, prices, pipelines, swaps, last_swap, depth + 1)?)
             }),
-            ConditionType::Or(sub) => sub.iter().try_fold(false, |acc, c| {
-                Ok(acc || Self::evaluate_condition(c, prices)?)
+            ConditionType::Or(sub) => sub.iter_mut().try_fold(false, |acc, c| {
+                Ok(acc
+                    || Self::evaluate_condition(c, prices, pipelines, swaps, last_swap, depth + 1)?)
             }),
             ConditionType::PercentageChange { asset, .. } => {
                 // Since we don't have historical data yet
@@ -68,3 +295,543 @@ impl Evaluator {
         }
     }
 }
+
+/// Whether `time` is from `start` up to `end`, wrapping midnight when `end`
+/// is earlier than `start`
+fn in_time_window(start: NaiveTime, end: NaiveTime, time: NaiveTime) -> bool {
+    if start <= end {
+        start <= time && time < end
+    } else {
+        time >= start || time < end
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::swap::SwapOutcome;
+    use chrono::Duration;
+
+    #[test]
+    fn test_one_shot_condition_not_reevaluated() {
+        let mut conditions = vec![
+            Condition::from(ConditionType::TimeAfter {
+                timestamp: Utc::now() - Duration::seconds(1),
+            }),
+            Condition::from(ConditionType::PriceAbove {
+                asset: "SOL".to_string(),
+                threshold: 100.0,
+            }),
+        ];
+
+        let prices = HashMap::from([("SOL".to_string(), 150.0)]);
+        let pipelines = HashMap::new();
+        assert!(Evaluator::evaluate_conditions(
+            &mut conditions,
+            &prices,
+            &pipelines,
+            &HashMap::new(),
+            None
+        )
+        .unwrap());
+        let fired_at = conditions[0].last_evaluated;
+        let price_evaluated_at = conditions[1].last_evaluated;
+        assert!(conditions[0].is_settled());
+
+        let prices = HashMap::from([("SOL".to_string(), 50.0)]);
+        assert!(!Evaluator::evaluate_conditions(
+            &mut conditions,
+            &prices,
+            &pipelines,
+            &HashMap::new(),
+            None
+        )
+        .unwrap());
+        assert!(conditions[0].triggered);
+        assert_eq!(conditions[0].last_evaluated, fired_at);
+        assert!(!conditions[1].triggered);
+        assert!(conditions[1].last_evaluated >= price_evaluated_at);
+        assert!(!conditions[1].is_settled());
+    }
+
+    #[test]
+    fn test_cooldown_holds_back_retriggers() {
+        let mut conditions = vec![Condition {
+            cooldown_secs: Some(60),
+            ..Condition::from(ConditionType::PriceAbove {
+                asset: "SOL".to_string(),
+                threshold: 100.0,
+            })
+        }];
+        let pipelines = HashMap::new();
+        let evaluate = |conditions: &mut Vec<Condition>, price: f64| {
+            let prices = HashMap::from([("SOL".to_string(), price)]);
+            Evaluator::evaluate_conditions(conditions, &prices, &pipelines, &HashMap::new(), None)
+                .unwrap()
+        };
+
+        // hovering at the threshold fires once within the window
+        let mut fired = 0;
+        for price in [101.0, 101.0, 99.0, 101.0, 99.0, 101.0] {
+            let was_triggered = conditions[0].triggered;
+            if evaluate(&mut conditions, price) && !was_triggered {
+                fired += 1;
+            }
+        }
+        assert_eq!(fired, 1);
+        assert!(!conditions[0].triggered);
+        let fired_at = conditions[0].last_triggered.unwrap();
+
+        // and again once it elapsed
+        conditions[0].last_triggered = Some(fired_at - Duration::seconds(60));
+        assert!(evaluate(&mut conditions, 101.0));
+        assert!(conditions[0].last_triggered.unwrap() >= fired_at);
+        assert!(!evaluate(&mut conditions, 99.0));
+        assert!(!evaluate(&mut conditions, 101.0));
+
+        // without a cooldown every crossing fires
+        conditions[0].cooldown_secs = None;
+        assert!(evaluate(&mut conditions, 101.0));
+    }
+
+    #[test]
+    fn test_is_one_shot() {
+        let time_after = Condition::from(ConditionType::TimeAfter {
+            timestamp: Utc::now(),
+        });
+        let price_above = Condition::from(ConditionType::PriceAbove {
+            asset: "SOL".to_string(),
+            threshold: 100.0,
+        });
+        assert!(time_after.condition_type.is_one_shot());
+        assert!(!price_above.condition_type.is_one_shot());
+        assert!(ConditionType::And(vec![time_after.clone()]).is_one_shot());
+        assert!(!ConditionType::And(vec![time_after, price_above]).is_one_shot());
+    }
+
+    #[test]
+    fn test_pipeline_completed() {
+        let pipeline_id = Uuid::new_v4();
+        let mut conditions = vec![Condition::from(ConditionType::PipelineCompleted {
+            pipeline_id,
+            status: None,
+        })];
+        let prices = HashMap::new();
+
+        for status in [Status::Pending, Status::Failed] {
+            let pipelines = HashMap::from([(pipeline_id, status)]);
+            assert!(!Evaluator::evaluate_conditions(
+                &mut conditions,
+                &prices,
+                &pipelines,
+                &HashMap::new(),
+                None
+            )
+            .unwrap());
+        }
+        assert!(matches!(
+            conditions[0].condition_type,
+            ConditionType::PipelineCompleted {
+                status: Some(Status::Failed),
+                ..
+            }
+        ));
+        assert!(!Evaluator::evaluate_conditions(
+            &mut conditions,
+            &prices,
+            &HashMap::new(),
+            &HashMap::new(),
+            None
+        )
+        .unwrap());
+
+        let pipelines = HashMap::from([(pipeline_id, Status::Completed)]);
+        assert!(Evaluator::evaluate_conditions(
+            &mut conditions,
+            &prices,
+            &pipelines,
+            &HashMap::new(),
+            None
+        )
+        .unwrap());
+        assert!(conditions[0].is_settled());
+        assert!(matches!(
+            conditions[0].condition_type,
+            ConditionType::PipelineCompleted {
+                status: Some(Status::Completed),
+                ..
+            }
+        ));
+    }
+
+    #[test]
+    fn test_pool_tx_count_above() {
+        let mut conditions = vec![Condition::from(ConditionType::PoolTxCountAbove {
+            amm_pool: "pool".to_string(),
+            threshold: 3,
+            window_seconds: 60,
+        })];
+        let now = Utc::now().timestamp() as u64;
+        // two swaps fell out of the window
+        let mut swaps = SwapActivity::from([(
+            "pool".to_string(),
+            VecDeque::from([now - 300, now - 120, now - 30, now - 20, now - 10]),
+        )]);
+        let (prices, pipelines) = (HashMap::new(), HashMap::new());
+
+        assert!(!Evaluator::evaluate_conditions(
+            &mut conditions,
+            &prices,
+            &pipelines,
+            &swaps,
+            None
+        )
+        .unwrap());
+        swaps.get_mut("pool").unwrap().push_back(now);
+        assert!(
+            Evaluator::evaluate_conditions(&mut conditions, &prices, &pipelines, &swaps, None)
+                .unwrap()
+        );
+        assert!(!conditions[0].is_settled());
+    }
+
+    #[test]
+    fn test_percent_change_boundary() {
+        let percent_change = |direction| {
+            Condition::from(ConditionType::PercentChange {
+                asset: "SOL".to_string(),
+                reference_price: Some(100.0),
+                percent: 10.0,
+                direction,
+            })
+        };
+        let (pipelines, swaps) = (HashMap::new(), HashMap::new());
+        let evaluate = |conditions: &mut Vec<Condition>, price: f64| {
+            let prices = HashMap::from([("SOL".to_string(), price)]);
+            Evaluator::evaluate_conditions(conditions, &prices, &pipelines, &swaps, None).unwrap()
+        };
+
+        let mut up = vec![percent_change(Direction::Up)];
+        assert!(!evaluate(&mut up, 109.99));
+        assert!(evaluate(&mut up, 110.0));
+        assert!(up[0].triggered);
+        assert!(!evaluate(&mut up, 80.0));
+        assert!(!up[0].triggered);
+
+        let mut down = vec![percent_change(Direction::Down)];
+        assert!(!evaluate(&mut down, 90.01));
+        assert!(evaluate(&mut down, 90.0));
+        assert!(!evaluate(&mut down, 110.0));
+
+        // without a reference the first price becomes it
+        let mut unset = vec![Condition::from(ConditionType::PercentChange {
+            asset: "SOL".to_string(),
+            reference_price: None,
+            percent: 10.0,
+            direction: Direction::Up,
+        })];
+        assert!(!evaluate(&mut unset, 50.0));
+        assert!(matches!(
+            unset[0].condition_type,
+            ConditionType::PercentChange {
+                reference_price: Some(price),
+                ..
+            } if price == 50.0
+        ));
+        assert!(evaluate(&mut unset, 55.0));
+    }
+
+    fn price_above(asset: &str, threshold: f64) -> Condition {
+        Condition::from(ConditionType::PriceAbove {
+            asset: asset.to_string(),
+            threshold,
+        })
+    }
+
+    #[test]
+    fn test_nested_and_or() {
+        // SOL above 100 and (BONK above 1 or WIF above 2)
+        let mut conditions = vec![Condition::from(ConditionType::And(vec![
+            price_above("SOL", 100.0),
+            Condition::from(ConditionType::Or(vec![
+                price_above("BONK", 1.0),
+                price_above("WIF", 2.0),
+            ])),
+        ]))];
+        let (pipelines, swaps) = (HashMap::new(), HashMap::new());
+        let mut evaluate = |sol: f64, bonk: f64, wif: f64| {
+            let prices = HashMap::from([
+                ("SOL".to_string(), sol),
+                ("BONK".to_string(), bonk),
+                ("WIF".to_string(), wif),
+            ]);
+            let triggered =
+                Evaluator::evaluate_conditions(&mut conditions, &prices, &pipelines, &swaps, None)
+                    .unwrap();
+            assert_eq!(conditions[0].triggered, triggered);
+            triggered
+        };
+
+        assert!(evaluate(150.0, 0.5, 3.0));
+        assert!(evaluate(150.0, 1.5, 0.5));
+        assert!(!evaluate(150.0, 0.5, 0.5));
+        assert!(!evaluate(50.0, 1.5, 3.0));
+    }
+
+    #[test]
+    fn test_short_circuit() {
+        let (pipelines, swaps) = (HashMap::new(), HashMap::new());
+        let prices = HashMap::from([("SOL".to_string(), 150.0)]);
+        // there is no price for BONK, evaluating it would fail
+        let mut or = vec![Condition::from(ConditionType::Or(vec![
+            price_above("SOL", 100.0),
+            price_above("BONK", 1.0),
+        ]))];
+        assert!(
+            Evaluator::evaluate_conditions(&mut or, &prices, &pipelines, &swaps, None).unwrap()
+        );
+
+        let mut and = vec![Condition::from(ConditionType::And(vec![
+            price_above("SOL", 200.0),
+            price_above("BONK", 1.0),
+        ]))];
+        assert!(
+            !Evaluator::evaluate_conditions(&mut and, &prices, &pipelines, &swaps, None).unwrap()
+        );
+
+        // once the first doesn't decide it, the second is evaluated
+        let mut or = vec![Condition::from(ConditionType::Or(vec![
+            price_above("SOL", 200.0),
+            price_above("BONK", 1.0),
+        ]))];
+        assert!(matches!(
+            Evaluator::evaluate_conditions(&mut or, &prices, &pipelines, &swaps, None),
+            Err(EvaluatorError::MissingPriceData(_))
+        ));
+    }
+
+    #[test]
+    fn test_condition_depth_cap() {
+        let nested = |depth: usize| {
+            (1..depth).fold(price_above("SOL", 100.0), |inner, _| {
+                Condition::from(ConditionType::And(vec![inner]))
+            })
+        };
+        let (pipelines, swaps) = (HashMap::new(), HashMap::new());
+        let prices = HashMap::from([("SOL".to_string(), 150.0)]);
+
+        let mut conditions = vec![nested(MAX_CONDITION_DEPTH)];
+        assert_eq!(conditions[0].condition_type.depth(), MAX_CONDITION_DEPTH);
+        assert!(
+            Evaluator::evaluate_conditions(&mut conditions, &prices, &pipelines, &swaps, None)
+                .unwrap()
+        );
+
+        let mut conditions = vec![nested(MAX_CONDITION_DEPTH + 1)];
+        assert!(matches!(
+            Evaluator::evaluate_conditions(&mut conditions, &prices, &pipelines, &swaps, None),
+            Err(EvaluatorError::InvalidConditionType(_))
+        ));
+    }
+
+    #[test]
+    fn test_swap_filled() {
+        let swap = |outcome, out_amount| SwapResult {
+            signature: "5xSig".to_string(),
+            input_mint: "So11111111111111111111111111111111111111112".to_string(),
+            output_mint: "Cn5Ne1vmR9ctMGY9z5NC71A3NYFvopjXNyxYtfVYpump".to_string(),
+            in_amount: 1_000_000_000,
+            out_amount,
+            outcome,
+            endpoint: "-".to_string(),
+            confirmed_by: vec![],
+            wsol: Default::default(),
+        };
+        let (prices, pipelines, swaps) = (HashMap::new(), HashMap::new(), HashMap::new());
+        let mut conditions = vec![Condition::from(ConditionType::SwapFilled {
+            min_amount: 500_000,
+        })];
+        let mut evaluate = |last_swap: Option<&SwapResult>| {
+            Evaluator::evaluate_conditions(&mut conditions, &prices, &pipelines, &swaps, last_swap)
+                .unwrap()
+        };
+
+        assert!(!evaluate(None));
+        assert!(!evaluate(Some(&swap(SwapOutcome::Confirmed, 400_000))));
+        assert!(!evaluate(Some(&swap(SwapOutcome::Dropped, 600_000))));
+        assert!(evaluate(Some(&swap(SwapOutcome::Confirmed, 500_000))));
+        // the fill happened, a later swap doesn't undo it
+        assert!(evaluate(None));
+        assert!(conditions[0].is_settled());
+    }
+
+    #[test]
+    fn test_what_if() {
+        use crate::engine::pipeline::{Action, Notification, PipelineStep};
+
+        let step = PipelineStep {
+            id: Uuid::new_v4(),
+            action: Action::Notification(Notification {
+                message: "-".to_string(),
+            }),
+            conditions: vec![price_above("SOL", 150.0), price_above("BONK", 0.00002)],
+            next_steps: vec![],
+            status: Status::Pending,
+            retry_policy: None,
+            attempts: 0,
+            next_attempt_at: None,
+        };
+        let step_id = step.id;
+        let pipeline = Pipeline {
+            id: Uuid::new_v4(),
+            user_id: "-".to_string(),
+            current_steps: vec![step_id],
+            steps: HashMap::from([(step_id, step)]),
+            status: Status::Pending,
+            created_at: Utc::now(),
+            swap_history: vec![],
+            failure_reason: None,
+            eval_interval_ms: None,
+            depth: 0,
+            expires_at: None,
+            version: 0,
+            dry_run: false,
+            dry_run_triggers: vec![],
+        };
+        let prices = HashMap::from([("SOL".to_string(), 140.0), ("BONK".to_string(), 0.00003)]);
+        let (pipelines, swaps) = (HashMap::new(), HashMap::new());
+
+        let what_if = Evaluator::what_if(&pipeline, "SOL", 160.0, &prices, &pipelines, &swaps);
+        assert_eq!(what_if.steps[&step_id].conditions, vec![true, true]);
+        assert!(what_if.steps[&step_id].would_trigger);
+
+        let what_if = Evaluator::what_if(&pipeline, "BONK", 0.00001, &prices, &pipelines, &swaps);
+        assert_eq!(what_if.steps[&step_id].conditions, vec![false, false]);
+
+        // read-only: nothing is recorded on the pipeline
+        let conditions = &pipeline.steps[&step_id].conditions;
+        assert!(conditions
+            .iter()
+            .all(|c| !c.triggered && c.last_evaluated.is_none()));
+    }
+
+    #[test]
+    fn test_price_band() {
+        let band = || {
+            vec![Condition::from(ConditionType::PriceBand {
+                asset: "SOL".to_string(),
+                upper: 200.0,
+                lower: 100.0,
+                crossed: None,
+            })]
+        };
+        let evaluate = |conditions: &mut Vec<Condition>, price: f64| {
+            let prices = HashMap::from([("SOL".to_string(), price)]);
+            Evaluator::evaluate_conditions(
+                conditions,
+                &prices,
+                &HashMap::new(),
+                &HashMap::new(),
+                None,
+            )
+            .unwrap()
+        };
+        let crossed = |conditions: &[Condition]| match &conditions[0].condition_type {
+            ConditionType::PriceBand { crossed, .. } => *crossed,
+            _ => unreachable!(),
+        };
+
+        // within the band
+        let mut conditions = band();
+        assert!(!evaluate(&mut conditions, 150.0));
+        assert_eq!(crossed(&conditions), None);
+
+        // take profit
+        assert!(evaluate(&mut conditions, 200.0));
+        assert_eq!(crossed(&conditions), Some(Bound::Upper));
+        let json = serde_json::to_value(&conditions[0]).unwrap();
+        assert_eq!(json["condition_type"]["PriceBand"]["crossed"], "Upper");
+
+        // stop loss
+        let mut conditions = band();
+        assert!(evaluate(&mut conditions, 99.5));
+        assert_eq!(crossed(&conditions), Some(Bound::Lower));
+
+        // back within the band it no longer holds
+        assert!(!evaluate(&mut conditions, 120.0));
+        assert_eq!(crossed(&conditions), None);
+    }
+
+    #[test]
+    fn test_trailing_stop_follows_peak() {
+        let mut conditions = vec![Condition::from(ConditionType::TrailingStop {
+            asset: "SOL".to_string(),
+            trail_percent: 10.0,
+            peak: Some(100.0),
+        })];
+        let mut evaluate = |price: f64| {
+            let prices = HashMap::from([("SOL".to_string(), price)]);
+            let fired = Evaluator::evaluate_conditions(
+                &mut conditions,
+                &prices,
+                &HashMap::new(),
+                &HashMap::new(),
+                None,
+            )
+            .unwrap();
+            let ConditionType::TrailingStop { peak, .. } = conditions[0].condition_type else {
+                unreachable!()
+            };
+            (fired, peak.unwrap())
+        };
+
+        assert_eq!(evaluate(120.0), (false, 120.0));
+        assert_eq!(evaluate(150.0), (false, 150.0));
+        // the dip doesn't lower the peak
+        assert_eq!(evaluate(140.0), (false, 150.0));
+        // 95 would be 5% under the entry, but is 10% under the peak already
+        assert_eq!(evaluate(135.0), (true, 150.0));
+    }
+
+    #[test]
+    fn test_time_window() {
+        let time = |h, m| NaiveTime::from_hms_opt(h, m, 0).unwrap();
+        // 09:00-17:00
+        assert!(in_time_window(time(9, 0), time(17, 0), time(12, 30)));
+        assert!(in_time_window(time(9, 0), time(17, 0), time(9, 0)));
+        assert!(!in_time_window(time(9, 0), time(17, 0), time(17, 0)));
+        assert!(!in_time_window(time(9, 0), time(17, 0), time(3, 0)));
+        // 22:00-04:00, across midnight
+        assert!(in_time_window(time(22, 0), time(4, 0), time(23, 59)));
+        assert!(in_time_window(time(22, 0), time(4, 0), time(0, 0)));
+        assert!(in_time_window(time(22, 0), time(4, 0), time(3, 59)));
+        assert!(!in_time_window(time(22, 0), time(4, 0), time(4, 0)));
+        assert!(!in_time_window(time(22, 0), time(4, 0), time(12, 0)));
+
+        // outside the window the step holds back though the price is met
+        let now = Utc::now().time();
+        let hour = chrono::Duration::hours(1);
+        let step = |start, end| {
+            vec![
+                Condition::from(ConditionType::TimeWindow { start, end }),
+                Condition::from(ConditionType::PriceAbove {
+                    asset: "SOL".to_string(),
+                    threshold: 100.0,
+                }),
+            ]
+        };
+        let prices = HashMap::from([("SOL".to_string(), 150.0)]);
+        let evaluate = |mut conditions: Vec<Condition>| {
+            Evaluator::evaluate_conditions(
+                &mut conditions,
+                &prices,
+                &HashMap::new(),
+                &HashMap::new(),
+                None,
+            )
+            .unwrap()
+        };
+        assert!(evaluate(step(now - hour, now + hour)));
+        assert!(!evaluate(step(now + hour, now + hour + hour)));
+    }
+}
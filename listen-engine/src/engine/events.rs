@@ -0,0 +1,99 @@
+use std::collections::HashMap;
+
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use super::pipeline::{Pipeline, Status};
+
+/// State of a pipeline as seen by subscribers, broadcast whenever its status
+/// or any condition's `triggered` changes
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PipelineEvent {
+    pub pipeline_id: Uuid,
+    pub status: Status,
+    pub steps: HashMap<Uuid, StepEvent>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct StepEvent {
+    pub status: Status,
+    pub triggered: Vec<bool>, // per condition, in order
+}
+
+impl PipelineEvent {
+    pub fn snapshot(pipeline: &Pipeline) -> Self {
+        Self {
+            pipeline_id: pipeline.id,
+            status: pipeline.status.clone(),
+            steps: pipeline
+                .steps
+                .iter()
+                .map(|(id, step)| {
+                    (
+                        *id,
+                        StepEvent {
+                            status: step.status.clone(),
+                            triggered: step.conditions.iter().map(|c| c.triggered).collect(),
+                        },
+                    )
+                })
+                .collect(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::pipeline::{Action, Condition, ConditionType, Notification, PipelineStep};
+    use chrono::Utc;
+
+    #[test]
+    fn test_snapshot_changes() {
+        let step = PipelineStep {
+            id: Uuid::new_v4(),
+            action: Action::Notification(Notification {
+                message: "-".to_string(),
+            }),
+            conditions: vec![Condition::from(ConditionType::PriceAbove {
+                asset: "SOL".to_string(),
+                threshold: 100.0,
+            })],
+            next_steps: vec![],
+            status: Status::Pending,
+            retry_policy: None,
+            attempts: 0,
+            next_attempt_at: None,
+        };
+        let step_id = step.id;
+        let mut pipeline = Pipeline {
+            id: Uuid::new_v4(),
+            user_id: "-".to_string(),
+            current_steps: vec![step_id],
+            steps: HashMap::from([(step_id, step)]),
+            status: Status::Pending,
+            created_at: Utc::now(),
+            swap_history: vec![],
+            failure_reason: None,
+            eval_interval_ms: None,
+            depth: 0,
+            expires_at: None,
+            version: 0,
+            dry_run: false,
+            dry_run_triggers: vec![],
+        };
+        let before = PipelineEvent::snapshot(&pipeline);
+
+        // evaluating without a change is not an event
+        pipeline.steps.get_mut(&step_id).unwrap().conditions[0].last_evaluated = Some(Utc::now());
+        assert_eq!(PipelineEvent::snapshot(&pipeline), before);
+
+        pipeline.steps.get_mut(&step_id).unwrap().conditions[0].triggered = true;
+        let triggered = PipelineEvent::snapshot(&pipeline);
+        assert_ne!(triggered, before);
+        assert_eq!(triggered.steps[&step_id].triggered, vec![true]);
+
+        pipeline.status = Status::Completed;
+        assert_ne!(PipelineEvent::snapshot(&pipeline), triggered);
+    }
+}
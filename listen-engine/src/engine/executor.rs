@@ -1,16 +1,23 @@
+use crate::config::Config;
 use crate::engine::privy_config::PrivyConfigError;
 
 use super::order::Order;
 use super::privy_config::PrivyConfig;
+use super::swap::{SwapOrder, SwapResult};
 use super::types::{SignAndSendEvmTransactionParams, SignAndSendEvmTransactionRequest};
 use super::types::{
     SignAndSendTransactionParams, SignAndSendTransactionRequest, SignAndSendTransactionResponse,
 };
 use super::util::create_http_client;
 use anyhow::{anyhow, Result};
+use reqwest::StatusCode;
 
 pub struct Executor {
     http_client: reqwest::Client,
+
+    // Raydium swaps are built and signed by the swap service (listen-service)
+    swap_http_client: reqwest::Client,
+    swap_service_url: Option<String>,
 }
 
 #[derive(Debug, thiserror::Error)]
@@ -27,15 +34,120 @@ pub enum ExecutorError {
     #[error("[Executor] Failed to execute Solana transaction: {0}")]
     ExecuteSolanaTransactionError(anyhow::Error),
 
+    #[error("[Executor] Failed to execute swap order: {0}")]
+    ExecuteSwapOrderError(String),
+
+    #[error("[Executor] Pool no longer exists: {0}")]
+    PoolNotFoundError(String),
+
+    #[error("[Executor] Swap service unavailable: {0}")]
+    SwapServiceUnavailableError(String),
+
+    #[error("[Executor] Swap actions are disabled, set SWAP_ACTIONS_ENABLED=true")]
+    SwapActionsDisabledError,
+
+    #[error("[Executor] Webhook failed: {0}")]
+    WebhookError(String),
+
+    #[error("[Executor] Telegram message failed: {0}")]
+    TelegramError(String),
+
+    #[error("[Executor] Discord message failed: {0}")]
+    DiscordError(String),
+
+    /// A webhook or Telegram kept failing to connect or answering with
+    /// server errors, it may take the message later
+    #[error("[Executor] Action target unavailable: {0}")]
+    ActionUnavailableError(String),
+
+    #[error("[Executor] Swap did not land: {0}")]
+    SwapNotLandedError(String),
+
+    /// The swap may have been sent, e.g. the swap service timed out waiting
+    /// for it to confirm or the response was lost, so it must not be sent
+    /// again. `signature` is the one the swap service reported, if any.
+    #[error("[Executor] Swap may have been sent, not retrying: {reason}")]
+    SwapOutcomeUnknownError {
+        signature: Option<String>,
+        reason: String,
+    },
+
     #[error("[Executor] HTTP request failed: {0}")]
     RequestError(#[from] reqwest::Error),
 }
 
+impl ExecutorError {
+    /// Transient errors may succeed if the action is attempted again, and
+    /// are known not to have had any effect: the request never reached its
+    /// target, or the swap service was unavailable before sending the swap
+    pub fn is_transient(&self) -> bool {
+        match self {
+            ExecutorError::RequestError(e) => e.is_connect(),
+            ExecutorError::SwapServiceUnavailableError(_)
+            | ExecutorError::ActionUnavailableError(_) => true,
+            _ => false,
+        }
+    }
+
+    /// A gateway that couldn't reach the swap service answers 502 or 503,
+    /// the swap service itself 504 once the swap was sent but didn't confirm
+    /// in time, with its signature. Any other server error may have come
+    /// after sending too.
+    fn from_swap_response(status: StatusCode, body: String) -> Self {
+        match status {
+            StatusCode::GONE => ExecutorError::PoolNotFoundError(body),
+            StatusCode::BAD_GATEWAY | StatusCode::SERVICE_UNAVAILABLE => {
+                ExecutorError::SwapServiceUnavailableError(body)
+            }
+            status if status.is_server_error() => ExecutorError::SwapOutcomeUnknownError {
+                signature: serde_json::from_str::<serde_json::Value>(&body)
+                    .ok()
+                    .and_then(|body| Some(body.get("signature")?.as_str()?.to_string())),
+                reason: body,
+            },
+            _ => ExecutorError::ExecuteSwapOrderError(body),
+        }
+    }
+
+    /// A request to the swap service that failed once it may have been
+    /// received
+    fn swap_request_failed(e: reqwest::Error) -> Self {
+        if e.is_connect() {
+            ExecutorError::SwapServiceUnavailableError(e.to_string())
+        } else {
+            ExecutorError::SwapOutcomeUnknownError {
+                signature: None,
+                reason: e.to_string(),
+            }
+        }
+    }
+}
+
 impl Executor {
     pub fn from_env() -> Result<Self, ExecutorError> {
         let privy_config = PrivyConfig::from_env().map_err(ExecutorError::InitializeError)?;
-        let http_client = create_http_client(&privy_config);
-        Ok(Self { http_client })
+        Ok(Self::new(
+            create_http_client(&privy_config),
+            std::env::var("SWAP_SERVICE_URL").ok(),
+        ))
+    }
+
+    pub fn from_config(config: &Config) -> Result<Self, ExecutorError> {
+        let privy_config =
+            PrivyConfig::from_config(config).map_err(ExecutorError::InitializeError)?;
+        Ok(Self::new(
+            create_http_client(&privy_config),
+            config.swap_service_url.clone(),
+        ))
+    }
+
+    /// `http_client` is authenticated with Privy, which signs the orders
+    pub fn new(http_client: reqwest::Client, swap_service_url: Option<String>) -> Self {
+        Self {
+            http_client,
+            swap_http_client: reqwest::Client::new(),
+            swap_service_url,
+        }
     }
 
     pub async fn execute_order(&self, order: Order) -> Result<String, ExecutorError> {
@@ -62,6 +174,40 @@ impl Executor {
         }
     }
 
+    /// Executes the swap with `slippage` (bps) already resolved from the
+    /// order's slippage spec
+    pub async fn execute_swap_order(
+        &self,
+        order: &SwapOrder,
+        slippage: u64,
+    ) -> Result<SwapResult, ExecutorError> {
+        let swap_service_url = self.swap_service_url.as_ref().ok_or_else(|| {
+            ExecutorError::ExecuteSwapOrderError("SWAP_SERVICE_URL is not set".to_string())
+        })?;
+        tracing::info!(?order.amm_pool, ?order.amount, slippage, "Executing swap order");
+
+        let response = self
+            .swap_http_client
+            .post(format!("{}/raydium-swap", swap_service_url))
+            .json(&order.to_request(slippage))
+            .send()
+            .await
+            .map_err(ExecutorError::swap_request_failed)?;
+
+        let status = response.status();
+        if !status.is_success() {
+            let body = response.text().await.unwrap_or_default();
+            return Err(ExecutorError::from_swap_response(status, body));
+        }
+
+        let result: SwapResult = response
+            .json()
+            .await
+            .map_err(ExecutorError::swap_request_failed)?;
+        tracing::info!(?result.signature, ?result.outcome, "Swap submitted");
+        Ok(result)
+    }
+
     async fn execute_evm_transaction(
         &self,
         address: String,
@@ -148,8 +294,85 @@ impl Executor {
 mod tests {
     use crate::engine::caip2::Caip2;
     use crate::engine::constants::*;
-    use crate::engine::executor::Executor;
+    use crate::engine::executor::{Executor, ExecutorError};
     use crate::engine::order::Order;
+    use crate::engine::swap::{SlippageSpec, SwapOrder};
+    use reqwest::StatusCode;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+
+    #[test]
+    fn test_missing_pool_is_not_transient() {
+        let body = r#"{"status":"error","reason":"amm pool 58oQ does not exist"}"#;
+        let err = ExecutorError::from_swap_response(StatusCode::GONE, body.to_string());
+        assert!(matches!(err, ExecutorError::PoolNotFoundError(_)));
+        assert!(!err.is_transient());
+        assert!(err.to_string().contains("does not exist"));
+
+        let err = ExecutorError::from_swap_response(
+            StatusCode::SERVICE_UNAVAILABLE,
+            "could not get account".to_string(),
+        );
+        assert!(err.is_transient());
+
+        let err = ExecutorError::from_swap_response(
+            StatusCode::UNPROCESSABLE_ENTITY,
+            "exceeds desired slippage limit".to_string(),
+        );
+        assert!(!err.is_transient());
+    }
+
+    #[tokio::test]
+    async fn test_unconfirmed_swap_is_not_retried() {
+        // stands in for a swap service that sent the swap but gave up
+        // waiting for it to confirm
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let swap_service_url = format!("http://{}", listener.local_addr().unwrap());
+        tokio::spawn(async move {
+            while let Ok((mut stream, _)) = listener.accept().await {
+                let mut buf = [0u8; 4096];
+                let _ = stream.read(&mut buf).await;
+                let body = r#"{"status":"error","reason":"timed out","signature":"5sig"}"#;
+                let response = format!(
+                    "HTTP/1.1 504 Gateway Timeout\r\ncontent-type: application/json\r\ncontent-length: {}\r\n\r\n{}",
+                    body.len(),
+                    body
+                );
+                let _ = stream.write_all(response.as_bytes()).await;
+            }
+        });
+        let executor = Executor::new(reqwest::Client::new(), Some(swap_service_url));
+        let order = SwapOrder {
+            amm_pool: "pool".to_string(),
+            input_mint: "in".to_string(),
+            output_mint: "out".to_string(),
+            amount: 1,
+            slippage: SlippageSpec::Fixed(50),
+        };
+
+        let err = executor.execute_swap_order(&order, 50).await.unwrap_err();
+        match &err {
+            ExecutorError::SwapOutcomeUnknownError { signature, .. } => {
+                assert_eq!(signature.as_deref(), Some("5sig"))
+            }
+            e => panic!("unexpected error {:?}", e),
+        }
+        assert!(!err.is_transient());
+
+        // as is any server error that may have come after sending it
+        let err = ExecutorError::from_swap_response(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "confirmed by 1 of 2 rpcs".to_string(),
+        );
+        assert!(matches!(
+            err,
+            ExecutorError::SwapOutcomeUnknownError {
+                signature: None,
+                ..
+            }
+        ));
+        assert!(!err.is_transient());
+    }
 
     #[tokio::test]
     async fn test_execute_order_eth() {
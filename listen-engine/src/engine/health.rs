@@ -0,0 +1,107 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use serde::Serialize;
+
+use super::constants::HEALTH_CHECK_TIMEOUT_MS;
+use crate::redis::client::RedisClient;
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "status", rename_all = "snake_case")]
+pub enum DependencyStatus {
+    Up,
+    Down { error: String },
+    NotConfigured,
+}
+
+/// Reachability of the engine's dependencies, see `check`
+#[derive(Debug, Clone, Serialize)]
+pub struct HealthReport {
+    pub redis: DependencyStatus,
+    pub solana_rpc: DependencyStatus,
+}
+
+impl HealthReport {
+    /// Names of the dependencies that are down
+    pub fn failing(&self) -> Vec<&'static str> {
+        [("redis", &self.redis), ("solana_rpc", &self.solana_rpc)]
+            .into_iter()
+            .filter(|(_, status)| matches!(status, DependencyStatus::Down { .. }))
+            .map(|(name, _)| name)
+            .collect()
+    }
+}
+
+/// Checks the dependencies concurrently, each bounded by
+/// `HEALTH_CHECK_TIMEOUT_MS`
+pub async fn check(
+    redis: Arc<RedisClient>,
+    http_client: Arc<reqwest::Client>,
+    rpc_url: Option<String>,
+) -> HealthReport {
+    let (redis, solana_rpc) = tokio::join!(
+        bounded(async move { redis.ping().await.map_err(|e| e.to_string()) }),
+        async move {
+            match rpc_url {
+                Some(rpc_url) => bounded(check_rpc(&http_client, &rpc_url)).await,
+                None => DependencyStatus::NotConfigured,
+            }
+        }
+    );
+    HealthReport { redis, solana_rpc }
+}
+
+async fn bounded(check: impl std::future::Future<Output = Result<(), String>>) -> DependencyStatus {
+    match tokio::time::timeout(Duration::from_millis(HEALTH_CHECK_TIMEOUT_MS), check).await {
+        Ok(Ok(())) => DependencyStatus::Up,
+        Ok(Err(error)) => DependencyStatus::Down { error },
+        Err(_) => DependencyStatus::Down {
+            error: "timed out".to_string(),
+        },
+    }
+}
+
+/// Solana's `getHealth`, which answers "ok" once the node has caught up
+async fn check_rpc(http_client: &reqwest::Client, rpc_url: &str) -> Result<(), String> {
+    let response: serde_json::Value = http_client
+        .post(rpc_url)
+        .json(&serde_json::json!({
+            "jsonrpc": "2.0",
+            "id": 1,
+            "method": "getHealth",
+        }))
+        .send()
+        .await
+        .map_err(|e| e.to_string())?
+        .json()
+        .await
+        .map_err(|e| e.to_string())?;
+    match response.get("result") {
+        Some(result) if result == "ok" => Ok(()),
+        _ => Err(response["error"].to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_unreachable_rpc_is_down() {
+        // nothing listens on the discard port
+        let solana_rpc = bounded(check_rpc(&reqwest::Client::new(), "http://127.0.0.1:9")).await;
+        assert!(matches!(solana_rpc, DependencyStatus::Down { .. }));
+
+        let report = HealthReport {
+            redis: DependencyStatus::Up,
+            solana_rpc,
+        };
+        assert_eq!(report.failing(), vec!["solana_rpc"]);
+
+        let report = HealthReport {
+            redis: DependencyStatus::Up,
+            solana_rpc: DependencyStatus::NotConfigured,
+        };
+        assert!(report.failing().is_empty());
+    }
+}
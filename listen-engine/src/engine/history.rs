@@ -0,0 +1,55 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use super::pipeline::{Action, Condition};
+
+/// Record of a step whose conditions were met, and what came of its action
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ExecutionEvent {
+    pub pipeline_id: Uuid,
+    pub step_id: Uuid,
+    pub timestamp: DateTime<Utc>,
+    pub conditions: Vec<Condition>, // as they were when the step fired
+    pub action: Action,
+    #[serde(flatten)]
+    pub outcome: Outcome,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "outcome", rename_all = "snake_case")]
+pub enum Outcome {
+    Executed,
+    /// Failed transiently, attempted again on the next evaluation
+    Retrying {
+        error: String,
+    },
+    Failed {
+        error: String,
+    },
+    /// The pipeline is a dry run, the action was not executed
+    DryRun,
+}
+
+impl ExecutionEvent {
+    pub fn new(
+        pipeline_id: Uuid,
+        step_id: Uuid,
+        conditions: &[Condition],
+        action: &Action,
+        outcome: Outcome,
+    ) -> Self {
+        Self {
+            pipeline_id,
+            step_id,
+            timestamp: Utc::now(),
+            conditions: conditions.to_vec(),
+            action: action.clone(),
+            outcome,
+        }
+    }
+
+    pub fn key(pipeline_id: impl std::fmt::Display) -> String {
+        format!("history:{}", pipeline_id)
+    }
+}
@@ -0,0 +1,149 @@
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+
+use chrono::{DateTime, Duration, Utc};
+use serde::Serialize;
+use uuid::Uuid;
+
+use super::constants::JOB_RETENTION_SECS;
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "status", rename_all = "snake_case")]
+pub enum JobStatus {
+    Running,
+    Completed,
+    Cancelled,
+    Failed { error: String },
+}
+
+/// Progress of a background admin operation, `total` is known once the job
+/// has found what it works on
+#[derive(Debug, Clone, Serialize)]
+pub struct Job {
+    pub id: Uuid,
+    pub kind: String,
+    #[serde(flatten)]
+    pub status: JobStatus,
+    pub done: usize,
+    pub total: Option<usize>,
+    pub cancel_requested: bool,
+    pub started_at: DateTime<Utc>,
+    pub finished_at: Option<DateTime<Utc>>,
+}
+
+struct Entry {
+    job: Job,
+    cancelled: Arc<AtomicBool>,
+}
+
+/// Long-running admin operations, each run in its own task and polled by
+/// id. Finished jobs are kept for `JOB_RETENTION_SECS`.
+#[derive(Clone, Default)]
+pub struct Jobs(Arc<Mutex<HashMap<Uuid, Entry>>>);
+
+impl Jobs {
+    /// Runs `task` in the background and returns the job's id right away.
+    /// The task reports progress and checks for cancellation through the
+    /// `JobHandle`; its result settles the job.
+    pub fn spawn<F, Fut>(&self, kind: &str, task: F) -> Uuid
+    where
+        F: FnOnce(JobHandle) -> Fut,
+        Fut: Future<Output = Result<(), String>> + Send + 'static,
+    {
+        let id = Uuid::new_v4();
+        let cancelled = Arc::new(AtomicBool::new(false));
+        {
+            let mut jobs = self.0.lock().unwrap();
+            let cutoff = Utc::now() - Duration::seconds(JOB_RETENTION_SECS);
+            jobs.retain(|_, entry| entry.job.finished_at.is_none_or(|at| at > cutoff));
+            jobs.insert(
+                id,
+                Entry {
+                    job: Job {
+                        id,
+                        kind: kind.to_string(),
+                        status: JobStatus::Running,
+                        done: 0,
+                        total: None,
+                        cancel_requested: false,
+                        started_at: Utc::now(),
+                        finished_at: None,
+                    },
+                    cancelled: cancelled.clone(),
+                },
+            );
+        }
+
+        let handle = JobHandle {
+            id,
+            jobs: self.clone(),
+            cancelled,
+        };
+        let task = task(handle.clone());
+        tokio::spawn(async move {
+            let status = match task.await {
+                Ok(()) if handle.is_cancelled() => JobStatus::Cancelled,
+                Ok(()) => JobStatus::Completed,
+                Err(error) => {
+                    tracing::error!(job_id = %id, "Job failed: {}", error);
+                    JobStatus::Failed { error }
+                }
+            };
+            handle.update(|job| {
+                job.status = status;
+                job.finished_at = Some(Utc::now());
+            });
+        });
+        id
+    }
+
+    pub fn get(&self, id: Uuid) -> Option<Job> {
+        self.0
+            .lock()
+            .unwrap()
+            .get(&id)
+            .map(|entry| entry.job.clone())
+    }
+
+    /// Asks the job to stop, it does at its next check. Returns the job as
+    /// of the request.
+    pub fn cancel(&self, id: Uuid) -> Option<Job> {
+        let mut jobs = self.0.lock().unwrap();
+        let entry = jobs.get_mut(&id)?;
+        if entry.job.status == JobStatus::Running {
+            entry.cancelled.store(true, Ordering::Relaxed);
+            entry.job.cancel_requested = true;
+        }
+        Some(entry.job.clone())
+    }
+}
+
+/// What a job's task reports through
+#[derive(Clone)]
+pub struct JobHandle {
+    id: Uuid,
+    jobs: Jobs,
+    cancelled: Arc<AtomicBool>,
+}
+
+impl JobHandle {
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::Relaxed)
+    }
+
+    pub fn set_total(&self, total: usize) {
+        self.update(|job| job.total = Some(total));
+    }
+
+    pub fn advance(&self, done: usize) {
+        self.update(|job| job.done += done);
+    }
+
+    fn update(&self, f: impl FnOnce(&mut Job)) {
+        if let Some(entry) = self.jobs.0.lock().unwrap().get_mut(&self.id) {
+            f(&mut entry.job);
+        }
+    }
+}
@@ -1,29 +1,60 @@
 pub mod caip2;
 pub mod constants;
+pub mod dlq;
 pub mod evaluator;
+pub mod events;
 pub mod executor;
+pub mod health;
+pub mod history;
+pub mod jobs;
 pub mod order;
 pub mod pipeline;
+pub mod pnl;
+pub mod price_source;
 pub mod privy_config;
+pub mod swap;
 pub mod types;
 pub mod util;
+pub mod webhook;
 
+use crate::config::Config;
 use crate::engine::evaluator::EvaluatorError;
 use crate::redis::client::{make_redis_client, RedisClient, RedisClientError};
+use crate::redis::lease::Lease;
 use crate::redis::subscriber::{
     make_redis_subscriber, PriceUpdate, RedisSubscriber, RedisSubscriberError,
 };
 use anyhow::Result;
-use metrics::{counter, gauge, histogram};
-use std::collections::{HashMap, HashSet};
+use metrics::{counter, decrement_gauge, gauge, histogram, increment_gauge};
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::future::Future;
 use std::sync::Arc;
-use std::time::Instant;
-use tokio::sync::mpsc;
+use std::time::{Duration, Instant};
 use tokio::sync::RwLock;
+use tokio::sync::{broadcast, mpsc, watch};
 use uuid::Uuid;
 
-use self::evaluator::Evaluator;
-use self::pipeline::{Action, Condition, ConditionType, Pipeline, Status};
+use self::constants::{
+    EXECUTION_LOG_MAX_LEN, MAX_PAGE_SIZE, MAX_SWAP_ACTIVITY_WINDOW_SECS, PIPELINE_EVENTS_CAPACITY,
+    PIPELINE_SAVE_ATTEMPTS, PIPELINE_SCAN_COUNT, PRICE_HISTORY_LEN, WEBHOOK_BACKOFF_MS,
+};
+use self::dlq::DeadLetter;
+use self::evaluator::{Evaluator, SwapActivity, WhatIf};
+use self::events::PipelineEvent;
+use self::executor::ExecutorError;
+use self::history::{ExecutionEvent, Outcome};
+use self::jobs::Jobs;
+use self::pipeline::{
+    Action, Condition, ConditionType, MessageContext, Pipeline, PipelinePage, Status,
+};
+use self::price_source::{
+    CachedPriceSource, ClickhousePriceSource, PriceError, PriceSource, PriceSources,
+    PythPriceSource,
+};
+use self::swap::{SwapOrder, SwapResult};
+use self::webhook::{
+    post_discord_message, post_step_webhook, send_telegram_message, StepWebhookEvent, SwapWebhook,
+};
 use crate::server::EngineMessage;
 
 #[derive(Debug, thiserror::Error)]
@@ -31,6 +62,9 @@ pub enum EngineError {
     #[error("[Engine] Failed to add pipeline: {0}")]
     AddPipelineError(RedisClientError),
 
+    #[error("[Engine] Invalid pipeline: {0}")]
+    InvalidPipelineError(String),
+
     #[error("[Engine] Failed to delete pipeline: {0}")]
     DeletePipelineError(RedisClientError),
 
@@ -40,6 +74,21 @@ pub enum EngineError {
     #[error("[Engine] Failed to evaluate pipeline: {0}")]
     EvaluatePipelineError(EvaluatorError),
 
+    #[error("[Engine] Pipeline already settled: {0}")]
+    PipelineSettledError(String),
+
+    #[error("[Engine] Invalid cursor: {0}")]
+    InvalidCursorError(String),
+
+    #[error("[Engine] Failed to get dead letter: {0}")]
+    GetDeadLetterError(String),
+
+    #[error("[Engine] Failed to retry dead letter: {0}")]
+    RetryDeadLetterError(String),
+
+    #[error("[Engine] Forced triggers are disabled, set FORCE_TRIGGER_ENABLED=true")]
+    ForceTriggerDisabledError,
+
     #[error("[Engine] Failed to extract assets: {0}")]
     ExtractAssetsError(anyhow::Error),
 
@@ -54,6 +103,172 @@ pub enum EngineError {
 
     #[error("[Engine] Redis subscriber error: {0}")]
     RedisSubscriberError(RedisSubscriberError),
+
+    #[error("[Engine] Price source error: {0}")]
+    PriceSourceError(PriceError),
+
+    #[error("[Engine] Missing or malformed environment variables: {}", missing.join(", "))]
+    Config { missing: Vec<String> },
+}
+
+/// Environment variables the engine can't start with, being unset or not
+/// parsing
+fn missing_config(config: &Config) -> Vec<String> {
+    let is_url = |url: &str, schemes: &[&str]| {
+        reqwest::Url::parse(url).is_ok_and(|url| schemes.contains(&url.scheme()))
+    };
+    let http = ["http", "https"];
+    let checks = [
+        ("PRIVY_APP_ID", config.privy_app_id.is_some()),
+        (
+            "PRIVY_APP_SECRET",
+            config.privy_app_secret.expose().is_some(),
+        ),
+        (
+            "REDIS_URL",
+            config
+                .redis_url
+                .expose()
+                .is_some_and(|url| is_url(url, &["redis", "rediss", "redis+unix", "unix"])),
+        ),
+        (
+            "SOLANA_RPC_URL",
+            config
+                .solana_rpc_url
+                .expose()
+                .is_none_or(|url| is_url(url, &http)),
+        ),
+        (
+            "SWAP_SERVICE_URL",
+            config
+                .swap_service_url
+                .as_deref()
+                .is_none_or(|url| is_url(url, &http)),
+        ),
+        (
+            "CLICKHOUSE_URL",
+            config
+                .clickhouse_url
+                .as_deref()
+                .is_none_or(|url| is_url(url, &http)),
+        ),
+    ];
+    checks
+        .into_iter()
+        .filter(|(_, ok)| !ok)
+        .map(|(name, _)| name.to_string())
+        .collect()
+}
+
+/// The configured price sources, Pyth's oracle ahead of ClickHouse's swap
+/// prices, behind a cache for `PRICE_CACHE_TTL_MS`
+fn price_source(config: &Config) -> Result<Option<Arc<dyn PriceSource>>, PriceError> {
+    let mut sources: Vec<Arc<dyn PriceSource>> = vec![];
+    if let Some(pyth) = PythPriceSource::from_config(config)? {
+        sources.push(Arc::new(pyth));
+    }
+    if let Some(clickhouse) = ClickhousePriceSource::from_config(config)? {
+        sources.push(Arc::new(clickhouse));
+    }
+    let source: Arc<dyn PriceSource> = match sources.len() {
+        0 => return Ok(None),
+        1 => sources.remove(0),
+        _ => Arc::new(PriceSources(sources)),
+    };
+    Ok(Some(Arc::new(CachedPriceSource::new(
+        source,
+        Duration::from_millis(config.price_cache_ttl_ms),
+    ))))
+}
+
+/// Volumes looked up for an evaluation round by asset and window, `None`
+/// for one that couldn't be looked up
+type Volumes = HashMap<(String, u64), Option<f64>>;
+
+/// Adds the asset and window of each pending `VolumeAbove` the pipeline's
+/// pending steps wait on to `windows`
+fn volume_windows(pipeline: &Pipeline, windows: &mut HashSet<(String, u64)>) {
+    let mut stack: Vec<&Condition> = pipeline
+        .current_steps
+        .iter()
+        .filter_map(|step_id| pipeline.steps.get(step_id))
+        .filter(|step| matches!(step.status, Status::Pending))
+        .flat_map(|step| &step.conditions)
+        .collect();
+    while let Some(condition) = stack.pop() {
+        if condition.is_settled() {
+            continue;
+        }
+        match &condition.condition_type {
+            ConditionType::VolumeAbove {
+                asset, window_secs, ..
+            } => {
+                windows.insert((asset.clone(), *window_secs));
+            }
+            ConditionType::And(sub) | ConditionType::Or(sub) => stack.extend(sub),
+            _ => {}
+        }
+    }
+}
+
+/// Sets the volume of each pending `VolumeAbove` among `conditions` to the
+/// one looked up. One that couldn't be looked up is left unknown rather
+/// than stale, so the condition doesn't fire on it.
+fn fill_volumes(volumes: &Volumes, conditions: &mut [Condition]) {
+    let mut stack: Vec<&mut Condition> = conditions.iter_mut().collect();
+    while let Some(condition) = stack.pop() {
+        if condition.is_settled() {
+            continue;
+        }
+        match &mut condition.condition_type {
+            ConditionType::VolumeAbove {
+                asset,
+                window_secs,
+                volume,
+                ..
+            } => {
+                *volume = volumes
+                    .get(&(asset.clone(), *window_secs))
+                    .copied()
+                    .flatten();
+            }
+            ConditionType::And(sub) | ConditionType::Or(sub) => stack.extend(sub.iter_mut()),
+            _ => {}
+        }
+    }
+}
+
+/// Moves a pipeline between the `pipelines` gauges by status, `None` being
+/// outside the engine, and counts it in `active_pipelines` while it has yet
+/// to settle
+fn record_status_change(from: Option<&Status>, to: Option<&Status>) {
+    if let Some(from) = from {
+        decrement_gauge!("pipelines", 1.0, "status" => from.as_str());
+    }
+    if let Some(to) = to {
+        increment_gauge!("pipelines", 1.0, "status" => to.as_str());
+    }
+    let active = |status: Option<&Status>| status.is_some_and(|status| !status.is_terminal());
+    match (active(from), active(to)) {
+        (false, true) => increment_gauge!("active_pipelines", 1.0),
+        (true, false) => decrement_gauge!("active_pipelines", 1.0),
+        _ => {}
+    }
+}
+
+/// Whether this instance is to evaluate a pipeline
+enum Ownership {
+    Owned,
+    NotOwned,
+    // deleted from Redis through another instance
+    Deleted,
+}
+
+/// The step whose action is being run
+struct FiredStep<'a> {
+    pipeline_id: Uuid,
+    step_id: Uuid,
+    conditions: &'a [Condition],
 }
 
 pub struct Engine {
@@ -66,18 +281,96 @@ pub struct Engine {
     // Active pipelines indexed by UUID
     active_pipelines: RwLock<HashMap<Uuid, Pipeline>>,
 
-    // Asset to pipeline index for efficient updates
+    // Asset to pipeline index for efficient updates, pipelines referenced by
+    // other pipelines' conditions are indexed under `pipeline_key`
     asset_subscriptions: RwLock<HashMap<String, HashSet<Uuid>>>,
 
     // Current market state
     price_cache: RwLock<HashMap<String, f64>>,
+
+    // Prices of assets no update has been received for yet
+    price_source: Option<Arc<dyn PriceSource>>,
+
+    // Last evaluation of each pipeline, for `Pipeline::eval_interval_ms`
+    last_evaluations: RwLock<HashMap<Uuid, Instant>>,
+
+    // Recent swap timestamps per pool, for `PoolTxCountAbove`
+    swap_activity: RwLock<SwapActivity>,
+
+    // Recent prices per asset, oldest first, for adaptive slippage. Only
+    // streamed prices are kept, so it also tells the assets the price source
+    // no longer needs to poll
+    price_history: RwLock<HashMap<String, VecDeque<f64>>>,
+
+    // `SwapOrder` actions only trade when `SWAP_ACTIONS_ENABLED=true`
+    swap_actions_enabled: bool,
+
+    // `force_trigger` only runs when `FORCE_TRIGGER_ENABLED=true`
+    force_trigger_enabled: bool,
+
+    // Webhooks may only post to local or private hosts when
+    // `WEBHOOK_ALLOW_PRIVATE_HOSTS=true`
+    webhook_allow_private_hosts: bool,
+
+    // Owner of the pipeline leases this instance takes
+    instance_id: String,
+
+    // Leases are only taken with `PIPELINE_LEASE_TTL_MS` set
+    lease_ttl: Option<Duration>,
+
+    // Leases held, with when each was last renewed
+    leases: RwLock<HashMap<Uuid, Instant>>,
+
+    // Upper bound for resolved swap slippage, in bps
+    max_slippage_bps: u64,
+
+    // Dead letters kept per user
+    dlq_max_len: usize,
+
+    // Receives every swap result when `SWAP_CONFIRMATION_WEBHOOK` is set
+    swap_webhook: Option<SwapWebhook>,
+
+    // Shared by `Action::Webhook`, `Action::Telegram` and `Action::Discord`
+    // requests and the health probe, see `util::create_shared_http_client`
+    http_client: Arc<reqwest::Client>,
+
+    // Bot API that `Action::Telegram` messages are sent to, `TELEGRAM_API_URL`
+    telegram_api_url: String,
+
+    // Checked by the health probe when set
+    rpc_url: Option<String>,
+
+    // Longest chain of steps a pipeline may have, see `Pipeline::validate_depth`
+    max_step_depth: usize,
+
+    // Tick of the loop in `run`, see `DEFAULT_EVAL_INTERVAL_MS`
+    eval_interval: Duration,
+
+    // Status and condition changes, for live updates
+    pipeline_events: broadcast::Sender<PipelineEvent>,
+
+    // Long-running admin operations
+    jobs: Jobs,
 }
 
 impl Engine {
-    pub async fn from_env() -> Result<Self, EngineError> {
+    pub async fn from_config(config: &Config) -> Result<Self, EngineError> {
+        let missing = missing_config(config);
+        if !missing.is_empty() {
+            return Err(EngineError::Config { missing });
+        }
+        let executor =
+            executor::Executor::from_config(config).map_err(EngineError::ExecutorError)?;
+        Self::with_executor(config, executor).await
+    }
+
+    pub async fn with_executor(
+        config: &Config,
+        executor: executor::Executor,
+    ) -> Result<Self, EngineError> {
         let (tx, rx) = mpsc::channel(1000);
         Ok(Self {
-            executor: executor::Executor::from_env().map_err(EngineError::ExecutorError)?,
+            executor,
             redis: make_redis_client()
                 .await
                 .map_err(EngineError::RedisClientError)?,
@@ -86,39 +379,129 @@ impl Engine {
             active_pipelines: RwLock::new(HashMap::new()),
             asset_subscriptions: RwLock::new(HashMap::new()),
             price_cache: RwLock::new(HashMap::new()),
+            price_source: price_source(config).map_err(EngineError::PriceSourceError)?,
+            last_evaluations: RwLock::new(HashMap::new()),
+            price_history: RwLock::new(HashMap::new()),
+            swap_activity: RwLock::new(HashMap::new()),
+            swap_actions_enabled: config.swap_actions_enabled,
+            force_trigger_enabled: config.force_trigger_enabled,
+            webhook_allow_private_hosts: config.webhook_allow_private_hosts,
+            instance_id: config.instance_id.clone(),
+            lease_ttl: (config.pipeline_lease_ttl_ms > 0)
+                .then(|| Duration::from_millis(config.pipeline_lease_ttl_ms)),
+            leases: RwLock::new(HashMap::new()),
+            max_slippage_bps: config.max_slippage_bps,
+            dlq_max_len: config.dlq_max_len,
+            swap_webhook: SwapWebhook::from_config(config),
+            http_client: Arc::new(
+                util::create_shared_http_client()
+                    .map_err(|e| EngineError::ExecutorError(ExecutorError::RequestError(e)))?,
+            ),
+            telegram_api_url: config.telegram_api_url.clone(),
+            rpc_url: config.solana_rpc_url.expose().map(str::to_string),
+            max_step_depth: config.max_step_depth,
+            eval_interval: Duration::from_millis(config.eval_interval_ms),
+            pipeline_events: broadcast::channel(PIPELINE_EVENTS_CAPACITY).0,
+            jobs: Jobs::default(),
         })
     }
 
-    pub async fn run(&mut self, mut command_rx: mpsc::Receiver<EngineMessage>) -> Result<()> {
+    /// Replaces the price sources configured with `PYTH_PRICE_ACCOUNTS` and
+    /// `CLICKHOUSE_URL`
+    pub fn with_price_source(mut self, price_source: Arc<dyn PriceSource>) -> Self {
+        self.price_source = Some(price_source);
+        self
+    }
+
+    /// Replaces the client for actions and health probes, to share the one
+    /// the server hands its handlers
+    pub fn with_http_client(mut self, http_client: Arc<reqwest::Client>) -> Self {
+        self.http_client = http_client;
+        self
+    }
+
+    /// Sender of the pipeline events, `subscribe` to receive them
+    pub fn pipeline_events(&self) -> broadcast::Sender<PipelineEvent> {
+        self.pipeline_events.clone()
+    }
+
+    /// Registry of the admin jobs, shared with the server that starts them
+    pub fn jobs(&self) -> Jobs {
+        self.jobs.clone()
+    }
+
+    /// Broadcasts the pipeline's state if it differs from `before`
+    fn publish_changes(&self, before: &PipelineEvent, pipeline: &Pipeline) {
+        if before.status != pipeline.status {
+            record_status_change(Some(&before.status), Some(&pipeline.status));
+        }
+        let after = PipelineEvent::snapshot(pipeline);
+        if after != *before {
+            // no subscribers is not an error
+            let _ = self.pipeline_events.send(after);
+        }
+    }
+
+    /// Processes commands and price updates until `shutdown` changes, then
+    /// handles the commands already queued and returns
+    pub async fn run(
+        &mut self,
+        mut command_rx: mpsc::Receiver<EngineMessage>,
+        mut shutdown: watch::Receiver<bool>,
+    ) -> Result<()> {
         let pipelines = self.redis.get_all_pipelines().await?;
         let total_pipelines = pipelines.len();
         for pipeline in pipelines {
+            self.fill_missing_prices(&self.extract_assets(&pipeline).await)
+                .await;
             self.add_pipeline(pipeline).await?;
         }
         tracing::info!("Added {} pipelines", total_pipelines);
 
         self.redis_sub.start_listening().await?;
 
+        tracing::info!(
+            "Evaluation loop ticking every {}ms",
+            self.eval_interval.as_millis()
+        );
+        let mut eval_tick = tokio::time::interval(self.eval_interval);
+        eval_tick.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+        let mut last_adoption = Instant::now();
         loop {
             tokio::select! {
                 Some(msg) = command_rx.recv() => {
-                    match msg {
-                        EngineMessage::AddPipeline { pipeline, response_tx } => {
-                            let result = self.add_pipeline(pipeline).await;
-                            // Ignore error from send - receiver may have dropped
-                            let _ = response_tx.send(result);
-                        },
-                        EngineMessage::DeletePipeline { pipeline_id, response_tx } => {
-                            let result = self.delete_pipeline(pipeline_id).await;
-                            let _ = response_tx.send(result);
-                        },
-                        EngineMessage::GetPipeline { pipeline_id, response_tx } => {
-                            let result = self.get_pipeline(pipeline_id).await;
-                            let _ = response_tx.send(result);
-                        },
+                    gauge!("engine_queue_depth", command_rx.len() as f64);
+                    self.handle_message(msg).await
+                }
+                _ = eval_tick.tick() => {
+                    counter!("evaluation_loop_iterations", 1);
+                    if let Err(e) = self.refresh_prices().await {
+                        tracing::error!("Error refreshing prices: {}", e);
+                    }
+                    if let Err(e) = self.retry_due_pipelines().await {
+                        tracing::error!("Error retrying actions: {}", e);
+                    }
+                    if let Err(e) = self.expire_due_pipelines().await {
+                        tracing::error!("Error expiring pipelines: {}", e);
                     }
+                    if let Err(e) = self.evaluate_timed_pipelines().await {
+                        tracing::error!("Error evaluating timed pipelines: {}", e);
+                    }
+                    if self.lease_ttl.is_some_and(|ttl| last_adoption.elapsed() >= ttl) {
+                        last_adoption = Instant::now();
+                        if let Err(e) = self.adopt_pipelines().await {
+                            tracing::error!("Error adopting pipelines: {}", e);
+                        }
+                    }
+                }
+                _ = shutdown.changed() => {
+                    // an in-flight swap has finished by the time this branch runs
+                    let drained = self.drain(&mut command_rx).await;
+                    tracing::info!("Engine stopped, drained {} messages", drained);
+                    break;
                 }
                 Some(price_update) = self.receiver.recv() => {
+                    self.record_swap(&price_update.pubkey, price_update.timestamp).await;
                     if let Err(e) = self.handle_price_update(&price_update.pubkey, price_update.price).await {
                         tracing::error!("Error handling price update: {}", e);
                     }
@@ -130,12 +513,210 @@ impl Engine {
         Ok(())
     }
 
-    pub async fn add_pipeline(&self, pipeline: Pipeline) -> Result<(), EngineError> {
-        if let Err(e) = self.redis.save_pipeline(&pipeline).await {
+    /// Answers the messages already sent, taking no more, then saves every
+    /// active pipeline. Returns the number of messages answered.
+    async fn drain(&self, command_rx: &mut mpsc::Receiver<EngineMessage>) -> usize {
+        command_rx.close();
+        let mut drained = 0;
+        while let Some(msg) = command_rx.recv().await {
+            self.handle_message(msg).await;
+            drained += 1;
+        }
+
+        for pipeline in self.active_pipelines.write().await.values_mut() {
+            match self.redis.save_pipeline(pipeline).await {
+                // saved by another writer since, which is newer
+                Ok(()) | Err(RedisClientError::Conflict(_)) => {}
+                Err(e) => {
+                    tracing::error!(pipeline_id = %pipeline.id, "Failed to save pipeline: {}", e)
+                }
+            }
+        }
+
+        // the other instances need not wait for them to expire
+        for (pipeline_id, _) in self.leases.write().await.drain() {
+            if let Err(e) = self
+                .redis
+                .release_lease(pipeline_id, &self.instance_id)
+                .await
+            {
+                tracing::warn!(%pipeline_id, "Failed to release lease: {}", e);
+            }
+        }
+        drained
+    }
+
+    async fn handle_message(&self, msg: EngineMessage) {
+        match msg {
+            EngineMessage::AddPipeline {
+                mut pipeline,
+                response_tx,
+            } => {
+                let pipeline_id = pipeline.id;
+                self.fill_missing_prices(&self.extract_assets(&pipeline).await)
+                    .await;
+                pipeline.capture_reference_prices(&*self.price_cache.read().await);
+                let has_references = !pipeline.referenced_pipelines().is_empty();
+                let result = match self.validate_pipeline(&pipeline).await {
+                    Ok(()) => self.add_pipeline(pipeline).await,
+                    Err(e) => Err(e),
+                };
+                let added = result.is_ok();
+                // Ignore error from send - receiver may have dropped
+                let _ = response_tx.send(result);
+                // Referenced pipelines may have completed already
+                if added && has_references {
+                    if let Err(e) = self.evaluate_pipelines(vec![pipeline_id]).await {
+                        tracing::error!("Error evaluating pipeline: {}", e);
+                    }
+                }
+            }
+            EngineMessage::DeletePipeline {
+                pipeline_id,
+                response_tx,
+            } => {
+                let result = self.delete_pipeline(pipeline_id).await;
+                let _ = response_tx.send(result);
+            }
+            EngineMessage::GetPipeline {
+                pipeline_id,
+                response_tx,
+            } => {
+                let result = self.get_pipeline(pipeline_id).await;
+                let _ = response_tx.send(result);
+            }
+            EngineMessage::ListPipelines {
+                user_id,
+                limit,
+                cursor,
+                response_tx,
+            } => {
+                let result = self.list_pipelines(&user_id, limit, cursor).await;
+                let _ = response_tx.send(result);
+            }
+            EngineMessage::PatchPipeline {
+                pipeline_id,
+                patch,
+                response_tx,
+            } => {
+                let result = self.patch_pipeline(pipeline_id, patch).await;
+                let patched = result.is_ok();
+                let _ = response_tx.send(result);
+                // A loosened condition may already be met
+                if patched {
+                    if let Err(e) = self.evaluate_pipelines(vec![pipeline_id]).await {
+                        tracing::error!("Error evaluating pipeline: {}", e);
+                    }
+                }
+            }
+            EngineMessage::PausePipeline {
+                pipeline_id,
+                response_tx,
+            } => {
+                let result = self.set_paused(pipeline_id, true).await;
+                let _ = response_tx.send(result);
+            }
+            EngineMessage::ResumePipeline {
+                pipeline_id,
+                response_tx,
+            } => {
+                let result = self.set_paused(pipeline_id, false).await;
+                let _ = response_tx.send(result);
+            }
+            EngineMessage::Ping { response_tx } => {
+                // checked off the loop so a slow dependency doesn't stall it
+                let check = health::check(
+                    self.redis.clone(),
+                    self.http_client.clone(),
+                    self.rpc_url.clone(),
+                );
+                tokio::spawn(async move {
+                    let _ = response_tx.send(check.await);
+                });
+            }
+            EngineMessage::WhatIf {
+                pipeline_id,
+                asset,
+                price,
+                response_tx,
+            } => {
+                let result = self.what_if(pipeline_id, &asset, price).await;
+                let _ = response_tx.send(result);
+            }
+            EngineMessage::GetExecutionLog {
+                pipeline_id,
+                response_tx,
+            } => {
+                let result = self.get_execution_log(pipeline_id).await;
+                let _ = response_tx.send(result);
+            }
+            EngineMessage::GetDeadLetters {
+                user_id,
+                response_tx,
+            } => {
+                let result = self.get_dead_letters(&user_id).await;
+                let _ = response_tx.send(result);
+            }
+            EngineMessage::RetryDeadLetter {
+                user_id,
+                id,
+                response_tx,
+            } => {
+                let result = self.retry_dead_letter(&user_id, id).await;
+                let _ = response_tx.send(result);
+            }
+            EngineMessage::ForceTrigger {
+                pipeline_id,
+                user_id,
+                response_tx,
+            } => {
+                let result = self.force_trigger(pipeline_id, user_id.as_deref()).await;
+                let _ = response_tx.send(result);
+            }
+        }
+    }
+
+    /// Looks up the assets without a price yet in the price source, so
+    /// conditions on them can be evaluated before their first update
+    async fn fill_missing_prices(&self, assets: &HashSet<String>) {
+        let Some(price_source) = &self.price_source else {
+            return;
+        };
+        let missing: Vec<String> = {
+            let price_cache = self.price_cache.read().await;
+            assets
+                .iter()
+                .filter(|asset| !price_cache.contains_key(*asset))
+                .cloned()
+                .collect()
+        };
+        if missing.is_empty() {
+            return;
+        }
+        let missing: Vec<&str> = missing.iter().map(String::as_str).collect();
+        match price_source.get_prices(&missing).await {
+            Ok(prices) => {
+                let mut price_cache = self.price_cache.write().await;
+                for (asset, price) in prices {
+                    // an update received meanwhile is newer
+                    price_cache.entry(asset).or_insert(price);
+                }
+            }
+            Err(e) => tracing::warn!("Failed to look up prices: {}", e),
+        }
+    }
+
+    pub async fn add_pipeline(&self, mut pipeline: Pipeline) -> Result<(), EngineError> {
+        if let Err(e) = self.redis.save_pipeline(&mut pipeline).await {
             return Err(EngineError::AddPipelineError(e));
         }
 
-        // Then add to engine
+        self.track_pipeline(pipeline).await;
+        Ok(())
+    }
+
+    /// Indexes the pipeline and starts evaluating it, as stored
+    async fn track_pipeline(&self, pipeline: Pipeline) {
         let mut active_pipelines = self.active_pipelines.write().await;
         let mut asset_subscriptions = self.asset_subscriptions.write().await;
 
@@ -150,139 +731,2396 @@ impl Engine {
                 .insert(pipeline.id);
         }
 
+        record_status_change(None, Some(&pipeline.status));
         active_pipelines.insert(pipeline.id, pipeline);
-        Ok(())
     }
 
-    pub async fn delete_pipeline(&self, pipeline_id: Uuid) -> Result<(), EngineError> {
-        let mut active_pipelines = self.active_pipelines.write().await;
-        active_pipelines.remove(&pipeline_id);
+    /// Starts evaluating the pipelines in Redis that this instance doesn't
+    /// have, as created through another instance, so that they are taken
+    /// over should it go away
+    async fn adopt_pipelines(&self) -> Result<(), EngineError> {
+        let keys = self
+            .redis
+            .scan_pipeline_keys(PIPELINE_SCAN_COUNT)
+            .await
+            .map_err(EngineError::RedisClientError)?;
+        let unknown: Vec<String> = {
+            let active_pipelines = self.active_pipelines.read().await;
+            keys.iter()
+                .filter_map(|key| key.strip_prefix("pipeline:"))
+                .filter(|id| {
+                    Uuid::parse_str(id).is_ok_and(|id| !active_pipelines.contains_key(&id))
+                })
+                .map(str::to_string)
+                .collect()
+        };
+        for id in unknown {
+            let Some(pipeline) = self
+                .redis
+                .get_pipeline(&id)
+                .await
+                .map_err(EngineError::RedisClientError)?
+            else {
+                continue;
+            };
+            if pipeline.status.is_terminal() {
+                continue;
+            }
+            self.fill_missing_prices(&self.extract_assets(&pipeline).await)
+                .await;
+            counter!("pipelines_adopted", 1);
+            tracing::info!(pipeline_id = %pipeline.id, "Adopted pipeline");
+            self.track_pipeline(pipeline).await;
+        }
         Ok(())
     }
 
-    pub async fn get_pipeline(&self, pipeline_id: Uuid) -> Result<Pipeline, EngineError> {
-        let active_pipelines = self.active_pipelines.read().await;
-        active_pipelines.get(&pipeline_id).cloned().ok_or_else(|| {
-            EngineError::GetPipelineError(format!("Pipeline not found: {}", pipeline_id))
-        })
+    /// Whether this instance owns the pipeline, renewing its lease when a
+    /// third of it has passed. A pipeline whose lease was just taken over is
+    /// reloaded first, as its last owner may have moved it on.
+    async fn hold_lease(&self, pipeline: &mut Pipeline) -> Ownership {
+        let Some(ttl) = self.lease_ttl else {
+            return Ownership::Owned;
+        };
+        let renewed = self.leases.read().await.get(&pipeline.id).copied();
+        if renewed.is_some_and(|renewed| renewed.elapsed() < ttl / 3) {
+            return Ownership::Owned;
+        }
+        let lease = match self
+            .redis
+            .acquire_lease(pipeline.id, &self.instance_id, ttl)
+            .await
+        {
+            Ok(lease) => lease,
+            Err(e) => {
+                // the lease can't be told apart from an expired one
+                tracing::warn!(pipeline_id = %pipeline.id, "Failed to renew lease: {}", e);
+                self.leases.write().await.remove(&pipeline.id);
+                return Ownership::NotOwned;
+            }
+        };
+        if lease == Lease::Acquired {
+            counter!("pipeline_leases_acquired", 1);
+            match self.redis.get_pipeline(&pipeline.id.to_string()).await {
+                Ok(Some(stored)) => {
+                    if stored.version > pipeline.version {
+                        record_status_change(Some(&pipeline.status), Some(&stored.status));
+                        *pipeline = stored;
+                    }
+                }
+                Ok(None) => return Ownership::Deleted,
+                Err(e) => {
+                    tracing::warn!(pipeline_id = %pipeline.id, "Failed to reload pipeline: {}", e);
+                    return Ownership::NotOwned;
+                }
+            }
+        }
+        let mut leases = self.leases.write().await;
+        if lease.is_owned() {
+            leases.insert(pipeline.id, Instant::now());
+            Ownership::Owned
+        } else {
+            leases.remove(&pipeline.id);
+            Ownership::NotOwned
+        }
     }
 
-    pub async fn handle_price_update(&self, asset: &str, price: f64) -> Result<()> {
-        let start = Instant::now();
-
-        // Increment counter
-        counter!("price_updates_processed", 1);
-
-        // Update price cache
-        let mut cache = self.price_cache.write().await;
-        cache.insert(asset.to_string(), price);
-        drop(cache); // Release lock early
+    /// Extends the lease on the pipeline for a full ttl, whenever it was last
+    /// renewed. False if it is no longer held: a lease found expired is let
+    /// go again, so that the next evaluation takes it over and reloads the
+    /// pipeline.
+    async fn renew_lease(&self, pipeline_id: Uuid) -> bool {
+        let Some(ttl) = self.lease_ttl else {
+            return true;
+        };
+        let lease = self
+            .redis
+            .acquire_lease(pipeline_id, &self.instance_id, ttl)
+            .await;
+        let mut leases = self.leases.write().await;
+        match lease {
+            Ok(Lease::Renewed) => {
+                leases.insert(pipeline_id, Instant::now());
+                true
+            }
+            Ok(lease) => {
+                counter!("pipeline_leases_lost", 1);
+                tracing::warn!(%pipeline_id, "Lease lost");
+                leases.remove(&pipeline_id);
+                drop(leases);
+                if lease == Lease::Acquired {
+                    if let Err(e) = self
+                        .redis
+                        .release_lease(pipeline_id, &self.instance_id)
+                        .await
+                    {
+                        tracing::warn!(%pipeline_id, "Failed to release lease: {}", e);
+                    }
+                }
+                false
+            }
+            Err(e) => {
+                tracing::warn!(%pipeline_id, "Failed to renew lease: {}", e);
+                leases.remove(&pipeline_id);
+                false
+            }
+        }
+    }
 
-        // Get affected pipelines
-        let subscriptions = self.asset_subscriptions.read().await;
-        if let Some(pipeline_ids) = subscriptions.get(asset) {
-            for pipeline_id in pipeline_ids {
-                if let Some(pipeline) = self.active_pipelines.write().await.get_mut(pipeline_id) {
-                    self.evaluate_pipeline(pipeline).await?;
+    /// Runs `action`, renewing the lease on the pipeline every third of its
+    /// ttl meanwhile, so that it isn't taken over while the action runs
+    async fn while_leased<T>(&self, pipeline_id: Uuid, action: impl Future<Output = T>) -> T {
+        let Some(ttl) = self.lease_ttl else {
+            return action.await;
+        };
+        let renewals = async {
+            loop {
+                tokio::time::sleep(ttl / 3).await;
+                if !self.renew_lease(pipeline_id).await {
+                    // the outcome is still saved, over any save since
+                    tracing::error!(%pipeline_id, "Lease lost while running an action");
+                    return;
                 }
             }
+        };
+        tokio::pin!(action, renewals);
+        let mut renewing = true;
+        loop {
+            tokio::select! {
+                output = &mut action => return output,
+                _ = &mut renewals, if renewing => renewing = false,
+            }
         }
+    }
 
-        // Record duration
-        histogram!("price_update_duration", start.elapsed());
+    /// Stops evaluating pipelines deleted through another instance
+    async fn forget_pipelines(&self, pipeline_ids: Vec<Uuid>) {
+        for pipeline_id in pipeline_ids {
+            let Some(pipeline) = self.active_pipelines.write().await.remove(&pipeline_id) else {
+                continue;
+            };
+            record_status_change(Some(&pipeline.status), None);
+            self.unsubscribe(pipeline_id).await;
+            if let Err(e) = self
+                .redis
+                .release_lease(pipeline_id, &self.instance_id)
+                .await
+            {
+                tracing::warn!(%pipeline_id, "Failed to release lease: {}", e);
+            }
+            self.leases.write().await.remove(&pipeline_id);
+        }
+    }
 
-        // Record current number of active pipelines
-        gauge!(
-            "active_pipelines",
-            self.active_pipelines.read().await.len() as f64
-        );
+    /// Referenced pipelines must exist and must not lead back to the
+    /// pipeline, its steps must form a DAG, and steps and conditions must
+    /// not be nested too deep
+    pub async fn validate_pipeline(&self, pipeline: &Pipeline) -> Result<(), EngineError> {
+        self.validate_among(pipeline, &*self.active_pipelines.read().await)
+    }
 
-        Ok(())
+    /// `validate_pipeline` against the given active pipelines
+    fn validate_among(
+        &self,
+        pipeline: &Pipeline,
+        active_pipelines: &HashMap<Uuid, Pipeline>,
+    ) -> Result<(), EngineError> {
+        pipeline
+            .validate()
+            .map_err(EngineError::InvalidPipelineError)?;
+        pipeline
+            .validate_actions(self.webhook_allow_private_hosts)
+            .map_err(EngineError::InvalidPipelineError)?;
+        pipeline
+            .validate_depth(self.max_step_depth)
+            .map_err(EngineError::InvalidPipelineError)?;
+        pipeline
+            .validate_expiry(chrono::Utc::now())
+            .map_err(EngineError::InvalidPipelineError)?;
+        pipeline
+            .validate_references(active_pipelines)
+            .map_err(EngineError::InvalidPipelineError)
     }
 
-    async fn evaluate_pipeline(&self, pipeline: &mut Pipeline) -> Result<(), EngineError> {
-        let start = Instant::now();
+    /// Applies a JSON merge patch to the pipeline. The read-modify-write
+    /// happens under the pipelines lock, so it can't interleave with an
+    /// evaluation or another update of the pipeline. Should the stored
+    /// pipeline have been saved from elsewhere since, the patch is applied
+    /// to that one instead.
+    pub async fn patch_pipeline(
+        &self,
+        pipeline_id: Uuid,
+        patch: serde_json::Value,
+    ) -> Result<Pipeline, EngineError> {
+        let mut active_pipelines = self.active_pipelines.write().await;
+        let current = active_pipelines.get(&pipeline_id).cloned().ok_or_else(|| {
+            EngineError::GetPipelineError(format!("Pipeline not found: {}", pipeline_id))
+        })?;
+        let status = current.status.clone();
+        let patched = self
+            .save_updated(current, |current| {
+                let mut patched = current
+                    .patched(&patch)
+                    .map_err(EngineError::InvalidPipelineError)?;
+                // the version is the one read, not for the patch to set
+                patched.version = current.version;
+                // held to the same rules as a new pipeline
+                self.validate_among(&patched, &active_pipelines)?;
+                Ok(patched)
+            })
+            .await?;
+        if patched.status != status {
+            record_status_change(Some(&status), Some(&patched.status));
+        }
+        active_pipelines.insert(pipeline_id, patched.clone());
+        drop(active_pipelines);
 
-        let current_step_ids = pipeline.current_steps.clone();
-        let price_cache = self.price_cache.read().await.clone();
+        // Conditions may now watch different assets
+        let assets = self.extract_assets(&patched).await;
+        let mut asset_subscriptions = self.asset_subscriptions.write().await;
+        for (asset, pipeline_ids) in asset_subscriptions.iter_mut() {
+            if !assets.contains(asset) {
+                pipeline_ids.remove(&pipeline_id);
+            }
+        }
+        for asset in assets {
+            asset_subscriptions
+                .entry(asset)
+                .or_default()
+                .insert(pipeline_id);
+        }
+        asset_subscriptions.retain(|_, pipeline_ids| !pipeline_ids.is_empty());
 
-        for step_id in current_step_ids {
-            if let Some(step) = pipeline.steps.get_mut(&step_id) {
-                if matches!(step.status, Status::Pending) {
-                    match Evaluator::evaluate_conditions(&step.conditions, &price_cache) {
-                        Ok(true) => match &step.action {
-                            Action::Order(order) => {
-                                match self.executor.execute_order(order.clone()).await {
-                                    Ok(_) => {
-                                        step.status = Status::Completed;
-                                        pipeline.current_steps = step.next_steps.clone();
-                                    }
-                                    Err(e) => {
-                                        step.status = Status::Failed;
-                                        pipeline.status = Status::Failed;
-                                        tracing::error!(%step_id, error = %e, "Order execution failed");
-                                    }
-                                }
-                            }
-                            Action::Notification(notification) => {
-                                tracing::info!(%step_id, ?notification, "TODO: Notification");
-                            }
-                        },
-                        Ok(false) => {
-                            // don't do anything
-                        }
-                        Err(e) => {
-                            return Err(EngineError::EvaluatePipelineError(e));
-                        }
-                    }
+        Ok(patched)
+    }
+
+    /// Stops evaluating the pipeline, or starts again from the next price
+    /// update when `paused` is false. Only pending pipelines can be paused;
+    /// pausing a paused pipeline, or resuming a pending one, leaves it as is.
+    pub async fn set_paused(
+        &self,
+        pipeline_id: Uuid,
+        paused: bool,
+    ) -> Result<Pipeline, EngineError> {
+        let (from, to) = if paused {
+            (Status::Pending, Status::Paused)
+        } else {
+            (Status::Paused, Status::Pending)
+        };
+        let mut active_pipelines = self.active_pipelines.write().await;
+        let current = active_pipelines.get(&pipeline_id).cloned().ok_or_else(|| {
+            EngineError::GetPipelineError(format!("Pipeline not found: {}", pipeline_id))
+        })?;
+        if current.status == to {
+            return Ok(current);
+        }
+        let before = PipelineEvent::snapshot(&current);
+        let updated = self
+            .save_updated(current, |current| {
+                if current.status != from {
+                    return Err(EngineError::PipelineSettledError(format!(
+                        "Pipeline {} is {:?}",
+                        pipeline_id, current.status
+                    )));
+                }
+                let mut updated = current.clone();
+                updated.status = to.clone();
+                Ok(updated)
+            })
+            .await?;
+        self.publish_changes(&before, &updated);
+        active_pipelines.insert(pipeline_id, updated.clone());
+        Ok(updated)
+    }
+
+    /// Saves `update` of the pipeline, applied again to the stored pipeline
+    /// should it have been saved from elsewhere since `current` was read.
+    /// Settled pipelines are not updated.
+    async fn save_updated(
+        &self,
+        mut current: Pipeline,
+        update: impl Fn(&Pipeline) -> Result<Pipeline, EngineError>,
+    ) -> Result<Pipeline, EngineError> {
+        let mut attempts = 0;
+        loop {
+            attempts += 1;
+            if current.status.is_terminal() {
+                return Err(EngineError::PipelineSettledError(format!(
+                    "Pipeline {} is {:?}",
+                    current.id, current.status
+                )));
+            }
+            let mut updated = update(&current)?;
+            match self.redis.save_pipeline(&mut updated).await {
+                Ok(()) => return Ok(updated),
+                Err(RedisClientError::Conflict(_)) if attempts < PIPELINE_SAVE_ATTEMPTS => {
+                    counter!("pipeline_save_conflicts", 1);
+                    current = self
+                        .redis
+                        .get_pipeline(&current.id.to_string())
+                        .await
+                        .map_err(EngineError::RedisClientError)?
+                        .ok_or_else(|| {
+                            EngineError::GetPipelineError(format!(
+                                "Pipeline not found: {}",
+                                current.id
+                            ))
+                        })?;
                 }
+                Err(e) => return Err(EngineError::RedisClientError(e)),
             }
         }
+    }
 
-        if pipeline.current_steps.is_empty() {
-            pipeline.status = Status::Completed;
+    /// Deleting an already deleted pipeline succeeds, one that never
+    /// existed is a `GetPipelineError`
+    pub async fn delete_pipeline(&self, pipeline_id: Uuid) -> Result<(), EngineError> {
+        let id = pipeline_id.to_string();
+        let mut active_pipelines = self.active_pipelines.write().await;
+        let user_id = match active_pipelines.get(&pipeline_id) {
+            Some(pipeline) => Some(pipeline.user_id.clone()),
+            None => self
+                .redis
+                .get_pipeline(&id)
+                .await
+                .map_err(EngineError::DeletePipelineError)?
+                .map(|pipeline| pipeline.user_id),
+        };
+        let Some(user_id) = user_id else {
+            let deleted = self
+                .redis
+                .is_pipeline_deleted(&id)
+                .await
+                .map_err(EngineError::DeletePipelineError)?;
+            return if deleted {
+                Ok(())
+            } else {
+                Err(EngineError::GetPipelineError(format!(
+                    "Pipeline not found: {}",
+                    pipeline_id
+                )))
+            };
+        };
+
+        self.redis
+            .delete_pipeline(&user_id, &id)
+            .await
+            .map_err(EngineError::DeletePipelineError)?;
+        self.redis
+            .mark_pipeline_deleted(&id)
+            .await
+            .map_err(EngineError::DeletePipelineError)?;
+        if let Some(pipeline) = active_pipelines.remove(&pipeline_id) {
+            record_status_change(Some(&pipeline.status), None);
         }
+        drop(active_pipelines);
+        self.unsubscribe(pipeline_id).await;
+        Ok(())
+    }
 
-        let duration = start.elapsed();
-        counter!("pipeline_evaluations", 1);
-        histogram!("pipeline_evaluation_duration", duration);
+    /// Drops what the engine tracks for a pipeline no longer active
+    async fn unsubscribe(&self, pipeline_id: Uuid) {
+        self.last_evaluations.write().await.remove(&pipeline_id);
 
-        Ok(())
+        let mut asset_subscriptions = self.asset_subscriptions.write().await;
+        for pipeline_ids in asset_subscriptions.values_mut() {
+            pipeline_ids.remove(&pipeline_id);
+        }
+        asset_subscriptions.retain(|_, pipeline_ids| !pipeline_ids.is_empty());
     }
 
-    /// Extract all unique assets mentioned in pipeline conditions
-    async fn extract_assets(&self, pipeline: &Pipeline) -> HashSet<String> {
-        let mut assets = HashSet::new();
-        for step in pipeline.steps.values() {
-            self.collect_assets_from_condition(&step.conditions, &mut assets)
-                .await;
+    /// Removes expired pipelines from Redis, including their users'
+    /// indexes, then from the engine. A pipeline Redis fails to delete is
+    /// kept for the next sweep, a load would otherwise adopt it again
+    async fn remove_expired(&self, pipeline_ids: Vec<Uuid>) {
+        for pipeline_id in pipeline_ids {
+            let Some(user_id) = self
+                .active_pipelines
+                .read()
+                .await
+                .get(&pipeline_id)
+                .map(|pipeline| pipeline.user_id.clone())
+            else {
+                continue;
+            };
+            if let Err(e) = self
+                .redis
+                .delete_pipeline(&user_id, &pipeline_id.to_string())
+                .await
+            {
+                tracing::warn!(%pipeline_id, "Failed to delete expired pipeline: {}", e);
+                continue;
+            }
+            let Some(pipeline) = self.active_pipelines.write().await.remove(&pipeline_id) else {
+                continue;
+            };
+            record_status_change(Some(&pipeline.status), None);
+            self.unsubscribe(pipeline_id).await;
+            counter!("pipelines_expired", 1);
+            tracing::info!(%pipeline_id, user_id = %pipeline.user_id, "Pipeline expired");
         }
-        assets
     }
 
-    async fn collect_assets_from_condition(
+    /// A page of the user's pipelines, read from Redis so that it includes
+    /// the ones no longer active
+    pub async fn list_pipelines(
         &self,
-        conditions: &[Condition],
-        assets: &mut HashSet<String>,
-    ) {
-        let mut stack = Vec::new();
-        stack.extend(conditions.iter());
+        user_id: &str,
+        limit: usize,
+        cursor: Option<String>,
+    ) -> Result<PipelinePage, EngineError> {
+        let cursor = match cursor {
+            Some(cursor) => decode_cursor(&cursor)
+                .ok_or_else(|| EngineError::InvalidCursorError(cursor.clone()))?,
+            None => (0, 0),
+        };
+        let (pipelines, next_cursor) = self
+            .redis
+            .list_user_pipelines(user_id, limit.clamp(1, MAX_PAGE_SIZE), cursor)
+            .await
+            .map_err(EngineError::RedisClientError)?;
+        Ok(PipelinePage {
+            pipelines,
+            next_cursor: next_cursor.map(encode_cursor),
+        })
+    }
 
-        while let Some(condition) = stack.pop() {
-            match &condition.condition_type {
-                ConditionType::PriceAbove { asset, .. } => {
-                    assets.insert(asset.clone());
-                }
-                ConditionType::PriceBelow { asset, .. } => {
-                    assets.insert(asset.clone());
-                }
-                ConditionType::PercentageChange { asset, .. } => {
-                    assets.insert(asset.clone());
-                }
-                ConditionType::And(sub_conditions) | ConditionType::Or(sub_conditions) => {
-                    stack.extend(sub_conditions.iter());
-                }
-            }
-        }
+    pub async fn get_pipeline(&self, pipeline_id: Uuid) -> Result<Pipeline, EngineError> {
+        let active_pipelines = self.active_pipelines.read().await;
+        active_pipelines.get(&pipeline_id).cloned().ok_or_else(|| {
+            EngineError::GetPipelineError(format!("Pipeline not found: {}", pipeline_id))
+        })
+    }
+
+    /// Which of the pipeline's conditions would trigger with `asset` at
+    /// `price`, nothing about the pipeline or the prices is changed
+    pub async fn what_if(
+        &self,
+        pipeline_id: Uuid,
+        asset: &str,
+        price: f64,
+    ) -> Result<WhatIf, EngineError> {
+        let active_pipelines = self.active_pipelines.read().await;
+        let pipeline = active_pipelines.get(&pipeline_id).ok_or_else(|| {
+            EngineError::GetPipelineError(format!("Pipeline not found: {}", pipeline_id))
+        })?;
+        let statuses: HashMap<Uuid, Status> = pipeline
+            .referenced_pipelines()
+            .into_iter()
+            .filter_map(|id| Some((id, active_pipelines.get(&id)?.status.clone())))
+            .collect();
+        let prices = self.price_cache.read().await;
+        let swap_activity = self.swap_activity.read().await;
+        Ok(Evaluator::what_if(
+            pipeline,
+            asset,
+            price,
+            &prices,
+            &statuses,
+            &swap_activity,
+        ))
+    }
+
+    /// Steps of the pipeline that fired and what came of their actions,
+    /// newest first
+    pub async fn get_execution_log(
+        &self,
+        pipeline_id: Uuid,
+    ) -> Result<Vec<ExecutionEvent>, EngineError> {
+        if !self
+            .active_pipelines
+            .read()
+            .await
+            .contains_key(&pipeline_id)
+        {
+            return Err(EngineError::GetPipelineError(format!(
+                "Pipeline not found: {}",
+                pipeline_id
+            )));
+        }
+        self.redis
+            .get_execution_log(pipeline_id)
+            .await
+            .map_err(EngineError::RedisClientError)
+    }
+
+    pub async fn get_dead_letters(&self, user_id: &str) -> Result<Vec<DeadLetter>, EngineError> {
+        self.redis
+            .get_dead_letters(user_id)
+            .await
+            .map_err(EngineError::RedisClientError)
+    }
+
+    /// Re-attempt a dead-lettered action. On success the entry is dropped and
+    /// its pipeline, if still active, resumes from the step's next steps;
+    /// otherwise the entry goes back on the queue with the new error.
+    pub async fn retry_dead_letter(
+        &self,
+        user_id: &str,
+        id: Uuid,
+    ) -> Result<DeadLetter, EngineError> {
+        let mut entry = self
+            .redis
+            .take_dead_letter(user_id, id)
+            .await
+            .map_err(EngineError::RedisClientError)?
+            .ok_or_else(|| {
+                EngineError::GetDeadLetterError(format!("Dead letter not found: {}", id))
+            })?;
+        counter!("dead_letter_retries", 1);
+
+        // the action runs with the pipelines free, as in an evaluation
+        let conditions = self
+            .active_pipelines
+            .read()
+            .await
+            .get(&entry.pipeline_id)
+            .and_then(|pipeline| pipeline.steps.get(&entry.step_id))
+            .map(|step| step.conditions.clone())
+            .unwrap_or_default();
+        let mut swap_history = Vec::new();
+        let fired = FiredStep {
+            pipeline_id: entry.pipeline_id,
+            step_id: entry.step_id,
+            conditions: &conditions,
+        };
+        let result = self
+            .execute_action(&entry.action, &fired, &mut swap_history)
+            .await
+            .map_err(|e| e.to_string());
+        let execution = ExecutionEvent::new(
+            entry.pipeline_id,
+            entry.step_id,
+            &conditions,
+            &entry.action,
+            match &result {
+                Ok(()) => Outcome::Executed,
+                Err(e) => Outcome::Failed { error: e.clone() },
+            },
+        );
+
+        // applied to the pipeline as it is now, it may have been evaluated
+        // while the action ran
+        let mut completed = false;
+        let pipeline = self
+            .active_pipelines
+            .read()
+            .await
+            .get(&entry.pipeline_id)
+            .cloned();
+        if let Some(mut pipeline) = pipeline {
+            let before = PipelineEvent::snapshot(&pipeline);
+            let from = pipeline.clone();
+            pipeline.swap_history.extend(swap_history);
+            if result.is_ok() {
+                if let Some(step) = pipeline.steps.get_mut(&entry.step_id) {
+                    step.status = Status::Completed;
+                    pipeline.status = Status::Pending;
+                    pipeline.failure_reason = None;
+                    pipeline.advance(entry.step_id);
+                    if pipeline.current_steps.is_empty() && pipeline.status == Status::Pending {
+                        pipeline.status = Status::Completed;
+                    }
+                    self.advance_depth(&mut pipeline);
+                    completed = matches!(pipeline.status, Status::Completed);
+                }
+            }
+            let saved = self.save_evaluated(&from, &mut pipeline).await;
+            self.publish_changes(&before, &pipeline);
+            self.put_evaluated(pipeline).await;
+            saved.map_err(EngineError::RedisClientError)?;
+            self.redis
+                .push_execution_events(entry.pipeline_id, &[execution], EXECUTION_LOG_MAX_LEN)
+                .await
+                .map_err(EngineError::RedisClientError)?;
+        }
+
+        if completed {
+            let dependents = self.dependents(entry.pipeline_id).await;
+            if let Err(e) = self.evaluate_pipelines(dependents).await {
+                tracing::error!("Error evaluating dependent pipelines: {}", e);
+            }
+        }
+
+        match result {
+            Ok(()) => Ok(entry),
+            Err(e) => {
+                entry.attempts += 1;
+                entry.error = e.clone();
+                entry.failed_at = chrono::Utc::now();
+                self.redis
+                    .push_dead_letter(&entry, self.dlq_max_len)
+                    .await
+                    .map_err(EngineError::RedisClientError)?;
+                Err(EngineError::RetryDeadLetterError(e))
+            }
+        }
+    }
+
+    /// Runs the actions of the pipeline's current steps now, whether or not
+    /// their conditions are met, to try out what they are wired to. Neither
+    /// conditions nor steps are changed, so the pipeline is evaluated as
+    /// before afterwards. Dry-run pipelines report their actions without
+    /// executing them.
+    pub async fn force_trigger(
+        &self,
+        pipeline_id: Uuid,
+        user_id: Option<&str>,
+    ) -> Result<Vec<ExecutionEvent>, EngineError> {
+        if !self.force_trigger_enabled {
+            return Err(EngineError::ForceTriggerDisabledError);
+        }
+        // the actions run on a copy, the pipelines stay free meanwhile
+        let mut pipeline = self
+            .active_pipelines
+            .read()
+            .await
+            .get(&pipeline_id)
+            // another user's pipeline is as good as missing
+            .filter(|pipeline| user_id.is_none_or(|user_id| pipeline.user_id == user_id))
+            .cloned()
+            .ok_or_else(|| {
+                EngineError::GetPipelineError(format!("Pipeline not found: {}", pipeline_id))
+            })?;
+        if pipeline.status.is_terminal() {
+            return Err(EngineError::PipelineSettledError(format!(
+                "Pipeline {} is {:?}",
+                pipeline_id, pipeline.status
+            )));
+        }
+        counter!("forced_triggers", 1);
+
+        let from = pipeline.clone();
+        let history_len = pipeline.swap_history.len();
+        let mut executions = Vec::new();
+        for step_id in pipeline.current_steps.clone() {
+            let Some(step) = pipeline.steps.get(&step_id) else {
+                continue;
+            };
+            let outcome = if pipeline.dry_run {
+                tracing::info!(%step_id, action = ?step.action, "Forced trigger of a dry run, action not executed");
+                Outcome::DryRun
+            } else {
+                if let Action::Notification(notification) = &step.action {
+                    tracing::info!(%step_id, ?notification, "Notification");
+                }
+                let fired = FiredStep {
+                    pipeline_id,
+                    step_id,
+                    conditions: &step.conditions,
+                };
+                match self
+                    .execute_action(&step.action, &fired, &mut pipeline.swap_history)
+                    .await
+                {
+                    Ok(()) => Outcome::Executed,
+                    Err(e) => {
+                        tracing::warn!(%step_id, error = %e, "Forced action failed");
+                        Outcome::Failed {
+                            error: e.to_string(),
+                        }
+                    }
+                }
+            };
+            executions.push(ExecutionEvent::new(
+                pipeline_id,
+                step_id,
+                &step.conditions,
+                &step.action,
+                outcome,
+            ));
+        }
+
+        // a forced swap counts towards the PnL like any other
+        if pipeline.swap_history.len() != history_len {
+            let saved = self.save_evaluated(&from, &mut pipeline).await;
+            self.put_evaluated(pipeline).await;
+            saved.map_err(EngineError::RedisClientError)?;
+        }
+        self.redis
+            .push_execution_events(pipeline_id, &executions, EXECUTION_LOG_MAX_LEN)
+            .await
+            .map_err(EngineError::RedisClientError)?;
+        Ok(executions)
+    }
+
+    /// Counts a swap towards the pool's activity, dropping the swaps that
+    /// are too old to matter
+    async fn record_swap(&self, amm_pool: &str, timestamp: u64) {
+        let since = timestamp.saturating_sub(MAX_SWAP_ACTIVITY_WINDOW_SECS);
+        let mut swap_activity = self.swap_activity.write().await;
+        let timestamps = swap_activity.entry(amm_pool.to_string()).or_default();
+        while timestamps.front().is_some_and(|t| *t < since) {
+            timestamps.pop_front();
+        }
+        timestamps.push_back(timestamp);
+    }
+
+    pub async fn handle_price_update(&self, asset: &str, price: f64) -> Result<()> {
+        let start = Instant::now();
+
+        // Increment counter
+        counter!("price_updates_processed", 1);
+        self.record_price(asset, price).await;
+
+        // Get affected pipelines
+        let pipeline_ids: Vec<Uuid> = self
+            .asset_subscriptions
+            .read()
+            .await
+            .get(asset)
+            .map(|ids| ids.iter().copied().collect())
+            .unwrap_or_default();
+        let pipeline_ids = self.due_pipelines(pipeline_ids).await;
+        self.evaluate_pipelines(pipeline_ids).await?;
+
+        // Record duration
+        histogram!("price_update_duration", start.elapsed());
+
+        Ok(())
+    }
+
+    /// Updates the cached price and its history
+    async fn record_price(&self, asset: &str, price: f64) {
+        self.price_cache
+            .write()
+            .await
+            .insert(asset.to_string(), price);
+
+        let mut history = self.price_history.write().await;
+        let prices = history.entry(asset.to_string()).or_default();
+        if prices.len() == PRICE_HISTORY_LEN {
+            prices.pop_front();
+        }
+        prices.push_back(price);
+    }
+
+    /// Looks up the prices of every asset the pending steps depend on that no
+    /// update has been streamed for yet in one batched call to the price
+    /// source, then evaluates the due pipelines on the assets whose price
+    /// moved against the refreshed prices, each once however many of its
+    /// assets moved. Polled prices stay out of the price history.
+    async fn refresh_prices(&self) -> Result<()> {
+        let Some(price_source) = &self.price_source else {
+            return Ok(());
+        };
+        let mut assets = self.pending_assets().await;
+        {
+            let streamed = self.price_history.read().await;
+            assets.retain(|asset| !streamed.contains_key(asset));
+        }
+        if assets.is_empty() {
+            return Ok(());
+        }
+        let assets: Vec<&str> = assets.iter().map(String::as_str).collect();
+        let prices = match price_source.get_prices(&assets).await {
+            Ok(prices) => prices,
+            Err(e) => {
+                tracing::warn!("Failed to look up prices: {}", e);
+                return Ok(());
+            }
+        };
+        counter!("price_batches_fetched", 1);
+
+        let mut moved = Vec::new();
+        {
+            let streamed = self.price_history.read().await;
+            let mut price_cache = self.price_cache.write().await;
+            for (asset, price) in prices {
+                // a streamed price received meanwhile is newer
+                if streamed.contains_key(&asset) || price_cache.get(&asset) == Some(&price) {
+                    continue;
+                }
+                price_cache.insert(asset.clone(), price);
+                moved.push(asset);
+            }
+        }
+        let pipeline_ids: HashSet<Uuid> = {
+            let asset_subscriptions = self.asset_subscriptions.read().await;
+            moved
+                .iter()
+                .filter_map(|asset| asset_subscriptions.get(asset))
+                .flatten()
+                .copied()
+                .collect()
+        };
+        let pipeline_ids = self.due_pipelines(pipeline_ids.into_iter().collect()).await;
+        self.evaluate_pipelines(pipeline_ids).await
+    }
+
+    /// Assets referenced by the unsettled conditions of the steps the
+    /// active pipelines wait on
+    async fn pending_assets(&self) -> HashSet<String> {
+        let mut assets = HashSet::new();
+        for pipeline in self.active_pipelines.read().await.values() {
+            if !matches!(pipeline.status, Status::Pending) {
+                continue;
+            }
+            for step_id in &pipeline.current_steps {
+                if let Some(step) = pipeline.steps.get(step_id) {
+                    if matches!(step.status, Status::Pending) {
+                        self.collect_assets_from_condition(&step.conditions, &mut assets)
+                            .await;
+                    }
+                }
+            }
+        }
+        assets.retain(|asset| !is_pipeline_key(asset));
+        assets
+    }
+
+    /// Evaluates the pipelines with an action due to be attempted again,
+    /// which can't wait for a price update
+    async fn retry_due_pipelines(&self) -> Result<()> {
+        let now = chrono::Utc::now();
+        let due: Vec<Uuid> = self
+            .active_pipelines
+            .read()
+            .await
+            .values()
+            .filter(|pipeline| {
+                matches!(pipeline.status, Status::Retrying)
+                    && pipeline.current_steps.iter().any(|id| {
+                        pipeline
+                            .steps
+                            .get(id)
+                            .is_some_and(|step| step.is_retry_due(now))
+                    })
+            })
+            .map(|pipeline| pipeline.id)
+            .collect();
+        self.evaluate_pipelines(due).await
+    }
+
+    /// Evaluates the pipelines past their `expires_at`, which expires them,
+    /// so that pipelines without price updates expire as well. Expired
+    /// pipelines still in the engine failed to delete, and are retried.
+    async fn expire_due_pipelines(&self) -> Result<()> {
+        let now = chrono::Utc::now();
+        let mut left = Vec::new();
+        let mut due = Vec::new();
+        for pipeline in self.active_pipelines.read().await.values() {
+            if matches!(pipeline.status, Status::Expired) {
+                left.push(pipeline.id);
+            } else if !pipeline.status.is_terminal()
+                && pipeline
+                    .expires_at
+                    .is_some_and(|expires_at| expires_at <= now)
+            {
+                due.push(pipeline.id);
+            }
+        }
+        self.remove_expired(left).await;
+        self.evaluate_pipelines(due).await
+    }
+
+    /// Evaluates the due pipelines waiting on time alone, which have no
+    /// updates to be evaluated on
+    async fn evaluate_timed_pipelines(&self) -> Result<()> {
+        let timed: Vec<Uuid> = self
+            .active_pipelines
+            .read()
+            .await
+            .values()
+            .filter(|pipeline| pipeline.waits_on_time())
+            .map(|pipeline| pipeline.id)
+            .collect();
+        let due = self.due_pipelines(timed).await;
+        self.evaluate_pipelines(due).await
+    }
+
+    /// Pipelines whose evaluation interval has passed, the rest are skipped
+    /// until a later update
+    async fn due_pipelines(&self, pipeline_ids: Vec<Uuid>) -> Vec<Uuid> {
+        let now = Instant::now();
+        let active_pipelines = self.active_pipelines.read().await;
+        let last_evaluations = self.last_evaluations.read().await;
+        let total = pipeline_ids.len();
+        let due: Vec<Uuid> = pipeline_ids
+            .into_iter()
+            .filter(|id| match active_pipelines.get(id) {
+                Some(pipeline) => pipeline.is_due(last_evaluations.get(id).copied(), now),
+                None => false,
+            })
+            .collect();
+        counter!("pipeline_evaluations_skipped", (total - due.len()) as u64);
+        due
+    }
+
+    /// Evaluate the pipelines, then the pipelines referencing any of them
+    /// that completed as a result
+    /// Each pipeline is evaluated on a copy, outside the pipelines lock, as
+    /// leases and actions wait on Redis and on the network. The copy is put
+    /// back once evaluated.
+    async fn evaluate_pipelines(&self, mut pipeline_ids: Vec<Uuid>) -> Result<()> {
+        let mut settled = Vec::new();
+        let mut expired = Vec::new();
+        let mut deleted = Vec::new();
+        // volumes move without updates of their own, so are looked up for
+        // every round, once per asset and window
+        let mut windows = HashSet::new();
+        {
+            let active_pipelines = self.active_pipelines.read().await;
+            for pipeline_id in &pipeline_ids {
+                if let Some(pipeline) = active_pipelines.get(pipeline_id) {
+                    volume_windows(pipeline, &mut windows);
+                }
+            }
+        }
+        let mut volumes = Volumes::new();
+        self.look_up_volumes(windows, &mut volumes).await;
+        while let Some(pipeline_id) = pipeline_ids.pop() {
+            let (mut pipeline, statuses) = {
+                let active_pipelines = self.active_pipelines.read().await;
+                let Some(pipeline) = active_pipelines.get(&pipeline_id) else {
+                    continue;
+                };
+                let statuses: HashMap<Uuid, Status> = pipeline
+                    .referenced_pipelines()
+                    .into_iter()
+                    .filter_map(|id| Some((id, active_pipelines.get(&id)?.status.clone())))
+                    .collect();
+                (pipeline.clone(), statuses)
+            };
+            // evaluated by the instance holding its lease only
+            match self.hold_lease(&mut pipeline).await {
+                Ownership::Owned => {}
+                Ownership::NotOwned => continue,
+                Ownership::Deleted => {
+                    deleted.push(pipeline_id);
+                    continue;
+                }
+            }
+            // paused or not
+            let before = PipelineEvent::snapshot(&pipeline);
+            if pipeline.expire(chrono::Utc::now()) {
+                self.publish_changes(&before, &pipeline);
+                self.put_evaluated(pipeline).await;
+                expired.push(pipeline_id);
+                continue;
+            }
+            // left as it is, conditions included, until resumed
+            if matches!(pipeline.status, Status::Paused) {
+                continue;
+            }
+
+            self.last_evaluations
+                .write()
+                .await
+                .insert(pipeline_id, Instant::now());
+            let was_completed = matches!(pipeline.status, Status::Completed);
+            let (depth, swaps) = (pipeline.depth, pipeline.swap_history.len());
+            // chained and dependent pipelines may wait on volumes of their own
+            let mut windows = HashSet::new();
+            volume_windows(&pipeline, &mut windows);
+            self.look_up_volumes(windows, &mut volumes).await;
+            let evaluated = self
+                .evaluate_pipeline(&mut pipeline, &statuses, &volumes)
+                .await;
+            if matches!(evaluated, Ok(None)) {
+                // another instance evaluates it from now on
+                continue;
+            }
+            self.publish_changes(&before, &pipeline);
+            // Steps after a swap may only wait on its fill, which no price
+            // update would evaluate. Bounded as every round advances.
+            let chained = pipeline.depth != depth
+                && pipeline.swap_history.len() != swaps
+                && matches!(pipeline.status, Status::Pending);
+            let completed = !was_completed && matches!(pipeline.status, Status::Completed);
+            // kept even when saving failed, so that actions aren't run again
+            self.put_evaluated(pipeline).await;
+            if evaluated? == Some(true) {
+                settled.push(pipeline_id);
+            }
+            if chained {
+                pipeline_ids.push(pipeline_id);
+            }
+            if completed {
+                pipeline_ids.extend(self.dependents(pipeline_id).await);
+            }
+        }
+
+        self.remove_expired(expired).await;
+        self.forget_pipelines(deleted).await;
+
+        // Pipelines with newly fired one-shot conditions no longer need
+        // updates for the assets only those conditions referenced
+        for pipeline_id in settled {
+            let pipeline = self
+                .active_pipelines
+                .read()
+                .await
+                .get(&pipeline_id)
+                .cloned();
+            if let Some(pipeline) = pipeline {
+                self.reindex_pipeline(&pipeline).await;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Looks up the volumes of the `windows` not in `volumes` yet, all at
+    /// once
+    async fn look_up_volumes(&self, mut windows: HashSet<(String, u64)>, volumes: &mut Volumes) {
+        windows.retain(|window| !volumes.contains_key(window));
+        let windows = windows.into_iter();
+        let Some(price_source) = &self.price_source else {
+            volumes.extend(windows.map(|window| (window, None)));
+            return;
+        };
+        let looked_up =
+            futures_util::future::join_all(windows.map(|(asset, window_secs)| async move {
+                let volume = match price_source.get_volume(&asset, window_secs).await {
+                    Ok(volume) => Some(volume),
+                    Err(e) => {
+                        tracing::warn!(%asset, "Failed to look up volume: {}", e);
+                        None
+                    }
+                };
+                ((asset, window_secs), volume)
+            }))
+            .await;
+        volumes.extend(looked_up);
+    }
+
+    /// Puts back the evaluated copy of a pipeline, unless it stopped being
+    /// active meanwhile
+    async fn put_evaluated(&self, pipeline: Pipeline) {
+        if let Some(active) = self.active_pipelines.write().await.get_mut(&pipeline.id) {
+            *active = pipeline;
+        }
+    }
+
+    /// Pipelines with conditions on the completion of the pipeline
+    async fn dependents(&self, pipeline_id: Uuid) -> Vec<Uuid> {
+        self.asset_subscriptions
+            .read()
+            .await
+            .get(&pipeline_key(pipeline_id))
+            .map(|ids| ids.iter().copied().collect())
+            .unwrap_or_default()
+    }
+
+    /// Returns whether any one-shot condition settled during the evaluation,
+    /// `None` if the lease on the pipeline was lost before an action could
+    /// be run, in which case the evaluation is to be discarded once the
+    /// actions already run are saved. `pipelines`
+    /// holds the statuses of the pipelines it references, `volumes` those
+    /// looked up for the round.
+    async fn evaluate_pipeline(
+        &self,
+        pipeline: &mut Pipeline,
+        pipelines: &HashMap<Uuid, Status>,
+        volumes: &Volumes,
+    ) -> Result<Option<bool>, EngineError> {
+        let start = Instant::now();
+        let settled_before = Self::count_settled(pipeline);
+        let from = pipeline.clone();
+
+        for step_id in &pipeline.current_steps {
+            if let Some(step) = pipeline.steps.get_mut(step_id) {
+                if matches!(step.status, Status::Pending) {
+                    fill_volumes(volumes, &mut step.conditions);
+                }
+            }
+        }
+
+        let current_step_ids = pipeline.current_steps.clone();
+        let price_cache = self.price_cache.read().await.clone();
+        let swap_activity = self.swap_activity.read().await;
+        let history_len = pipeline.swap_history.len();
+        let dry_runs = pipeline.dry_run_triggers.len();
+        let trailing_peaks = pipeline.trailing_peaks();
+        let step_states = pipeline.step_states();
+        let mut dead_letters = Vec::new();
+        let mut executions = Vec::new();
+        let mut depth_exceeded = false;
+        let mut retries_changed = false;
+        let mut lease_lost = false;
+
+        let now = chrono::Utc::now();
+        for step_id in current_step_ids {
+            let last_swap = pipeline.swap_history.last().cloned();
+            let Some(step) = pipeline.steps.get_mut(&step_id) else {
+                continue;
+            };
+            let fires = match step.status {
+                Status::Pending => Evaluator::evaluate_conditions(
+                    &mut step.conditions,
+                    &price_cache,
+                    pipelines,
+                    &swap_activity,
+                    last_swap.as_ref(),
+                )
+                .map_err(EngineError::EvaluatePipelineError)?,
+                // its conditions were met already, only the action is attempted again
+                Status::Retrying => step.is_retry_due(now),
+                _ => false,
+            };
+            if !fires {
+                continue;
+            }
+
+            if pipeline.dry_run {
+                // reported as what would have fired, then on as if it had
+                counter!("dry_run_triggers", 1);
+                tracing::info!(%step_id, action = ?step.action, "Dry run, action not executed");
+                let execution = ExecutionEvent::new(
+                    pipeline.id,
+                    step_id,
+                    &step.conditions,
+                    &step.action,
+                    Outcome::DryRun,
+                );
+                pipeline.dry_run_triggers.push(execution.clone());
+                executions.push(execution);
+                step.status = Status::Completed;
+                pipeline.advance(step_id);
+                if !self.advance_depth(pipeline) {
+                    depth_exceeded = true;
+                    break;
+                }
+                continue;
+            }
+            if let Action::Notification(notification) = &step.action {
+                tracing::info!(%step_id, ?notification, "TODO: Notification");
+                continue;
+            }
+            // the lease has to last the action out, or another instance
+            // could run it too
+            if !self.renew_lease(pipeline.id).await {
+                lease_lost = true;
+                break;
+            }
+            let fired = FiredStep {
+                pipeline_id: pipeline.id,
+                step_id,
+                conditions: &step.conditions,
+            };
+            let result = self
+                .while_leased(
+                    pipeline.id,
+                    self.execute_action(&step.action, &fired, &mut pipeline.swap_history),
+                )
+                .await;
+            let outcome = match result {
+                Ok(()) => {
+                    retries_changed |= step.attempts > 0;
+                    step.status = Status::Completed;
+                    step.next_attempt_at = None;
+                    Outcome::Executed
+                }
+                // only what is known to have had no effect is attempted again
+                Err(e) => match e
+                    .is_transient()
+                    .then(|| step.schedule_retry(now, rand::random()))
+                    .flatten()
+                {
+                    Some(at) => {
+                        retries_changed = true;
+                        counter!("action_retries", 1);
+                        tracing::warn!(%step_id, attempts = step.attempts, %at, error = %e, "Action execution failed, will retry");
+                        Outcome::Retrying {
+                            error: e.to_string(),
+                        }
+                    }
+                    None if e.is_transient() && step.retry_policy.is_none() => {
+                        // left pending, attempted again on the next evaluation
+                        counter!("transient_action_errors", 1);
+                        tracing::warn!(%step_id, error = %e, "Action execution failed, will retry");
+                        Outcome::Retrying {
+                            error: e.to_string(),
+                        }
+                    }
+                    None => {
+                        step.status = Status::Failed;
+                        pipeline.status = Status::Failed;
+                        pipeline.failure_reason = Some(e.to_string());
+                        tracing::error!(%step_id, user_id = %pipeline.user_id, attempts = step.attempts, error = %e, "Action execution failed, pipeline failed");
+                        dead_letters.push(DeadLetter::new(
+                            &pipeline.user_id,
+                            pipeline.id,
+                            step,
+                            e.to_string(),
+                        ));
+                        Outcome::Failed {
+                            error: e.to_string(),
+                        }
+                    }
+                },
+            };
+            executions.push(ExecutionEvent::new(
+                pipeline.id,
+                step_id,
+                &step.conditions,
+                &step.action,
+                outcome,
+            ));
+            if matches!(step.status, Status::Completed) {
+                pipeline.advance(step_id);
+                if !self.advance_depth(pipeline) {
+                    depth_exceeded = true;
+                    break;
+                }
+            }
+        }
+
+        // Actions run before the lease was lost are saved for the next
+        // owner, which would otherwise run them again
+        if lease_lost && executions.is_empty() {
+            return Ok(None);
+        }
+
+        if matches!(pipeline.status, Status::Failed) {
+            // by an action or a step that can never run
+        } else if pipeline.current_steps.is_empty() && !depth_exceeded {
+            pipeline.status = Status::Completed;
+        } else if matches!(pipeline.status, Status::Pending | Status::Retrying) {
+            let retrying = pipeline.current_steps.iter().any(|id| {
+                pipeline
+                    .steps
+                    .get(id)
+                    .is_some_and(|step| matches!(step.status, Status::Retrying))
+            });
+            pipeline.status = if retrying {
+                Status::Retrying
+            } else {
+                Status::Pending
+            };
+        }
+
+        // Swap history backs the PnL endpoint, failures and dry-run triggers
+        // are reported to the user, retries are scheduled and trailing stops
+        // follow their peak, so all have to outlive a restart. So do fired
+        // conditions and completed steps, or a restart would fire them
+        // again; `last_evaluated` alone changes on every evaluation and is
+        // only saved along with them.
+        if pipeline.swap_history.len() != history_len
+            || pipeline.step_states() != step_states
+            || pipeline.dry_run_triggers.len() != dry_runs
+            || pipeline.trailing_peaks() != trailing_peaks
+            || retries_changed
+            || !dead_letters.is_empty()
+            || depth_exceeded
+        {
+            self.save_evaluated(&from, pipeline)
+                .await
+                .map_err(EngineError::RedisClientError)?;
+        }
+
+        if !executions.is_empty() {
+            self.redis
+                .push_execution_events(pipeline.id, &executions, EXECUTION_LOG_MAX_LEN)
+                .await
+                .map_err(EngineError::RedisClientError)?;
+        }
+
+        // Failed actions are kept for operators to inspect and retry
+        for entry in dead_letters {
+            counter!("dead_letters", 1);
+            self.redis
+                .push_dead_letter(&entry, self.dlq_max_len)
+                .await
+                .map_err(EngineError::RedisClientError)?;
+        }
+
+        let duration = start.elapsed();
+        counter!("pipeline_evaluations", 1);
+        histogram!("pipeline_evaluation_duration", duration);
+
+        if lease_lost {
+            return Ok(None);
+        }
+        Ok(Some(Self::count_settled(pipeline) != settled_before))
+    }
+
+    /// Saves the outcome of evaluating `from` into `pipeline`, executed
+    /// actions included, which has to be kept whatever was written
+    /// meanwhile: on a conflict the outcome is merged onto the stored
+    /// pipeline, which is saved and becomes `pipeline`. A pipeline deleted
+    /// meanwhile stays deleted.
+    async fn save_evaluated(
+        &self,
+        from: &Pipeline,
+        pipeline: &mut Pipeline,
+    ) -> Result<(), RedisClientError> {
+        match self.redis.save_pipeline(pipeline).await {
+            Err(RedisClientError::Conflict(_)) => {
+                counter!("pipeline_save_conflicts", 1);
+                tracing::warn!(pipeline_id = %pipeline.id, "Pipeline saved concurrently, merging");
+                let evaluated = pipeline.clone();
+                let merged = self
+                    .redis
+                    .update_pipeline(&pipeline.id.to_string(), |stored| {
+                        stored.merge_evaluation(from, &evaluated)
+                    })
+                    .await?;
+                match merged {
+                    Some(merged) => *pipeline = merged,
+                    None => {
+                        tracing::warn!(pipeline_id = %pipeline.id, "Pipeline deleted meanwhile")
+                    }
+                }
+                Ok(())
+            }
+            result => result,
+        }
+    }
+
+    /// Counts the pipeline advancing to its next steps, failing it once it
+    /// has gone deeper than `max_step_depth`. Returns whether it may go on.
+    fn advance_depth(&self, pipeline: &mut Pipeline) -> bool {
+        pipeline.depth += 1;
+        if pipeline.depth <= self.max_step_depth {
+            return true;
+        }
+        pipeline.status = Status::Failed;
+        pipeline.failure_reason = Some(format!(
+            "Pipeline advanced past the maximum depth of {} steps",
+            self.max_step_depth
+        ));
+        tracing::error!(pipeline_id = %pipeline.id, depth = pipeline.depth, "Pipeline exceeded the maximum depth");
+        false
+    }
+
+    /// Runs the action of the `fired` step, recording any swap it submits
+    async fn execute_action(
+        &self,
+        action: &Action,
+        fired: &FiredStep<'_>,
+        swap_history: &mut Vec<SwapResult>,
+    ) -> Result<(), ExecutorError> {
+        match action {
+            Action::Order(order) => self.executor.execute_order(order.clone()).await.map(|_| ()),
+            Action::SwapOrder(swap_order) => {
+                let result = self.execute_swap_order(swap_order, swap_history).await;
+                if result.is_err() {
+                    counter!("swap_action_errors", 1);
+                }
+                result
+            }
+            Action::Notification(_) => Ok(()),
+            Action::Webhook(webhook) => {
+                let event = StepWebhookEvent {
+                    pipeline_id: fired.pipeline_id,
+                    step_id: fired.step_id,
+                    conditions: fired
+                        .conditions
+                        .iter()
+                        .map(|c| c.condition_type.clone())
+                        .collect(),
+                    message: webhook.render(fired.pipeline_id, fired.step_id),
+                };
+                let result = post_step_webhook(
+                    &self.http_client,
+                    webhook,
+                    &event,
+                    Duration::from_millis(WEBHOOK_BACKOFF_MS),
+                )
+                .await;
+                if let Err(e) = &result {
+                    tracing::error!(step_id = %fired.step_id, "Step webhook gave up: {}", e);
+                }
+                result
+            }
+            Action::Telegram(telegram) => {
+                let result = send_telegram_message(
+                    &self.http_client,
+                    &self.telegram_api_url,
+                    telegram,
+                    &telegram.render(fired.pipeline_id, fired.step_id),
+                    Duration::from_millis(WEBHOOK_BACKOFF_MS),
+                )
+                .await;
+                if let Err(e) = &result {
+                    counter!("telegram_action_errors", 1);
+                    tracing::error!(step_id = %fired.step_id, "Telegram message gave up: {}", e);
+                }
+                result
+            }
+            Action::Discord(discord) => {
+                let (asset, threshold) = fired
+                    .conditions
+                    .iter()
+                    .find_map(|c| c.condition_type.price_level())
+                    .map_or((None, None), |(asset, threshold)| (Some(asset), threshold));
+                let price = match asset {
+                    Some(asset) => self.price_cache.read().await.get(asset).copied(),
+                    None => None,
+                };
+                let content = discord.render(&MessageContext {
+                    pipeline_id: fired.pipeline_id,
+                    step_id: fired.step_id,
+                    asset,
+                    price,
+                    threshold,
+                });
+                let result = post_discord_message(&self.http_client, discord, &content).await;
+                if let Err(e) = &result {
+                    counter!("discord_action_errors", 1);
+                    tracing::error!(step_id = %fired.step_id, "Discord message failed: {}", e);
+                }
+                result
+            }
+        }
+    }
+
+    /// Submits the swap when swap actions are enabled, a swap that doesn't
+    /// land, or may not have, is an error once recorded
+    async fn execute_swap_order(
+        &self,
+        swap_order: &SwapOrder,
+        swap_history: &mut Vec<SwapResult>,
+    ) -> Result<(), ExecutorError> {
+        if !self.swap_actions_enabled {
+            return Err(ExecutorError::SwapActionsDisabledError);
+        }
+        let slippage = self.resolve_slippage(swap_order).await;
+        let result = match self.executor.execute_swap_order(swap_order, slippage).await {
+            Ok(result) => result,
+            Err(ExecutorError::SwapOutcomeUnknownError {
+                signature: Some(signature),
+                reason,
+            }) => {
+                // kept so that it can be looked up, it may yet land
+                tracing::warn!(%signature, "Swap sent without a known outcome: {}", reason);
+                swap_history.push(SwapResult::unknown(swap_order, signature.clone()));
+                return Err(ExecutorError::SwapOutcomeUnknownError {
+                    signature: Some(signature),
+                    reason,
+                });
+            }
+            Err(e) => return Err(e),
+        };
+        if let Some(webhook) = &self.swap_webhook {
+            webhook.notify(&result);
+        }
+        let landed = result.is_confirmed();
+        let error = ExecutorError::SwapNotLandedError(format!(
+            "{}: {:?}",
+            result.signature, result.outcome
+        ));
+        swap_history.push(result);
+        if landed {
+            Ok(())
+        } else {
+            Err(error)
+        }
+    }
+
+    /// Resolve the order's slippage against the recent prices of the token
+    /// being traded, the output mint unless only the input mint is tracked
+    async fn resolve_slippage(&self, order: &SwapOrder) -> u64 {
+        let history = self.price_history.read().await;
+        let recent_prices: Vec<f64> = history
+            .get(&order.output_mint)
+            .or_else(|| history.get(&order.input_mint))
+            .map(|prices| prices.iter().copied().collect())
+            .unwrap_or_default();
+        order
+            .slippage
+            .resolve(&recent_prices, self.max_slippage_bps)
+    }
+
+    fn count_settled(pipeline: &Pipeline) -> usize {
+        pipeline
+            .steps
+            .values()
+            .flat_map(|step| step.conditions.iter())
+            .filter(|c| c.is_settled())
+            .count()
+    }
+
+    /// Drop the pipeline from subscriptions to assets it no longer evaluates
+    async fn reindex_pipeline(&self, pipeline: &Pipeline) {
+        let assets = self.extract_assets(pipeline).await;
+        let mut asset_subscriptions = self.asset_subscriptions.write().await;
+        for (asset, pipeline_ids) in asset_subscriptions.iter_mut() {
+            if !assets.contains(asset) {
+                pipeline_ids.remove(&pipeline.id);
+            }
+        }
+        asset_subscriptions.retain(|_, pipeline_ids| !pipeline_ids.is_empty());
+    }
+
+    /// Extract all unique assets mentioned in pipeline conditions
+    async fn extract_assets(&self, pipeline: &Pipeline) -> HashSet<String> {
+        let mut assets = HashSet::new();
+        for step in pipeline.steps.values() {
+            self.collect_assets_from_condition(&step.conditions, &mut assets)
+                .await;
+        }
+        assets
+    }
+
+    async fn collect_assets_from_condition(
+        &self,
+        conditions: &[Condition],
+        assets: &mut HashSet<String>,
+    ) {
+        let mut stack = Vec::new();
+        stack.extend(conditions.iter());
+
+        while let Some(condition) = stack.pop() {
+            if condition.is_settled() {
+                continue;
+            }
+            match &condition.condition_type {
+                ConditionType::PriceAbove { asset, .. } => {
+                    assets.insert(asset.clone());
+                }
+                ConditionType::PriceBelow { asset, .. }
+                | ConditionType::PriceBand { asset, .. }
+                | ConditionType::TrailingStop { asset, .. } => {
+                    assets.insert(asset.clone());
+                }
+                ConditionType::PercentageChange { asset, .. }
+                | ConditionType::PercentChange { asset, .. }
+                | ConditionType::VolumeAbove { asset, .. } => {
+                    assets.insert(asset.clone());
+                }
+                ConditionType::PoolTxCountAbove { amm_pool, .. } => {
+                    assets.insert(amm_pool.clone());
+                }
+                ConditionType::TimeAfter { .. }
+                | ConditionType::TimeWindow { .. }
+                | ConditionType::SwapFilled { .. } => {}
+                ConditionType::PipelineCompleted { pipeline_id, .. } => {
+                    assets.insert(pipeline_key(*pipeline_id));
+                }
+                ConditionType::And(sub_conditions) | ConditionType::Or(sub_conditions) => {
+                    stack.extend(sub_conditions.iter());
+                }
+            }
+        }
+    }
+}
+
+/// Subscription key under which pipelines waiting on the pipeline's
+/// completion are indexed
+fn pipeline_key(pipeline_id: Uuid) -> String {
+    format!("pipeline:{}", pipeline_id)
+}
+
+fn is_pipeline_key(key: &str) -> bool {
+    key.starts_with("pipeline:")
+}
+
+/// Page cursors are opaque to clients: the Redis scan cursor and the offset
+/// into its batch
+fn encode_cursor((scan_cursor, offset): (u64, usize)) -> String {
+    format!("{}-{}", scan_cursor, offset)
+}
+
+fn decode_cursor(cursor: &str) -> Option<(u64, usize)> {
+    let (scan_cursor, offset) = cursor.split_once('-')?;
+    Some((scan_cursor.parse().ok()?, offset.parse().ok()?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::constants::ACTION_WEBHOOK_MAX_ATTEMPTS;
+    use crate::engine::pipeline::{Notification, PipelineStep, RetryPolicy, Webhook};
+    use crate::engine::swap::SlippageSpec;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::{TcpListener, TcpStream};
+
+    fn pipeline(asset: &str, action: Action) -> Pipeline {
+        let step = PipelineStep {
+            id: Uuid::new_v4(),
+            action,
+            conditions: vec![Condition::from(ConditionType::PriceAbove {
+                asset: asset.to_string(),
+                threshold: 100.0,
+            })],
+            next_steps: vec![],
+            status: Status::Pending,
+            retry_policy: None,
+            attempts: 0,
+            next_attempt_at: None,
+        };
+        Pipeline {
+            id: Uuid::new_v4(),
+            user_id: format!("test-{}", Uuid::new_v4()),
+            current_steps: vec![step.id],
+            steps: HashMap::from([(step.id, step)]),
+            status: Status::Pending,
+            created_at: chrono::Utc::now(),
+            swap_history: vec![],
+            failure_reason: None,
+            eval_interval_ms: None,
+            depth: 0,
+            expires_at: None,
+            version: 0,
+            dry_run: false,
+            dry_run_triggers: vec![],
+        }
+    }
+
+    fn swap_order() -> Action {
+        Action::SwapOrder(SwapOrder {
+            amm_pool: "pool".to_string(),
+            input_mint: "in".to_string(),
+            output_mint: "out".to_string(),
+            amount: 1,
+            slippage: SlippageSpec::Fixed(50),
+        })
+    }
+
+    async fn engine(swap_service_url: Option<String>) -> Engine {
+        let config = Config::from_vars(|name| {
+            matches!(name, "SWAP_ACTIONS_ENABLED" | "WEBHOOK_ALLOW_PRIVATE_HOSTS")
+                .then(|| "true".to_string())
+        })
+        .unwrap();
+        let executor = executor::Executor::new(reqwest::Client::new(), swap_service_url);
+        Engine::with_executor(&config, executor).await.unwrap()
+    }
+
+    /// Reads the whole request off `stream`, so that it can be answered
+    async fn read_request(stream: &mut TcpStream) {
+        let mut request = Vec::new();
+        let mut buf = [0u8; 4096];
+        loop {
+            match stream.read(&mut buf).await {
+                Ok(0) | Err(_) => return,
+                Ok(n) => request.extend_from_slice(&buf[..n]),
+            }
+            let text = String::from_utf8_lossy(&request);
+            let Some((head, body)) = text.split_once("\r\n\r\n") else {
+                continue;
+            };
+            let content_length = head
+                .lines()
+                .find_map(|line| {
+                    let (name, value) = line.split_once(':')?;
+                    name.eq_ignore_ascii_case("content-length")
+                        .then(|| value.trim().parse::<usize>().ok())?
+                })
+                .unwrap_or(0);
+            if body.len() >= content_length {
+                return;
+            }
+        }
+    }
+
+    /// Answers the webhook's requests with `statuses` in turn, the last one
+    /// for any after. Returns its url and the number of requests received.
+    async fn serve_webhook(statuses: &'static [&'static str]) -> (String, Arc<AtomicUsize>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/hook", listener.local_addr().unwrap());
+        let requests = Arc::new(AtomicUsize::new(0));
+        let counted = requests.clone();
+        tokio::spawn(async move {
+            while let Ok((mut stream, _)) = listener.accept().await {
+                read_request(&mut stream).await;
+                let i = counted.fetch_add(1, Ordering::SeqCst);
+                let status = statuses[i.min(statuses.len() - 1)];
+                let response = format!("HTTP/1.1 {}\r\ncontent-length: 0\r\n\r\n", status);
+                let _ = stream.write_all(response.as_bytes()).await;
+            }
+        });
+        (url, requests)
+    }
+
+    fn webhook_pipeline(asset: &str, url: String, max_attempts: u32) -> Pipeline {
+        let mut pipeline = pipeline(
+            asset,
+            Action::Webhook(Webhook {
+                url,
+                headers: HashMap::new(),
+                template: "{pipeline_id}".to_string(),
+            }),
+        );
+        for step in pipeline.steps.values_mut() {
+            step.retry_policy = Some(RetryPolicy {
+                max_attempts,
+                base_delay_ms: 10,
+                max_delay_ms: 40,
+            });
+        }
+        pipeline
+    }
+
+    /// Attempts the pipeline's retrying action until it settles
+    async fn retry_until_settled(engine: &Engine, pipeline_id: Uuid) -> Pipeline {
+        for _ in 0..20 {
+            let pipeline = engine.get_pipeline(pipeline_id).await.unwrap();
+            if !matches!(pipeline.status, Status::Retrying) {
+                return pipeline;
+            }
+            tokio::time::sleep(Duration::from_millis(50)).await;
+            engine.retry_due_pipelines().await.unwrap();
+        }
+        panic!("pipeline {} still retrying", pipeline_id);
+    }
+
+    #[tokio::test]
+    async fn test_action_succeeds_on_third_attempt() {
+        // each attempt posts until the webhook's own attempts run out
+        let mut statuses =
+            vec!["503 Service Unavailable"; 2 * ACTION_WEBHOOK_MAX_ATTEMPTS as usize];
+        statuses.push("200 OK");
+        let (url, requests) = serve_webhook(statuses.leak()).await;
+        let engine = engine(None).await;
+        let asset = format!("test-{}", Uuid::new_v4());
+        let pipeline = webhook_pipeline(&asset, url, 3);
+        let pipeline_id = pipeline.id;
+        engine.add_pipeline(pipeline).await.unwrap();
+
+        engine.handle_price_update(&asset, 200.0).await.unwrap();
+        let pipeline = engine.get_pipeline(pipeline_id).await.unwrap();
+        assert_eq!(pipeline.status, Status::Retrying);
+
+        let pipeline = retry_until_settled(&engine, pipeline_id).await;
+        assert_eq!(pipeline.status, Status::Completed);
+        assert_eq!(
+            requests.load(Ordering::SeqCst),
+            2 * ACTION_WEBHOOK_MAX_ATTEMPTS as usize + 1
+        );
+        let step = pipeline.steps.values().next().unwrap();
+        assert_eq!((step.status.clone(), step.attempts), (Status::Completed, 2));
+        engine.delete_pipeline(pipeline_id).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_client_error_is_not_retried() {
+        let (url, requests) = serve_webhook(&["400 Bad Request"]).await;
+        let engine = engine(None).await;
+        let asset = format!("test-{}", Uuid::new_v4());
+        let pipeline = webhook_pipeline(&asset, url, 3);
+        let (pipeline_id, user_id) = (pipeline.id, pipeline.user_id.clone());
+        engine.add_pipeline(pipeline).await.unwrap();
+
+        // failed at once, whatever attempts the policy has left
+        engine.handle_price_update(&asset, 200.0).await.unwrap();
+        let pipeline = engine.get_pipeline(pipeline_id).await.unwrap();
+        assert_eq!(pipeline.status, Status::Failed);
+        assert_eq!(requests.load(Ordering::SeqCst), 1);
+        let step = pipeline.steps.values().next().unwrap();
+        assert_eq!((step.status.clone(), step.attempts), (Status::Failed, 0));
+        assert_eq!(engine.get_dead_letters(&user_id).await.unwrap().len(), 1);
+        engine.delete_pipeline(pipeline_id).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_engines_sharing_redis_run_an_action_once() {
+        let leased_engine = |instance_id: &'static str| async move {
+            let config = Config::from_vars(|name| match name {
+                "PIPELINE_LEASE_TTL_MS" => Some("300".to_string()),
+                "ENGINE_INSTANCE_ID" => Some(instance_id.to_string()),
+                "WEBHOOK_ALLOW_PRIVATE_HOSTS" => Some("true".to_string()),
+                _ => None,
+            })
+            .unwrap();
+            let executor = executor::Executor::new(reqwest::Client::new(), None);
+            Engine::with_executor(&config, executor).await.unwrap()
+        };
+        let instance = format!("test-{}", Uuid::new_v4()).leak();
+        let other = format!("test-{}", Uuid::new_v4()).leak();
+        let engines = [leased_engine(instance).await, leased_engine(other).await];
+        let (url, requests) = serve_webhook(&["200 OK"]).await;
+        let asset = format!("test-{}", Uuid::new_v4());
+        let pipeline = webhook_pipeline(&asset, url, 3);
+        let pipeline_id = pipeline.id;
+        engines[0].add_pipeline(pipeline).await.unwrap();
+        engines[1].adopt_pipelines().await.unwrap();
+
+        // both instances see the update, only the lease holder acts on it
+        for engine in &engines {
+            engine.handle_price_update(&asset, 200.0).await.unwrap();
+        }
+        assert_eq!(requests.load(Ordering::SeqCst), 1);
+        assert_eq!(
+            engines[0].get_pipeline(pipeline_id).await.unwrap().status,
+            Status::Completed
+        );
+        // the other instance took no lease, nor changed its copy
+        assert!(!engines[1].leases.read().await.contains_key(&pipeline_id));
+        assert_eq!(
+            engines[1].active_pipelines.read().await[&pipeline_id].status,
+            Status::Pending
+        );
+
+        // once the holder is gone the other takes over the stored pipeline
+        engines[0].forget_pipelines(vec![pipeline_id]).await;
+        engines[1].handle_price_update(&asset, 200.0).await.unwrap();
+        assert_eq!(requests.load(Ordering::SeqCst), 1);
+        assert_eq!(
+            engines[1].active_pipelines.read().await[&pipeline_id].status,
+            Status::Completed
+        );
+        engines[1].delete_pipeline(pipeline_id).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_swap_run_before_losing_the_lease_is_saved() {
+        let instance_id = format!("test-{}", Uuid::new_v4());
+        let config = Config::from_vars(|name| match name {
+            "PIPELINE_LEASE_TTL_MS" => Some("5000".to_string()),
+            "ENGINE_INSTANCE_ID" => Some(instance_id.clone()),
+            "SWAP_ACTIONS_ENABLED" => Some("true".to_string()),
+            _ => None,
+        })
+        .unwrap();
+        let ttl = Duration::from_millis(config.pipeline_lease_ttl_ms);
+        let asset = format!("test-{}", Uuid::new_v4());
+        // two swaps firing on the same update
+        let mut pipeline = pipeline(&asset, swap_order());
+        let mut second = pipeline.steps.values().next().unwrap().clone();
+        second.id = Uuid::new_v4();
+        pipeline.current_steps.push(second.id);
+        pipeline.steps.insert(second.id, second);
+        let pipeline_id = pipeline.id;
+
+        // stands in for the swap service, another instance taking the lease
+        // over while the first swap is sent
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let swap_service_url = format!("http://{}", listener.local_addr().unwrap());
+        let executor = executor::Executor::new(reqwest::Client::new(), Some(swap_service_url));
+        let engine = Engine::with_executor(&config, executor).await.unwrap();
+        let requests = Arc::new(AtomicUsize::new(0));
+        let counted = requests.clone();
+        let redis = engine.redis.clone();
+        tokio::spawn(async move {
+            while let Ok((mut stream, _)) = listener.accept().await {
+                read_request(&mut stream).await;
+                counted.fetch_add(1, Ordering::SeqCst);
+                redis
+                    .release_lease(pipeline_id, &instance_id)
+                    .await
+                    .unwrap();
+                redis
+                    .acquire_lease(pipeline_id, "other", ttl)
+                    .await
+                    .unwrap();
+                let body = r#"{"signature":"sig","input_mint":"in","output_mint":"out","in_amount":1,"out_amount":2,"outcome":"Confirmed"}"#;
+                let response = format!(
+                    "HTTP/1.1 200 OK\r\ncontent-type: application/json\r\ncontent-length: {}\r\n\r\n{}",
+                    body.len(),
+                    body
+                );
+                let _ = stream.write_all(response.as_bytes()).await;
+            }
+        });
+        engine.add_pipeline(pipeline).await.unwrap();
+
+        engine.handle_price_update(&asset, 200.0).await.unwrap();
+        assert_eq!(requests.load(Ordering::SeqCst), 1);
+        // the next owner finds the first swap done, and runs the second only
+        let stored = engine
+            .redis
+            .get_pipeline(&pipeline_id.to_string())
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(stored.swap_history.len(), 1);
+        let completed = stored
+            .steps
+            .values()
+            .filter(|step| step.status == Status::Completed)
+            .count();
+        assert_eq!(completed, 1);
+        assert_eq!(stored.current_steps.len(), 1);
+
+        engine
+            .redis
+            .release_lease(pipeline_id, "other")
+            .await
+            .unwrap();
+        engine.delete_pipeline(pipeline_id).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_dry_run_swap_never_reaches_provider() {
+        // stands in for the swap service, counting the requests it gets
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let swap_service_url = format!("http://{}", listener.local_addr().unwrap());
+        let requests = Arc::new(AtomicUsize::new(0));
+        let counted = requests.clone();
+        tokio::spawn(async move {
+            while let Ok((_socket, _)) = listener.accept().await {
+                counted.fetch_add(1, Ordering::SeqCst);
+            }
+        });
+        let engine = engine(Some(swap_service_url)).await;
+        let asset = format!("test-{}", Uuid::new_v4());
+
+        let mut dry_run = pipeline(&asset, swap_order());
+        dry_run.dry_run = true;
+        let dry_run_id = dry_run.id;
+        engine.add_pipeline(dry_run).await.unwrap();
+        engine.handle_price_update(&asset, 200.0).await.unwrap();
+        assert_eq!(requests.load(Ordering::SeqCst), 0);
+        let settled = engine.get_pipeline(dry_run_id).await.unwrap();
+        assert_eq!(settled.status, Status::Completed);
+        assert!(settled.swap_history.is_empty());
+        assert_eq!(settled.dry_run_triggers.len(), 1);
+        assert_eq!(settled.dry_run_triggers[0].outcome, Outcome::DryRun);
+
+        // the same pipeline armed does reach it
+        let armed = pipeline(&asset, swap_order());
+        let armed_id = armed.id;
+        engine.add_pipeline(armed).await.unwrap();
+        engine.handle_price_update(&asset, 200.0).await.unwrap();
+        assert!(requests.load(Ordering::SeqCst) > 0);
+
+        engine.delete_pipeline(dry_run_id).await.unwrap();
+        engine.delete_pipeline(armed_id).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_messages_queued_before_shutdown_are_persisted() {
+        let mut engine = engine(None).await;
+        let (command_tx, command_rx) = mpsc::channel(16);
+        let (shutdown_tx, shutdown_rx) = watch::channel(false);
+
+        let mut queued = vec![];
+        for _ in 0..3 {
+            let pipeline = pipeline("SOL", swap_order());
+            let pipeline_id = pipeline.id;
+            let (response_tx, response_rx) = tokio::sync::oneshot::channel();
+            command_tx
+                .send(EngineMessage::AddPipeline {
+                    pipeline,
+                    response_tx,
+                })
+                .await
+                .unwrap();
+            queued.push((pipeline_id, response_rx));
+        }
+        shutdown_tx.send(true).unwrap();
+        engine.run(command_rx, shutdown_rx).await.unwrap();
+
+        for (pipeline_id, response_rx) in queued {
+            response_rx.await.unwrap().unwrap();
+            let stored = engine.redis.get_pipeline(&pipeline_id.to_string()).await;
+            assert!(stored.unwrap().is_some());
+            engine.delete_pipeline(pipeline_id).await.unwrap();
+        }
+    }
+
+    struct FixedPrices(HashMap<String, f64>);
+
+    #[async_trait::async_trait]
+    impl PriceSource for FixedPrices {
+        async fn get_price(&self, asset: &str) -> Result<f64, PriceError> {
+            self.0
+                .get(asset)
+                .copied()
+                .ok_or_else(|| PriceError::NoPriceError(asset.to_string()))
+        }
+    }
+
+    #[tokio::test]
+    async fn test_price_source_fills_unseen_assets() {
+        let asset = format!("test-{}", Uuid::new_v4());
+        let unseen = format!("test-{}", Uuid::new_v4());
+        let prices = FixedPrices(HashMap::from([(unseen.clone(), 10.0)]));
+        let engine = engine(None).await.with_price_source(Arc::new(prices));
+
+        // fires on `asset` while `unseen`, which gets no updates, is below 50
+        let mut pipeline = pipeline(
+            &asset,
+            Action::Notification(pipeline::Notification {
+                message: "-".to_string(),
+            }),
+        );
+        for step in pipeline.steps.values_mut() {
+            step.conditions
+                .push(Condition::from(ConditionType::PriceBelow {
+                    asset: unseen.clone(),
+                    threshold: 50.0,
+                }));
+        }
+        let pipeline_id = pipeline.id;
+        let (response_tx, response_rx) = tokio::sync::oneshot::channel();
+        engine
+            .handle_message(EngineMessage::AddPipeline {
+                pipeline,
+                response_tx,
+            })
+            .await;
+        response_rx.await.unwrap().unwrap();
+
+        engine.handle_price_update(&asset, 200.0).await.unwrap();
+        let pipeline = engine.get_pipeline(pipeline_id).await.unwrap();
+        let step = pipeline.steps.values().next().unwrap();
+        assert!(step.conditions.iter().all(|c| c.triggered));
+        engine.delete_pipeline(pipeline_id).await.unwrap();
+    }
+
+    /// Prices every asset at 50, recording each batch it is asked for
+    #[derive(Default)]
+    struct BatchedPrices {
+        batches: std::sync::Mutex<Vec<Vec<String>>>,
+        lookups: AtomicUsize,
+    }
+
+    #[async_trait::async_trait]
+    impl PriceSource for BatchedPrices {
+        async fn get_price(&self, _asset: &str) -> Result<f64, PriceError> {
+            self.lookups.fetch_add(1, Ordering::SeqCst);
+            Ok(50.0)
+        }
+
+        async fn get_prices(&self, assets: &[&str]) -> Result<HashMap<String, f64>, PriceError> {
+            let mut batch: Vec<String> = assets.iter().map(|asset| asset.to_string()).collect();
+            batch.sort();
+            self.batches.lock().unwrap().push(batch);
+            Ok(assets
+                .iter()
+                .map(|asset| (asset.to_string(), 50.0))
+                .collect())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_pipelines_on_one_asset_share_a_batch() {
+        let asset = format!("test-{}", Uuid::new_v4());
+        let prices = Arc::new(BatchedPrices::default());
+        let engine = engine(None).await.with_price_source(prices.clone());
+
+        let mut pipeline_ids = Vec::new();
+        for _ in 0..10 {
+            let pipeline = pipeline(
+                &asset,
+                Action::Notification(pipeline::Notification {
+                    message: "-".to_string(),
+                }),
+            );
+            pipeline_ids.push(pipeline.id);
+            let (response_tx, response_rx) = tokio::sync::oneshot::channel();
+            engine
+                .handle_message(EngineMessage::AddPipeline {
+                    pipeline,
+                    response_tx,
+                })
+                .await;
+            response_rx.await.unwrap().unwrap();
+        }
+
+        for tick in 1..=3 {
+            engine.refresh_prices().await.unwrap();
+            let batches = prices.batches.lock().unwrap().clone();
+            assert_eq!(batches.len(), tick);
+            assert_eq!(batches[tick - 1], vec![asset.clone()]);
+        }
+        assert_eq!(prices.lookups.load(Ordering::SeqCst), 0);
+        assert_eq!(engine.price_cache.read().await.get(&asset), Some(&50.0));
+        assert!(!engine.price_history.read().await.contains_key(&asset));
+
+        // once streamed, the asset is no longer polled nor overwritten
+        engine.handle_price_update(&asset, 60.0).await.unwrap();
+        engine.refresh_prices().await.unwrap();
+        assert_eq!(prices.batches.lock().unwrap().len(), 3);
+        assert_eq!(engine.price_cache.read().await.get(&asset), Some(&60.0));
+        assert_eq!(
+            engine.price_history.read().await.get(&asset),
+            Some(&VecDeque::from([60.0]))
+        );
+
+        for pipeline_id in pipeline_ids {
+            engine.delete_pipeline(pipeline_id).await.unwrap();
+        }
+    }
+
+    /// Simulated volume, the same whatever the asset and window, counting
+    /// its lookups
+    #[derive(Default)]
+    struct FixedVolume {
+        volume: f64,
+        lookups: AtomicUsize,
+    }
+
+    #[async_trait::async_trait]
+    impl PriceSource for FixedVolume {
+        async fn get_price(&self, asset: &str) -> Result<f64, PriceError> {
+            Err(PriceError::NoPriceError(asset.to_string()))
+        }
+
+        async fn get_volume(&self, _asset: &str, _window_secs: u64) -> Result<f64, PriceError> {
+            self.lookups.fetch_add(1, Ordering::SeqCst);
+            Ok(self.volume)
+        }
+    }
+
+    #[test]
+    fn test_volume_above_fires_over_threshold() {
+        let evaluate = |volume: Option<f64>| {
+            let mut conditions = vec![Condition::from(ConditionType::VolumeAbove {
+                asset: "SOL".to_string(),
+                window_secs: 3600,
+                threshold: 1_000_000.0,
+                volume: Some(5_000_000.0),
+            })];
+            let volumes = Volumes::from([(("SOL".to_string(), 3600), volume)]);
+            fill_volumes(&volumes, &mut conditions);
+            Evaluator::evaluate_conditions(
+                &mut conditions,
+                &HashMap::new(),
+                &HashMap::new(),
+                &HashMap::new(),
+                None,
+            )
+        };
+
+        assert!(evaluate(Some(1_500_000.0)).unwrap());
+        assert!(!evaluate(Some(999_999.0)).unwrap());
+        // a volume that couldn't be looked up isn't trusted, nor the last one
+        assert!(matches!(
+            evaluate(None),
+            Err(EvaluatorError::MissingVolumeData(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_volumes_are_looked_up_once_per_window() {
+        let volumes = Arc::new(FixedVolume {
+            volume: 2_000_000.0,
+            ..Default::default()
+        });
+        let engine = engine(None).await.with_price_source(volumes.clone());
+        let asset = format!("test-{}", Uuid::new_v4());
+        let mut pipeline_ids = Vec::new();
+        for window_secs in [3600, 3600, 3600, 60] {
+            let mut pipeline = pipeline(
+                &asset,
+                Action::Notification(Notification {
+                    message: "-".to_string(),
+                }),
+            );
+            let step_id = pipeline.current_steps[0];
+            pipeline.steps.get_mut(&step_id).unwrap().conditions =
+                vec![Condition::from(ConditionType::VolumeAbove {
+                    asset: asset.clone(),
+                    window_secs,
+                    threshold: 1_000_000.0,
+                    volume: None,
+                })];
+            pipeline_ids.push(pipeline.id);
+            engine.add_pipeline(pipeline).await.unwrap();
+        }
+
+        engine
+            .evaluate_pipelines(pipeline_ids.clone())
+            .await
+            .unwrap();
+        assert_eq!(volumes.lookups.load(Ordering::SeqCst), 2);
+        for pipeline_id in pipeline_ids {
+            let pipeline = engine.get_pipeline(pipeline_id).await.unwrap();
+            assert_eq!(pipeline.status, Status::Completed);
+            engine.delete_pipeline(pipeline_id).await.unwrap();
+        }
+    }
+
+    #[tokio::test]
+    async fn test_timed_pipeline_fires_on_the_tick() {
+        let engine = engine(None).await;
+        let mut pipeline = pipeline(
+            "-",
+            Action::Notification(Notification {
+                message: "-".to_string(),
+            }),
+        );
+        let pipeline_id = pipeline.id;
+        let step_id = pipeline.current_steps[0];
+        pipeline.steps.get_mut(&step_id).unwrap().conditions =
+            vec![Condition::from(ConditionType::TimeAfter {
+                timestamp: chrono::Utc::now() - chrono::Duration::seconds(1),
+            })];
+        engine.add_pipeline(pipeline).await.unwrap();
+
+        // no price update is coming
+        engine.evaluate_timed_pipelines().await.unwrap();
+        let pipeline = engine.get_pipeline(pipeline_id).await.unwrap();
+        assert_eq!(pipeline.status, Status::Completed);
+        engine.delete_pipeline(pipeline_id).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_paused_pipeline_expires() {
+        let asset = format!("test-{}", Uuid::new_v4());
+        let engine = engine(None).await;
+        let pipeline = pipeline(
+            &asset,
+            Action::Notification(Notification {
+                message: "-".to_string(),
+            }),
+        );
+        let pipeline_id = pipeline.id;
+        let mut events = engine.pipeline_events().subscribe();
+        engine.add_pipeline(pipeline).await.unwrap();
+        engine.set_paused(pipeline_id, true).await.unwrap();
+        // past its expiry by now, which a new pipeline can't be
+        engine
+            .active_pipelines
+            .write()
+            .await
+            .get_mut(&pipeline_id)
+            .unwrap()
+            .expires_at = Some(chrono::Utc::now() - chrono::Duration::seconds(1));
+
+        // on the tick, without a price update
+        engine.expire_due_pipelines().await.unwrap();
+        assert!(matches!(
+            engine.get_pipeline(pipeline_id).await,
+            Err(EngineError::GetPipelineError(_))
+        ));
+        assert!(engine
+            .redis
+            .get_pipeline(&pipeline_id.to_string())
+            .await
+            .unwrap()
+            .is_none());
+        let mut statuses = Vec::new();
+        while let Ok(event) = events.try_recv() {
+            statuses.push(event.status);
+        }
+        assert_eq!(statuses.last(), Some(&Status::Expired));
+    }
+
+    #[tokio::test]
+    async fn test_trailing_stop_peak_is_persisted() {
+        let asset = format!("test-{}", Uuid::new_v4());
+        let engine = engine(None).await;
+        let mut pipeline = pipeline(
+            &asset,
+            Action::Notification(pipeline::Notification {
+                message: "-".to_string(),
+            }),
+        );
+        for step in pipeline.steps.values_mut() {
+            step.conditions = vec![Condition::from(ConditionType::TrailingStop {
+                asset: asset.clone(),
+                trail_percent: 10.0,
+                peak: Some(100.0),
+            })];
+        }
+        let pipeline_id = pipeline.id;
+        let (response_tx, response_rx) = tokio::sync::oneshot::channel();
+        engine
+            .handle_message(EngineMessage::AddPipeline {
+                pipeline,
+                response_tx,
+            })
+            .await;
+        response_rx.await.unwrap().unwrap();
+
+        engine.handle_price_update(&asset, 130.0).await.unwrap();
+        let stored = engine
+            .redis
+            .get_pipeline(&pipeline_id.to_string())
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(stored.trailing_peaks(), vec![Some(130.0)]);
+        engine.delete_pipeline(pipeline_id).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_missing_config_is_named() {
+        let vars = HashMap::from([
+            ("PRIVY_APP_ID", "app"),
+            ("PRIVY_APP_SECRET", "secret"),
+            ("SOLANA_RPC_URL", "https://api.mainnet-beta.solana.com"),
+        ]);
+        let config = |cleared: &[&str], set: &[(&str, &str)]| {
+            Config::from_vars(|name| {
+                let set = set.iter().find(|(var, _)| *var == name).map(|(_, v)| *v);
+                match set.or_else(|| vars.get(name).copied()) {
+                    _ if cleared.contains(&name) => None,
+                    value => value.map(str::to_string),
+                }
+            })
+            .unwrap()
+        };
+        assert!(missing_config(&config(&[], &[])).is_empty());
+
+        // rejected before anything is connected to
+        let Err(EngineError::Config { missing }) =
+            Engine::from_config(&config(&["PRIVY_APP_SECRET"], &[])).await
+        else {
+            panic!("expected a config error");
+        };
+        assert_eq!(missing, vec!["PRIVY_APP_SECRET"]);
+
+        let missing = missing_config(&config(
+            &["PRIVY_APP_ID"],
+            &[
+                ("REDIS_URL", "127.0.0.1:6379"),
+                ("SWAP_SERVICE_URL", "not a url"),
+            ],
+        ));
+        assert_eq!(
+            missing,
+            vec!["PRIVY_APP_ID", "REDIS_URL", "SWAP_SERVICE_URL"]
+        );
+        let e = EngineError::Config { missing };
+        assert!(e
+            .to_string()
+            .contains("PRIVY_APP_ID, REDIS_URL, SWAP_SERVICE_URL"));
+    }
+
+    #[tokio::test]
+    async fn test_force_trigger_notification() {
+        let asset = format!("test-{}", Uuid::new_v4());
+        let pipeline = pipeline(
+            &asset,
+            Action::Notification(Notification {
+                message: "forced".to_string(),
+            }),
+        );
+        let pipeline_id = pipeline.id;
+        let step_id = pipeline.current_steps[0];
+
+        let disabled = engine(None).await;
+        disabled.add_pipeline(pipeline.clone()).await.unwrap();
+        assert!(matches!(
+            disabled.force_trigger(pipeline_id, None).await,
+            Err(EngineError::ForceTriggerDisabledError)
+        ));
+
+        let config =
+            Config::from_vars(|name| (name == "FORCE_TRIGGER_ENABLED").then(|| "true".to_string()))
+                .unwrap();
+        let executor = executor::Executor::new(reqwest::Client::new(), None);
+        let engine = Engine::with_executor(&config, executor).await.unwrap();
+        engine.add_pipeline(pipeline).await.unwrap();
+        // only the owner's keys may force it
+        assert!(matches!(
+            engine
+                .force_trigger(pipeline_id, Some("someone-else"))
+                .await,
+            Err(EngineError::GetPipelineError(_))
+        ));
+        assert!(engine
+            .get_execution_log(pipeline_id)
+            .await
+            .unwrap()
+            .is_empty());
+        let owner = engine.get_pipeline(pipeline_id).await.unwrap().user_id;
+        let executions = engine
+            .force_trigger(pipeline_id, Some(&owner))
+            .await
+            .unwrap();
+        assert_eq!(executions.len(), 1);
+        assert_eq!(executions[0].step_id, step_id);
+        assert!(matches!(executions[0].action, Action::Notification(_)));
+        assert_eq!(executions[0].outcome, Outcome::Executed);
+        let log = engine.get_execution_log(pipeline_id).await.unwrap();
+        assert_eq!(log[0].outcome, Outcome::Executed);
+
+        // the price was never reached, the pipeline is as it was
+        let pipeline = engine.get_pipeline(pipeline_id).await.unwrap();
+        assert_eq!(pipeline.status, Status::Pending);
+        assert_eq!(pipeline.current_steps, vec![step_id]);
+        assert!(!pipeline.steps[&step_id].conditions[0].triggered);
+
+        engine.delete_pipeline(pipeline_id).await.unwrap();
     }
 }
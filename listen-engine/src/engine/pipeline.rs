@@ -1,10 +1,28 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::time::{Duration, Instant};
 
-use chrono::{DateTime, Utc};
+use chrono::{DateTime, NaiveTime, Utc};
 use serde::{Deserialize, Serialize};
 use uuid::Uuid;
 
+use super::constants::{
+    MAX_CONDITIONS_PER_STEP, MAX_CONDITION_DEPTH, MAX_COOLDOWN_SECS, MAX_STEPS_PER_PIPELINE,
+};
+use super::history::ExecutionEvent;
 use super::order::Order;
+use super::swap::{SwapOrder, SwapResult};
+use super::util::merge_patch;
+
+/// Fields a patch can't change, they identify the pipeline or record its history
+const IMMUTABLE_FIELDS: [&str; 6] = [
+    "id",
+    "user_id",
+    "created_at",
+    "swap_history",
+    "depth",
+    "dry_run_triggers",
+];
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum ConditionType {
@@ -16,20 +34,315 @@ pub enum ConditionType {
         asset: String,
         threshold: f64,
     },
+    /// Met once `asset` is at or above `upper` (take profit) or at or below
+    /// `lower` (stop loss), `crossed` is the bound it was past as of the
+    /// last evaluation
+    PriceBand {
+        asset: String,
+        upper: f64,
+        lower: f64,
+        #[serde(default)]
+        crossed: Option<Bound>,
+    },
+    /// Met once `asset` is `trail_percent` below `peak`, the highest price
+    /// seen since the pipeline was created
+    TrailingStop {
+        asset: String,
+        trail_percent: f64,
+        #[serde(default)]
+        peak: Option<f64>,
+    },
     PercentageChange {
         asset: String,
         change: f64,
         timeframe: u64,
     },
+    /// `asset` moved by at least `percent` in `direction` from
+    /// `reference_price`, the price when the pipeline was created unless
+    /// given
+    PercentChange {
+        asset: String,
+        #[serde(default)]
+        reference_price: Option<f64>,
+        percent: f64,
+        direction: Direction,
+    },
+    TimeAfter {
+        timestamp: DateTime<Utc>,
+    },
+    /// Met from `start` up to `end` each day, in UTC, wrapping midnight
+    /// when `end` is earlier than `start`; gates the other conditions of
+    /// the step to those hours
+    TimeWindow {
+        start: NaiveTime,
+        end: NaiveTime,
+    },
+    /// More than `threshold` swaps in the last `window_seconds`, counted
+    /// from the swap events published under `amm_pool`
+    PoolTxCountAbove {
+        amm_pool: String,
+        threshold: u64,
+        window_seconds: u64,
+    },
+    /// At least `threshold` USD of `asset` swapped over the last
+    /// `window_secs`, looked up from the price source before each
+    /// evaluation; `volume` is the one seen as of the last
+    VolumeAbove {
+        asset: String,
+        window_secs: u64,
+        threshold: f64,
+        #[serde(default)]
+        volume: Option<f64>,
+    },
+    /// The pipeline's latest swap landed with an output of at least
+    /// `min_amount` base units, for steps chained after a `SwapOrder`
+    SwapFilled {
+        min_amount: u64,
+    },
+    PipelineCompleted {
+        pipeline_id: Uuid,
+        // status of the referenced pipeline as of the last evaluation
+        #[serde(default)]
+        status: Option<Status>,
+    },
+    /// Met when all of the conditions are, a step's own conditions are
+    /// combined the same way
     And(Vec<Condition>),
+    /// Met when any of the conditions is
     Or(Vec<Condition>),
 }
 
+impl ConditionType {
+    /// One-shot conditions can't become false again once met, so they are
+    /// not re-evaluated after firing; continuous ones are checked on every
+    /// relevant update
+    pub fn is_one_shot(&self) -> bool {
+        match self {
+            ConditionType::TimeAfter { .. }
+            | ConditionType::SwapFilled { .. }
+            | ConditionType::PipelineCompleted { .. } => true,
+            ConditionType::And(sub) | ConditionType::Or(sub) => {
+                sub.iter().all(|c| c.condition_type.is_one_shot())
+            }
+            ConditionType::PriceAbove { .. }
+            | ConditionType::PriceBelow { .. }
+            | ConditionType::PriceBand { .. }
+            | ConditionType::TrailingStop { .. }
+            | ConditionType::TimeWindow { .. }
+            | ConditionType::PercentageChange { .. }
+            | ConditionType::PercentChange { .. }
+            | ConditionType::PoolTxCountAbove { .. }
+            | ConditionType::VolumeAbove { .. } => false,
+        }
+    }
+
+    /// Whether only the passing of time can meet the condition, no update
+    /// of a price, a pool or a pipeline
+    pub fn is_timed(&self) -> bool {
+        match self {
+            ConditionType::TimeAfter { .. } | ConditionType::TimeWindow { .. } => true,
+            ConditionType::And(sub) | ConditionType::Or(sub) => sub
+                .iter()
+                .all(|c| c.is_settled() || c.condition_type.is_timed()),
+            _ => false,
+        }
+    }
+
+    /// The variant's name, as in the JSON, for metric labels
+    pub fn kind(&self) -> &'static str {
+        match self {
+            ConditionType::PriceAbove { .. } => "PriceAbove",
+            ConditionType::PriceBelow { .. } => "PriceBelow",
+            ConditionType::PriceBand { .. } => "PriceBand",
+            ConditionType::TrailingStop { .. } => "TrailingStop",
+            ConditionType::PercentageChange { .. } => "PercentageChange",
+            ConditionType::PercentChange { .. } => "PercentChange",
+            ConditionType::TimeAfter { .. } => "TimeAfter",
+            ConditionType::TimeWindow { .. } => "TimeWindow",
+            ConditionType::PoolTxCountAbove { .. } => "PoolTxCountAbove",
+            ConditionType::VolumeAbove { .. } => "VolumeAbove",
+            ConditionType::SwapFilled { .. } => "SwapFilled",
+            ConditionType::PipelineCompleted { .. } => "PipelineCompleted",
+            ConditionType::And(_) => "And",
+            ConditionType::Or(_) => "Or",
+        }
+    }
+
+    /// Nesting of `And`/`Or`, 1 for any other condition
+    pub fn depth(&self) -> usize {
+        match self {
+            ConditionType::And(sub) | ConditionType::Or(sub) => {
+                1 + sub
+                    .iter()
+                    .map(|c| c.condition_type.depth())
+                    .max()
+                    .unwrap_or(0)
+            }
+            _ => 1,
+        }
+    }
+
+    /// Checks what deserializing can't, that a `PriceBand` has its lower
+    /// bound below the upper one, a `TrailingStop` trails by a share of
+    /// the price, a `TimeWindow` isn't empty and a `VolumeAbove` has a
+    /// window
+    pub fn validate(&self) -> Result<(), String> {
+        match self {
+            ConditionType::PriceBand {
+                asset,
+                upper,
+                lower,
+                ..
+            } if lower >= upper => Err(format!(
+                "Price band of {} has its lower bound {} at or above the upper {}",
+                asset, lower, upper
+            )),
+            ConditionType::TrailingStop {
+                asset,
+                trail_percent,
+                ..
+            } if !(*trail_percent > 0.0 && *trail_percent < 100.0) => Err(format!(
+                "Trailing stop of {} must trail by more than 0 and less than 100 percent, got {}",
+                asset, trail_percent
+            )),
+            ConditionType::TimeWindow { start, end } if start == end => Err(format!(
+                "Time window starts and ends at the same time, {}",
+                start
+            )),
+            ConditionType::VolumeAbove {
+                asset, window_secs, ..
+            } if *window_secs == 0 => Err(format!(
+                "Volume of {} must be over a window of at least a second",
+                asset
+            )),
+            ConditionType::And(sub) | ConditionType::Or(sub) => {
+                sub.iter().try_for_each(|c| c.condition_type.validate())
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Asset of the first price condition, depth first, with the price it
+    /// fires at when that is known: the threshold, the crossed bound of a
+    /// `PriceBand` or the stop of a `TrailingStop`
+    pub fn price_level(&self) -> Option<(&str, Option<f64>)> {
+        match self {
+            ConditionType::PriceAbove { asset, threshold }
+            | ConditionType::PriceBelow { asset, threshold } => Some((asset, Some(*threshold))),
+            ConditionType::PriceBand {
+                asset,
+                upper,
+                lower,
+                crossed,
+            } => Some((
+                asset,
+                crossed.map(|bound| match bound {
+                    Bound::Upper => *upper,
+                    Bound::Lower => *lower,
+                }),
+            )),
+            ConditionType::TrailingStop {
+                asset,
+                trail_percent,
+                peak,
+            } => Some((asset, peak.map(|peak| peak * (1.0 - trail_percent / 100.0)))),
+            ConditionType::PercentageChange { asset, .. }
+            | ConditionType::PercentChange { asset, .. } => Some((asset, None)),
+            ConditionType::And(sub) | ConditionType::Or(sub) => {
+                sub.iter().find_map(|c| c.condition_type.price_level())
+            }
+            ConditionType::TimeAfter { .. }
+            | ConditionType::TimeWindow { .. }
+            | ConditionType::PoolTxCountAbove { .. }
+            | ConditionType::VolumeAbove { .. }
+            | ConditionType::SwapFilled { .. }
+            | ConditionType::PipelineCompleted { .. } => None,
+        }
+    }
+
+    /// Conditions this one is made of, counting itself
+    pub fn count(&self) -> usize {
+        match self {
+            ConditionType::And(sub) | ConditionType::Or(sub) => {
+                1 + sub.iter().map(|c| c.condition_type.count()).sum::<usize>()
+            }
+            _ => 1,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum Direction {
+    Up,
+    Down,
+}
+
+/// Side of a `PriceBand` the price crossed
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum Bound {
+    Upper,
+    Lower,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Condition {
     pub condition_type: ConditionType,
     pub triggered: bool,
     pub last_evaluated: Option<DateTime<Utc>>,
+    /// Once triggered the condition can't trigger again for this long, so
+    /// a price hovering at the threshold doesn't fire it on every update.
+    /// Only applies to a step's own conditions, not those nested in
+    /// `And`/`Or`.
+    #[serde(default)]
+    pub cooldown_secs: Option<u64>,
+    /// When the condition last went from untriggered to triggered
+    #[serde(default)]
+    pub last_triggered: Option<DateTime<Utc>>,
+}
+
+impl From<ConditionType> for Condition {
+    /// A condition yet to be evaluated, without a cooldown
+    fn from(condition_type: ConditionType) -> Self {
+        Condition {
+            condition_type,
+            triggered: false,
+            last_evaluated: None,
+            cooldown_secs: None,
+            last_triggered: None,
+        }
+    }
+}
+
+impl Condition {
+    /// Fired one-shot condition, no longer needs evaluating
+    pub fn is_settled(&self) -> bool {
+        self.triggered && self.condition_type.is_one_shot()
+    }
+
+    /// Whether the condition or any nested in it has a cooldown
+    fn has_cooldown(&self) -> bool {
+        self.cooldown_secs.is_some()
+            || match &self.condition_type {
+                ConditionType::And(sub) | ConditionType::Or(sub) => {
+                    sub.iter().any(Condition::has_cooldown)
+                }
+                _ => false,
+            }
+    }
+
+    /// Whether the condition triggered less than `cooldown_secs` before
+    /// `now`, for good when the cooldown ends past the dates representable
+    pub fn in_cooldown(&self, now: DateTime<Utc>) -> bool {
+        match (self.cooldown_secs, self.last_triggered) {
+            (Some(cooldown_secs), Some(last_triggered)) => i64::try_from(cooldown_secs)
+                .ok()
+                .and_then(|secs| {
+                    last_triggered.checked_add_signed(chrono::Duration::try_seconds(secs)?)
+                })
+                .is_none_or(|until| now < until),
+            _ => false,
+        }
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -37,10 +350,211 @@ pub struct Notification {
     pub message: String,
 }
 
+/// POSTs the triggered step to `url` with the given headers, `{pipeline_id}`
+/// and `{step_id}` in `template` are filled in for the message
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Webhook {
+    pub url: String,
+    #[serde(default)]
+    pub headers: HashMap<String, String>,
+    pub template: String,
+}
+
+impl Webhook {
+    pub fn render(&self, pipeline_id: Uuid, step_id: Uuid) -> String {
+        self.template
+            .replace("{pipeline_id}", &pipeline_id.to_string())
+            .replace("{step_id}", &step_id.to_string())
+    }
+
+    /// The URL must be http(s) and, unless `allow_private_hosts`, must not
+    /// point at localhost or a loopback, private or link-local address,
+    /// which would let a pipeline reach the engine's own network
+    pub fn validate(&self, allow_private_hosts: bool) -> Result<(), String> {
+        let url = reqwest::Url::parse(&self.url)
+            .map_err(|e| format!("Invalid webhook URL {}: {}", self.url, e))?;
+        if !matches!(url.scheme(), "http" | "https") {
+            return Err(format!("Webhook URL must be http(s): {}", self.url));
+        }
+        if !allow_private_hosts && url.host_str().is_none_or(is_private_host) {
+            return Err(format!(
+                "Webhook URL must not point at a local or private host: {}",
+                self.url
+            ));
+        }
+        Ok(())
+    }
+}
+
+fn is_private_host(host: &str) -> bool {
+    let host = host.trim_end_matches('.').to_ascii_lowercase();
+    if host == "localhost" || host.ends_with(".localhost") {
+        return true;
+    }
+    // IPv6 hosts come in brackets
+    match host.trim_start_matches('[').trim_end_matches(']').parse() {
+        Ok(IpAddr::V4(ip)) => is_private_ipv4(ip),
+        Ok(IpAddr::V6(ip)) => is_private_ipv6(ip),
+        Err(_) => false,
+    }
+}
+
+fn is_private_ipv4(ip: Ipv4Addr) -> bool {
+    let [a, b, ..] = ip.octets();
+    ip.is_loopback()
+        || ip.is_private()
+        || ip.is_link_local()
+        || ip.is_unspecified()
+        || ip.is_broadcast()
+        // carrier-grade NAT, 100.64.0.0/10
+        || (a == 100 && (b & 0xc0) == 64)
+}
+
+fn is_private_ipv6(ip: Ipv6Addr) -> bool {
+    if let Some(ip) = ip.to_ipv4_mapped() {
+        return is_private_ipv4(ip);
+    }
+    let first = ip.segments()[0];
+    ip.is_loopback()
+        || ip.is_unspecified()
+        // unique local, fc00::/7
+        || (first & 0xfe00) == 0xfc00
+        // link-local, fe80::/10
+        || (first & 0xffc0) == 0xfe80
+}
+
+/// Sends a message to `chat_id` through the Telegram Bot API. The bot token
+/// is never stored with the pipeline, it is read from the
+/// `TELEGRAM_BOT_TOKEN_<bot_token_ref>` environment variable when the step
+/// fires. `{pipeline_id}` and `{step_id}` in `template` are filled in.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Telegram {
+    pub chat_id: String,
+    pub bot_token_ref: String,
+    #[serde(default)]
+    pub template: Option<String>,
+}
+
+impl Telegram {
+    pub fn render(&self, pipeline_id: Uuid, step_id: Uuid) -> String {
+        self.template
+            .as_deref()
+            .unwrap_or("Pipeline {pipeline_id}: step {step_id} triggered")
+            .replace("{pipeline_id}", &pipeline_id.to_string())
+            .replace("{step_id}", &step_id.to_string())
+    }
+
+    /// Name of the environment variable holding the bot token
+    pub fn token_var(&self) -> String {
+        format!("TELEGRAM_BOT_TOKEN_{}", self.bot_token_ref.to_uppercase())
+    }
+
+    pub fn bot_token(&self) -> Result<String, String> {
+        let var = self.token_var();
+        std::env::var(&var).map_err(|_| format!("Telegram bot token {} is not set", var))
+    }
+
+    pub fn validate(&self) -> Result<(), String> {
+        if self.chat_id.trim().is_empty() {
+            return Err("Telegram chat_id must not be empty".to_string());
+        }
+        if self.bot_token_ref.is_empty()
+            || !self
+                .bot_token_ref
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '_')
+        {
+            return Err(format!(
+                "Telegram bot_token_ref must be letters, digits or '_': {}",
+                self.bot_token_ref
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// Posts `content_template` to a Discord channel webhook. `{asset}`,
+/// `{price}` and `{threshold}` are filled in from the condition that fired,
+/// `{pipeline_id}` and `{step_id}` from the step.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Discord {
+    pub webhook_url: String,
+    #[serde(default)]
+    pub username: Option<String>,
+    pub content_template: String,
+}
+
+/// What the template of a `Discord` message is filled in with, `-` stands
+/// in for anything unknown
+#[derive(Debug, Clone, Default)]
+pub struct MessageContext<'a> {
+    pub pipeline_id: Uuid,
+    pub step_id: Uuid,
+    pub asset: Option<&'a str>,
+    pub price: Option<f64>,
+    pub threshold: Option<f64>,
+}
+
+impl Discord {
+    pub fn render(&self, context: &MessageContext) -> String {
+        let value = |v: Option<f64>| v.map_or("-".to_string(), |v| v.to_string());
+        self.content_template
+            .replace("{pipeline_id}", &context.pipeline_id.to_string())
+            .replace("{step_id}", &context.step_id.to_string())
+            .replace("{asset}", context.asset.unwrap_or("-"))
+            .replace("{price}", &value(context.price))
+            .replace("{threshold}", &value(context.threshold))
+    }
+
+    /// Only Discord's own webhooks are posted to
+    pub fn validate(&self) -> Result<(), String> {
+        let url = reqwest::Url::parse(&self.webhook_url)
+            .map_err(|e| format!("Invalid Discord webhook URL: {}", e))?;
+        let host = url.host_str().unwrap_or_default();
+        let is_discord = ["discord.com", "discordapp.com"]
+            .iter()
+            .any(|domain| host == *domain || host.ends_with(&format!(".{}", domain)));
+        if url.scheme() != "https" || !is_discord {
+            return Err(format!(
+                "Discord webhook URL must be https on discord.com or discordapp.com, got host {:?}",
+                host
+            ));
+        }
+        Ok(())
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum Action {
     Order(Order),
+    SwapOrder(SwapOrder),
     Notification(Notification),
+    Webhook(Webhook),
+    Telegram(Telegram),
+    Discord(Discord),
+}
+
+/// How the action of a step is attempted again after failing,
+/// `max_attempts` counts the first attempt
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RetryPolicy {
+    pub max_attempts: u32,
+    pub base_delay_ms: u64,
+    pub max_delay_ms: u64,
+}
+
+impl RetryPolicy {
+    /// Delay after the `attempts`th failed attempt, doubling from
+    /// `base_delay_ms` up to `max_delay_ms`. `jitter` in [0, 1] picks it
+    /// from the upper half of that.
+    pub fn delay(&self, attempts: u32, jitter: f64) -> Duration {
+        let delay_ms = self
+            .base_delay_ms
+            .saturating_mul(2u64.saturating_pow(attempts.saturating_sub(1)))
+            .min(self.max_delay_ms);
+        let jitter_ms = (delay_ms as f64 / 2.0 * jitter.clamp(0.0, 1.0)) as u64;
+        Duration::from_millis(delay_ms - delay_ms / 2 + jitter_ms)
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -50,6 +564,39 @@ pub struct PipelineStep {
     pub conditions: Vec<Condition>,
     pub next_steps: Vec<Uuid>,
     pub status: Status,
+    /// Actions failing transiently are attempted again as per the policy,
+    /// or without one on the next evaluation. Any other failure, a swap
+    /// that may have been sent included, fails the step.
+    #[serde(default)]
+    pub retry_policy: Option<RetryPolicy>,
+    /// Failed attempts of the action so far
+    #[serde(default)]
+    pub attempts: u32,
+    #[serde(default)]
+    pub next_attempt_at: Option<DateTime<Utc>>,
+}
+
+impl PipelineStep {
+    /// Counts a failed attempt of the action and, should the retry policy
+    /// allow another, schedules it. Returns when it is due.
+    pub fn schedule_retry(&mut self, now: DateTime<Utc>, jitter: f64) -> Option<DateTime<Utc>> {
+        let policy = self.retry_policy.as_ref()?;
+        self.attempts += 1;
+        self.next_attempt_at = None;
+        if self.attempts >= policy.max_attempts {
+            return None;
+        }
+        let delay = chrono::Duration::from_std(policy.delay(self.attempts, jitter)).ok()?;
+        self.status = Status::Retrying;
+        self.next_attempt_at = Some(now + delay);
+        self.next_attempt_at
+    }
+
+    /// Whether the step is waiting to attempt its action again and the
+    /// backoff has passed
+    pub fn is_retry_due(&self, now: DateTime<Utc>) -> bool {
+        matches!(self.status, Status::Retrying) && self.next_attempt_at.is_none_or(|at| at <= now)
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -60,12 +607,1413 @@ pub struct Pipeline {
     pub steps: HashMap<Uuid, PipelineStep>,
     pub status: Status,
     pub created_at: DateTime<Utc>,
+    #[serde(default)]
+    pub swap_history: Vec<SwapResult>,
+    #[serde(default)]
+    pub failure_reason: Option<String>,
+    /// Minimum time between evaluations on price updates, every update is
+    /// evaluated if unset
+    #[serde(default)]
+    pub eval_interval_ms: Option<u64>,
+    /// Number of times the pipeline advanced to its next steps
+    #[serde(default)]
+    pub depth: usize,
+    /// After this the pipeline is no longer evaluated and is removed, it
+    /// runs until it settles if unset
+    #[serde(default)]
+    pub expires_at: Option<DateTime<Utc>>,
+    /// Bumped on every save, a save over a newer version is a conflict
+    #[serde(default)]
+    pub version: u64,
+    /// Steps whose conditions are met advance without their actions being
+    /// executed, each is recorded in `dry_run_triggers` instead
+    #[serde(default)]
+    pub dry_run: bool,
+    #[serde(default)]
+    pub dry_run_triggers: Vec<ExecutionEvent>,
 }
 
+impl Pipeline {
+    /// Whether the pipeline's evaluation interval has passed since it was
+    /// last evaluated, a paused pipeline never is
+    pub fn is_due(&self, last_evaluated: Option<Instant>, now: Instant) -> bool {
+        if matches!(self.status, Status::Paused) {
+            return false;
+        }
+        match (self.eval_interval_ms, last_evaluated) {
+            (Some(interval_ms), Some(last_evaluated)) => {
+                now.saturating_duration_since(last_evaluated) >= Duration::from_millis(interval_ms)
+            }
+            _ => true,
+        }
+    }
+
+    /// Whether a current step of the pending pipeline waits on time alone,
+    /// so that no update would get it evaluated
+    pub fn waits_on_time(&self) -> bool {
+        matches!(self.status, Status::Pending)
+            && self
+                .current_steps
+                .iter()
+                .filter_map(|step_id| self.steps.get(step_id))
+                .any(|step| {
+                    matches!(step.status, Status::Pending)
+                        && step
+                            .conditions
+                            .iter()
+                            .all(|c| c.is_settled() || c.condition_type.is_timed())
+                })
+    }
+
+    /// Pipelines referenced by `PipelineCompleted` conditions of any step
+    pub fn referenced_pipelines(&self) -> HashSet<Uuid> {
+        let mut referenced = HashSet::new();
+        let mut stack: Vec<&Condition> = self
+            .steps
+            .values()
+            .flat_map(|step| step.conditions.iter())
+            .collect();
+        while let Some(condition) = stack.pop() {
+            match &condition.condition_type {
+                ConditionType::PipelineCompleted { pipeline_id, .. } => {
+                    referenced.insert(*pipeline_id);
+                }
+                ConditionType::And(sub) | ConditionType::Or(sub) => stack.extend(sub.iter()),
+                _ => {}
+            }
+        }
+        referenced
+    }
+
+    /// Sets the `reference_price` of `PercentChange` conditions and the
+    /// `peak` of `TrailingStop` ones that have none to the asset's price in
+    /// `prices`; those without a price yet take the first one they are
+    /// evaluated with
+    pub fn capture_reference_prices(&mut self, prices: &HashMap<String, f64>) {
+        let mut stack: Vec<&mut Condition> = self
+            .steps
+            .values_mut()
+            .flat_map(|step| step.conditions.iter_mut())
+            .collect();
+        while let Some(condition) = stack.pop() {
+            match &mut condition.condition_type {
+                ConditionType::PercentChange {
+                    asset,
+                    reference_price: reference_price @ None,
+                    ..
+                } => {
+                    *reference_price = prices.get(asset.as_str()).copied();
+                }
+                ConditionType::TrailingStop {
+                    asset,
+                    peak: peak @ None,
+                    ..
+                } => {
+                    *peak = prices.get(asset.as_str()).copied();
+                }
+                ConditionType::And(sub) | ConditionType::Or(sub) => stack.extend(sub.iter_mut()),
+                _ => {}
+            }
+        }
+    }
+
+    /// The `peak` of every `TrailingStop`, to tell whether an evaluation
+    /// moved any of them
+    pub fn trailing_peaks(&self) -> Vec<Option<f64>> {
+        let mut peaks = Vec::new();
+        let mut stack: Vec<&Condition> = self
+            .steps
+            .values()
+            .flat_map(|step| step.conditions.iter())
+            .collect();
+        while let Some(condition) = stack.pop() {
+            match &condition.condition_type {
+                ConditionType::TrailingStop { peak, .. } => peaks.push(*peak),
+                ConditionType::And(sub) | ConditionType::Or(sub) => stack.extend(sub.iter()),
+                _ => {}
+            }
+        }
+        peaks
+    }
+
+    /// Applies what evaluating `from` into `evaluated` changed onto this
+    /// pipeline, a version of `from` saved meanwhile: the swaps and dry-run
+    /// triggers it added are appended, and steps, current steps and status
+    /// are taken from `evaluated` where the evaluation changed them. Steps
+    /// changed meanwhile too, e.g. patched, keep their own conditions.
+    pub fn merge_evaluation(&mut self, from: &Pipeline, evaluated: &Pipeline) {
+        self.swap_history.extend(
+            evaluated
+                .swap_history
+                .iter()
+                .skip(from.swap_history.len())
+                .cloned(),
+        );
+        self.dry_run_triggers.extend(
+            evaluated
+                .dry_run_triggers
+                .iter()
+                .skip(from.dry_run_triggers.len())
+                .cloned(),
+        );
+        let unchanged = |a: &PipelineStep, b: &PipelineStep| {
+            serde_json::to_value(a).ok() == serde_json::to_value(b).ok()
+        };
+        for (id, step) in &evaluated.steps {
+            let (Some(stored), Some(original)) = (self.steps.get_mut(id), from.steps.get(id))
+            else {
+                continue;
+            };
+            if unchanged(stored, original) {
+                *stored = step.clone();
+            } else if (&step.status, step.attempts, step.next_attempt_at)
+                != (
+                    &original.status,
+                    original.attempts,
+                    original.next_attempt_at,
+                )
+            {
+                stored.status = step.status.clone();
+                stored.attempts = step.attempts;
+                stored.next_attempt_at = step.next_attempt_at;
+            }
+        }
+        if evaluated.current_steps != from.current_steps {
+            self.current_steps = evaluated.current_steps.clone();
+            self.depth = evaluated.depth;
+        }
+        if evaluated.status != from.status {
+            self.status = evaluated.status.clone();
+            self.failure_reason = evaluated.failure_reason.clone();
+        }
+    }
+
+    /// Each step's status and the `triggered` of its conditions, nested
+    /// ones included, by step id, to tell whether an evaluation fired or
+    /// advanced anything
+    pub fn step_states(&self) -> Vec<(Uuid, Status, Vec<bool>)> {
+        let mut states: Vec<_> = self
+            .steps
+            .values()
+            .map(|step| {
+                let mut triggered = Vec::new();
+                let mut stack: Vec<&Condition> = step.conditions.iter().collect();
+                while let Some(condition) = stack.pop() {
+                    triggered.push(condition.triggered);
+                    if let ConditionType::And(sub) | ConditionType::Or(sub) =
+                        &condition.condition_type
+                    {
+                        stack.extend(sub.iter());
+                    }
+                }
+                (step.id, step.status.clone(), triggered)
+            })
+            .collect();
+        states.sort_by_key(|(id, ..)| *id);
+        states
+    }
+
+    /// Steps must be keyed by their id and only point at steps of the
+    /// pipeline, without leading back to themselves
+    pub fn validate(&self) -> Result<(), String> {
+        if self.steps.len() > MAX_STEPS_PER_PIPELINE {
+            return Err(format!(
+                "Pipeline has {} steps, more than the maximum of {}",
+                self.steps.len(),
+                MAX_STEPS_PER_PIPELINE
+            ));
+        }
+        for (id, step) in &self.steps {
+            if *id != step.id {
+                return Err(format!("Step {} is keyed as {}", step.id, id));
+            }
+            if let Some(next) = step
+                .next_steps
+                .iter()
+                .find(|next| !self.steps.contains_key(next))
+            {
+                return Err(format!("Step {} points at unknown step {}", id, next));
+            }
+        }
+        if let Some(current) = self
+            .current_steps
+            .iter()
+            .find(|current| !self.steps.contains_key(current))
+        {
+            return Err(format!("Unknown current step {}", current));
+        }
+        if let Some(cycle) = self.step_cycle() {
+            let cycle: Vec<String> = cycle.iter().map(Uuid::to_string).collect();
+            return Err(format!("Steps form a cycle: {}", cycle.join(" -> ")));
+        }
+        self.validate_conditions()
+    }
+
+    /// Steps leading back to the first of them through `next_steps`, which
+    /// is repeated at the end. Step ids are walked in order and the cycle
+    /// starts from its smallest id, so the same steps give the same cycle
+    pub fn step_cycle(&self) -> Option<Vec<Uuid>> {
+        let mut done = HashSet::new();
+        let mut starts: Vec<Uuid> = self.steps.keys().copied().collect();
+        starts.sort();
+        for start in starts {
+            // depth-first, each step on the path with the index of the next
+            // of its `next_steps` to follow
+            let mut path = vec![(start, 0)];
+            while let Some(&(id, i)) = path.last() {
+                let Some(next) = self.steps.get(&id).and_then(|s| s.next_steps.get(i)) else {
+                    done.insert(id);
+                    path.pop();
+                    continue;
+                };
+                if let Some(last) = path.last_mut() {
+                    last.1 += 1;
+                }
+                if let Some(pos) = path.iter().position(|(on_path, _)| on_path == next) {
+                    let mut cycle: Vec<Uuid> = path[pos..].iter().map(|(id, _)| *id).collect();
+                    let smallest = (0..cycle.len()).min_by_key(|&i| cycle[i]).unwrap_or(0);
+                    cycle.rotate_left(smallest);
+                    cycle.push(cycle[0]);
+                    return Some(cycle);
+                }
+                if !done.contains(next) {
+                    path.push((*next, 0));
+                }
+            }
+        }
+        None
+    }
+
+    /// Replaces the completed step in `current_steps` with those of its
+    /// `next_steps` that are ready, a step with several predecessors waits
+    /// for all of them to complete. A step waiting on one that can never
+    /// complete, as no current step leads to it, fails the pipeline.
+    pub fn advance(&mut self, step_id: Uuid) {
+        self.current_steps.retain(|id| *id != step_id);
+        let next_steps = self
+            .steps
+            .get(&step_id)
+            .map(|step| step.next_steps.clone())
+            .unwrap_or_default();
+        let live = self.live_steps(self.current_steps.iter().chain(&next_steps).copied());
+        for next in next_steps {
+            let pending = self
+                .steps
+                .get(&next)
+                .is_some_and(|step| matches!(step.status, Status::Pending));
+            let waits_on: Vec<Uuid> = self
+                .steps
+                .values()
+                .filter(|step| step.next_steps.contains(&next))
+                .filter(|step| !matches!(step.status, Status::Completed))
+                .map(|step| step.id)
+                .collect();
+            if let Some(stuck) = waits_on.iter().find(|id| !live.contains(id)) {
+                if let Some(step) = self.steps.get_mut(&next) {
+                    step.status = Status::Failed;
+                }
+                self.status = Status::Failed;
+                self.failure_reason = Some(format!(
+                    "Step {} waits on step {}, which can never complete",
+                    next, stuck
+                ));
+                continue;
+            }
+            if pending && waits_on.is_empty() && !self.current_steps.contains(&next) {
+                self.current_steps.push(next);
+            }
+        }
+    }
+
+    /// The steps still to complete among `from` and those they lead to
+    fn live_steps(&self, from: impl Iterator<Item = Uuid>) -> HashSet<Uuid> {
+        let mut live = HashSet::new();
+        let mut stack: Vec<Uuid> = from.collect();
+        while let Some(id) = stack.pop() {
+            let Some(step) = self.steps.get(&id) else {
+                continue;
+            };
+            if matches!(step.status, Status::Pending | Status::Retrying) && live.insert(id) {
+                stack.extend(step.next_steps.iter().copied());
+            }
+        }
+        live
+    }
+
+    /// Webhook actions must post to a public http(s) URL, unless
+    /// `allow_private_hosts`, Telegram actions need a chat and a well-formed
+    /// token reference, Discord ones a Discord URL
+    pub fn validate_actions(&self, allow_private_hosts: bool) -> Result<(), String> {
+        for step in self.steps.values() {
+            match &step.action {
+                Action::Webhook(webhook) => webhook.validate(allow_private_hosts)?,
+                Action::Telegram(telegram) => telegram.validate()?,
+                Action::Discord(discord) => discord.validate()?,
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
+    /// A new pipeline must not have expired already
+    pub fn validate_expiry(&self, now: DateTime<Utc>) -> Result<(), String> {
+        match self.expires_at {
+            Some(expires_at) if expires_at <= now => {
+                Err(format!("Pipeline expired at {}", expires_at))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Marks the pipeline `Expired` once `now` is past its `expires_at`,
+    /// unless it already settled. Returns whether it did.
+    pub fn expire(&mut self, now: DateTime<Utc>) -> bool {
+        match self.expires_at {
+            Some(expires_at) if expires_at <= now && !self.status.is_terminal() => {
+                self.status = Status::Expired;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Conditions must not be nested deeper than `MAX_CONDITION_DEPTH`,
+    /// nor number more than `MAX_CONDITIONS_PER_STEP` in a step, and each
+    /// must be valid on its own. Only a step's own conditions may have a
+    /// cooldown, of at most `MAX_COOLDOWN_SECS`.
+    pub fn validate_conditions(&self) -> Result<(), String> {
+        for step in self.steps.values() {
+            let count: usize = step
+                .conditions
+                .iter()
+                .map(|c| c.condition_type.count())
+                .sum();
+            if count > MAX_CONDITIONS_PER_STEP {
+                return Err(format!(
+                    "Step {} has {} conditions, more than the maximum of {}",
+                    step.id, count, MAX_CONDITIONS_PER_STEP
+                ));
+            }
+        }
+        let too_deep = self
+            .steps
+            .values()
+            .flat_map(|step| step.conditions.iter())
+            .any(|c| c.condition_type.depth() > MAX_CONDITION_DEPTH);
+        if too_deep {
+            return Err(format!(
+                "Conditions are nested deeper than the maximum of {}",
+                MAX_CONDITION_DEPTH
+            ));
+        }
+        for step in self.steps.values() {
+            for c in &step.conditions {
+                if c.cooldown_secs.is_some_and(|secs| secs > MAX_COOLDOWN_SECS) {
+                    return Err(format!(
+                        "Step {} has a cooldown longer than the maximum of {} seconds",
+                        step.id, MAX_COOLDOWN_SECS
+                    ));
+                }
+                if let ConditionType::And(sub) | ConditionType::Or(sub) = &c.condition_type {
+                    if sub.iter().any(Condition::has_cooldown) {
+                        return Err(format!(
+                            "Step {} has a cooldown on a condition nested in And/Or, only its own conditions can have one",
+                            step.id
+                        ));
+                    }
+                }
+            }
+        }
+        self.steps
+            .values()
+            .flat_map(|step| step.conditions.iter())
+            .try_for_each(|c| c.condition_type.validate())
+    }
+
+    /// The longest chain of `next_steps` from the current steps must not
+    /// be longer than `max_depth` steps. Walked one level at a time, so a
+    /// cycle counts as too deep rather than looping.
+    pub fn validate_depth(&self, max_depth: usize) -> Result<(), String> {
+        let mut level: HashSet<Uuid> = self.current_steps.iter().copied().collect();
+        let mut depth = 0;
+        while !level.is_empty() {
+            depth += 1;
+            if depth > max_depth {
+                return Err(format!(
+                    "Steps are chained deeper than the maximum of {}",
+                    max_depth
+                ));
+            }
+            level = level
+                .iter()
+                .filter_map(|id| self.steps.get(id))
+                .flat_map(|step| step.next_steps.iter().copied())
+                .collect();
+        }
+        Ok(())
+    }
+
+    /// Referenced pipelines must be among `pipelines` and must not lead back
+    /// to this one
+    pub fn validate_references(&self, pipelines: &HashMap<Uuid, Pipeline>) -> Result<(), String> {
+        if let Some(missing) = self
+            .referenced_pipelines()
+            .into_iter()
+            .find(|id| !pipelines.contains_key(id))
+        {
+            return Err(format!("Referenced pipeline not found: {}", missing));
+        }
+        if self.has_reference_cycle(pipelines) {
+            return Err("Pipeline references form a cycle".to_string());
+        }
+        Ok(())
+    }
+
+    /// Returns the pipeline with the JSON merge patch applied, e.g.
+    /// `{"steps": {"<id>": {"status": "Cancelled"}}}` only touches that step
+    pub fn patched(&self, patch: &serde_json::Value) -> Result<Pipeline, String> {
+        if let Some(field) = IMMUTABLE_FIELDS
+            .iter()
+            .find(|field| patch.get(**field).is_some())
+        {
+            return Err(format!("Field {} can't be patched", field));
+        }
+        let mut value = serde_json::to_value(self).map_err(|e| e.to_string())?;
+        merge_patch(&mut value, patch);
+        let patched: Pipeline = serde_json::from_value(value).map_err(|e| e.to_string())?;
+        patched.validate()?;
+        Ok(patched)
+    }
+
+    /// Whether following references from this pipeline through `pipelines`
+    /// leads back to it
+    pub fn has_reference_cycle(&self, pipelines: &HashMap<Uuid, Pipeline>) -> bool {
+        let mut visited = HashSet::new();
+        let mut stack: Vec<Uuid> = self.referenced_pipelines().into_iter().collect();
+        while let Some(pipeline_id) = stack.pop() {
+            if pipeline_id == self.id {
+                return true;
+            }
+            if !visited.insert(pipeline_id) {
+                continue;
+            }
+            if let Some(pipeline) = pipelines.get(&pipeline_id) {
+                stack.extend(pipeline.referenced_pipelines());
+            }
+        }
+        false
+    }
+}
+
+/// Page of pipelines, `next_cursor` is `None` once there are no more
 #[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PipelinePage {
+    pub pipelines: Vec<Pipeline>,
+    pub next_cursor: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub enum Status {
     Pending,   // Not yet started
     Completed, // Successfully finished
     Failed,    // Execution failed
     Cancelled, // Manually cancelled
+    Expired,   // Passed its expires_at before settling
+    Paused,    // Not evaluated until resumed
+    Retrying,  // Waiting to attempt a failed action again
+}
+
+impl Status {
+    /// Completed, failed and expired pipelines are kept for their history
+    /// only and can't be updated
+    pub fn is_terminal(&self) -> bool {
+        matches!(self, Status::Completed | Status::Failed | Status::Expired)
+    }
+
+    /// The variant's name, as in the JSON, for metric labels
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Status::Pending => "Pending",
+            Status::Completed => "Completed",
+            Status::Failed => "Failed",
+            Status::Cancelled => "Cancelled",
+            Status::Expired => "Expired",
+            Status::Paused => "Paused",
+            Status::Retrying => "Retrying",
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::swap::SlippageSpec;
+
+    fn pipeline(references: &[Uuid]) -> Pipeline {
+        let step = PipelineStep {
+            id: Uuid::new_v4(),
+            action: Action::Notification(Notification {
+                message: "-".to_string(),
+            }),
+            conditions: references
+                .iter()
+                .map(|pipeline_id| {
+                    Condition::from(ConditionType::PipelineCompleted {
+                        pipeline_id: *pipeline_id,
+                        status: None,
+                    })
+                })
+                .collect(),
+            next_steps: vec![],
+            status: Status::Pending,
+            retry_policy: None,
+            attempts: 0,
+            next_attempt_at: None,
+        };
+        Pipeline {
+            id: Uuid::new_v4(),
+            user_id: "-".to_string(),
+            current_steps: vec![step.id],
+            steps: HashMap::from([(step.id, step)]),
+            status: Status::Pending,
+            created_at: Utc::now(),
+            swap_history: vec![],
+            failure_reason: None,
+            eval_interval_ms: None,
+            depth: 0,
+            expires_at: None,
+            version: 0,
+            dry_run: false,
+            dry_run_triggers: vec![],
+        }
+    }
+
+    #[test]
+    fn test_reference_cycle() {
+        let a = pipeline(&[]);
+        let b = pipeline(&[a.id]);
+        let mut pipelines = HashMap::from([(a.id, a.clone()), (b.id, b.clone())]);
+
+        let c = pipeline(&[b.id]);
+        assert!(!c.has_reference_cycle(&pipelines));
+
+        // a -> c -> b -> a
+        let mut a = a;
+        a.steps = pipeline(&[c.id]).steps;
+        pipelines.insert(c.id, c);
+        assert!(a.has_reference_cycle(&pipelines));
+
+        let mut self_referencing = pipeline(&[]);
+        self_referencing.steps = pipeline(&[self_referencing.id]).steps;
+        assert!(self_referencing.has_reference_cycle(&pipelines));
+    }
+
+    #[test]
+    fn test_patch_single_threshold() {
+        let mut pipeline = pipeline(&[]);
+        let step_id = pipeline.current_steps[0];
+        let price_condition = |threshold| {
+            Condition::from(ConditionType::PriceAbove {
+                asset: "SOL".to_string(),
+                threshold,
+            })
+        };
+        pipeline.steps.get_mut(&step_id).unwrap().conditions = vec![price_condition(100.0)];
+
+        let patch = serde_json::json!({
+            "steps": {
+                step_id.to_string(): {
+                    "conditions": [price_condition(120.0)],
+                }
+            }
+        });
+        let patched = pipeline.patched(&patch).unwrap();
+
+        let conditions = &patched.steps[&step_id].conditions;
+        assert!(matches!(
+            conditions[0].condition_type,
+            ConditionType::PriceAbove { threshold, .. } if threshold == 120.0
+        ));
+        // everything else is left as it was
+        let mut expected = serde_json::to_value(&pipeline).unwrap();
+        expected["steps"][step_id.to_string()]["conditions"] =
+            serde_json::to_value(conditions).unwrap();
+        assert_eq!(serde_json::to_value(&patched).unwrap(), expected);
+    }
+
+    #[test]
+    fn test_patch_rejected() {
+        let pipeline = pipeline(&[]);
+        assert!(pipeline
+            .patched(&serde_json::json!({ "user_id": "someone-else" }))
+            .is_err());
+        assert!(pipeline
+            .patched(&serde_json::json!({ "current_steps": [Uuid::new_v4()] }))
+            .is_err());
+        assert!(pipeline
+            .patched(&serde_json::json!({ "status": "Unknown" }))
+            .is_err());
+    }
+
+    #[test]
+    fn test_eval_interval() {
+        let mut pipeline = pipeline(&[]);
+        let start = Instant::now();
+        let updates = |pipeline: &Pipeline| {
+            let mut last_evaluated = None;
+            let mut evaluations = 0;
+            // a price update every 100ms for a second
+            for i in 0..10 {
+                let now = start + Duration::from_millis(100 * i);
+                if pipeline.is_due(last_evaluated, now) {
+                    evaluations += 1;
+                    last_evaluated = Some(now);
+                }
+            }
+            evaluations
+        };
+
+        assert_eq!(updates(&pipeline), 10);
+        pipeline.eval_interval_ms = Some(60_000);
+        assert_eq!(updates(&pipeline), 1);
+        pipeline.eval_interval_ms = Some(250);
+        assert_eq!(updates(&pipeline), 4);
+    }
+
+    #[test]
+    fn test_paused_does_not_trigger() {
+        use crate::engine::evaluator::Evaluator;
+
+        let referenced = Uuid::new_v4();
+        let statuses = HashMap::from([(referenced, Status::Completed)]);
+        let mut pipeline = pipeline(&[referenced]);
+        pipeline.status = Status::Paused;
+        let now = Instant::now();
+
+        // its condition is met, but a paused pipeline is never evaluated
+        assert!(!pipeline.is_due(None, now));
+        assert!(!pipeline.is_due(Some(now - Duration::from_secs(60)), now));
+        let step = pipeline.steps.values().next().unwrap();
+        assert!(step.conditions.iter().all(|c| c.last_evaluated.is_none()));
+
+        pipeline.status = Status::Pending;
+        assert!(pipeline.is_due(None, now));
+        let step = pipeline.steps.values_mut().next().unwrap();
+        assert!(Evaluator::evaluate_conditions(
+            &mut step.conditions,
+            &HashMap::new(),
+            &statuses,
+            &HashMap::new(),
+            None,
+        )
+        .unwrap());
+    }
+
+    #[test]
+    fn test_retry_backoff() {
+        let policy = RetryPolicy {
+            max_attempts: 3,
+            base_delay_ms: 100,
+            max_delay_ms: 1_000,
+        };
+        let delays: Vec<u64> = (1..=6)
+            .map(|attempts| policy.delay(attempts, 1.0).as_millis() as u64)
+            .collect();
+        assert_eq!(delays, vec![100, 200, 400, 800, 1_000, 1_000]);
+        assert_eq!(policy.delay(3, 0.0), Duration::from_millis(200));
+
+        let mut step = pipeline(&[]).steps.into_values().next().unwrap();
+        step.retry_policy = Some(policy);
+        let now = Utc::now();
+        let at = step.schedule_retry(now, 0.0).unwrap();
+        assert_eq!(at - now, chrono::Duration::milliseconds(50));
+        assert_eq!(step.status, Status::Retrying);
+        assert!(!step.is_retry_due(now));
+        assert!(step.is_retry_due(at));
+        assert!(step.schedule_retry(now, 0.0).is_some());
+        // the third failed attempt is the last
+        assert!(step.schedule_retry(now, 0.0).is_none());
+        assert_eq!(step.attempts, 3);
+
+        // without a policy nothing is scheduled
+        let mut step = pipeline(&[]).steps.into_values().next().unwrap();
+        assert!(step.schedule_retry(now, 0.0).is_none());
+        assert_eq!((step.status, step.attempts), (Status::Pending, 0));
+    }
+
+    /// Pipeline of `next_steps.len()` steps, the ith pointing at the steps
+    /// at the indices of `next_steps[i]`, starting from the steps nothing
+    /// points at
+    fn dag(next_steps: &[&[usize]]) -> (Pipeline, Vec<Uuid>) {
+        let mut pipeline = pipeline(&[]);
+        let template = pipeline.steps.drain().next().unwrap().1;
+        // ascending, so a cycle starts from the step listed first
+        let mut ids: Vec<Uuid> = next_steps.iter().map(|_| Uuid::new_v4()).collect();
+        ids.sort();
+        for (i, next) in next_steps.iter().enumerate() {
+            let mut step = template.clone();
+            step.id = ids[i];
+            step.next_steps = next.iter().map(|j| ids[*j]).collect();
+            pipeline.steps.insert(step.id, step);
+        }
+        pipeline.current_steps = (0..ids.len())
+            .filter(|i| !next_steps.iter().any(|next| next.contains(i)))
+            .map(|i| ids[i])
+            .collect();
+        (pipeline, ids)
+    }
+
+    fn complete(pipeline: &mut Pipeline, step_id: Uuid) {
+        pipeline.steps.get_mut(&step_id).unwrap().status = Status::Completed;
+        pipeline.advance(step_id);
+    }
+
+    #[test]
+    fn test_linear_chain() {
+        // price above, then swap, then notify
+        let (mut pipeline, ids) = dag(&[&[1], &[2], &[]]);
+        assert!(pipeline.validate().is_ok());
+        assert_eq!(pipeline.current_steps, vec![ids[0]]);
+        complete(&mut pipeline, ids[0]);
+        assert_eq!(pipeline.current_steps, vec![ids[1]]);
+        complete(&mut pipeline, ids[1]);
+        assert_eq!(pipeline.current_steps, vec![ids[2]]);
+        complete(&mut pipeline, ids[2]);
+        assert!(pipeline.current_steps.is_empty());
+    }
+
+    #[test]
+    fn test_merge_evaluation() {
+        let (from, ids) = dag(&[&[1], &[]]);
+        // evaluated: the first step fired a swap and the pipeline advanced
+        let mut evaluated = from.clone();
+        evaluated.swap_history.push(SwapResult::unknown(
+            &SwapOrder {
+                amm_pool: "pool".to_string(),
+                input_mint: "in".to_string(),
+                output_mint: "out".to_string(),
+                amount: 1,
+                slippage: SlippageSpec::Fixed(50),
+            },
+            "sig".to_string(),
+        ));
+        complete(&mut evaluated, ids[0]);
+        // stored meanwhile: the next step patched
+        let mut stored = from.clone();
+        stored.version += 1;
+        stored.eval_interval_ms = Some(1_000);
+        stored.steps.get_mut(&ids[1]).unwrap().conditions = pipeline(&[Uuid::new_v4()])
+            .steps
+            .into_values()
+            .next()
+            .unwrap()
+            .conditions;
+
+        stored.merge_evaluation(&from, &evaluated);
+        assert_eq!(stored.swap_history.len(), 1);
+        assert_eq!(stored.steps[&ids[0]].status, Status::Completed);
+        assert_eq!(stored.current_steps, vec![ids[1]]);
+        assert_eq!(stored.steps[&ids[1]].conditions.len(), 1);
+        assert_eq!(stored.eval_interval_ms, Some(1_000));
+        assert_eq!(stored.version, from.version + 1);
+    }
+
+    #[test]
+    fn test_fan_out_and_join() {
+        // 0 fans out to 1 and 2, which both lead to 3
+        let (mut pipeline, ids) = dag(&[&[1, 2], &[3], &[3], &[]]);
+        assert!(pipeline.validate().is_ok());
+        complete(&mut pipeline, ids[0]);
+        assert_eq!(pipeline.current_steps, vec![ids[1], ids[2]]);
+
+        // 3 waits for 2 as well, which stays current
+        complete(&mut pipeline, ids[1]);
+        assert_eq!(pipeline.current_steps, vec![ids[2]]);
+        complete(&mut pipeline, ids[2]);
+        assert_eq!(pipeline.current_steps, vec![ids[3]]);
+
+        // 1 joins 0 and 2, which nothing leads to once 0 is started on its
+        // own, so 1 could never run
+        let (mut pipeline, ids) = dag(&[&[1], &[], &[1]]);
+        pipeline.current_steps = vec![ids[0]];
+        complete(&mut pipeline, ids[0]);
+        assert_eq!(pipeline.status, Status::Failed);
+        assert_eq!(pipeline.steps[&ids[1]].status, Status::Failed);
+        assert!(pipeline.current_steps.is_empty());
+        assert!(pipeline
+            .failure_reason
+            .unwrap()
+            .contains(&ids[2].to_string()));
+    }
+
+    #[test]
+    fn test_step_cycle_rejected() {
+        let (mut pipeline, ids) = dag(&[&[1], &[2], &[]]);
+        assert!(pipeline.step_cycle().is_none());
+
+        pipeline.steps.get_mut(&ids[2]).unwrap().next_steps = vec![ids[1]];
+        assert_eq!(pipeline.step_cycle(), Some(vec![ids[1], ids[2], ids[1]]));
+        let err = pipeline.validate().unwrap_err();
+        assert!(err.starts_with("Steps form a cycle"), "{}", err);
+
+        // a step pointing at itself
+        let (mut pipeline, ids) = dag(&[&[]]);
+        pipeline.steps.get_mut(&ids[0]).unwrap().next_steps = vec![ids[0]];
+        assert_eq!(pipeline.step_cycle(), Some(vec![ids[0], ids[0]]));
+    }
+
+    #[test]
+    fn test_max_depth() {
+        let mut pipeline = pipeline(&[]);
+        let mut last = pipeline.current_steps[0];
+        // chain four more steps after the first
+        for _ in 0..4 {
+            let mut step = pipeline.steps[&last].clone();
+            step.id = Uuid::new_v4();
+            pipeline.steps.get_mut(&last).unwrap().next_steps = vec![step.id];
+            last = step.id;
+            pipeline.steps.insert(step.id, step);
+        }
+
+        assert!(pipeline.validate_depth(5).is_ok());
+        assert!(pipeline.validate_depth(4).is_err());
+
+        // a cycle is never within the limit
+        let first = pipeline.current_steps[0];
+        pipeline.steps.get_mut(&last).unwrap().next_steps = vec![first];
+        assert!(pipeline.validate_depth(1_000).is_err());
+    }
+
+    #[test]
+    fn test_size_limits() {
+        let next_steps: Vec<&[usize]> = vec![&[]; MAX_STEPS_PER_PIPELINE];
+        let (mut wide, ids) = dag(&next_steps);
+        assert!(wide.validate().is_ok());
+        let mut step = wide.steps[&ids[0]].clone();
+        step.id = Uuid::new_v4();
+        wide.steps.insert(step.id, step);
+        assert!(wide.validate().unwrap_err().contains("steps"));
+
+        // nested conditions count towards the step's total
+        let condition = Condition::from;
+        let leaves = |n| {
+            (0..n)
+                .map(|_| {
+                    condition(ConditionType::PipelineCompleted {
+                        pipeline_id: Uuid::new_v4(),
+                        status: None,
+                    })
+                })
+                .collect::<Vec<_>>()
+        };
+        let mut pipeline = pipeline(&[]);
+        let step = pipeline.steps.values_mut().next().unwrap();
+        step.conditions = leaves(MAX_CONDITIONS_PER_STEP - 1);
+        step.conditions.push(condition(ConditionType::Or(vec![])));
+        assert!(pipeline.validate().is_ok());
+        let step = pipeline.steps.values_mut().next().unwrap();
+        step.conditions.pop();
+        step.conditions
+            .push(condition(ConditionType::Or(leaves(1))));
+        assert!(pipeline.validate().unwrap_err().contains("conditions"));
+    }
+
+    #[test]
+    fn test_cooldown_limits() {
+        let condition = |condition_type, cooldown_secs| Condition {
+            cooldown_secs,
+            ..Condition::from(condition_type)
+        };
+        let leaf = |cooldown_secs| {
+            condition(
+                ConditionType::PipelineCompleted {
+                    pipeline_id: Uuid::new_v4(),
+                    status: None,
+                },
+                cooldown_secs,
+            )
+        };
+        let mut pipeline = pipeline(&[]);
+        let with = |pipeline: &mut Pipeline, conditions| {
+            pipeline.steps.values_mut().next().unwrap().conditions = conditions;
+        };
+
+        with(&mut pipeline, vec![leaf(Some(MAX_COOLDOWN_SECS))]);
+        assert!(pipeline.validate().is_ok());
+        with(&mut pipeline, vec![leaf(Some(MAX_COOLDOWN_SECS + 1))]);
+        assert!(pipeline.validate().unwrap_err().contains("cooldown"));
+        with(&mut pipeline, vec![leaf(Some(u64::MAX))]);
+        assert!(pipeline.validate().unwrap_err().contains("cooldown"));
+        with(
+            &mut pipeline,
+            vec![condition(
+                ConditionType::And(vec![condition(
+                    ConditionType::Or(vec![leaf(Some(60))]),
+                    None,
+                )]),
+                None,
+            )],
+        );
+        assert!(pipeline.validate().unwrap_err().contains("nested"));
+
+        // cooldowns out of range never panic, holding the condition back
+        let mut triggered = leaf(Some(u64::MAX));
+        triggered.last_triggered = Some(Utc::now());
+        assert!(triggered.in_cooldown(Utc::now()));
+        triggered.cooldown_secs = Some(i64::MAX as u64);
+        assert!(triggered.in_cooldown(Utc::now()));
+    }
+
+    #[test]
+    fn test_expire() {
+        let mut pipeline = pipeline(&[]);
+        let now = Utc::now();
+        assert!(!pipeline.expire(now));
+        assert!(pipeline.validate_expiry(now).is_ok());
+
+        pipeline.expires_at = Some(now + chrono::Duration::hours(1));
+        assert!(pipeline.validate_expiry(now).is_ok());
+        assert!(!pipeline.expire(now));
+        assert_eq!(pipeline.status, Status::Pending);
+
+        let later = now + chrono::Duration::hours(2);
+        assert!(pipeline.validate_expiry(later).is_err());
+        assert!(pipeline.expire(later));
+        assert_eq!(pipeline.status, Status::Expired);
+        // only expires once, and never a settled pipeline
+        assert!(!pipeline.expire(later));
+        pipeline.status = Status::Completed;
+        assert!(!pipeline.expire(later));
+    }
+
+    #[test]
+    fn test_waits_on_time() {
+        let mut pipeline = pipeline(&[Uuid::new_v4()]);
+        let step_id = pipeline.current_steps[0];
+        assert!(!pipeline.waits_on_time());
+
+        let time_after = Condition::from(ConditionType::TimeAfter {
+            timestamp: Utc::now(),
+        });
+        let time_window = Condition::from(ConditionType::TimeWindow {
+            start: NaiveTime::from_hms_opt(9, 0, 0).unwrap(),
+            end: NaiveTime::from_hms_opt(17, 0, 0).unwrap(),
+        });
+        let price_above = Condition::from(ConditionType::PriceAbove {
+            asset: "SOL".to_string(),
+            threshold: 100.0,
+        });
+        let cases = [
+            (vec![time_after.clone()], true),
+            (
+                vec![Condition::from(ConditionType::Or(vec![
+                    time_after.clone(),
+                    time_window.clone(),
+                ]))],
+                true,
+            ),
+            (vec![time_window.clone(), price_above.clone()], false),
+            (
+                vec![Condition::from(ConditionType::And(vec![
+                    time_after.clone(),
+                    price_above.clone(),
+                ]))],
+                false,
+            ),
+            // settled conditions are waited on no longer
+            (
+                vec![
+                    time_window.clone(),
+                    Condition {
+                        triggered: true,
+                        ..Condition::from(ConditionType::PipelineCompleted {
+                            pipeline_id: Uuid::new_v4(),
+                            status: None,
+                        })
+                    },
+                ],
+                true,
+            ),
+        ];
+        for (conditions, waits_on_time) in cases {
+            pipeline.steps.get_mut(&step_id).unwrap().conditions = conditions;
+            assert_eq!(pipeline.waits_on_time(), waits_on_time);
+        }
+
+        // nor is a paused pipeline
+        pipeline.status = Status::Paused;
+        assert!(!pipeline.waits_on_time());
+    }
+
+    #[test]
+    fn test_terminal_status() {
+        assert!(Status::Completed.is_terminal());
+        assert!(Status::Failed.is_terminal());
+        assert!(!Status::Pending.is_terminal());
+        assert!(!Status::Cancelled.is_terminal());
+        assert!(Status::Expired.is_terminal());
+    }
+
+    #[test]
+    fn test_percent_change_serde() {
+        let condition_type: ConditionType = serde_json::from_value(serde_json::json!({
+            "PercentChange": { "asset": "SOL", "percent": 5.0, "direction": "Down" }
+        }))
+        .unwrap();
+        assert!(matches!(
+            condition_type,
+            ConditionType::PercentChange {
+                reference_price: None,
+                direction: Direction::Down,
+                ..
+            }
+        ));
+
+        let condition_type = ConditionType::PercentChange {
+            asset: "SOL".to_string(),
+            reference_price: Some(142.5),
+            percent: 5.0,
+            direction: Direction::Up,
+        };
+        let json = serde_json::to_value(&condition_type).unwrap();
+        assert_eq!(json["PercentChange"]["reference_price"], 142.5);
+        assert_eq!(json["PercentChange"]["direction"], "Up");
+        let round_trip: ConditionType = serde_json::from_value(json).unwrap();
+        assert!(matches!(
+            round_trip,
+            ConditionType::PercentChange {
+                reference_price: Some(price),
+                percent,
+                direction: Direction::Up,
+                ..
+            } if price == 142.5 && percent == 5.0
+        ));
+    }
+
+    #[test]
+    fn test_capture_reference_prices() {
+        let percent_change = |asset: &str, reference_price| {
+            Condition::from(ConditionType::PercentChange {
+                asset: asset.to_string(),
+                reference_price,
+                percent: 10.0,
+                direction: Direction::Up,
+            })
+        };
+        let mut pipeline = pipeline(&[]);
+        let step_id = pipeline.current_steps[0];
+        pipeline.steps.get_mut(&step_id).unwrap().conditions = vec![
+            percent_change("SOL", None),
+            percent_change("SOL", Some(100.0)),
+            Condition::from(ConditionType::Or(vec![percent_change("BONK", None)])),
+        ];
+
+        pipeline.capture_reference_prices(&HashMap::from([("SOL".to_string(), 150.0)]));
+        let conditions = &pipeline.steps[&step_id].conditions;
+        let reference_price = |condition: &Condition| match &condition.condition_type {
+            ConditionType::PercentChange {
+                reference_price, ..
+            } => *reference_price,
+            ConditionType::Or(sub) => match &sub[0].condition_type {
+                ConditionType::PercentChange {
+                    reference_price, ..
+                } => *reference_price,
+                _ => unreachable!(),
+            },
+            _ => unreachable!(),
+        };
+        assert_eq!(reference_price(&conditions[0]), Some(150.0));
+        // a given reference is kept, an unknown price is left for later
+        assert_eq!(reference_price(&conditions[1]), Some(100.0));
+        assert_eq!(reference_price(&conditions[2]), None);
+    }
+
+    #[test]
+    fn test_webhook_url_scheme() {
+        let mut pipeline = pipeline(&[]);
+        let step_id = pipeline.current_steps[0];
+        let webhook = |url: &str| {
+            Action::Webhook(Webhook {
+                url: url.to_string(),
+                headers: HashMap::new(),
+                template: "{pipeline_id}/{step_id} fired".to_string(),
+            })
+        };
+
+        for url in ["https://example.com/hook", "http://93.184.216.34:8080/hook"] {
+            pipeline.steps.get_mut(&step_id).unwrap().action = webhook(url);
+            assert!(pipeline.validate_actions(false).is_ok(), "{}", url);
+        }
+        for url in [
+            "ftp://example.com/hook",
+            "file:///etc/passwd",
+            "example.com",
+        ] {
+            pipeline.steps.get_mut(&step_id).unwrap().action = webhook(url);
+            assert!(pipeline.validate_actions(true).is_err(), "{}", url);
+        }
+
+        // local and private hosts, unless allowed
+        for url in [
+            "http://localhost:8080/hook",
+            "http://api.localhost./hook",
+            "http://127.0.0.1:8080/hook",
+            "http://10.0.0.1/hook",
+            "http://172.16.0.1/hook",
+            "http://192.168.1.1/hook",
+            "http://169.254.169.254/latest/meta-data",
+            "http://100.64.0.1/hook",
+            "http://0.0.0.0/hook",
+            "http://[::1]/hook",
+            "http://[fd00::1]/hook",
+            "http://[fe80::1]/hook",
+            "http://[::ffff:127.0.0.1]/hook",
+        ] {
+            pipeline.steps.get_mut(&step_id).unwrap().action = webhook(url);
+            let e = pipeline.validate_actions(false).unwrap_err();
+            assert!(e.contains("private host"), "{}: {}", url, e);
+            assert!(pipeline.validate_actions(true).is_ok(), "{}", url);
+        }
+
+        let Action::Webhook(webhook) = webhook("https://example.com") else {
+            unreachable!()
+        };
+        assert_eq!(
+            webhook.render(pipeline.id, step_id),
+            format!("{}/{} fired", pipeline.id, step_id)
+        );
+    }
+
+    #[test]
+    fn test_telegram_token_ref() {
+        let mut pipeline = pipeline(&[]);
+        let step_id = pipeline.current_steps[0];
+        let telegram = |chat_id: &str, bot_token_ref: &str| Telegram {
+            chat_id: chat_id.to_string(),
+            bot_token_ref: bot_token_ref.to_string(),
+            template: None,
+        };
+
+        let alerts = telegram("-1001234", "alerts");
+        assert_eq!(alerts.token_var(), "TELEGRAM_BOT_TOKEN_ALERTS");
+        assert_eq!(
+            alerts.render(pipeline.id, step_id),
+            format!("Pipeline {}: step {} triggered", pipeline.id, step_id)
+        );
+        pipeline.steps.get_mut(&step_id).unwrap().action = Action::Telegram(alerts);
+        assert!(pipeline.validate_actions(false).is_ok());
+
+        for (chat_id, bot_token_ref) in [("", "alerts"), ("42", ""), ("42", "../SECRET")] {
+            pipeline.steps.get_mut(&step_id).unwrap().action =
+                Action::Telegram(telegram(chat_id, bot_token_ref));
+            assert!(
+                pipeline.validate_actions(false).is_err(),
+                "{:?}",
+                bot_token_ref
+            );
+        }
+
+        // the token itself never appears in the pipeline
+        let json = serde_json::to_value(Action::Telegram(telegram("42", "alerts"))).unwrap();
+        assert_eq!(
+            json,
+            serde_json::json!({
+                "Telegram": {"chat_id": "42", "bot_token_ref": "alerts", "template": null}
+            })
+        );
+    }
+
+    #[test]
+    fn test_discord_template() {
+        let mut pipeline = pipeline(&[]);
+        let step_id = pipeline.current_steps[0];
+        let discord = |webhook_url: &str| Discord {
+            webhook_url: webhook_url.to_string(),
+            username: None,
+            content_template: "{asset} at {price}, past {threshold} ({step_id})".to_string(),
+        };
+
+        let conditions = [ConditionType::And(vec![
+            Condition {
+                triggered: true,
+                ..Condition::from(ConditionType::TimeAfter {
+                    timestamp: Utc::now(),
+                })
+            },
+            Condition {
+                triggered: true,
+                ..Condition::from(ConditionType::PriceBelow {
+                    asset: "SOL".to_string(),
+                    threshold: 100.0,
+                })
+            },
+        ])];
+        let (asset, threshold) = conditions[0].price_level().unwrap();
+        let message = discord("https://discord.com/api/webhooks/1/token").render(&MessageContext {
+            pipeline_id: pipeline.id,
+            step_id,
+            asset: Some(asset),
+            price: Some(99.5),
+            threshold,
+        });
+        assert_eq!(message, format!("SOL at 99.5, past 100 ({})", step_id));
+
+        // unknown values are left as a dash
+        let message = discord("https://discord.com/api/webhooks/1/token").render(&MessageContext {
+            step_id,
+            ..Default::default()
+        });
+        assert_eq!(message, format!("- at -, past - ({})", step_id));
+
+        for url in [
+            "https://discord.com/api/webhooks/1/token",
+            "https://discordapp.com/api/webhooks/1/token",
+            "https://canary.discord.com/api/webhooks/1/token",
+        ] {
+            pipeline.steps.get_mut(&step_id).unwrap().action = Action::Discord(discord(url));
+            assert!(pipeline.validate_actions(false).is_ok(), "{}", url);
+        }
+        for url in [
+            "http://discord.com/api/webhooks/1/token",
+            "https://discord.com.example.com/api/webhooks/1/token",
+            "https://notdiscord.com/api/webhooks/1/token",
+            "https://example.com/api/webhooks/1/token",
+            "discord.com",
+        ] {
+            pipeline.steps.get_mut(&step_id).unwrap().action = Action::Discord(discord(url));
+            assert!(pipeline.validate_actions(false).is_err(), "{}", url);
+        }
+    }
+
+    #[test]
+    fn test_metric_labels_match_json() {
+        let condition_types = [
+            ConditionType::PriceBelow {
+                asset: "SOL".to_string(),
+                threshold: 100.0,
+            },
+            ConditionType::SwapFilled { min_amount: 1 },
+            ConditionType::Or(vec![]),
+        ];
+        for condition_type in condition_types {
+            let json = serde_json::to_value(&condition_type).unwrap();
+            assert!(json.get(condition_type.kind()).is_some());
+        }
+        for status in [Status::Pending, Status::Retrying, Status::Expired] {
+            assert_eq!(serde_json::to_value(&status).unwrap(), status.as_str());
+        }
+    }
+
+    #[test]
+    fn test_price_band_swap_step() {
+        let step_id = Uuid::new_v4();
+        let step: PipelineStep = serde_json::from_value(serde_json::json!({
+            "id": step_id,
+            "action": {
+                "SwapOrder": {
+                    "amm_pool": "pool",
+                    "input_mint": "BONK",
+                    "output_mint": "SOL",
+                    "amount": 1_000,
+                    "slippage": 50
+                }
+            },
+            "conditions": [{
+                "condition_type": {
+                    "PriceBand": { "asset": "BONK", "upper": 0.00003, "lower": 0.00001 }
+                },
+                "triggered": false,
+                "last_evaluated": null
+            }],
+            "next_steps": [],
+            "status": "Pending"
+        }))
+        .unwrap();
+        let mut pipeline = pipeline(&[]);
+        pipeline.steps = HashMap::from([(step_id, step)]);
+        pipeline.current_steps = vec![step_id];
+        assert!(pipeline.validate().is_ok());
+
+        let condition = &mut pipeline.steps.get_mut(&step_id).unwrap().conditions[0];
+        condition.condition_type =
+            ConditionType::Or(vec![Condition::from(ConditionType::PriceBand {
+                asset: "BONK".to_string(),
+                upper: 0.00001,
+                lower: 0.00001,
+                crossed: None,
+            })]);
+        assert!(pipeline.validate().unwrap_err().contains("lower bound"));
+    }
+
+    #[test]
+    fn test_trailing_stop_peak_from_creation() {
+        let trailing_stop = |trail_percent| {
+            Condition::from(ConditionType::TrailingStop {
+                asset: "SOL".to_string(),
+                trail_percent,
+                peak: None,
+            })
+        };
+        let mut pipeline = pipeline(&[]);
+        let step = pipeline.steps.values_mut().next().unwrap();
+        step.conditions = vec![trailing_stop(5.0)];
+        assert!(pipeline.validate().is_ok());
+        assert_eq!(pipeline.trailing_peaks(), vec![None]);
+
+        pipeline.capture_reference_prices(&HashMap::from([("SOL".to_string(), 100.0)]));
+        assert_eq!(pipeline.trailing_peaks(), vec![Some(100.0)]);
+        let json = serde_json::to_value(&pipeline).unwrap();
+        let step = json["steps"].as_object().unwrap().values().next().unwrap();
+        assert_eq!(
+            step["conditions"][0]["condition_type"]["TrailingStop"]["peak"],
+            100.0
+        );
+
+        for trail_percent in [0.0, 100.0, f64::NAN] {
+            let step = pipeline.steps.values_mut().next().unwrap();
+            step.conditions = vec![trailing_stop(trail_percent)];
+            assert!(pipeline.validate().is_err());
+        }
+    }
+
+    #[test]
+    fn test_settled_condition_survives_reload() {
+        use crate::engine::evaluator::Evaluator;
+
+        let referenced = Uuid::new_v4();
+        let statuses = HashMap::from([(referenced, Status::Completed)]);
+        let mut pipeline = pipeline(&[referenced]);
+        let before = pipeline.step_states();
+        let step = pipeline.steps.values_mut().next().unwrap();
+        assert!(Evaluator::evaluate_conditions(
+            &mut step.conditions,
+            &HashMap::new(),
+            &statuses,
+            &HashMap::new(),
+            None,
+        )
+        .unwrap());
+        let last_evaluated = step.conditions[0].last_evaluated;
+        // a fired condition is a change to save
+        assert_ne!(pipeline.step_states(), before);
+
+        // as saved to and loaded from Redis
+        let mut reloaded: Pipeline =
+            serde_json::from_str(&serde_json::to_string(&pipeline).unwrap()).unwrap();
+        assert_eq!(reloaded.step_states(), pipeline.step_states());
+
+        // the referenced pipeline is gone after the restart, which would
+        // fail the condition were it evaluated again
+        let step = reloaded.steps.values_mut().next().unwrap();
+        assert!(Evaluator::evaluate_conditions(
+            &mut step.conditions,
+            &HashMap::new(),
+            &HashMap::new(),
+            &HashMap::new(),
+            None,
+        )
+        .unwrap());
+        assert!(step.conditions[0].triggered);
+        assert_eq!(step.conditions[0].last_evaluated, last_evaluated);
+    }
 }
@@ -0,0 +1,113 @@
+use serde::Serialize;
+
+use super::swap::SwapResult;
+
+/// Realized profit/loss of a pipeline's swaps. All amounts are in base units,
+/// costs and proceeds are denominated in the mint the first swap was paid with
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct Pnl {
+    pub denomination: Option<String>,
+    pub entry_cost: u64,
+    pub exit_proceeds: u64,
+    pub realized_pnl: i128,
+    pub open_position: u64,   // bought and not yet sold
+    pub open_cost_basis: u64, // cost of the open position
+}
+
+impl Pnl {
+    /// Average-cost PnL over the confirmed swaps, in order. Swaps that neither
+    /// spend nor receive the denomination don't affect the result.
+    pub fn from_swaps(swaps: &[SwapResult]) -> Self {
+        let mut pnl = Pnl::default();
+
+        for swap in swaps.iter().filter(|s| s.is_confirmed()) {
+            let denomination = pnl
+                .denomination
+                .get_or_insert_with(|| swap.input_mint.clone());
+
+            if swap.input_mint == *denomination {
+                pnl.entry_cost += swap.in_amount;
+                pnl.open_position += swap.out_amount;
+                pnl.open_cost_basis += swap.in_amount;
+            } else if swap.output_mint == *denomination {
+                // only the part of the sale covered by the position is realized
+                let sold = swap.in_amount.min(pnl.open_position);
+                if sold == 0 {
+                    continue;
+                }
+                let cost =
+                    (pnl.open_cost_basis as u128 * sold as u128 / pnl.open_position as u128) as u64;
+                let proceeds =
+                    (swap.out_amount as u128 * sold as u128 / swap.in_amount as u128) as u64;
+
+                pnl.exit_proceeds += proceeds;
+                pnl.realized_pnl += proceeds as i128 - cost as i128;
+                pnl.open_position -= sold;
+                pnl.open_cost_basis -= cost;
+            }
+        }
+
+        pnl
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::swap::SwapOutcome;
+
+    const SOL: &str = "So11111111111111111111111111111111111111112";
+    const TOKEN: &str = "Cn5Ne1vmR9ctMGY9z5NC71A3NYFvopjXNyxYtfVYpump";
+
+    fn swap(input_mint: &str, output_mint: &str, in_amount: u64, out_amount: u64) -> SwapResult {
+        SwapResult {
+            signature: "-".to_string(),
+            input_mint: input_mint.to_string(),
+            output_mint: output_mint.to_string(),
+            in_amount,
+            out_amount,
+            outcome: SwapOutcome::Confirmed,
+            endpoint: "-".to_string(),
+            confirmed_by: vec![],
+            wsol: Default::default(),
+        }
+    }
+
+    #[test]
+    fn test_pnl_buy_then_sell() {
+        let pnl = Pnl::from_swaps(&[
+            swap(SOL, TOKEN, 1_000_000_000, 500_000),
+            swap(TOKEN, SOL, 500_000, 1_500_000_000),
+        ]);
+        assert_eq!(pnl.denomination.as_deref(), Some(SOL));
+        assert_eq!(pnl.entry_cost, 1_000_000_000);
+        assert_eq!(pnl.exit_proceeds, 1_500_000_000);
+        assert_eq!(pnl.realized_pnl, 500_000_000);
+        assert_eq!(pnl.open_position, 0);
+        assert_eq!(pnl.open_cost_basis, 0);
+    }
+
+    #[test]
+    fn test_pnl_partially_closed() {
+        let pnl = Pnl::from_swaps(&[
+            swap(SOL, TOKEN, 1_000_000_000, 500_000),
+            swap(SOL, TOKEN, 1_000_000_000, 250_000),
+            swap(TOKEN, SOL, 250_000, 400_000_000),
+        ]);
+        // average cost is 2 SOL per 750k tokens, 250k sold cost 0.666.. SOL
+        assert_eq!(pnl.entry_cost, 2_000_000_000);
+        assert_eq!(pnl.exit_proceeds, 400_000_000);
+        assert_eq!(pnl.realized_pnl, 400_000_000 - 666_666_666);
+        assert_eq!(pnl.open_position, 500_000);
+        assert_eq!(pnl.open_cost_basis, 1_333_333_334);
+    }
+
+    #[test]
+    fn test_pnl_ignores_unconfirmed_swaps() {
+        let mut dropped = swap(TOKEN, SOL, 500_000, 1_500_000_000);
+        dropped.outcome = SwapOutcome::Dropped;
+        let pnl = Pnl::from_swaps(&[swap(SOL, TOKEN, 1_000_000_000, 500_000), dropped]);
+        assert_eq!(pnl.realized_pnl, 0);
+        assert_eq!(pnl.open_position, 500_000);
+    }
+}
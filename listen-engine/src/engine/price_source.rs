@@ -0,0 +1,795 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use base64::{engine::general_purpose::STANDARD, Engine as _};
+use serde::Deserialize;
+
+use super::constants::PRICE_SOURCE_TIMEOUT_MS;
+use crate::config::Config;
+
+#[derive(Debug, thiserror::Error)]
+pub enum PriceError {
+    #[error("[PriceSource] No price for asset: {0}")]
+    NoPriceError(String),
+
+    #[error("[PriceSource] No volume for asset: {0}")]
+    NoVolumeError(String),
+
+    #[error("[PriceSource] Unexpected response: {0}")]
+    InvalidResponseError(String),
+
+    #[error("[PriceSource] Stale price: {0}")]
+    StalePriceError(String),
+
+    #[error("[PriceSource] Invalid config: {0}")]
+    InvalidConfigError(String),
+
+    #[error("[PriceSource] HTTP request failed: {0}")]
+    RequestError(#[from] reqwest::Error),
+}
+
+/// Where the engine looks up the price of an asset it has not seen a price
+/// update for, e.g. the other asset of a condition on two of them, and the
+/// volume swapped in it for `VolumeAbove`
+#[async_trait::async_trait]
+pub trait PriceSource: Send + Sync {
+    async fn get_price(&self, asset: &str) -> Result<f64, PriceError>;
+
+    /// Prices of `assets`, in one round trip where the source can batch
+    /// them, leaving out the assets it has no price for
+    async fn get_prices(&self, assets: &[&str]) -> Result<HashMap<String, f64>, PriceError> {
+        let mut prices = HashMap::new();
+        for asset in assets {
+            match self.get_price(asset).await {
+                Ok(price) => {
+                    prices.insert(asset.to_string(), price);
+                }
+                Err(e) => tracing::debug!(%asset, "No price: {}", e),
+            }
+        }
+        Ok(prices)
+    }
+
+    /// USD volume swapped in `asset` over the last `window_secs`, sources
+    /// without swap data have none
+    async fn get_volume(&self, asset: &str, _window_secs: u64) -> Result<f64, PriceError> {
+        Err(PriceError::NoVolumeError(asset.to_string()))
+    }
+}
+
+/// Asks each source in turn, answering with the first price found
+pub struct PriceSources(pub Vec<Arc<dyn PriceSource>>);
+
+#[async_trait::async_trait]
+impl PriceSource for PriceSources {
+    async fn get_price(&self, asset: &str) -> Result<f64, PriceError> {
+        let mut last_error = PriceError::NoPriceError(asset.to_string());
+        for source in &self.0 {
+            match source.get_price(asset).await {
+                Ok(price) => return Ok(price),
+                Err(e) => last_error = e,
+            }
+        }
+        Err(last_error)
+    }
+
+    /// Fails only when every source asked failed, so an empty batch means
+    /// none of them had a price
+    async fn get_prices(&self, assets: &[&str]) -> Result<HashMap<String, f64>, PriceError> {
+        let mut prices = HashMap::new();
+        let mut last_error = None;
+        let mut answered = false;
+        for source in &self.0 {
+            let missing: Vec<&str> = assets
+                .iter()
+                .copied()
+                .filter(|asset| !prices.contains_key(*asset))
+                .collect();
+            if missing.is_empty() {
+                break;
+            }
+            match source.get_prices(&missing).await {
+                Ok(found) => {
+                    answered = true;
+                    prices.extend(found);
+                }
+                Err(e) => {
+                    tracing::warn!("Failed to look up prices: {}", e);
+                    last_error = Some(e);
+                }
+            }
+        }
+        match last_error {
+            Some(e) if !answered => Err(e),
+            _ => Ok(prices),
+        }
+    }
+
+    async fn get_volume(&self, asset: &str, window_secs: u64) -> Result<f64, PriceError> {
+        let mut last_error = PriceError::NoVolumeError(asset.to_string());
+        for source in &self.0 {
+            match source.get_volume(asset, window_secs).await {
+                Ok(volume) => return Ok(volume),
+                Err(e) => last_error = e,
+            }
+        }
+        Err(last_error)
+    }
+}
+
+/// Keeps each price fetched from `source` for `ttl`. Lookups of an asset
+/// wait on one another, so of simultaneous misses only the first reaches
+/// the source and the rest get its price. A batch fetches its misses from
+/// the source in one batch. Volumes are kept per asset and window for `ttl`
+/// too.
+pub struct CachedPriceSource {
+    source: Arc<dyn PriceSource>,
+    ttl: Duration,
+    prices: Mutex<HashMap<String, CachedPrice>>,
+    volumes: Mutex<HashMap<(String, u64), CachedPrice>>,
+}
+
+// The price and when it was fetched, locked for the duration of a fetch
+type CachedPrice = Arc<tokio::sync::Mutex<Option<(f64, Instant)>>>;
+
+impl CachedPriceSource {
+    pub fn new(source: Arc<dyn PriceSource>, ttl: Duration) -> Self {
+        Self {
+            source,
+            ttl,
+            prices: Mutex::new(HashMap::new()),
+            volumes: Mutex::new(HashMap::new()),
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl PriceSource for CachedPriceSource {
+    async fn get_price(&self, asset: &str) -> Result<f64, PriceError> {
+        let entry = self
+            .prices
+            .lock()
+            .unwrap()
+            .entry(asset.to_string())
+            .or_default()
+            .clone();
+        let mut cached = entry.lock().await;
+        if let Some((price, fetched_at)) = *cached {
+            if fetched_at.elapsed() < self.ttl {
+                metrics::counter!("price_cache_hits", 1);
+                return Ok(price);
+            }
+        }
+        metrics::counter!("price_cache_misses", 1);
+        // errors are not kept, the next lookup tries again
+        let price = self.source.get_price(asset).await?;
+        *cached = Some((price, Instant::now()));
+        Ok(price)
+    }
+
+    async fn get_prices(&self, assets: &[&str]) -> Result<HashMap<String, f64>, PriceError> {
+        let mut assets = assets.to_vec();
+        assets.sort_unstable();
+        assets.dedup();
+        let entries: Vec<CachedPrice> = {
+            let mut prices = self.prices.lock().unwrap();
+            assets
+                .iter()
+                .map(|asset| prices.entry(asset.to_string()).or_default().clone())
+                .collect()
+        };
+        // locked in order, so that batches waiting on one another can't
+        // deadlock
+        let mut cached = Vec::with_capacity(entries.len());
+        for entry in &entries {
+            cached.push(entry.lock().await);
+        }
+
+        let mut prices = HashMap::new();
+        let mut missing = Vec::new();
+        for (asset, cached) in assets.iter().zip(&cached) {
+            match **cached {
+                Some((price, fetched_at)) if fetched_at.elapsed() < self.ttl => {
+                    prices.insert(asset.to_string(), price);
+                }
+                _ => missing.push(*asset),
+            }
+        }
+        metrics::counter!("price_cache_hits", prices.len() as u64);
+        if missing.is_empty() {
+            return Ok(prices);
+        }
+        metrics::counter!("price_cache_misses", missing.len() as u64);
+        let fetched = self.source.get_prices(&missing).await?;
+        let now = Instant::now();
+        for (asset, cached) in assets.iter().zip(cached.iter_mut()) {
+            if let Some(price) = fetched.get(*asset) {
+                **cached = Some((*price, now));
+                prices.insert(asset.to_string(), *price);
+            }
+        }
+        Ok(prices)
+    }
+
+    async fn get_volume(&self, asset: &str, window_secs: u64) -> Result<f64, PriceError> {
+        let entry = self
+            .volumes
+            .lock()
+            .unwrap()
+            .entry((asset.to_string(), window_secs))
+            .or_default()
+            .clone();
+        let mut cached = entry.lock().await;
+        if let Some((volume, fetched_at)) = *cached {
+            if fetched_at.elapsed() < self.ttl {
+                return Ok(volume);
+            }
+        }
+        let volume = self.source.get_volume(asset, window_secs).await?;
+        *cached = Some((volume, Instant::now()));
+        Ok(volume)
+    }
+}
+
+/// The latest of the `price_updates` that listen-data writes to ClickHouse,
+/// queried over its HTTP interface, and the sum of their swap amounts for
+/// volumes
+pub struct ClickhousePriceSource {
+    http_client: reqwest::Client,
+    url: String,
+    user: Option<String>,
+    password: Option<String>,
+    database: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct PriceRow {
+    price: f64,
+}
+
+#[derive(Deserialize)]
+struct AssetPriceRow {
+    pubkey: String,
+    price: f64,
+}
+
+#[derive(Deserialize)]
+struct VolumeRow {
+    volume: f64,
+}
+
+// `{asset:String}` is bound server side from the `param_asset` parameter
+const LATEST_PRICE_QUERY: &str = "SELECT price FROM price_updates WHERE pubkey = {asset:String} \
+     ORDER BY timestamp DESC LIMIT 1 FORMAT JSONEachRow";
+
+// The latest price of each of `{assets:Array(String)}`, bound from
+// `param_assets`
+const LATEST_PRICES_QUERY: &str = "SELECT pubkey, argMax(price, timestamp) AS price \
+     FROM price_updates WHERE pubkey IN {assets:Array(String)} GROUP BY pubkey \
+     FORMAT JSONEachRow";
+
+// `swap_amount` is in USD and `timestamp` in unix seconds, `{window:UInt64}`
+// is bound from `param_window`
+const VOLUME_QUERY: &str = "SELECT sum(swap_amount) AS volume FROM price_updates \
+     WHERE pubkey = {asset:String} AND timestamp >= toUnixTimestamp(now()) - {window:UInt64} \
+     FORMAT JSONEachRow";
+
+impl ClickhousePriceSource {
+    /// From `CLICKHOUSE_URL`, `None` when unset
+    pub fn from_config(config: &Config) -> Result<Option<Self>, PriceError> {
+        let Some(url) = config.clickhouse_url.clone() else {
+            return Ok(None);
+        };
+        Ok(Some(Self {
+            http_client: reqwest::Client::builder()
+                .timeout(Duration::from_millis(PRICE_SOURCE_TIMEOUT_MS))
+                .build()?,
+            url,
+            user: config.clickhouse_user.clone(),
+            password: config.clickhouse_password.expose().map(str::to_string),
+            database: config.clickhouse_database.clone(),
+        }))
+    }
+
+    /// Runs `query` with its `params`, returning the response body
+    async fn query(
+        &self,
+        query: &'static str,
+        params: &[(&str, &str)],
+    ) -> Result<String, PriceError> {
+        let mut request = self.http_client.post(&self.url).query(params).body(query);
+        if let Some(database) = &self.database {
+            request = request.query(&[("database", database)]);
+        }
+        if let Some(user) = &self.user {
+            request = request.header("X-ClickHouse-User", user);
+        }
+        if let Some(password) = &self.password {
+            request = request.header("X-ClickHouse-Key", password);
+        }
+
+        let response = request.send().await?;
+        let status = response.status();
+        let body = response.text().await?;
+        if !status.is_success() {
+            return Err(PriceError::InvalidResponseError(format!(
+                "{}: {}",
+                status, body
+            )));
+        }
+        Ok(body)
+    }
+}
+
+#[async_trait::async_trait]
+impl PriceSource for ClickhousePriceSource {
+    async fn get_price(&self, asset: &str) -> Result<f64, PriceError> {
+        let body = self
+            .query(LATEST_PRICE_QUERY, &[("param_asset", asset)])
+            .await?;
+        parse_latest_price(asset, &body)
+    }
+
+    async fn get_prices(&self, assets: &[&str]) -> Result<HashMap<String, f64>, PriceError> {
+        if assets.is_empty() {
+            return Ok(HashMap::new());
+        }
+        let body = self
+            .query(
+                LATEST_PRICES_QUERY,
+                &[("param_assets", &array_param(assets))],
+            )
+            .await?;
+        parse_latest_prices(&body)
+    }
+
+    async fn get_volume(&self, asset: &str, window_secs: u64) -> Result<f64, PriceError> {
+        let window = window_secs.to_string();
+        let body = self
+            .query(
+                VOLUME_QUERY,
+                &[("param_asset", asset), ("param_window", &window)],
+            )
+            .await?;
+        parse_volume(asset, &body)
+    }
+}
+
+fn parse_latest_price(asset: &str, body: &str) -> Result<f64, PriceError> {
+    let Some(row) = body.lines().find(|line| !line.trim().is_empty()) else {
+        return Err(PriceError::NoPriceError(asset.to_string()));
+    };
+    serde_json::from_str::<PriceRow>(row)
+        .map(|row| row.price)
+        .map_err(|e| PriceError::InvalidResponseError(e.to_string()))
+}
+
+/// A ClickHouse `Array(String)` query parameter, e.g. `['a','b']`
+fn array_param(values: &[&str]) -> String {
+    let quoted: Vec<String> = values
+        .iter()
+        .map(|value| format!("'{}'", value.replace('\\', "\\\\").replace('\'', "\\'")))
+        .collect();
+    format!("[{}]", quoted.join(","))
+}
+
+fn parse_latest_prices(body: &str) -> Result<HashMap<String, f64>, PriceError> {
+    body.lines()
+        .filter(|line| !line.trim().is_empty())
+        .map(|row| {
+            serde_json::from_str::<AssetPriceRow>(row)
+                .map(|row| (row.pubkey, row.price))
+                .map_err(|e| PriceError::InvalidResponseError(e.to_string()))
+        })
+        .collect()
+}
+
+fn parse_volume(asset: &str, body: &str) -> Result<f64, PriceError> {
+    let Some(row) = body.lines().find(|line| !line.trim().is_empty()) else {
+        return Err(PriceError::NoVolumeError(asset.to_string()));
+    };
+    serde_json::from_str::<VolumeRow>(row)
+        .map(|row| row.volume)
+        .map_err(|e| PriceError::InvalidResponseError(e.to_string()))
+}
+
+/// The aggregate price of Pyth price accounts, read from the Solana RPC.
+/// Assets are mapped to their accounts by `PYTH_PRICE_ACCOUNTS`, e.g.
+/// `SOL=H6ARHf6YXhGYeQfUzQNGk6rDNnLBQKrenN712K4AQJEG`, others have no price.
+pub struct PythPriceSource {
+    http_client: reqwest::Client,
+    rpc_url: String,
+    accounts: HashMap<String, String>,
+    max_age_secs: u64,
+}
+
+// Pyth's magic number and the type of price accounts
+const PYTH_MAGIC: u32 = 0xa1b2c3d4;
+const PYTH_PRICE_ACCOUNT: u32 = 3;
+const PYTH_STATUS_TRADING: u32 = 1;
+
+// Offsets into a price account of the fields read
+const EXPO_OFFSET: usize = 20;
+const TIMESTAMP_OFFSET: usize = 96;
+const AGG_PRICE_OFFSET: usize = 208;
+const AGG_STATUS_OFFSET: usize = 224;
+
+impl PythPriceSource {
+    /// From `PYTH_PRICE_ACCOUNTS` and `SOLANA_RPC_URL`, `None` unless both
+    /// are set
+    pub fn from_config(config: &Config) -> Result<Option<Self>, PriceError> {
+        let (Some(accounts), Some(rpc_url)) = (
+            config.pyth_price_accounts.as_deref(),
+            config.solana_rpc_url.expose(),
+        ) else {
+            return Ok(None);
+        };
+        Ok(Some(Self {
+            http_client: reqwest::Client::builder()
+                .timeout(Duration::from_millis(PRICE_SOURCE_TIMEOUT_MS))
+                .build()?,
+            rpc_url: rpc_url.to_string(),
+            accounts: parse_price_accounts(accounts)?,
+            max_age_secs: config.pyth_max_price_age_secs,
+        }))
+    }
+}
+
+fn parse_price_accounts(accounts: &str) -> Result<HashMap<String, String>, PriceError> {
+    accounts
+        .split(',')
+        .map(str::trim)
+        .filter(|entry| !entry.is_empty())
+        .map(|entry| match entry.split_once('=') {
+            Some((asset, account)) if !asset.trim().is_empty() && !account.trim().is_empty() => {
+                Ok((asset.trim().to_string(), account.trim().to_string()))
+            }
+            _ => Err(PriceError::InvalidConfigError(format!(
+                "Invalid Pyth price account {:?}, expected asset=pubkey",
+                entry
+            ))),
+        })
+        .collect()
+}
+
+#[async_trait::async_trait]
+impl PriceSource for PythPriceSource {
+    async fn get_price(&self, asset: &str) -> Result<f64, PriceError> {
+        let account = self
+            .accounts
+            .get(asset)
+            .ok_or_else(|| PriceError::NoPriceError(asset.to_string()))?;
+        let response = self
+            .rpc(
+                "getAccountInfo",
+                serde_json::json!([account, { "encoding": "base64" }]),
+            )
+            .await?;
+        self.parse_account_value(
+            account,
+            &response["result"]["value"],
+            chrono::Utc::now().timestamp(),
+        )
+    }
+
+    /// Reads every account in one `getMultipleAccounts`
+    async fn get_prices(&self, assets: &[&str]) -> Result<HashMap<String, f64>, PriceError> {
+        let accounts: Vec<(&str, &String)> = assets
+            .iter()
+            .filter_map(|asset| Some((*asset, self.accounts.get(*asset)?)))
+            .collect();
+        if accounts.is_empty() {
+            return Ok(HashMap::new());
+        }
+        let keys: Vec<&String> = accounts.iter().map(|(_, account)| *account).collect();
+        let response = self
+            .rpc(
+                "getMultipleAccounts",
+                serde_json::json!([keys, { "encoding": "base64" }]),
+            )
+            .await?;
+        let values = response["result"]["value"].as_array().ok_or_else(|| {
+            PriceError::InvalidResponseError(format!("No Pyth accounts: {}", response))
+        })?;
+        let now = chrono::Utc::now().timestamp();
+        Ok(accounts
+            .iter()
+            .zip(values)
+            .filter_map(|((asset, account), value)| {
+                match self.parse_account_value(account, value, now) {
+                    Ok(price) => Some((asset.to_string(), price)),
+                    Err(e) => {
+                        tracing::warn!(%asset, "No Pyth price: {}", e);
+                        None
+                    }
+                }
+            })
+            .collect())
+    }
+}
+
+impl PythPriceSource {
+    async fn rpc(
+        &self,
+        method: &str,
+        params: serde_json::Value,
+    ) -> Result<serde_json::Value, PriceError> {
+        Ok(self
+            .http_client
+            .post(&self.rpc_url)
+            .json(&serde_json::json!({
+                "jsonrpc": "2.0",
+                "id": 1,
+                "method": method,
+                "params": params,
+            }))
+            .send()
+            .await?
+            .json()
+            .await?)
+    }
+
+    /// The price held by an account as returned by the RPC, base64 encoded
+    fn parse_account_value(
+        &self,
+        account: &str,
+        value: &serde_json::Value,
+        now: i64,
+    ) -> Result<f64, PriceError> {
+        let data = value["data"][0].as_str().ok_or_else(|| {
+            PriceError::InvalidResponseError(format!(
+                "No data for Pyth account {}: {}",
+                account, value
+            ))
+        })?;
+        let data = STANDARD
+            .decode(data)
+            .map_err(|e| PriceError::InvalidResponseError(e.to_string()))?;
+        parse_price_account(&data, now, self.max_age_secs)
+    }
+}
+
+/// The aggregate price scaled by the account's exponent, provided it is
+/// trading and was published at most `max_age_secs` before `now`
+fn parse_price_account(data: &[u8], now: i64, max_age_secs: u64) -> Result<f64, PriceError> {
+    let u32_at = |offset: usize| -> Option<u32> {
+        Some(u32::from_le_bytes(
+            data.get(offset..offset + 4)?.try_into().ok()?,
+        ))
+    };
+    let i64_at = |offset: usize| -> Option<i64> {
+        Some(i64::from_le_bytes(
+            data.get(offset..offset + 8)?.try_into().ok()?,
+        ))
+    };
+    let invalid = |reason: &str| PriceError::InvalidResponseError(reason.to_string());
+
+    if u32_at(0) != Some(PYTH_MAGIC) || u32_at(8) != Some(PYTH_PRICE_ACCOUNT) {
+        return Err(invalid("Not a Pyth price account"));
+    }
+    let (Some(expo), Some(timestamp), Some(price), Some(status)) = (
+        u32_at(EXPO_OFFSET).map(|expo| expo as i32),
+        i64_at(TIMESTAMP_OFFSET),
+        i64_at(AGG_PRICE_OFFSET),
+        u32_at(AGG_STATUS_OFFSET),
+    ) else {
+        return Err(invalid("Pyth price account too short"));
+    };
+    if status != PYTH_STATUS_TRADING {
+        return Err(PriceError::StalePriceError(format!(
+            "Pyth price is not trading, status {}",
+            status
+        )));
+    }
+    let age = now - timestamp;
+    if age > max_age_secs as i64 {
+        return Err(PriceError::StalePriceError(format!(
+            "Pyth price published {}s ago, at most {}s allowed",
+            age, max_age_secs
+        )));
+    }
+    Ok(price as f64 * 10f64.powi(expo))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_latest_price() {
+        assert_eq!(
+            parse_latest_price("SOL", "{\"price\":142.5}\n").unwrap(),
+            142.5
+        );
+        assert!(matches!(
+            parse_latest_price("SOL", ""),
+            Err(PriceError::NoPriceError(_))
+        ));
+        assert!(matches!(
+            parse_latest_price("SOL", "{\"price\":\"-\"}"),
+            Err(PriceError::InvalidResponseError(_))
+        ));
+    }
+
+    #[test]
+    fn test_parse_latest_prices() {
+        assert_eq!(array_param(&["SOL", "it's"]), "['SOL','it\\'s']");
+        let prices = parse_latest_prices(
+            "{\"pubkey\":\"SOL\",\"price\":142.5}\n{\"pubkey\":\"ETH\",\"price\":2500}\n",
+        )
+        .unwrap();
+        assert_eq!(
+            prices,
+            HashMap::from([("SOL".to_string(), 142.5), ("ETH".to_string(), 2500.0)])
+        );
+        assert!(parse_latest_prices("").unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_parse_volume() {
+        assert_eq!(
+            parse_volume("SOL", "{\"volume\":1250000.5}\n").unwrap(),
+            1_250_000.5
+        );
+        assert!(matches!(
+            parse_volume("SOL", ""),
+            Err(PriceError::NoVolumeError(_))
+        ));
+    }
+
+    /// A price account as Pyth lays it out, with the fields read set and
+    /// the rest, publishers' components included, zeroed
+    fn pyth_account(expo: i32, price: i64, status: u32, timestamp: i64) -> Vec<u8> {
+        let mut data = vec![0u8; 3312];
+        data[0..4].copy_from_slice(&PYTH_MAGIC.to_le_bytes());
+        data[4..8].copy_from_slice(&2u32.to_le_bytes());
+        data[8..12].copy_from_slice(&PYTH_PRICE_ACCOUNT.to_le_bytes());
+        data[12..16].copy_from_slice(&3312u32.to_le_bytes());
+        data[EXPO_OFFSET..EXPO_OFFSET + 4].copy_from_slice(&expo.to_le_bytes());
+        data[TIMESTAMP_OFFSET..TIMESTAMP_OFFSET + 8].copy_from_slice(&timestamp.to_le_bytes());
+        data[AGG_PRICE_OFFSET..AGG_PRICE_OFFSET + 8].copy_from_slice(&price.to_le_bytes());
+        data[AGG_STATUS_OFFSET..AGG_STATUS_OFFSET + 4].copy_from_slice(&status.to_le_bytes());
+        data
+    }
+
+    #[test]
+    fn test_parse_pyth_price_account() {
+        let now = 1_700_000_000;
+        // SOL at $142.53012345
+        let data = pyth_account(-8, 14_253_012_345, PYTH_STATUS_TRADING, now - 5);
+        let price = parse_price_account(&data, now, 60).unwrap();
+        assert!((price - 142.53012345).abs() < 1e-9);
+
+        let stale = pyth_account(-8, 14_253_012_345, PYTH_STATUS_TRADING, now - 61);
+        assert!(matches!(
+            parse_price_account(&stale, now, 60),
+            Err(PriceError::StalePriceError(_))
+        ));
+        let halted = pyth_account(-8, 14_253_012_345, 0, now);
+        assert!(matches!(
+            parse_price_account(&halted, now, 60),
+            Err(PriceError::StalePriceError(_))
+        ));
+        assert!(matches!(
+            parse_price_account(&data[..100], now, 60),
+            Err(PriceError::InvalidResponseError(_))
+        ));
+        assert!(matches!(
+            parse_price_account(&[0u8; 3312], now, 60),
+            Err(PriceError::InvalidResponseError(_))
+        ));
+    }
+
+    #[test]
+    fn test_parse_price_accounts() {
+        let accounts =
+            parse_price_accounts("SOL=H6ARHf6YXhGYeQfUzQNGk6rDNnLBQKrenN712K4AQJEG, ").unwrap();
+        assert_eq!(
+            accounts["SOL"],
+            "H6ARHf6YXhGYeQfUzQNGk6rDNnLBQKrenN712K4AQJEG"
+        );
+        assert!(parse_price_accounts("SOL").is_err());
+        assert!(parse_price_accounts("=pubkey").is_err());
+    }
+
+    struct CountingSource(std::sync::atomic::AtomicUsize);
+
+    #[async_trait::async_trait]
+    impl PriceSource for CountingSource {
+        async fn get_price(&self, _asset: &str) -> Result<f64, PriceError> {
+            let calls = self.0.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+            tokio::time::sleep(Duration::from_millis(20)).await;
+            Ok(100.0 + calls as f64)
+        }
+    }
+
+    #[tokio::test]
+    async fn test_concurrent_lookups_share_one_fetch() {
+        let source = Arc::new(CountingSource(Default::default()));
+        let cached = CachedPriceSource::new(source.clone(), Duration::from_millis(100));
+        let calls = || source.0.load(std::sync::atomic::Ordering::SeqCst);
+
+        let prices = futures_util::future::join_all((0..10).map(|_| cached.get_price("SOL"))).await;
+        assert!(prices.iter().all(|price| *price.as_ref().unwrap() == 100.0));
+        assert_eq!(calls(), 1);
+
+        // other assets are fetched on their own
+        cached.get_price("ETH").await.unwrap();
+        assert_eq!(calls(), 2);
+
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert_eq!(cached.get_price("SOL").await.unwrap(), 102.0);
+        assert_eq!(calls(), 3);
+    }
+
+    /// Counts the batches it is asked for, pricing every asset at 100
+    #[derive(Default)]
+    struct BatchSource(Mutex<Vec<Vec<String>>>);
+
+    #[async_trait::async_trait]
+    impl PriceSource for BatchSource {
+        async fn get_price(&self, asset: &str) -> Result<f64, PriceError> {
+            panic!("{} looked up on its own", asset);
+        }
+
+        async fn get_prices(&self, assets: &[&str]) -> Result<HashMap<String, f64>, PriceError> {
+            self.0
+                .lock()
+                .unwrap()
+                .push(assets.iter().map(|asset| asset.to_string()).collect());
+            Ok(assets
+                .iter()
+                .map(|asset| (asset.to_string(), 100.0))
+                .collect())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_cached_batch_fetches_misses_once() {
+        let source = Arc::new(BatchSource::default());
+        let cached = CachedPriceSource::new(source.clone(), Duration::from_millis(100));
+
+        let prices = cached.get_prices(&["SOL", "ETH", "SOL"]).await.unwrap();
+        assert_eq!(prices.len(), 2);
+        // only the uncached asset is fetched
+        let prices = cached.get_prices(&["SOL", "BTC"]).await.unwrap();
+        assert_eq!(prices.len(), 2);
+        assert_eq!(
+            *source.0.lock().unwrap(),
+            vec![
+                vec!["ETH".to_string(), "SOL".to_string()],
+                vec!["BTC".to_string()]
+            ]
+        );
+        assert_eq!(cached.get_price("ETH").await.unwrap(), 100.0);
+    }
+
+    struct FailingSource;
+
+    #[async_trait::async_trait]
+    impl PriceSource for FailingSource {
+        async fn get_price(&self, asset: &str) -> Result<f64, PriceError> {
+            Err(PriceError::InvalidResponseError(asset.to_string()))
+        }
+
+        async fn get_prices(&self, _assets: &[&str]) -> Result<HashMap<String, f64>, PriceError> {
+            Err(PriceError::InvalidResponseError("down".to_string()))
+        }
+    }
+
+    #[tokio::test]
+    async fn test_sources_fail_only_when_every_source_failed() {
+        let failing = PriceSources(vec![Arc::new(FailingSource), Arc::new(FailingSource)]);
+        assert!(matches!(
+            failing.get_prices(&["SOL"]).await,
+            Err(PriceError::InvalidResponseError(_))
+        ));
+
+        let fallback = PriceSources(vec![
+            Arc::new(FailingSource),
+            Arc::new(BatchSource::default()),
+        ]);
+        assert_eq!(fallback.get_prices(&["SOL"]).await.unwrap().len(), 1);
+    }
+}
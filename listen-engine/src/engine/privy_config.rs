@@ -1,5 +1,7 @@
 use anyhow::Result;
 
+use crate::config::Config;
+
 #[derive(Clone)]
 pub struct PrivyConfig {
     pub(crate) app_id: String,
@@ -22,6 +24,21 @@ impl PrivyConfig {
 
         Ok(Self { app_id, app_secret })
     }
+
+    pub fn from_config(config: &Config) -> Result<Self, PrivyConfigError> {
+        let app_id = config
+            .privy_app_id
+            .clone()
+            .ok_or(PrivyConfigError::MissingEnvVar("PRIVY_APP_ID"))?;
+
+        let app_secret = config
+            .privy_app_secret
+            .expose()
+            .map(str::to_string)
+            .ok_or(PrivyConfigError::MissingEnvVar("PRIVY_APP_SECRET"))?;
+
+        Ok(Self { app_id, app_secret })
+    }
 }
 
 #[cfg(test)]
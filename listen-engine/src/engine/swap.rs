@@ -0,0 +1,172 @@
+use serde::{Deserialize, Serialize};
+
+/// Raydium swap executed through the swap service, amounts are in base units
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SwapOrder {
+    pub amm_pool: String,
+    pub input_mint: String,
+    pub output_mint: String,
+    pub amount: u64,
+    pub slippage: SlippageSpec,
+}
+
+impl SwapOrder {
+    /// Request body for the swap service with the slippage resolved
+    pub fn to_request(&self, slippage: u64) -> SwapRequest {
+        SwapRequest {
+            amm_pool: self.amm_pool.clone(),
+            input_mint: self.input_mint.clone(),
+            output_mint: self.output_mint.clone(),
+            amount: self.amount,
+            slippage,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SwapRequest {
+    pub amm_pool: String,
+    pub input_mint: String,
+    pub output_mint: String,
+    pub amount: u64,
+    pub slippage: u64, // bps
+}
+
+/// Fixed slippage is a plain number of bps; adaptive slippage widens the base
+/// by the asset's recent volatility at execution time
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum SlippageSpec {
+    Fixed(u64),
+    Adaptive {
+        base_bps: u64,
+        volatility_multiplier: f64,
+    },
+}
+
+impl SlippageSpec {
+    /// Resolves to bps given the recent prices of the asset, oldest first.
+    /// The result never exceeds `max_bps`
+    pub fn resolve(&self, recent_prices: &[f64], max_bps: u64) -> u64 {
+        let bps = match self {
+            SlippageSpec::Fixed(bps) => *bps,
+            SlippageSpec::Adaptive {
+                base_bps,
+                volatility_multiplier,
+            } => {
+                let widening = volatility_bps(recent_prices) * volatility_multiplier.max(0.0);
+                base_bps.saturating_add(widening.round() as u64)
+            }
+        };
+        bps.min(max_bps)
+    }
+}
+
+/// Price range over the window relative to the lowest price, in bps
+fn volatility_bps(recent_prices: &[f64]) -> f64 {
+    let (min, max) = recent_prices
+        .iter()
+        .filter(|p| p.is_finite() && **p > 0.0)
+        .fold((f64::MAX, 0.0_f64), |(min, max), p| {
+            (min.min(*p), max.max(*p))
+        });
+    if max <= min {
+        return 0.0;
+    }
+    (max - min) / min * 10_000.0
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum SwapOutcome {
+    Confirmed,
+    Failed(serde_json::Value),
+    Dropped,
+    /// Sent, but whether it landed wasn't known when the swap service
+    /// answered
+    Unknown,
+}
+
+/// Record of a submitted swap as reported by the swap service
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SwapResult {
+    pub signature: String,
+    pub input_mint: String,
+    pub output_mint: String,
+    pub in_amount: u64,
+    pub out_amount: u64,
+    pub outcome: SwapOutcome,
+    #[serde(default)]
+    pub endpoint: String, // rpc that reported the outcome
+    #[serde(default)]
+    pub confirmed_by: Vec<String>, // rpcs that agreed the swap confirmed
+    #[serde(default)]
+    pub wsol: WsolFlow,
+}
+
+/// SOL that went through temporary WSOL accounts during a swap, in lamports
+#[derive(Debug, Default, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct WsolFlow {
+    pub wrapped: u64,
+    pub unwrapped: u64,
+    pub rent_reclaimed: u64, // paid on open, returned on close
+}
+
+impl SwapResult {
+    /// Record of the order sent as `signature` without a known outcome
+    pub fn unknown(order: &SwapOrder, signature: String) -> Self {
+        Self {
+            signature,
+            input_mint: order.input_mint.clone(),
+            output_mint: order.output_mint.clone(),
+            in_amount: order.amount,
+            out_amount: 0,
+            outcome: SwapOutcome::Unknown,
+            endpoint: String::new(),
+            confirmed_by: vec![],
+            wsol: WsolFlow::default(),
+        }
+    }
+
+    pub fn is_confirmed(&self) -> bool {
+        matches!(self.outcome, SwapOutcome::Confirmed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ADAPTIVE: SlippageSpec = SlippageSpec::Adaptive {
+        base_bps: 50,
+        volatility_multiplier: 0.5,
+    };
+
+    #[test]
+    fn test_adaptive_slippage_widens_with_volatility() {
+        let calm = [100.0, 100.1, 99.9, 100.0];
+        let volatile = [100.0, 104.0, 100.0, 108.0];
+
+        let calm_bps = ADAPTIVE.resolve(&calm, 1_000);
+        let volatile_bps = ADAPTIVE.resolve(&volatile, 1_000);
+        assert!(calm_bps < volatile_bps);
+        // 8% range -> 800 bps * 0.5 on top of the base
+        assert_eq!(volatile_bps, 450);
+        assert_eq!(ADAPTIVE.resolve(&[], 1_000), 50);
+    }
+
+    #[test]
+    fn test_slippage_capped() {
+        let crash = [100.0, 50.0];
+        assert_eq!(ADAPTIVE.resolve(&crash, 300), 300);
+        assert_eq!(SlippageSpec::Fixed(500).resolve(&[], 300), 300);
+    }
+
+    #[test]
+    fn test_slippage_spec_serde() {
+        let fixed: SlippageSpec = serde_json::from_str("100").unwrap();
+        assert_eq!(fixed, SlippageSpec::Fixed(100));
+        let adaptive: SlippageSpec =
+            serde_json::from_str(r#"{"base_bps":50,"volatility_multiplier":0.5}"#).unwrap();
+        assert_eq!(adaptive, ADAPTIVE);
+    }
+}
@@ -1,3 +1,8 @@
+use std::time::Duration;
+
+use super::constants::{
+    ACTION_WEBHOOK_TIMEOUT_MS, HTTP_POOL_IDLE_TIMEOUT_SECS, HTTP_POOL_MAX_IDLE_PER_HOST,
+};
 use super::privy_config::PrivyConfig;
 use base64::{engine::general_purpose::STANDARD, Engine as _};
 
@@ -33,3 +38,39 @@ pub fn create_http_client(privy_config: &PrivyConfig) -> reqwest::Client {
         .build()
         .unwrap()
 }
+
+/// Client for the engine's outbound requests (actions, health probes), built
+/// once and shared so connections are pooled: each request is bounded by
+/// `ACTION_WEBHOOK_TIMEOUT_MS`, and up to `HTTP_POOL_MAX_IDLE_PER_HOST` idle
+/// connections per host are kept for `HTTP_POOL_IDLE_TIMEOUT_SECS`
+pub fn create_shared_http_client() -> reqwest::Result<reqwest::Client> {
+    reqwest::Client::builder()
+        .timeout(Duration::from_millis(ACTION_WEBHOOK_TIMEOUT_MS))
+        .pool_max_idle_per_host(HTTP_POOL_MAX_IDLE_PER_HOST)
+        .pool_idle_timeout(Duration::from_secs(HTTP_POOL_IDLE_TIMEOUT_SECS))
+        .build()
+}
+
+/// Applies a JSON merge patch (RFC 7396): objects are merged recursively,
+/// `null` removes a field and anything else replaces the target value
+pub fn merge_patch(target: &mut serde_json::Value, patch: &serde_json::Value) {
+    let serde_json::Value::Object(patch) = patch else {
+        *target = patch.clone();
+        return;
+    };
+    if !target.is_object() {
+        *target = serde_json::Value::Object(Default::default());
+    }
+    if let serde_json::Value::Object(target) = target {
+        for (key, value) in patch {
+            if value.is_null() {
+                target.remove(key);
+            } else {
+                merge_patch(
+                    target.entry(key.clone()).or_insert(serde_json::Value::Null),
+                    value,
+                );
+            }
+        }
+    }
+}
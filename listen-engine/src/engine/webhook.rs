@@ -0,0 +1,446 @@
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+use tokio::sync::mpsc;
+use uuid::Uuid;
+
+use crate::config::Config;
+
+use super::constants::{
+    ACTION_WEBHOOK_MAX_ATTEMPTS, DISCORD_MAX_CONTENT_CHARS, DISCORD_MAX_RETRY_AFTER_MS,
+    TELEGRAM_MAX_ATTEMPTS, WEBHOOK_BACKOFF_MS, WEBHOOK_MAX_ATTEMPTS, WEBHOOK_QUEUE_LEN,
+};
+use super::executor::ExecutorError;
+use super::pipeline::{ConditionType, Discord, Telegram, Webhook};
+use super::swap::SwapResult;
+
+/// Posts every swap result to `SWAP_CONFIRMATION_WEBHOOK` from a background
+/// task, so that a slow webhook never holds up execution
+pub struct SwapWebhook {
+    tx: mpsc::Sender<SwapResult>,
+}
+
+impl SwapWebhook {
+    pub fn from_config(config: &Config) -> Option<Self> {
+        let url = config.swap_confirmation_webhook.expose()?.to_string();
+        Some(Self::spawn(
+            url,
+            WEBHOOK_QUEUE_LEN,
+            Duration::from_millis(WEBHOOK_BACKOFF_MS),
+        ))
+    }
+
+    pub fn spawn(url: String, queue_len: usize, backoff: Duration) -> Self {
+        let (tx, mut rx) = mpsc::channel::<SwapResult>(queue_len);
+        tokio::spawn(async move {
+            let client = reqwest::Client::new();
+            while let Some(result) = rx.recv().await {
+                deliver(&client, &url, &result, backoff).await;
+            }
+        });
+        Self { tx }
+    }
+
+    /// Queues the result for delivery, dropping it if the queue is full
+    pub fn notify(&self, result: &SwapResult) {
+        if let Err(e) = self.tx.try_send(result.clone()) {
+            metrics::counter!("swap_webhook_dropped", 1);
+            tracing::warn!(signature = %result.signature, "Swap webhook queue: {}", e);
+        }
+    }
+}
+
+/// Posts the result, retrying with exponential backoff
+async fn deliver(client: &reqwest::Client, url: &str, result: &SwapResult, backoff: Duration) {
+    for attempt in 0..WEBHOOK_MAX_ATTEMPTS {
+        if attempt > 0 {
+            tokio::time::sleep(backoff * 2u32.pow(attempt - 1)).await;
+        }
+        match client.post(url).json(result).send().await {
+            Ok(response) if response.status().is_success() => {
+                metrics::counter!("swap_webhook_delivered", 1);
+                return;
+            }
+            Ok(response) => {
+                tracing::warn!(signature = %result.signature, status = %response.status(), attempt, "Swap webhook rejected");
+            }
+            Err(e) => {
+                tracing::warn!(signature = %result.signature, attempt, "Swap webhook failed: {}", e);
+            }
+        }
+    }
+    metrics::counter!("swap_webhook_failed", 1);
+    tracing::error!(signature = %result.signature, "Swap webhook gave up");
+}
+
+/// Body posted by `Action::Webhook`, `conditions` are those of the step
+/// that fired
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StepWebhookEvent {
+    pub pipeline_id: Uuid,
+    pub step_id: Uuid,
+    pub conditions: Vec<ConditionType>,
+    pub message: String,
+}
+
+/// Posts the event to the webhook, retrying server errors and failed
+/// connections with exponential backoff. Any other response is final,
+/// running out of attempts is an `ActionUnavailableError`.
+pub async fn post_step_webhook(
+    client: &reqwest::Client,
+    webhook: &Webhook,
+    event: &StepWebhookEvent,
+    backoff: Duration,
+) -> Result<(), ExecutorError> {
+    let mut error = String::new();
+    for attempt in 0..ACTION_WEBHOOK_MAX_ATTEMPTS {
+        if attempt > 0 {
+            tokio::time::sleep(backoff * 2u32.pow(attempt - 1)).await;
+        }
+        let mut request = client.post(&webhook.url).json(event);
+        for (name, value) in &webhook.headers {
+            request = request.header(name, value);
+        }
+        match request.send().await {
+            Ok(response) if response.status().is_success() => return Ok(()),
+            Ok(response) if response.status().is_server_error() => {
+                tracing::warn!(step_id = %event.step_id, status = %response.status(), attempt, "Step webhook rejected");
+                error = format!("{} responded {}", webhook.url, response.status());
+            }
+            Ok(response) => {
+                return Err(ExecutorError::WebhookError(format!(
+                    "{} responded {}",
+                    webhook.url,
+                    response.status()
+                )));
+            }
+            Err(e) => {
+                tracing::warn!(step_id = %event.step_id, attempt, "Step webhook failed: {}", e);
+                error = e.to_string();
+            }
+        }
+    }
+    Err(ExecutorError::ActionUnavailableError(error))
+}
+
+/// Sends `text` with the Bot API's `sendMessage` under `api_url`, retrying
+/// once if Telegram is unreachable, rate limiting or failing, then giving
+/// up with an `ActionUnavailableError`. Any other response is final. The token is left out of every error, it is part of
+/// the request URL.
+pub async fn send_telegram_message(
+    client: &reqwest::Client,
+    api_url: &str,
+    telegram: &Telegram,
+    text: &str,
+    backoff: Duration,
+) -> Result<(), ExecutorError> {
+    let token = telegram.bot_token().map_err(ExecutorError::TelegramError)?;
+    let url = format!("{}/bot{}/sendMessage", api_url.trim_end_matches('/'), token);
+    let body = serde_json::json!({ "chat_id": telegram.chat_id, "text": text });
+    let mut error = String::new();
+    for attempt in 0..TELEGRAM_MAX_ATTEMPTS {
+        if attempt > 0 {
+            tokio::time::sleep(backoff).await;
+        }
+        match client.post(&url).json(&body).send().await {
+            Ok(response) if response.status().is_success() => return Ok(()),
+            Ok(response)
+                if response.status().is_server_error()
+                    || response.status() == reqwest::StatusCode::TOO_MANY_REQUESTS =>
+            {
+                tracing::warn!(chat_id = %telegram.chat_id, status = %response.status(), attempt, "Telegram message rejected");
+                error = format!("Telegram responded {}", response.status());
+            }
+            Ok(response) => {
+                return Err(ExecutorError::TelegramError(format!(
+                    "Telegram responded {}",
+                    response.status()
+                )));
+            }
+            Err(e) => {
+                let e = e.without_url();
+                tracing::warn!(chat_id = %telegram.chat_id, attempt, "Telegram message failed: {}", e);
+                error = e.to_string();
+            }
+        }
+    }
+    Err(ExecutorError::ActionUnavailableError(error))
+}
+
+/// Posts `content` to the Discord webhook. A rate limit is waited out and
+/// retried once if Discord asks for at most `DISCORD_MAX_RETRY_AFTER_MS`,
+/// any other response is final. The webhook URL holds its token, so it is
+/// left out of every error.
+pub async fn post_discord_message(
+    client: &reqwest::Client,
+    discord: &Discord,
+    content: &str,
+) -> Result<(), ExecutorError> {
+    let content: String = content.chars().take(DISCORD_MAX_CONTENT_CHARS).collect();
+    let mut body = serde_json::json!({ "content": content });
+    if let Some(username) = &discord.username {
+        body["username"] = username.clone().into();
+    }
+    let mut rate_limited = false;
+    loop {
+        let response = client
+            .post(&discord.webhook_url)
+            .json(&body)
+            .send()
+            .await
+            .map_err(|e| ExecutorError::RequestError(e.without_url()))?;
+        let status = response.status();
+        if status.is_success() {
+            return Ok(());
+        }
+        if status != reqwest::StatusCode::TOO_MANY_REQUESTS || rate_limited {
+            return Err(ExecutorError::DiscordError(format!(
+                "Discord responded {}",
+                status
+            )));
+        }
+        let retry_after = retry_after(response).await;
+        if retry_after > Duration::from_millis(DISCORD_MAX_RETRY_AFTER_MS) {
+            return Err(ExecutorError::DiscordError(format!(
+                "Discord rate limited for {:?}",
+                retry_after
+            )));
+        }
+        tracing::warn!(?retry_after, "Discord webhook rate limited");
+        rate_limited = true;
+        tokio::time::sleep(retry_after).await;
+    }
+}
+
+/// How long a 429 from Discord asks to wait, from `retry_after` in the body
+/// or else the `Retry-After` header, in seconds
+async fn retry_after(response: reqwest::Response) -> Duration {
+    let header = response
+        .headers()
+        .get(reqwest::header::RETRY_AFTER)
+        .and_then(|value| value.to_str().ok()?.parse::<f64>().ok());
+    let body = response
+        .json::<serde_json::Value>()
+        .await
+        .ok()
+        .and_then(|body| body["retry_after"].as_f64());
+    Duration::try_from_secs_f64(body.or(header).unwrap_or(0.0).max(0.0)).unwrap_or(Duration::MAX)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::swap::SwapOutcome;
+    use std::collections::HashMap;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+
+    /// Accepts a single request, answers with `status` and returns its body
+    async fn receive(listener: &TcpListener, status: &str) -> String {
+        respond(listener, status, "").await
+    }
+
+    /// Accepts a single request, answers with `status` and the JSON `body`
+    /// and returns the request's body
+    async fn respond(listener: &TcpListener, status: &str, response_body: &str) -> String {
+        let (mut stream, _) = listener.accept().await.unwrap();
+        let mut request = Vec::new();
+        let mut buf = [0u8; 4096];
+        loop {
+            let n = stream.read(&mut buf).await.unwrap();
+            request.extend_from_slice(&buf[..n]);
+            let text = String::from_utf8_lossy(&request);
+            if let Some((head, body)) = text.split_once("\r\n\r\n") {
+                let content_length = head
+                    .lines()
+                    .find_map(|line| {
+                        let (name, value) = line.split_once(':')?;
+                        name.eq_ignore_ascii_case("content-length")
+                            .then(|| value.trim().parse::<usize>().ok())?
+                    })
+                    .unwrap_or(0);
+                if body.len() >= content_length {
+                    let response = format!(
+                        "HTTP/1.1 {}\r\ncontent-type: application/json\r\ncontent-length: {}\r\n\r\n{}",
+                        status,
+                        response_body.len(),
+                        response_body
+                    );
+                    stream.write_all(response.as_bytes()).await.unwrap();
+                    return body.to_string();
+                }
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_confirmed_swap_is_posted() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/swaps", listener.local_addr().unwrap());
+        let webhook = SwapWebhook::spawn(url, 8, Duration::from_millis(10));
+
+        webhook.notify(&SwapResult {
+            signature: "5xSig".to_string(),
+            input_mint: "So11111111111111111111111111111111111111112".to_string(),
+            output_mint: "Cn5Ne1vmR9ctMGY9z5NC71A3NYFvopjXNyxYtfVYpump".to_string(),
+            in_amount: 1_000_000_000,
+            out_amount: 500_000,
+            outcome: SwapOutcome::Confirmed,
+            endpoint: "-".to_string(),
+            confirmed_by: vec![],
+            wsol: Default::default(),
+        });
+
+        // the first attempt fails, the retry delivers the same payload
+        let first = receive(&listener, "503 Service Unavailable").await;
+        let second = receive(&listener, "200 OK").await;
+        assert_eq!(first, second);
+
+        let posted: SwapResult = serde_json::from_str(&second).unwrap();
+        assert_eq!(posted.signature, "5xSig");
+        assert_eq!(posted.in_amount, 1_000_000_000);
+        assert_eq!(posted.out_amount, 500_000);
+        assert!(posted.is_confirmed());
+    }
+
+    #[tokio::test]
+    async fn test_step_webhook_retries_server_errors() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let webhook = Webhook {
+            url: format!("http://{}/hook", listener.local_addr().unwrap()),
+            headers: HashMap::from([("x-token".to_string(), "secret".to_string())]),
+            template: "-".to_string(),
+        };
+        let event = StepWebhookEvent {
+            pipeline_id: Uuid::new_v4(),
+            step_id: Uuid::new_v4(),
+            conditions: vec![],
+            message: "step fired".to_string(),
+        };
+        let client = reqwest::Client::new();
+        let post = post_step_webhook(&client, &webhook, &event, Duration::from_millis(10));
+        let responses = async {
+            let first = receive(&listener, "502 Bad Gateway").await;
+            let second = receive(&listener, "200 OK").await;
+            (first, second)
+        };
+        let (result, (first, second)) = tokio::join!(post, responses);
+        assert!(result.is_ok());
+        assert_eq!(first, second);
+        let posted: StepWebhookEvent = serde_json::from_str(&second).unwrap();
+        assert_eq!(posted.step_id, event.step_id);
+        assert_eq!(posted.message, "step fired");
+
+        // a client error is final
+        let post = post_step_webhook(&client, &webhook, &event, Duration::from_millis(10));
+        let (result, _) = tokio::join!(post, receive(&listener, "404 Not Found"));
+        assert!(matches!(result, Err(ExecutorError::WebhookError(_))));
+    }
+
+    #[tokio::test]
+    async fn test_telegram_message_is_posted() {
+        std::env::set_var("TELEGRAM_BOT_TOKEN_WEBHOOK_TEST", "123:abc");
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let api_url = format!("http://{}", listener.local_addr().unwrap());
+        let telegram = Telegram {
+            chat_id: "-1001234".to_string(),
+            bot_token_ref: "webhook_test".to_string(),
+            template: None,
+        };
+        let client = reqwest::Client::new();
+
+        // a failing Telegram is retried once
+        let send = send_telegram_message(
+            &client,
+            &api_url,
+            &telegram,
+            "SOL below 100",
+            Duration::from_millis(10),
+        );
+        let responses = async {
+            let first = receive(&listener, "502 Bad Gateway").await;
+            let second = receive(&listener, "200 OK").await;
+            (first, second)
+        };
+        let (result, (first, second)) = tokio::join!(send, responses);
+        assert!(result.is_ok());
+        assert_eq!(first, second);
+        let posted: serde_json::Value = serde_json::from_str(&second).unwrap();
+        assert_eq!(posted["chat_id"], "-1001234");
+        assert_eq!(posted["text"], "SOL below 100");
+
+        // a rejected message fails the step without a second attempt
+        let send = send_telegram_message(
+            &client,
+            &api_url,
+            &telegram,
+            "SOL below 100",
+            Duration::from_millis(10),
+        );
+        let (result, _) = tokio::join!(send, receive(&listener, "400 Bad Request"));
+        let Err(ExecutorError::TelegramError(error)) = result else {
+            panic!("expected a Telegram error");
+        };
+        assert!(!error.contains("123:abc"));
+
+        // so does a token that isn't configured
+        let unknown = Telegram {
+            bot_token_ref: "webhook_test_unset".to_string(),
+            ..telegram
+        };
+        let result =
+            send_telegram_message(&client, &api_url, &unknown, "-", Duration::from_millis(10))
+                .await;
+        assert!(matches!(result, Err(ExecutorError::TelegramError(_))));
+    }
+
+    #[tokio::test]
+    async fn test_discord_rate_limit_is_waited_out() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let discord = Discord {
+            webhook_url: format!(
+                "http://{}/api/webhooks/1/token",
+                listener.local_addr().unwrap()
+            ),
+            username: Some("listen".to_string()),
+            content_template: "-".to_string(),
+        };
+        let client = reqwest::Client::new();
+
+        let started = std::time::Instant::now();
+        let post = post_discord_message(&client, &discord, "SOL at 99.5");
+        let responses = async {
+            let first = respond(
+                &listener,
+                "429 Too Many Requests",
+                r#"{"message": "You are being rate limited.", "retry_after": 0.2, "global": false}"#,
+            )
+            .await;
+            let second = receive(&listener, "204 No Content").await;
+            (first, second)
+        };
+        let (result, (first, second)) = tokio::join!(post, responses);
+        assert!(result.is_ok());
+        assert!(started.elapsed() >= Duration::from_millis(200));
+        assert_eq!(first, second);
+        let posted: serde_json::Value = serde_json::from_str(&second).unwrap();
+        assert_eq!(posted["content"], "SOL at 99.5");
+        assert_eq!(posted["username"], "listen");
+
+        // only one retry
+        let post = post_discord_message(&client, &discord, "-");
+        let limited = r#"{"retry_after": 0.01}"#;
+        let responses = async {
+            respond(&listener, "429 Too Many Requests", limited).await;
+            respond(&listener, "429 Too Many Requests", limited).await;
+        };
+        let (result, _) = tokio::join!(post, responses);
+        assert!(matches!(result, Err(ExecutorError::DiscordError(_))));
+
+        // nor is a long rate limit waited out
+        let post = post_discord_message(&client, &discord, "-");
+        let limited = r#"{"retry_after": 60}"#;
+        let (result, _) = tokio::join!(post, respond(&listener, "429 Too Many Requests", limited));
+        assert!(matches!(result, Err(ExecutorError::DiscordError(_))));
+    }
+}
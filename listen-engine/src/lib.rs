@@ -1,4 +1,6 @@
+pub mod config;
 pub mod engine;
+pub mod logging;
 pub mod metrics;
 pub mod redis;
 pub mod server;
@@ -8,14 +8,41 @@ static PROMETHEUS_HANDLE: OnceCell<PrometheusHandle> = OnceCell::new();
 pub enum MetricsError {
     #[error("Failed to install metrics recorder")]
     InstallRecorderError(BuildError),
+
+    #[error("Invalid global label {0:?}, expected key=value")]
+    InvalidGlobalLabelError(String),
 }
 
-pub fn setup_metrics_exporter() -> Result<PrometheusHandle, MetricsError> {
+/// Labels from `METRICS_GLOBAL_LABELS`, e.g. `region=us-east,env=prod`
+fn parse_global_labels(labels: &str) -> Result<Vec<(String, String)>, MetricsError> {
+    labels
+        .split(',')
+        .map(str::trim)
+        .filter(|label| !label.is_empty())
+        .map(|label| match label.split_once('=') {
+            Some((key, value)) if !key.trim().is_empty() => {
+                Ok((key.trim().to_string(), value.trim().to_string()))
+            }
+            _ => Err(MetricsError::InvalidGlobalLabelError(label.to_string())),
+        })
+        .collect()
+}
+
+fn metrics_builder(global_labels: &str) -> Result<PrometheusBuilder, MetricsError> {
     // Create Prometheus recorder
     let builder = PrometheusBuilder::new();
 
-    // Add global labels if needed
+    // Labels attached to every metric, so deployments can be told apart
     let builder = builder.add_global_label("service", "listen-engine");
+    Ok(parse_global_labels(global_labels)?
+        .into_iter()
+        .fold(builder, |builder, (key, value)| {
+            builder.add_global_label(key, value)
+        }))
+}
+
+pub fn setup_metrics_exporter() -> Result<PrometheusHandle, MetricsError> {
+    let builder = metrics_builder(&std::env::var("METRICS_GLOBAL_LABELS").unwrap_or_default())?;
 
     // Install global recorder
     let handle = builder
@@ -49,10 +76,106 @@ pub fn init_metrics() {
         "price_update_duration",
         "Time taken to process price updates"
     );
+    metrics::describe_counter!(
+        "pipeline_creation_timeouts",
+        "Number of pipeline creations the engine did not answer in time"
+    );
     metrics::describe_counter!("pipeline_evaluations", "Number of pipeline evaluations");
     metrics::describe_histogram!(
         "pipeline_evaluation_duration",
         "Time taken to evaluate pipelines"
     );
-    metrics::describe_gauge!("active_pipelines", "Number of active pipelines");
+    metrics::describe_counter!(
+        "swap_action_errors",
+        "Number of swap actions that failed or did not land"
+    );
+    metrics::describe_counter!(
+        "pipelines_expired",
+        "Number of pipelines removed after their expires_at"
+    );
+    metrics::describe_counter!(
+        "pipeline_save_conflicts",
+        "Number of pipeline saves over a newer stored version"
+    );
+    metrics::describe_counter!(
+        "action_retries",
+        "Number of failed actions scheduled to be attempted again"
+    );
+    metrics::describe_counter!(
+        "dry_run_triggers",
+        "Number of steps of dry-run pipelines that would have fired"
+    );
+    metrics::describe_counter!(
+        "condition_evaluations",
+        "Number of condition evaluations, by condition_type"
+    );
+    metrics::describe_histogram!(
+        "condition_evaluation_duration",
+        "Time taken to evaluate a condition, by condition_type"
+    );
+    metrics::describe_counter!(
+        "condition_evaluation_errors",
+        "Number of conditions that could not be evaluated, e.g. for lack of a price, by condition_type"
+    );
+    metrics::describe_counter!(
+        "price_cache_hits",
+        "Number of price source lookups answered from the cache"
+    );
+    metrics::describe_counter!(
+        "price_cache_misses",
+        "Number of price source lookups fetched upstream, the hit ratio is hits / (hits + misses)"
+    );
+    metrics::describe_gauge!(
+        "active_pipelines",
+        "Number of pipelines yet to complete, fail or expire"
+    );
+    metrics::describe_gauge!("pipelines", "Number of pipelines in the engine, by status");
+    metrics::describe_gauge!(
+        "engine_queue_depth",
+        "Number of requests waiting in the engine's queue"
+    );
+    metrics::describe_counter!(
+        "engine_queue_saturated",
+        "Number of requests refused with 503 because the engine's queue was full"
+    );
+    metrics::describe_counter!(
+        "redis_reconnects",
+        "Number of times the Redis connection pool was rebuilt after losing Redis"
+    );
+    metrics::describe_counter!(
+        "pipeline_leases_acquired",
+        "Number of pipeline leases taken over, from an expired owner or none"
+    );
+    metrics::describe_counter!(
+        "pipelines_adopted",
+        "Number of pipelines loaded from Redis that another instance created"
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use metrics::{Key, Recorder};
+
+    #[test]
+    fn test_global_labels() {
+        let recorder = metrics_builder("region=us-east, env=prod")
+            .unwrap()
+            .build_recorder();
+        recorder
+            .register_counter(&Key::from_name("pipeline_creation_success"))
+            .increment(1);
+
+        let rendered = recorder.handle().render();
+        let line = rendered
+            .lines()
+            .find(|line| line.starts_with("pipeline_creation_success"))
+            .unwrap();
+        assert!(line.contains(r#"region="us-east""#));
+        assert!(line.contains(r#"env="prod""#));
+        assert!(line.contains(r#"service="listen-engine""#));
+
+        assert!(metrics_builder("region").is_err());
+        assert!(metrics_builder("=us-east").is_err());
+    }
 }
@@ -1,60 +1,272 @@
 // TODO! this should be a listen-redis create (the base) and each tenant can add
 // their own commands to proc
+use crate::engine::constants::PIPELINE_SAVE_ATTEMPTS;
+use crate::engine::dlq::DeadLetter;
+use crate::engine::history::ExecutionEvent;
 use crate::engine::pipeline::Pipeline;
 use anyhow::Result;
 use bb8_redis::{
-    bb8::{self, PooledConnection},
-    redis::{cmd, pipe},
+    bb8::{self, ManageConnection, PooledConnection},
+    redis::{cmd, pipe, RedisError},
     RedisConnectionManager,
 };
-use serde::{de::DeserializeOwned, Serialize};
-use std::sync::Arc;
-use tracing::{debug, warn};
+use metrics::counter;
+use serde::de::DeserializeOwned;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, Instant};
+use tracing::{debug, error, info, warn};
+use uuid::Uuid;
 
 const PIPELINE_BATCH_SIZE: usize = 1000;
 
+// Saves a pipeline unless the stored one moved past the version it was read
+// at (ARGV[2]), then sets its expiry (ARGV[4], if any) and indexes it
+// (ARGV[3]) under its user. Returns 0 on a conflict.
+const SAVE_PIPELINE_SCRIPT: &str = r"
+local stored = redis.call('GET', KEYS[1])
+if stored and (cjson.decode(stored)['version'] or 0) ~= tonumber(ARGV[2]) then
+    return 0
+end
+redis.call('SET', KEYS[1], ARGV[1])
+if ARGV[4] ~= '' then
+    redis.call('PEXPIREAT', KEYS[1], ARGV[4])
+end
+redis.call('SADD', KEYS[2], ARGV[3])
+return 1
+";
+
+// How long a deleted pipeline is remembered, so deletes can be repeated
+const DELETED_PIPELINE_TTL_SECS: u64 = 7 * 24 * 60 * 60;
+
+const DEFAULT_POOL_MAX_SIZE: u32 = 16;
+const DEFAULT_POOL_MIN_IDLE: u32 = 4;
+const DEFAULT_POOL_CONNECTION_TIMEOUT_MS: u64 = 5_000;
+
+// Wait before rebuilding the pool again after a failed rebuild, doubling
+// with each failure up to the max
+const RECONNECT_BACKOFF_MS: u64 = 100;
+const MAX_RECONNECT_BACKOFF_MS: u64 = 10_000;
+
+type Pool = bb8::Pool<RedisConnectionManager>;
+
 pub struct RedisClient {
-    pool: bb8::Pool<RedisConnectionManager>,
+    redis_url: String,
+    pool_config: PoolConfig,
+    pool: RwLock<Pool>,
+    // Bumped each time the pool is rebuilt, so a caller that failed on an
+    // old pool doesn't rebuild the new one
+    generation: AtomicU64,
+    reconnect: tokio::sync::Mutex<Reconnect>,
+}
+
+#[derive(Default)]
+struct Reconnect {
+    failures: u32,
+    next_attempt_at: Option<Instant>,
+}
+
+impl Reconnect {
+    fn backoff(&self) -> Duration {
+        let backoff = RECONNECT_BACKOFF_MS.saturating_mul(1 << self.failures.min(16));
+        Duration::from_millis(backoff.min(MAX_RECONNECT_BACKOFF_MS))
+    }
+}
+
+/// Size of the connection pool, `min_idle` connections are kept open and at
+/// most `max_size` are handed out at once, waiting at most
+/// `connection_timeout_ms` for one
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PoolConfig {
+    pub max_size: u32,
+    pub min_idle: u32,
+    pub connection_timeout_ms: u64,
+}
+
+impl Default for PoolConfig {
+    fn default() -> Self {
+        Self {
+            max_size: DEFAULT_POOL_MAX_SIZE,
+            min_idle: DEFAULT_POOL_MIN_IDLE,
+            connection_timeout_ms: DEFAULT_POOL_CONNECTION_TIMEOUT_MS,
+        }
+    }
+}
+
+impl PoolConfig {
+    pub fn from_env() -> Self {
+        Self::from_vars(|name| std::env::var(name).ok())
+    }
+
+    /// Reads `REDIS_POOL_MAX_SIZE`, `REDIS_POOL_MIN_IDLE` and
+    /// `REDIS_CONNECTION_TIMEOUT_MS` through `var`, unparseable or zero values
+    /// fall back to the defaults and `min_idle` is capped at `max_size`
+    pub fn from_vars(var: impl Fn(&str) -> Option<String>) -> Self {
+        let max_size = var("REDIS_POOL_MAX_SIZE")
+            .and_then(|v| v.parse().ok())
+            .filter(|size| *size > 0)
+            .unwrap_or(DEFAULT_POOL_MAX_SIZE);
+        let min_idle = var("REDIS_POOL_MIN_IDLE")
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(DEFAULT_POOL_MIN_IDLE);
+        let connection_timeout_ms = var("REDIS_CONNECTION_TIMEOUT_MS")
+            .and_then(|v| v.parse().ok())
+            .filter(|ms| *ms > 0)
+            .unwrap_or(DEFAULT_POOL_CONNECTION_TIMEOUT_MS);
+        Self {
+            max_size,
+            min_idle: min_idle.min(max_size),
+            connection_timeout_ms,
+        }
+    }
 }
 
 #[derive(Debug, thiserror::Error)]
 pub enum RedisClientError {
     #[error("[Redis] Failed to connect: {0}")]
     ConnectionError(#[from] bb8::RunError<bb8_redis::redis::RedisError>),
+    /// Redis couldn't be reached or the connection dropped mid-command, as
+    /// opposed to Redis answering with an error
+    #[error("[Redis] Disconnected: {0}")]
+    Disconnected(String),
     #[error("[Redis] Failed to serialize: {0}")]
     SerializeError(#[from] serde_json::Error),
     #[error("[Redis] Failed to deserialize: {0}")]
     DeserializeError(serde_json::Error),
     #[error("[Redis] Redis error: {0}")]
-    RedisError(#[from] bb8_redis::redis::RedisError),
+    RedisError(bb8_redis::redis::RedisError),
+    #[error("[Redis] Pipeline {0} was saved by another writer since it was read")]
+    Conflict(String),
+}
+
+impl From<RedisError> for RedisClientError {
+    fn from(e: RedisError) -> Self {
+        if is_disconnect(&e) {
+            RedisClientError::Disconnected(e.to_string())
+        } else {
+            RedisClientError::RedisError(e)
+        }
+    }
+}
+
+/// Redis couldn't be reached or the connection dropped
+fn is_disconnect(e: &RedisError) -> bool {
+    e.is_connection_dropped() || e.is_connection_refusal() || e.is_io_error() || e.is_timeout()
 }
 
 impl RedisClient {
     pub async fn new(redis_url: &str) -> Result<Self, RedisClientError> {
-        let manager =
-            RedisConnectionManager::new(redis_url).map_err(|e| RedisClientError::RedisError(e))?;
-
-        let pool = bb8::Pool::builder()
-            .max_size(16)
-            .min_idle(Some(4))
-            .build(manager)
-            .await
-            .map_err(|e| RedisClientError::ConnectionError(e.into()))?;
+        Self::with_pool(redis_url, PoolConfig::default()).await
+    }
 
-        Ok(Self { pool })
+    pub async fn with_pool(
+        redis_url: &str,
+        pool_config: PoolConfig,
+    ) -> Result<Self, RedisClientError> {
+        let pool = build_pool(redis_url, pool_config).await?;
+        Ok(Self {
+            redis_url: redis_url.to_string(),
+            pool_config,
+            pool: RwLock::new(pool),
+            generation: AtomicU64::new(0),
+            reconnect: Default::default(),
+        })
     }
 
+    /// A connection from the pool. When none can be had because Redis
+    /// can't be reached, the pool is rebuilt, at most once per backoff, and
+    /// the connection is taken from the new one; if that fails too the
+    /// error is `Disconnected`. A pool out of connections is left as it is.
     pub async fn get_connection(
         &self,
-    ) -> Result<PooledConnection<'_, RedisConnectionManager>, RedisClientError> {
-        self.pool
-            .get()
+    ) -> Result<PooledConnection<'static, RedisConnectionManager>, RedisClientError> {
+        let generation = self.generation.load(Ordering::SeqCst);
+        let pool = self.pool.read().unwrap().clone();
+        let e = match pool.get_owned().await {
+            Ok(conn) => return Ok(conn),
+            Err(e) => e,
+        };
+        if !self.is_unreachable(&e).await {
+            // every connection is in use, a new pool wouldn't have more
+            counter!("redis_pool_exhausted", 1);
+            warn!(error = %e, "Redis connection pool exhausted");
+            return Err(RedisClientError::ConnectionError(e));
+        }
+        warn!(error = %e, "Failed to get a Redis connection");
+        if !self.reconnect(generation).await {
+            return Err(RedisClientError::Disconnected(e.to_string()));
+        }
+        let pool = self.pool.read().unwrap().clone();
+        pool.get_owned()
             .await
-            .map_err(RedisClientError::ConnectionError)
+            .map_err(|e| RedisClientError::Disconnected(e.to_string()))
+    }
+
+    /// Whether the pool failed for Redis being unreachable. The pool only
+    /// tells it timed out, which it also does with all of its connections
+    /// in use, so a connection of its own is tried then.
+    async fn is_unreachable(&self, e: &bb8::RunError<RedisError>) -> bool {
+        match e {
+            bb8::RunError::User(e) => is_disconnect(e),
+            bb8::RunError::TimedOut => {
+                let Ok(manager) = RedisConnectionManager::new(self.redis_url.as_str()) else {
+                    return false;
+                };
+                let timeout = Duration::from_millis(self.pool_config.connection_timeout_ms);
+                match tokio::time::timeout(timeout, manager.connect()).await {
+                    Ok(Ok(_)) => false,
+                    Ok(Err(e)) => is_disconnect(&e),
+                    Err(_) => true,
+                }
+            }
+        }
+    }
+
+    /// Replaces the pool with a new one, unless it was replaced since
+    /// `generation` or the last failed attempt is too recent. Returns
+    /// whether there is a new pool to try.
+    async fn reconnect(&self, generation: u64) -> bool {
+        // a rebuild already underway is as good as this one
+        let Ok(mut reconnect) = self.reconnect.try_lock() else {
+            return false;
+        };
+        if self.generation.load(Ordering::SeqCst) != generation {
+            return true;
+        }
+        if reconnect
+            .next_attempt_at
+            .is_some_and(|at| Instant::now() < at)
+        {
+            return false;
+        }
+        match build_pool(&self.redis_url, self.pool_config).await {
+            Ok(pool) => {
+                *self.pool.write().unwrap() = pool;
+                self.generation.fetch_add(1, Ordering::SeqCst);
+                counter!("redis_reconnects", 1);
+                info!(failures = reconnect.failures, "Reconnected to Redis");
+                *reconnect = Reconnect::default();
+                true
+            }
+            Err(e) => {
+                let backoff = reconnect.backoff();
+                reconnect.failures += 1;
+                reconnect.next_attempt_at = Some(Instant::now() + backoff);
+                error!(error = %e, failures = reconnect.failures, ?backoff, "Failed to reconnect to Redis");
+                false
+            }
+        }
+    }
+
+    pub async fn ping(&self) -> Result<(), RedisClientError> {
+        let mut conn = self.get_connection().await?;
+        let _: String = cmd("PING").query_async(&mut *conn).await?;
+        Ok(())
     }
 
-    async fn set<T: Serialize>(&self, key: &str, value: &T) -> Result<(), RedisClientError> {
-        let mut conn = self.pool.get().await?;
+    #[cfg(test)]
+    async fn set<T: serde::Serialize>(&self, key: &str, value: &T) -> Result<(), RedisClientError> {
+        let mut conn = self.get_connection().await?;
         let serialized = serde_json::to_string(value)?;
 
         let _: () = cmd("SET")
@@ -67,7 +279,7 @@ impl RedisClient {
     }
 
     async fn get<T: DeserializeOwned>(&self, key: &str) -> Result<Option<T>, RedisClientError> {
-        let mut conn = self.pool.get().await?;
+        let mut conn = self.get_connection().await?;
 
         let json_str: Option<String> = cmd("GET").arg(key).query_async(&mut *conn).await?;
 
@@ -81,41 +293,182 @@ impl RedisClient {
         self.get(&format!("pipeline:{}", key)).await
     }
 
-    pub async fn save_pipeline(&self, pipeline: &Pipeline) -> Result<(), RedisClientError> {
-        self.set(&format!("pipeline:{}", pipeline.id), pipeline)
-            .await
-    }
-
-    pub async fn get_all_pipelines(&self) -> Result<Vec<Pipeline>, RedisClientError> {
-        let mut conn = self.pool.get().await?;
+    /// Saves the pipeline and adds it to its user's index set, bumping its
+    /// `version`. If the stored pipeline is no longer at the version this
+    /// one was read at, nothing is written and the save is a `Conflict`;
+    /// read it again and reapply the change. A pipeline with `expires_at` is
+    /// dropped by Redis at that time, should the engine not get to it first.
+    pub async fn save_pipeline(&self, pipeline: &mut Pipeline) -> Result<(), RedisClientError> {
+        let read_version = pipeline.version;
+        pipeline.version += 1;
+        let value = serde_json::to_string(pipeline);
+        pipeline.version = read_version;
 
-        // Get all keys in one operation
-        let keys: Vec<String> = cmd("KEYS")
-            .arg("pipeline:*")
+        let mut conn = self.get_connection().await?;
+        let saved: i64 = cmd("EVAL")
+            .arg(SAVE_PIPELINE_SCRIPT)
+            .arg(2)
+            .arg(format!("pipeline:{}", pipeline.id))
+            .arg(user_pipelines_key(&pipeline.user_id))
+            .arg(value?)
+            .arg(read_version)
+            .arg(pipeline.id.to_string())
+            .arg(
+                pipeline
+                    .expires_at
+                    .map(|at| at.timestamp_millis().to_string())
+                    .unwrap_or_default(),
+            )
             .query_async(&mut *conn)
             .await?;
+        if saved == 0 {
+            return Err(RedisClientError::Conflict(pipeline.id.to_string()));
+        }
+        pipeline.version += 1;
+        Ok(())
+    }
 
-        // Use Redis pipeline for bulk get
-        let mut pipe = pipe();
-        for key in &keys {
-            pipe.get(key);
+    /// Read-modify-write of the stored pipeline, `update` is applied again
+    /// to a fresh read whenever another write gets in between. `None` if
+    /// there is no such pipeline.
+    pub async fn update_pipeline(
+        &self,
+        id: &str,
+        mut update: impl FnMut(&mut Pipeline),
+    ) -> Result<Option<Pipeline>, RedisClientError> {
+        for _ in 0..PIPELINE_SAVE_ATTEMPTS {
+            let Some(mut pipeline) = self.get_pipeline(id).await? else {
+                return Ok(None);
+            };
+            update(&mut pipeline);
+            match self.save_pipeline(&mut pipeline).await {
+                Ok(()) => return Ok(Some(pipeline)),
+                Err(RedisClientError::Conflict(_)) => continue,
+                Err(e) => return Err(e),
+            }
         }
+        Err(RedisClientError::Conflict(id.to_string()))
+    }
 
-        let results: Vec<Option<String>> = pipe.query_async(&mut *conn).await?;
+    /// Page through the user's pipelines with `SSCAN` over their index set.
+    /// `cursor` is the scan cursor and how many of the scan batch were
+    /// already returned; the returned cursor is `None` once exhausted.
+    /// Pipelines Redis dropped at their `expires_at` are taken out of the
+    /// index as they are come across.
+    pub async fn list_user_pipelines(
+        &self,
+        user_id: &str,
+        limit: usize,
+        cursor: (u64, usize),
+    ) -> Result<(Vec<Pipeline>, Option<(u64, usize)>), RedisClientError> {
+        let mut conn = self.get_connection().await?;
+        let key = user_pipelines_key(user_id);
+        let (mut scan_cursor, mut skip) = cursor;
+        let mut ids: Vec<String> = Vec::with_capacity(limit);
+        let next_cursor = loop {
+            let (next, batch): (u64, Vec<String>) = cmd("SSCAN")
+                .arg(&key)
+                .arg(scan_cursor)
+                .arg("COUNT")
+                .arg(limit)
+                .query_async(&mut *conn)
+                .await?;
+            // SSCAN may return more than COUNT, the rest of the batch is
+            // picked up from the same scan cursor on the next page
+            let batch: Vec<String> = batch.into_iter().skip(skip).collect();
+            let room = limit - ids.len();
+            if batch.len() > room {
+                ids.extend(batch.into_iter().take(room));
+                break Some((scan_cursor, skip + room));
+            }
+            ids.extend(batch);
+            skip = 0;
+            if next == 0 {
+                break None;
+            }
+            scan_cursor = next;
+            if ids.len() == limit {
+                break Some((scan_cursor, 0));
+            }
+        };
+
+        if ids.is_empty() {
+            return Ok((vec![], next_cursor));
+        }
+        let keys: Vec<String> = ids.iter().map(|id| format!("pipeline:{}", id)).collect();
+        let results: Vec<Option<String>> = cmd("MGET").arg(keys).query_async(&mut *conn).await?;
 
         let mut pipelines = Vec::with_capacity(results.len());
-        for json_str in results.into_iter().flatten() {
+        let mut expired = Vec::new();
+        for (id, result) in ids.into_iter().zip(results) {
+            let Some(json_str) = result else {
+                expired.push(id);
+                continue;
+            };
             match serde_json::from_str(&json_str) {
                 Ok(pipeline) => pipelines.push(pipeline),
                 Err(e) => warn!("Failed to deserialize pipeline: {}", e),
             }
         }
+        if !expired.is_empty() {
+            let _: () = cmd("SREM")
+                .arg(&key)
+                .arg(expired)
+                .query_async(&mut *conn)
+                .await?;
+        }
+        Ok((pipelines, next_cursor))
+    }
+
+    /// Every `pipeline:*` key, found with `SCAN` so Redis is never blocked
+    /// for the whole keyspace; `count` is the `COUNT` hint of each call
+    pub async fn scan_pipeline_keys(&self, count: usize) -> Result<Vec<String>, RedisClientError> {
+        let mut conn = self.get_connection().await?;
+        let mut keys = Vec::new();
+        let mut cursor: u64 = 0;
+        loop {
+            let (next, batch): (u64, Vec<String>) = cmd("SCAN")
+                .arg(cursor)
+                .arg("MATCH")
+                .arg("pipeline:*")
+                .arg("COUNT")
+                .arg(count)
+                .query_async(&mut *conn)
+                .await?;
+            keys.extend(batch);
+            if next == 0 {
+                break;
+            }
+            cursor = next;
+        }
+        // SCAN may return a key more than once while the keyspace is rehashed
+        keys.sort();
+        keys.dedup();
+        Ok(keys)
+    }
+
+    pub async fn get_all_pipelines(&self) -> Result<Vec<Pipeline>, RedisClientError> {
+        let keys = self.scan_pipeline_keys(PIPELINE_BATCH_SIZE).await?;
+        let mut conn = self.get_connection().await?;
+
+        let mut pipelines = Vec::with_capacity(keys.len());
+        for chunk in keys.chunks(PIPELINE_BATCH_SIZE) {
+            let results: Vec<Option<String>> =
+                cmd("MGET").arg(chunk).query_async(&mut *conn).await?;
+            for json_str in results.into_iter().flatten() {
+                match serde_json::from_str(&json_str) {
+                    Ok(pipeline) => pipelines.push(pipeline),
+                    Err(e) => warn!("Failed to deserialize pipeline: {}", e),
+                }
+            }
+        }
 
         Ok(pipelines)
     }
 
+    /// Writes the pipelines as they are, whatever their stored versions
     pub async fn save_all_pipelines(&self, pipelines: &[Pipeline]) -> Result<(), RedisClientError> {
-        let mut conn = self.pool.get().await?;
+        let mut conn = self.get_connection().await?;
 
         for chunk in pipelines.chunks(PIPELINE_BATCH_SIZE) {
             let mut pipe = pipe();
@@ -124,6 +477,7 @@ impl RedisClient {
                 let key = format!("pipeline:{}", pipeline.id);
                 let value = serde_json::to_string(pipeline)?;
                 pipe.set(key, value);
+                index_pipeline(&mut pipe, pipeline);
             }
 
             let _: () = pipe.query_async(&mut *conn).await?;
@@ -133,23 +487,57 @@ impl RedisClient {
         Ok(())
     }
 
-    pub async fn delete_pipeline(&self, id: &str) -> Result<(), RedisClientError> {
-        let mut conn = self.pool.get().await?;
-        let _: () = cmd("DEL")
-            .arg(format!("pipeline:{}", id))
+    pub async fn delete_pipeline(&self, user_id: &str, id: &str) -> Result<(), RedisClientError> {
+        let mut conn = self.get_connection().await?;
+        let _: () = pipe()
+            .atomic()
+            .del(format!("pipeline:{}", id))
+            .del(ExecutionEvent::key(id))
+            .srem(user_pipelines_key(user_id), id)
             .query_async(&mut *conn)
             .await?;
         Ok(())
     }
 
-    pub async fn delete_all_pipelines(&self, ids: &[String]) -> Result<(), RedisClientError> {
-        let mut conn = self.pool.get().await?;
+    /// Remember the pipeline as deleted for `DELETED_PIPELINE_TTL_SECS`
+    pub async fn mark_pipeline_deleted(&self, id: &str) -> Result<(), RedisClientError> {
+        let mut conn = self.get_connection().await?;
+        let _: () = cmd("SET")
+            .arg(format!("deleted_pipeline:{}", id))
+            .arg(1)
+            .arg("EX")
+            .arg(DELETED_PIPELINE_TTL_SECS)
+            .query_async(&mut *conn)
+            .await?;
+        Ok(())
+    }
+
+    pub async fn is_pipeline_deleted(&self, id: &str) -> Result<bool, RedisClientError> {
+        let mut conn = self.get_connection().await?;
+        let exists: bool = cmd("EXISTS")
+            .arg(format!("deleted_pipeline:{}", id))
+            .query_async(&mut *conn)
+            .await?;
+        Ok(exists)
+    }
 
-        for chunk in ids.chunks(PIPELINE_BATCH_SIZE) {
+    /// Deletes the pipelines and removes them from their users' index sets
+    pub async fn delete_all_pipelines(
+        &self,
+        pipelines: &[Pipeline],
+    ) -> Result<(), RedisClientError> {
+        let mut conn = self.get_connection().await?;
+
+        for chunk in pipelines.chunks(PIPELINE_BATCH_SIZE) {
             let mut pipe = pipe();
 
-            for id in chunk {
-                pipe.del(format!("pipeline:{}", id));
+            for pipeline in chunk {
+                pipe.del(format!("pipeline:{}", pipeline.id));
+                pipe.del(ExecutionEvent::key(pipeline.id));
+                pipe.srem(
+                    user_pipelines_key(&pipeline.user_id),
+                    pipeline.id.to_string(),
+                );
             }
 
             let _: () = pipe.query_async(&mut *conn).await?;
@@ -158,11 +546,171 @@ impl RedisClient {
 
         Ok(())
     }
+
+    /// Push to the front of the user's dead-letter list, dropping the oldest
+    /// entries beyond `max_len`
+    pub async fn push_dead_letter(
+        &self,
+        entry: &DeadLetter,
+        max_len: usize,
+    ) -> Result<(), RedisClientError> {
+        let mut conn = self.get_connection().await?;
+        let key = DeadLetter::key(&entry.user_id);
+        let _: () = pipe()
+            .atomic()
+            .lpush(&key, serde_json::to_string(entry)?)
+            .ltrim(&key, 0, max_len as isize - 1)
+            .query_async(&mut *conn)
+            .await?;
+        Ok(())
+    }
+
+    /// Push to the front of the pipeline's execution log, dropping the
+    /// oldest events beyond `max_len`
+    pub async fn push_execution_events(
+        &self,
+        pipeline_id: Uuid,
+        events: &[ExecutionEvent],
+        max_len: usize,
+    ) -> Result<(), RedisClientError> {
+        let mut conn = self.get_connection().await?;
+        let key = ExecutionEvent::key(pipeline_id);
+        let mut pipe = pipe();
+        pipe.atomic();
+        for event in events {
+            pipe.lpush(&key, serde_json::to_string(event)?);
+        }
+        let _: () = pipe
+            .ltrim(&key, 0, max_len as isize - 1)
+            .query_async(&mut *conn)
+            .await?;
+        Ok(())
+    }
+
+    /// Execution log of the pipeline, newest first
+    pub async fn get_execution_log(
+        &self,
+        pipeline_id: Uuid,
+    ) -> Result<Vec<ExecutionEvent>, RedisClientError> {
+        let mut conn = self.get_connection().await?;
+        let raw: Vec<String> = cmd("LRANGE")
+            .arg(ExecutionEvent::key(pipeline_id))
+            .arg(0)
+            .arg(-1)
+            .query_async(&mut *conn)
+            .await?;
+
+        let mut events = Vec::with_capacity(raw.len());
+        for json_str in raw {
+            match serde_json::from_str(&json_str) {
+                Ok(event) => events.push(event),
+                Err(e) => warn!("Failed to deserialize execution event: {}", e),
+            }
+        }
+        Ok(events)
+    }
+
+    /// Dead letters of the user, newest first
+    pub async fn get_dead_letters(
+        &self,
+        user_id: &str,
+    ) -> Result<Vec<DeadLetter>, RedisClientError> {
+        Ok(self
+            .get_raw_dead_letters(user_id)
+            .await?
+            .into_iter()
+            .map(|(_, entry)| entry)
+            .collect())
+    }
+
+    /// Remove the entry from the user's dead-letter list and return it
+    pub async fn take_dead_letter(
+        &self,
+        user_id: &str,
+        id: Uuid,
+    ) -> Result<Option<DeadLetter>, RedisClientError> {
+        let entry = self
+            .get_raw_dead_letters(user_id)
+            .await?
+            .into_iter()
+            .find(|(_, entry)| entry.id == id);
+
+        let Some((raw, entry)) = entry else {
+            return Ok(None);
+        };
+        let mut conn = self.get_connection().await?;
+        let removed: usize = cmd("LREM")
+            .arg(DeadLetter::key(user_id))
+            .arg(1)
+            .arg(raw)
+            .query_async(&mut *conn)
+            .await?;
+        // a concurrent take got there first
+        Ok((removed > 0).then_some(entry))
+    }
+
+    async fn get_raw_dead_letters(
+        &self,
+        user_id: &str,
+    ) -> Result<Vec<(String, DeadLetter)>, RedisClientError> {
+        let mut conn = self.get_connection().await?;
+        let raw: Vec<String> = cmd("LRANGE")
+            .arg(DeadLetter::key(user_id))
+            .arg(0)
+            .arg(-1)
+            .query_async(&mut *conn)
+            .await?;
+
+        let mut entries = Vec::with_capacity(raw.len());
+        for json_str in raw {
+            match serde_json::from_str(&json_str) {
+                Ok(entry) => entries.push((json_str, entry)),
+                Err(e) => warn!("Failed to deserialize dead letter: {}", e),
+            }
+        }
+        Ok(entries)
+    }
+}
+
+/// Queues the expiry of a just saved pipeline and its user index entry.
+/// `SET` clears a key's expiry, so it is set again on every save.
+fn index_pipeline(pipe: &mut bb8_redis::redis::Pipeline, pipeline: &Pipeline) {
+    if let Some(expires_at) = pipeline.expires_at {
+        pipe.cmd("PEXPIREAT")
+            .arg(format!("pipeline:{}", pipeline.id))
+            .arg(expires_at.timestamp_millis());
+    }
+    pipe.sadd(
+        user_pipelines_key(&pipeline.user_id),
+        pipeline.id.to_string(),
+    );
+}
+
+async fn build_pool(redis_url: &str, config: PoolConfig) -> Result<Pool, RedisClientError> {
+    let manager = RedisConnectionManager::new(redis_url).map_err(RedisClientError::RedisError)?;
+    bb8::Pool::builder()
+        .max_size(config.max_size)
+        .min_idle(Some(config.min_idle))
+        .connection_timeout(Duration::from_millis(config.connection_timeout_ms))
+        .build(manager)
+        .await
+        .map_err(|e| RedisClientError::ConnectionError(e.into()))
+}
+
+fn user_pipelines_key(user_id: &str) -> String {
+    format!("user:{}:pipelines", user_id)
 }
 
 pub async fn make_redis_client() -> Result<Arc<RedisClient>, RedisClientError> {
     let redis_url = std::env::var("REDIS_URL").unwrap_or_else(|_| "redis://127.0.0.1/".to_string());
-    let client = RedisClient::new(&redis_url).await?;
+    let pool = PoolConfig::from_env();
+    info!(
+        max_size = pool.max_size,
+        min_idle = pool.min_idle,
+        connection_timeout_ms = pool.connection_timeout_ms,
+        "Redis connection pool"
+    );
+    let client = RedisClient::with_pool(&redis_url, pool).await?;
     Ok(Arc::new(client))
 }
 
@@ -184,4 +732,519 @@ mod tests {
         assert!(value.is_some());
         assert_eq!(value.unwrap(), json!({"test": "value"}));
     }
+
+    #[test]
+    fn test_pool_config_from_vars() {
+        let vars = |max_size: &str, min_idle: &str| {
+            let (max_size, min_idle) = (max_size.to_string(), min_idle.to_string());
+            PoolConfig::from_vars(move |name| match name {
+                "REDIS_POOL_MAX_SIZE" => Some(max_size.clone()),
+                "REDIS_POOL_MIN_IDLE" => Some(min_idle.clone()),
+                _ => None,
+            })
+        };
+        assert_eq!(PoolConfig::from_vars(|_| None), PoolConfig::default());
+        assert_eq!(
+            vars("64", "8"),
+            PoolConfig {
+                max_size: 64,
+                min_idle: 8,
+                ..PoolConfig::default()
+            }
+        );
+        assert_eq!(vars("0", "x"), PoolConfig::default());
+        assert_eq!(vars("2", "8").min_idle, 2);
+    }
+
+    /// Answers PING with PONG on `listener`, until the returned task is
+    /// aborted, which drops every connection it accepted
+    fn serve_pong(listener: tokio::net::TcpListener) -> tokio::task::JoinHandle<()> {
+        use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+
+        tokio::spawn(async move {
+            let mut connections = tokio::task::JoinSet::new();
+            while let Ok((stream, _)) = listener.accept().await {
+                connections.spawn(async move {
+                    let (read, mut write) = stream.into_split();
+                    let mut lines = BufReader::new(read).lines();
+                    // each command is an array of bulk strings, the name first
+                    while let Ok(Some(line)) = lines.next_line().await {
+                        let Some(len) = line.strip_prefix('*') else {
+                            continue;
+                        };
+                        let mut args = Vec::new();
+                        for _ in 0..len.parse::<usize>().unwrap_or(0) * 2 {
+                            args.push(lines.next_line().await.ok().flatten().unwrap_or_default());
+                        }
+                        let reply = match args.get(1).map(|name| name.to_uppercase()) {
+                            Some(name) if name == "PING" => "+PONG\r\n",
+                            _ => "-ERR unknown command\r\n",
+                        };
+                        if write.write_all(reply.as_bytes()).await.is_err() {
+                            break;
+                        }
+                    }
+                });
+            }
+        })
+    }
+
+    #[tokio::test]
+    async fn test_reconnects_after_connection_drop() {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = serve_pong(listener);
+        let client = RedisClient::with_pool(
+            &format!("redis://{}", addr),
+            PoolConfig {
+                max_size: 2,
+                min_idle: 1,
+                connection_timeout_ms: 300,
+            },
+        )
+        .await
+        .unwrap();
+        client.ping().await.unwrap();
+
+        // Redis goes away, taking its connections with it
+        server.abort();
+        let _ = server.await;
+        assert!(matches!(
+            client.ping().await,
+            Err(RedisClientError::Disconnected(_))
+        ));
+
+        // Redis is back on the same address
+        let server = serve_pong(tokio::net::TcpListener::bind(addr).await.unwrap());
+        let reconnected = tokio::time::timeout(Duration::from_secs(10), async {
+            while client.ping().await.is_err() {
+                tokio::time::sleep(Duration::from_millis(50)).await;
+            }
+        })
+        .await;
+        assert!(reconnected.is_ok());
+        assert!(client.ping().await.is_ok());
+        // a command Redis refuses is not a disconnect
+        let mut conn = client.get_connection().await.unwrap();
+        let unknown: Result<String, _> = cmd("NOPE").query_async(&mut *conn).await;
+        assert!(matches!(
+            unknown.map_err(RedisClientError::from),
+            Err(RedisClientError::RedisError(_))
+        ));
+        server.abort();
+    }
+
+    #[tokio::test]
+    async fn test_exhausted_pool_is_not_rebuilt() {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = serve_pong(listener);
+        let client = RedisClient::with_pool(
+            &format!("redis://{}", addr),
+            PoolConfig {
+                max_size: 1,
+                min_idle: 1,
+                connection_timeout_ms: 100,
+            },
+        )
+        .await
+        .unwrap();
+
+        let held = client.get_connection().await.unwrap();
+        assert!(matches!(
+            client.get_connection().await,
+            Err(RedisClientError::ConnectionError(bb8::RunError::TimedOut))
+        ));
+        assert_eq!(client.generation.load(Ordering::SeqCst), 0);
+        drop(held);
+        client.ping().await.unwrap();
+        server.abort();
+    }
+
+    #[tokio::test]
+    async fn test_raised_pool_serves_more_connections() {
+        let max_size = DEFAULT_POOL_MAX_SIZE * 2;
+        let client = RedisClient::with_pool(
+            "redis://localhost:6379",
+            PoolConfig {
+                max_size,
+                ..PoolConfig::default()
+            },
+        )
+        .await
+        .unwrap();
+
+        // every connection is held at once, which the default pool can't do
+        let conns = tokio::time::timeout(
+            std::time::Duration::from_secs(10),
+            futures_util::future::join_all((0..max_size).map(|_| client.get_connection())),
+        )
+        .await
+        .unwrap();
+        assert!(conns.iter().all(|conn| conn.is_ok()));
+    }
+
+    #[tokio::test]
+    async fn test_list_user_pipelines() {
+        let client = RedisClient::new("redis://localhost:6379").await.unwrap();
+        let user_id = format!("test-{}", Uuid::new_v4());
+
+        let mut saved = Vec::new();
+        for _ in 0..5 {
+            let mut pipeline = Pipeline {
+                id: Uuid::new_v4(),
+                user_id: user_id.clone(),
+                current_steps: vec![],
+                steps: Default::default(),
+                status: crate::engine::pipeline::Status::Pending,
+                created_at: chrono::Utc::now(),
+                swap_history: vec![],
+                failure_reason: None,
+                eval_interval_ms: None,
+                depth: 0,
+                expires_at: None,
+                version: 0,
+                dry_run: false,
+                dry_run_triggers: vec![],
+            };
+            client.save_pipeline(&mut pipeline).await.unwrap();
+            saved.push(pipeline.id);
+        }
+
+        // pages of 2 cover every pipeline exactly once
+        let mut listed = Vec::new();
+        let mut cursor = Some((0, 0));
+        while let Some(page_cursor) = cursor {
+            let (page, next) = client
+                .list_user_pipelines(&user_id, 2, page_cursor)
+                .await
+                .unwrap();
+            assert!(page.len() <= 2);
+            listed.extend(page.into_iter().map(|p| p.id));
+            cursor = next;
+        }
+        listed.sort();
+        saved.sort();
+        assert_eq!(listed, saved);
+
+        for id in &saved {
+            client
+                .delete_pipeline(&user_id, &id.to_string())
+                .await
+                .unwrap();
+        }
+        let (page, next) = client
+            .list_user_pipelines(&user_id, 2, (0, 0))
+            .await
+            .unwrap();
+        assert!(page.is_empty());
+        assert!(next.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_user_index_consistent_after_deletes() {
+        let client = RedisClient::new("redis://localhost:6379").await.unwrap();
+        let user_id = format!("test-{}", Uuid::new_v4());
+        let pipelines: Vec<Pipeline> = (0..4)
+            .map(|_| Pipeline {
+                id: Uuid::new_v4(),
+                user_id: user_id.clone(),
+                current_steps: vec![],
+                steps: Default::default(),
+                status: crate::engine::pipeline::Status::Pending,
+                created_at: chrono::Utc::now(),
+                swap_history: vec![],
+                failure_reason: None,
+                eval_interval_ms: None,
+                depth: 0,
+                expires_at: None,
+                version: 0,
+                dry_run: false,
+                dry_run_triggers: vec![],
+            })
+            .collect();
+        client.save_all_pipelines(&pipelines).await.unwrap();
+
+        client.delete_all_pipelines(&pipelines[..2]).await.unwrap();
+        client
+            .delete_pipeline(&user_id, &pipelines[2].id.to_string())
+            .await
+            .unwrap();
+
+        let (listed, _) = client
+            .list_user_pipelines(&user_id, 10, (0, 0))
+            .await
+            .unwrap();
+        assert_eq!(
+            listed.iter().map(|p| p.id).collect::<Vec<_>>(),
+            vec![pipelines[3].id]
+        );
+        let mut conn = client.get_connection().await.unwrap();
+        let indexed: Vec<String> = cmd("SMEMBERS")
+            .arg(user_pipelines_key(&user_id))
+            .query_async(&mut *conn)
+            .await
+            .unwrap();
+        assert_eq!(indexed, vec![pipelines[3].id.to_string()]);
+
+        // a small COUNT takes several SCAN calls
+        let keys = client.scan_pipeline_keys(1).await.unwrap();
+        for (i, pipeline) in pipelines.iter().enumerate() {
+            let key = format!("pipeline:{}", pipeline.id);
+            assert_eq!(keys.contains(&key), i == 3);
+        }
+        client.delete_all_pipelines(&pipelines[3..]).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_pipeline_expiry() {
+        let client = RedisClient::new("redis://localhost:6379").await.unwrap();
+        let user_id = format!("test-{}", Uuid::new_v4());
+        let mut pipeline = Pipeline {
+            id: Uuid::new_v4(),
+            user_id: user_id.clone(),
+            current_steps: vec![],
+            steps: Default::default(),
+            status: crate::engine::pipeline::Status::Pending,
+            created_at: chrono::Utc::now(),
+            swap_history: vec![],
+            failure_reason: None,
+            eval_interval_ms: None,
+            depth: 0,
+            expires_at: Some(chrono::Utc::now() + chrono::Duration::hours(1)),
+            version: 0,
+            dry_run: false,
+            dry_run_triggers: vec![],
+        };
+        let key = format!("pipeline:{}", pipeline.id);
+        // saving again keeps the expiry
+        client.save_pipeline(&mut pipeline).await.unwrap();
+        client.save_pipeline(&mut pipeline).await.unwrap();
+        let mut conn = client.get_connection().await.unwrap();
+        let ttl: i64 = cmd("PTTL").arg(&key).query_async(&mut *conn).await.unwrap();
+        drop(conn);
+        assert!(ttl > 0 && ttl <= 60 * 60 * 1000);
+
+        // dropped by Redis with no engine around, the index is left behind
+        // until listed
+        pipeline.expires_at = Some(chrono::Utc::now() - chrono::Duration::milliseconds(1));
+        client.save_pipeline(&mut pipeline).await.unwrap();
+        assert!(client
+            .get_pipeline(&pipeline.id.to_string())
+            .await
+            .unwrap()
+            .is_none());
+        let (listed, _) = client
+            .list_user_pipelines(&user_id, 10, (0, 0))
+            .await
+            .unwrap();
+        assert!(listed.is_empty());
+        let mut conn = client.get_connection().await.unwrap();
+        let indexed: Vec<String> = cmd("SMEMBERS")
+            .arg(user_pipelines_key(&user_id))
+            .query_async(&mut *conn)
+            .await
+            .unwrap();
+        assert!(indexed.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_deleted_pipeline_is_remembered() {
+        let client = RedisClient::new("redis://localhost:6379").await.unwrap();
+        let id = Uuid::new_v4().to_string();
+
+        assert!(!client.is_pipeline_deleted(&id).await.unwrap());
+        client.mark_pipeline_deleted(&id).await.unwrap();
+        assert!(client.is_pipeline_deleted(&id).await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_dead_letters() {
+        use crate::engine::pipeline::{Action, Notification, PipelineStep, Status};
+        use crate::engine::swap::{SlippageSpec, SwapOrder};
+        use std::collections::HashMap;
+
+        let client = RedisClient::new("redis://localhost:6379").await.unwrap();
+        let user_id = format!("test-{}", Uuid::new_v4());
+
+        let step = PipelineStep {
+            id: Uuid::new_v4(),
+            action: Action::SwapOrder(SwapOrder {
+                amm_pool: "pool".to_string(),
+                input_mint: "in".to_string(),
+                output_mint: "out".to_string(),
+                amount: 1,
+                slippage: SlippageSpec::Fixed(50),
+            }),
+            conditions: vec![],
+            next_steps: vec![],
+            status: Status::Failed,
+            retry_policy: None,
+            attempts: 0,
+            next_attempt_at: None,
+        };
+        let pipeline = Pipeline {
+            id: Uuid::new_v4(),
+            user_id: user_id.clone(),
+            current_steps: vec![step.id],
+            steps: HashMap::from([(step.id, step.clone())]),
+            status: Status::Failed,
+            created_at: chrono::Utc::now(),
+            swap_history: vec![],
+            failure_reason: None,
+            eval_interval_ms: None,
+            depth: 0,
+            expires_at: None,
+            version: 0,
+            dry_run: false,
+            dry_run_triggers: vec![],
+        };
+
+        let failed = DeadLetter::new(&user_id, pipeline.id, &step, "swap dropped".to_string());
+        client.push_dead_letter(&failed, 2).await.unwrap();
+        let listed = client.get_dead_letters(&user_id).await.unwrap();
+        assert_eq!(listed.len(), 1);
+        assert_eq!(listed[0].id, failed.id);
+        assert!(matches!(listed[0].action, Action::SwapOrder(_)));
+
+        // capped, oldest entries are dropped
+        let mut notification = step.clone();
+        notification.action = Action::Notification(Notification {
+            message: "-".to_string(),
+        });
+        for _ in 0..2 {
+            let entry = DeadLetter::new(&user_id, pipeline.id, &notification, "-".to_string());
+            client.push_dead_letter(&entry, 2).await.unwrap();
+        }
+        let listed = client.get_dead_letters(&user_id).await.unwrap();
+        assert_eq!(listed.len(), 2);
+        assert!(listed.iter().all(|entry| entry.id != failed.id));
+
+        // taking for a retry removes the entry
+        let retried = client
+            .take_dead_letter(&user_id, listed[0].id)
+            .await
+            .unwrap();
+        assert_eq!(retried.map(|entry| entry.id), Some(listed[0].id));
+        assert!(client
+            .take_dead_letter(&user_id, listed[0].id)
+            .await
+            .unwrap()
+            .is_none());
+        assert_eq!(client.get_dead_letters(&user_id).await.unwrap().len(), 1);
+
+        let mut conn = client.get_connection().await.unwrap();
+        let _: () = cmd("DEL")
+            .arg(DeadLetter::key(&user_id))
+            .query_async(&mut *conn)
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_concurrent_updates_are_kept() {
+        let client = RedisClient::new("redis://localhost:6379").await.unwrap();
+        let user_id = format!("test-{}", Uuid::new_v4());
+        let mut pipeline = Pipeline {
+            id: Uuid::new_v4(),
+            user_id: user_id.clone(),
+            current_steps: vec![],
+            steps: Default::default(),
+            status: crate::engine::pipeline::Status::Pending,
+            created_at: chrono::Utc::now(),
+            swap_history: vec![],
+            failure_reason: None,
+            eval_interval_ms: None,
+            depth: 0,
+            expires_at: None,
+            version: 0,
+            dry_run: false,
+            dry_run_triggers: vec![],
+        };
+        client.save_pipeline(&mut pipeline).await.unwrap();
+        let stale = pipeline.clone();
+        let id = pipeline.id.to_string();
+
+        let (interval, reason) = tokio::join!(
+            client.update_pipeline(&id, |p| p.eval_interval_ms = Some(5_000)),
+            client.update_pipeline(&id, |p| p.failure_reason = Some("-".to_string())),
+        );
+        interval.unwrap().unwrap();
+        reason.unwrap().unwrap();
+
+        let stored = client.get_pipeline(&id).await.unwrap().unwrap();
+        assert_eq!(stored.eval_interval_ms, Some(5_000));
+        assert_eq!(stored.failure_reason.as_deref(), Some("-"));
+        assert_eq!(stored.version, 3);
+
+        // a copy read before the updates can't overwrite them
+        let mut stale = stale;
+        assert!(matches!(
+            client.save_pipeline(&mut stale).await,
+            Err(RedisClientError::Conflict(_))
+        ));
+        assert_eq!(stale.version, 1);
+        client.delete_pipeline(&user_id, &id).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_execution_log() {
+        use crate::engine::history::{ExecutionEvent, Outcome};
+        use crate::engine::pipeline::{Action, Notification};
+
+        let client = RedisClient::new("redis://localhost:6379").await.unwrap();
+        let pipeline_id = Uuid::new_v4();
+        let action = Action::Notification(Notification {
+            message: "-".to_string(),
+        });
+        let events: Vec<ExecutionEvent> = (0..5)
+            .map(|i| {
+                ExecutionEvent::new(
+                    pipeline_id,
+                    Uuid::new_v4(),
+                    &[],
+                    &action,
+                    Outcome::Retrying {
+                        error: i.to_string(),
+                    },
+                )
+            })
+            .collect();
+        client
+            .push_execution_events(pipeline_id, &events[..3], 4)
+            .await
+            .unwrap();
+        client
+            .push_execution_events(pipeline_id, &events[3..], 4)
+            .await
+            .unwrap();
+
+        // newest first, the oldest beyond the cap are dropped
+        let log = client.get_execution_log(pipeline_id).await.unwrap();
+        assert_eq!(
+            log.iter().map(|e| e.step_id).collect::<Vec<_>>(),
+            events
+                .iter()
+                .rev()
+                .take(4)
+                .map(|e| e.step_id)
+                .collect::<Vec<_>>()
+        );
+        assert_eq!(
+            log[0].outcome,
+            Outcome::Retrying {
+                error: "4".to_string()
+            }
+        );
+
+        client
+            .delete_pipeline("-", &pipeline_id.to_string())
+            .await
+            .unwrap();
+        assert!(client
+            .get_execution_log(pipeline_id)
+            .await
+            .unwrap()
+            .is_empty());
+    }
 }
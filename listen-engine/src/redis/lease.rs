@@ -0,0 +1,165 @@
+use super::client::{RedisClient, RedisClientError};
+use bb8_redis::redis::cmd;
+use std::time::Duration;
+use uuid::Uuid;
+
+// Takes the lease (KEYS[1]) for ARGV[1] for ARGV[2] ms, or extends it if
+// ARGV[1] holds it already. Returns 2 when taken, 1 when extended and 0
+// while another owner holds it.
+const ACQUIRE_LEASE_SCRIPT: &str = r"
+local owner = redis.call('GET', KEYS[1])
+if owner == ARGV[1] then
+    redis.call('PEXPIRE', KEYS[1], ARGV[2])
+    return 1
+end
+if owner then
+    return 0
+end
+redis.call('SET', KEYS[1], ARGV[1], 'PX', ARGV[2])
+return 2
+";
+
+// Drops the lease (KEYS[1]) if ARGV[1] holds it
+const RELEASE_LEASE_SCRIPT: &str = r"
+if redis.call('GET', KEYS[1]) == ARGV[1] then
+    return redis.call('DEL', KEYS[1])
+end
+return 0
+";
+
+/// Outcome of asking for a pipeline's lease
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Lease {
+    /// Newly taken, the pipeline may have been changed by its last owner
+    Acquired,
+    /// Held already and extended
+    Renewed,
+    /// Held by another owner until it lets it expire
+    Held,
+}
+
+impl Lease {
+    pub fn is_owned(&self) -> bool {
+        !matches!(self, Lease::Held)
+    }
+}
+
+fn lease_key(pipeline_id: Uuid) -> String {
+    format!("lease:pipeline:{}", pipeline_id)
+}
+
+impl RedisClient {
+    /// Takes or extends the lease on the pipeline for `owner`, for `ttl`.
+    /// Only the owner evaluates the pipeline, and keeps it by renewing
+    /// before `ttl` is up; an owner that stops, e.g. as its instance died,
+    /// loses it to the next to ask once it expires.
+    pub async fn acquire_lease(
+        &self,
+        pipeline_id: Uuid,
+        owner: &str,
+        ttl: Duration,
+    ) -> Result<Lease, RedisClientError> {
+        let mut conn = self.get_connection().await?;
+        let lease: i64 = cmd("EVAL")
+            .arg(ACQUIRE_LEASE_SCRIPT)
+            .arg(1)
+            .arg(lease_key(pipeline_id))
+            .arg(owner)
+            .arg(ttl.as_millis().max(1) as u64)
+            .query_async(&mut *conn)
+            .await?;
+        Ok(match lease {
+            2 => Lease::Acquired,
+            1 => Lease::Renewed,
+            _ => Lease::Held,
+        })
+    }
+
+    /// Gives up the lease, if `owner` holds it, so another owner can take
+    /// it without waiting for it to expire
+    pub async fn release_lease(
+        &self,
+        pipeline_id: Uuid,
+        owner: &str,
+    ) -> Result<(), RedisClientError> {
+        let mut conn = self.get_connection().await?;
+        let _: i64 = cmd("EVAL")
+            .arg(RELEASE_LEASE_SCRIPT)
+            .arg(1)
+            .arg(lease_key(pipeline_id))
+            .arg(owner)
+            .query_async(&mut *conn)
+            .await?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    #[tokio::test]
+    async fn test_pipeline_evaluated_by_one_owner() {
+        let client = Arc::new(RedisClient::new("redis://localhost:6379").await.unwrap());
+        let pipeline_id = Uuid::new_v4();
+        let ttl = Duration::from_millis(300);
+        let evaluations = Arc::new([AtomicUsize::new(0), AtomicUsize::new(0)]);
+
+        // two instances evaluating the same pipeline on every tick
+        let run = |owner: usize, ticks: usize| {
+            let (client, evaluations) = (client.clone(), evaluations.clone());
+            tokio::spawn(async move {
+                for _ in 0..ticks {
+                    let lease = client
+                        .acquire_lease(pipeline_id, &format!("instance-{}", owner), ttl)
+                        .await
+                        .unwrap();
+                    if lease.is_owned() {
+                        evaluations[owner].fetch_add(1, Ordering::SeqCst);
+                    }
+                    tokio::time::sleep(Duration::from_millis(10)).await;
+                }
+            })
+        };
+        let (a, b) = (run(0, 20), run(1, 20));
+        a.await.unwrap();
+        b.await.unwrap();
+        let counts = [0, 1].map(|owner| evaluations[owner].load(Ordering::SeqCst));
+        assert_eq!(counts.iter().filter(|count| **count > 0).count(), 1);
+        assert_eq!(counts.iter().sum::<usize>(), 20);
+
+        // the owner stops renewing, the other takes over once it expires
+        let owner = if counts[0] > 0 { 0 } else { 1 };
+        let other = format!("instance-{}", 1 - owner);
+        assert_eq!(
+            client
+                .acquire_lease(pipeline_id, &other, ttl)
+                .await
+                .unwrap(),
+            Lease::Held
+        );
+        tokio::time::sleep(ttl).await;
+        assert_eq!(
+            client
+                .acquire_lease(pipeline_id, &other, ttl)
+                .await
+                .unwrap(),
+            Lease::Acquired
+        );
+
+        client.release_lease(pipeline_id, &other).await.unwrap();
+        assert_eq!(
+            client
+                .acquire_lease(pipeline_id, &format!("instance-{}", owner), ttl)
+                .await
+                .unwrap(),
+            Lease::Acquired
+        );
+        client
+            .release_lease(pipeline_id, &format!("instance-{}", owner))
+            .await
+            .unwrap();
+    }
+}
@@ -0,0 +1,49 @@
+use std::time::Instant;
+
+use actix_web::{
+    body::MessageBody,
+    dev::{ServiceRequest, ServiceResponse},
+    http::StatusCode,
+    middleware::Next,
+    Error, HttpMessage, HttpRequest,
+};
+
+use super::auth::ApiKeyIdentity;
+
+/// Logs every request with its method, path, status, latency and the API
+/// key's user as fields, in place of actix's text access log when
+/// `LOG_FORMAT=json`
+pub async fn access_log(
+    req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<impl MessageBody>, Error> {
+    let started = Instant::now();
+    let http_req = req.request().clone();
+    let res = next.call(req).await;
+    let status = match &res {
+        Ok(res) => res.status(),
+        Err(e) => e.as_response_error().status_code(),
+    };
+    log_request(&http_req, status, started);
+    res
+}
+
+fn log_request(req: &HttpRequest, status: StatusCode, started: Instant) {
+    let user_id = req
+        .extensions()
+        .get::<ApiKeyIdentity>()
+        .and_then(|identity| identity.user_id.clone())
+        .unwrap_or_default();
+    tracing::info!(
+        target: "access",
+        method = %req.method(),
+        path = req.path(),
+        status = status.as_u16(),
+        latency_ms = started.elapsed().as_millis() as u64,
+        user_id = user_id.as_str(),
+        "{} {} {}",
+        req.method(),
+        req.path(),
+        status.as_u16()
+    );
+}
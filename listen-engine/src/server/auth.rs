@@ -0,0 +1,174 @@
+use std::collections::HashMap;
+
+use actix_web::{
+    body::{EitherBody, MessageBody},
+    dev::{ServiceRequest, ServiceResponse},
+    http::{header::AUTHORIZATION, StatusCode},
+    middleware::Next,
+    web::Data,
+    Error, HttpMessage, ResponseError,
+};
+
+use super::error::ApiError;
+
+/// Keys accepted by `require_api_key`, parsed from the comma-separated
+/// `API_KEYS`. An entry `<user_id>:<key>` binds the key to the user, one
+/// without a user is a service key acting for any user.
+#[derive(Debug, Clone, Default)]
+pub struct ApiKeys(HashMap<String, Option<String>>);
+
+impl ApiKeys {
+    pub fn parse(keys: &str) -> Self {
+        Self(
+            keys.split(',')
+                .map(str::trim)
+                .filter(|entry| !entry.is_empty())
+                .map(|entry| match entry.split_once(':') {
+                    Some((user_id, key)) => (key.to_string(), Some(user_id.to_string())),
+                    None => (entry.to_string(), None),
+                })
+                .collect(),
+        )
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    fn identify(&self, authorization: &str) -> Option<ApiKeyIdentity> {
+        let (key, user_id) = self.0.get_key_value(bearer(authorization)?)?;
+        Some(ApiKeyIdentity {
+            key: key.clone(),
+            user_id: user_id.clone(),
+        })
+    }
+}
+
+/// Token accepted by `require_admin_token`, from `ADMIN_TOKEN`
+#[derive(Debug, Clone, Default)]
+pub struct AdminToken(pub Option<String>);
+
+fn bearer(authorization: &str) -> Option<&str> {
+    Some(authorization.strip_prefix("Bearer ")?.trim())
+}
+
+/// The API key a request was authenticated with, in the request extensions
+#[derive(Debug, Clone, PartialEq)]
+pub struct ApiKeyIdentity {
+    pub key: String,
+    /// The user the key is bound to, none for a service key
+    pub user_id: Option<String>,
+}
+
+/// Rejects requests without an `Authorization: Bearer <key>` header holding
+/// one of the `ApiKeys` in the app data
+pub async fn require_api_key(
+    req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<EitherBody<impl MessageBody>>, Error> {
+    let identity = req
+        .app_data::<Data<ApiKeys>>()
+        .zip(req.headers().get(AUTHORIZATION))
+        .and_then(|(keys, header)| keys.identify(header.to_str().ok()?));
+
+    match identity {
+        Some(identity) => {
+            req.extensions_mut().insert(identity);
+            Ok(next.call(req).await?.map_into_left_body())
+        }
+        None => {
+            metrics::counter!("api_key_rejections", 1);
+            let response = ApiError::new(
+                StatusCode::UNAUTHORIZED,
+                "INVALID_API_KEY",
+                "Missing or invalid API key",
+            )
+            .error_response();
+            Ok(req.into_response(response).map_into_right_body())
+        }
+    }
+}
+
+/// Rejects requests without an `Authorization: Bearer <token>` header
+/// holding the `AdminToken` in the app data, and all of them when it is unset
+pub async fn require_admin_token(
+    req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<EitherBody<impl MessageBody>>, Error> {
+    let authorized = req
+        .app_data::<Data<AdminToken>>()
+        .and_then(|token| token.0.as_deref())
+        .zip(req.headers().get(AUTHORIZATION))
+        .is_some_and(|(token, header)| header.to_str().ok().and_then(bearer) == Some(token));
+
+    if authorized {
+        Ok(next.call(req).await?.map_into_left_body())
+    } else {
+        metrics::counter!("admin_token_rejections", 1);
+        let response = ApiError::new(
+            StatusCode::UNAUTHORIZED,
+            "INVALID_ADMIN_TOKEN",
+            "Missing or invalid admin token",
+        )
+        .error_response();
+        Ok(req.into_response(response).map_into_right_body())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::{middleware::from_fn, test, web, App, HttpRequest};
+
+    async fn whoami(req: HttpRequest) -> String {
+        req.extensions()
+            .get::<ApiKeyIdentity>()
+            .map(|identity| format!("{}:{:?}", identity.key, identity.user_id))
+            .unwrap_or_default()
+    }
+
+    #[actix_web::test]
+    async fn test_require_api_key() {
+        let app = test::init_service(
+            App::new()
+                .app_data(Data::new(ApiKeys::parse("key-a, alice:key-b")))
+                .service(
+                    web::scope("/api")
+                        .wrap(from_fn(require_api_key))
+                        .route("/whoami", web::get().to(whoami)),
+                ),
+        )
+        .await;
+
+        let req = test::TestRequest::get()
+            .uri("/api/whoami")
+            .insert_header((AUTHORIZATION, "Bearer key-b"))
+            .to_request();
+        let res = test::call_service(&app, req).await;
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(test::read_body(res).await, "key-b:Some(\"alice\")");
+        let req = test::TestRequest::get()
+            .uri("/api/whoami")
+            .insert_header((AUTHORIZATION, "Bearer key-a"))
+            .to_request();
+        let res = test::call_service(&app, req).await;
+        assert_eq!(test::read_body(res).await, "key-a:None");
+
+        // the user is no part of the key
+        for header in [
+            None,
+            Some("Bearer key-c"),
+            Some("key-a"),
+            Some("Bearer alice:key-b"),
+        ] {
+            let mut req = test::TestRequest::get().uri("/api/whoami");
+            if let Some(header) = header {
+                req = req.insert_header((AUTHORIZATION, header));
+            }
+            let res = test::call_service(&app, req.to_request()).await;
+            assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+            let body: serde_json::Value = test::read_body_json(res).await;
+            assert_eq!(body["code"], "INVALID_API_KEY");
+        }
+    }
+}
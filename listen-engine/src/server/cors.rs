@@ -0,0 +1,185 @@
+use std::collections::HashSet;
+
+use actix_web::{
+    body::{EitherBody, MessageBody},
+    dev::{ServiceRequest, ServiceResponse},
+    http::{
+        header::{
+            HeaderValue, ACCESS_CONTROL_ALLOW_HEADERS, ACCESS_CONTROL_ALLOW_METHODS,
+            ACCESS_CONTROL_ALLOW_ORIGIN, ACCESS_CONTROL_MAX_AGE, ACCESS_CONTROL_REQUEST_METHOD,
+            ORIGIN, VARY,
+        },
+        Method, StatusCode,
+    },
+    middleware::Next,
+    web::Data,
+    Error, HttpResponse, ResponseError,
+};
+
+use super::error::ApiError;
+
+const ALLOWED_METHODS: &str = "GET, POST, PATCH, DELETE, OPTIONS";
+const ALLOWED_HEADERS: &str = "Content-Type, Authorization";
+const PREFLIGHT_MAX_AGE_SECS: &str = "3600";
+
+/// Origins a browser may call the API from, parsed from the comma-separated
+/// `ALLOWED_ORIGINS`. Empty by default, which allows no cross-origin
+/// requests and leaves same-origin ones as they are.
+#[derive(Debug, Clone, Default)]
+pub struct AllowedOrigins(HashSet<String>);
+
+impl AllowedOrigins {
+    pub fn parse(origins: &str) -> Self {
+        Self(
+            origins
+                .split(',')
+                .map(|origin| origin.trim().trim_end_matches('/'))
+                .filter(|origin| !origin.is_empty())
+                .map(str::to_string)
+                .collect(),
+        )
+    }
+
+    fn allows(&self, origin: &str) -> bool {
+        self.0.contains(origin)
+    }
+}
+
+/// Answers preflight requests from the `AllowedOrigins` in the app data and
+/// lets their responses be read cross-origin. Wrapped around the whole app,
+/// as preflights carry no `Authorization` for the API key check.
+pub async fn cors(
+    req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<EitherBody<impl MessageBody>>, Error> {
+    let Some(origin) = req.headers().get(ORIGIN).cloned() else {
+        return Ok(next.call(req).await?.map_into_left_body());
+    };
+    let allowed = req
+        .app_data::<Data<AllowedOrigins>>()
+        .zip(origin.to_str().ok())
+        .is_some_and(|(origins, origin)| origins.allows(origin));
+    let preflight = req.method() == Method::OPTIONS
+        && req.headers().contains_key(ACCESS_CONTROL_REQUEST_METHOD);
+
+    match (preflight, allowed) {
+        (true, true) => {
+            let response = HttpResponse::NoContent()
+                .insert_header((ACCESS_CONTROL_ALLOW_ORIGIN, origin))
+                .insert_header((ACCESS_CONTROL_ALLOW_METHODS, ALLOWED_METHODS))
+                .insert_header((ACCESS_CONTROL_ALLOW_HEADERS, ALLOWED_HEADERS))
+                .insert_header((ACCESS_CONTROL_MAX_AGE, PREFLIGHT_MAX_AGE_SECS))
+                .insert_header((VARY, "Origin"))
+                .finish();
+            Ok(req.into_response(response).map_into_right_body())
+        }
+        (true, false) => {
+            let response = ApiError::new(
+                StatusCode::FORBIDDEN,
+                "CORS_ORIGIN_NOT_ALLOWED",
+                "Origin is not allowed",
+            )
+            .error_response();
+            Ok(req.into_response(response).map_into_right_body())
+        }
+        (false, allowed) => {
+            let mut res = next.call(req).await?;
+            if allowed {
+                let headers = res.headers_mut();
+                headers.insert(ACCESS_CONTROL_ALLOW_ORIGIN, origin);
+                headers.insert(VARY, HeaderValue::from_static("Origin"));
+            }
+            Ok(res.map_into_left_body())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::{
+        http::header::{ACCESS_CONTROL_REQUEST_HEADERS, AUTHORIZATION},
+        middleware::from_fn,
+        test, web, App, HttpResponse,
+    };
+
+    use crate::server::auth::{require_api_key, ApiKeys};
+
+    #[actix_web::test]
+    async fn test_preflight() {
+        let app = test::init_service(
+            App::new()
+                .app_data(Data::new(AllowedOrigins::parse(
+                    "https://dashboard.example.com/, http://localhost:3000",
+                )))
+                .app_data(Data::new(ApiKeys::parse("key-a")))
+                .wrap(from_fn(cors))
+                .service(
+                    web::scope("/api")
+                        .wrap(from_fn(require_api_key))
+                        .route("/pipeline", web::post().to(HttpResponse::Created)),
+                ),
+        )
+        .await;
+
+        let req = test::TestRequest::default()
+            .method(Method::OPTIONS)
+            .uri("/api/pipeline")
+            .insert_header((ORIGIN, "https://dashboard.example.com"))
+            .insert_header((ACCESS_CONTROL_REQUEST_METHOD, "POST"))
+            .insert_header((
+                ACCESS_CONTROL_REQUEST_HEADERS,
+                "content-type, authorization",
+            ))
+            .to_request();
+        let res = test::call_service(&app, req).await;
+        assert_eq!(res.status(), StatusCode::NO_CONTENT);
+        let headers = res.headers();
+        assert_eq!(
+            headers.get(ACCESS_CONTROL_ALLOW_ORIGIN).unwrap(),
+            "https://dashboard.example.com"
+        );
+        assert!(headers
+            .get(ACCESS_CONTROL_ALLOW_METHODS)
+            .unwrap()
+            .to_str()
+            .unwrap()
+            .contains("POST"));
+        assert_eq!(
+            headers.get(ACCESS_CONTROL_ALLOW_HEADERS).unwrap(),
+            ALLOWED_HEADERS
+        );
+
+        // the request itself still needs the API key
+        let req = test::TestRequest::post()
+            .uri("/api/pipeline")
+            .insert_header((ORIGIN, "http://localhost:3000"))
+            .insert_header((AUTHORIZATION, "Bearer key-a"))
+            .to_request();
+        let res = test::call_service(&app, req).await;
+        assert_eq!(res.status(), StatusCode::CREATED);
+        assert_eq!(
+            res.headers().get(ACCESS_CONTROL_ALLOW_ORIGIN).unwrap(),
+            "http://localhost:3000"
+        );
+
+        let req = test::TestRequest::default()
+            .method(Method::OPTIONS)
+            .uri("/api/pipeline")
+            .insert_header((ORIGIN, "https://elsewhere.example.com"))
+            .insert_header((ACCESS_CONTROL_REQUEST_METHOD, "POST"))
+            .to_request();
+        let res = test::call_service(&app, req).await;
+        assert_eq!(res.status(), StatusCode::FORBIDDEN);
+        assert!(!res.headers().contains_key(ACCESS_CONTROL_ALLOW_ORIGIN));
+
+        // same-origin requests carry no Origin and are left alone
+        let req = test::TestRequest::post()
+            .uri("/api/pipeline")
+            .insert_header((AUTHORIZATION, "Bearer key-a"))
+            .to_request();
+        let res = test::call_service(&app, req).await;
+        assert_eq!(res.status(), StatusCode::CREATED);
+        assert!(!res.headers().contains_key(ACCESS_CONTROL_ALLOW_ORIGIN));
+    }
+}
@@ -0,0 +1,318 @@
+use std::time::Duration;
+
+use actix_web::{
+    error::JsonPayloadError,
+    http::{header::RETRY_AFTER, StatusCode},
+    HttpRequest, HttpResponse, ResponseError,
+};
+use serde::Serialize;
+use tokio::sync::mpsc::{self, error::TrySendError};
+use tokio::sync::oneshot;
+
+use super::EngineMessage;
+use crate::engine::EngineError;
+use crate::redis::client::RedisClientError;
+use crate::redis::subscriber::RedisSubscriberError;
+
+/// How long clients are asked to back off when the engine's queue is full
+const ENGINE_SATURATED_RETRY_AFTER: Duration = Duration::from_secs(1);
+
+/// Body of every error response. `code` is stable for clients to match on,
+/// `message` is for humans and may change.
+#[derive(Debug, Serialize, thiserror::Error)]
+#[error("[Server] {code}: {message}")]
+pub struct ApiError {
+    #[serde(skip)]
+    pub status: StatusCode,
+    pub code: &'static str,
+    pub message: String,
+    /// Sent as `Retry-After`, in whole seconds rounded up
+    #[serde(skip)]
+    pub retry_after: Option<Duration>,
+}
+
+impl ApiError {
+    pub fn new(status: StatusCode, code: &'static str, message: impl Into<String>) -> Self {
+        Self {
+            status,
+            code,
+            message: message.into(),
+            retry_after: None,
+        }
+    }
+
+    pub fn rate_limited(retry_after: Duration) -> Self {
+        Self {
+            retry_after: Some(retry_after),
+            ..Self::new(
+                StatusCode::TOO_MANY_REQUESTS,
+                "RATE_LIMITED",
+                format!(
+                    "Too many requests, retry in {}s",
+                    retry_after_secs(retry_after)
+                ),
+            )
+        }
+    }
+
+    /// The engine is gone, or dropped the request without answering
+    pub fn engine_unavailable(message: impl Into<String>) -> Self {
+        Self::new(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "ENGINE_UNAVAILABLE",
+            message,
+        )
+    }
+
+    /// The engine's queue is full, the request is refused rather than left
+    /// waiting for room
+    pub fn engine_saturated() -> Self {
+        Self {
+            retry_after: Some(ENGINE_SATURATED_RETRY_AFTER),
+            ..Self::new(
+                StatusCode::SERVICE_UNAVAILABLE,
+                "ENGINE_SATURATED",
+                "The engine is busy, try again shortly",
+            )
+        }
+    }
+
+    pub fn engine_timeout(message: impl Into<String>) -> Self {
+        Self::new(StatusCode::GATEWAY_TIMEOUT, "ENGINE_TIMEOUT", message)
+    }
+
+    /// The body of an item of a batch response, which carries its own status
+    pub fn to_batch_item(&self) -> serde_json::Value {
+        serde_json::json!({
+            "status": self.status.as_u16(),
+            "code": self.code,
+            "error": self.message
+        })
+    }
+}
+
+impl From<EngineError> for ApiError {
+    fn from(e: EngineError) -> Self {
+        let (status, code) = match &e {
+            EngineError::InvalidPipelineError(_) => (StatusCode::BAD_REQUEST, "INVALID_PIPELINE"),
+            EngineError::InvalidCursorError(_) => (StatusCode::BAD_REQUEST, "INVALID_CURSOR"),
+            EngineError::GetPipelineError(_) => (StatusCode::NOT_FOUND, "PIPELINE_NOT_FOUND"),
+            EngineError::PipelineSettledError(_) => (StatusCode::CONFLICT, "PIPELINE_SETTLED"),
+            EngineError::GetDeadLetterError(_) => (StatusCode::NOT_FOUND, "DEAD_LETTER_NOT_FOUND"),
+            EngineError::RetryDeadLetterError(_) => {
+                (StatusCode::UNPROCESSABLE_ENTITY, "DEAD_LETTER_RETRY_FAILED")
+            }
+            EngineError::ForceTriggerDisabledError => {
+                (StatusCode::FORBIDDEN, "FORCE_TRIGGER_DISABLED")
+            }
+            EngineError::AddPipelineError(e)
+            | EngineError::DeletePipelineError(e)
+            | EngineError::RedisClientError(e) => redis_status(e),
+            EngineError::RedisSubscriberError(RedisSubscriberError::Redis(_)) => {
+                (StatusCode::SERVICE_UNAVAILABLE, "REDIS_UNAVAILABLE")
+            }
+            EngineError::EvaluatePipelineError(_) => {
+                (StatusCode::INTERNAL_SERVER_ERROR, "EVALUATION_FAILED")
+            }
+            EngineError::ExecutorError(_) => (StatusCode::BAD_GATEWAY, "ACTION_FAILED"),
+            EngineError::RedisSubscriberError(_)
+            | EngineError::PriceSourceError(_)
+            | EngineError::ExtractAssetsError(_)
+            | EngineError::HandlePriceUpdateError(_)
+            | EngineError::Config { .. } => (StatusCode::INTERNAL_SERVER_ERROR, "INTERNAL_ERROR"),
+        };
+        Self::new(status, code, e.to_string())
+    }
+}
+
+fn redis_status(e: &RedisClientError) -> (StatusCode, &'static str) {
+    match e {
+        RedisClientError::ConnectionError(_)
+        | RedisClientError::Disconnected(_)
+        | RedisClientError::RedisError(_) => (StatusCode::SERVICE_UNAVAILABLE, "REDIS_UNAVAILABLE"),
+        RedisClientError::Conflict(_) => (StatusCode::CONFLICT, "PIPELINE_CONFLICT"),
+        RedisClientError::SerializeError(_) | RedisClientError::DeserializeError(_) => {
+            (StatusCode::INTERNAL_SERVER_ERROR, "INTERNAL_ERROR")
+        }
+    }
+}
+
+impl ResponseError for ApiError {
+    fn status_code(&self) -> StatusCode {
+        self.status
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        let mut response = HttpResponse::build(self.status);
+        if let Some(retry_after) = self.retry_after {
+            response.insert_header((RETRY_AFTER, retry_after_secs(retry_after)));
+        }
+        response.json(self)
+    }
+}
+
+fn retry_after_secs(retry_after: Duration) -> u64 {
+    retry_after.as_secs_f64().ceil().max(1.0) as u64
+}
+
+/// Error handler of the `JsonConfig`s, so bodies that are too large or
+/// fail to parse get the same error body as the rest of the API
+pub fn json_error(e: JsonPayloadError, _req: &HttpRequest) -> actix_web::Error {
+    match e {
+        JsonPayloadError::Overflow { limit }
+        | JsonPayloadError::OverflowKnownLength { limit, .. } => ApiError::new(
+            StatusCode::PAYLOAD_TOO_LARGE,
+            "PAYLOAD_TOO_LARGE",
+            format!("Body is larger than the limit of {} bytes", limit),
+        ),
+        e => ApiError::new(StatusCode::BAD_REQUEST, "INVALID_JSON", e.to_string()),
+    }
+    .into()
+}
+
+/// Queues the message for the engine without waiting for room, a full queue
+/// is `ApiError::engine_saturated`. Records the depth of the queue.
+pub fn queue_message(
+    engine_bridge_tx: &mpsc::Sender<EngineMessage>,
+    message: EngineMessage,
+) -> Result<(), ApiError> {
+    let result = engine_bridge_tx.try_send(message).map_err(|e| match e {
+        TrySendError::Full(_) => {
+            metrics::counter!("engine_queue_saturated", 1);
+            ApiError::engine_saturated()
+        }
+        TrySendError::Closed(_) => {
+            ApiError::engine_unavailable(format!("Failed to communicate with engine: {}", e))
+        }
+    });
+    metrics::gauge!(
+        "engine_queue_depth",
+        (engine_bridge_tx.max_capacity() - engine_bridge_tx.capacity()) as f64
+    );
+    result
+}
+
+/// Sends the message built around a fresh response channel to the engine and
+/// waits up to `timeout` for the answer. `what` names the request in the
+/// timeout message.
+pub async fn ask_engine<T>(
+    engine_bridge_tx: &mpsc::Sender<EngineMessage>,
+    timeout: Duration,
+    what: &str,
+    message: impl FnOnce(oneshot::Sender<Result<T, EngineError>>) -> EngineMessage,
+) -> Result<T, ApiError> {
+    let (response_tx, response_rx) = oneshot::channel();
+    queue_message(engine_bridge_tx, message(response_tx))?;
+
+    match tokio::time::timeout(timeout, response_rx).await {
+        Ok(Ok(result)) => result.map_err(ApiError::from),
+        Ok(Err(e)) => Err(ApiError::engine_unavailable(format!(
+            "Failed to receive response from engine: {}",
+            e
+        ))),
+        Err(_) => Err(ApiError::engine_timeout(format!("{} timed out", what))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_engine_error_codes() {
+        let cases = [
+            (
+                EngineError::GetPipelineError("-".to_string()),
+                StatusCode::NOT_FOUND,
+                "PIPELINE_NOT_FOUND",
+            ),
+            (
+                EngineError::InvalidPipelineError("-".to_string()),
+                StatusCode::BAD_REQUEST,
+                "INVALID_PIPELINE",
+            ),
+            (
+                EngineError::PipelineSettledError("-".to_string()),
+                StatusCode::CONFLICT,
+                "PIPELINE_SETTLED",
+            ),
+            (
+                EngineError::AddPipelineError(RedisClientError::RedisError(
+                    (bb8_redis::redis::ErrorKind::IoError, "refused").into(),
+                )),
+                StatusCode::SERVICE_UNAVAILABLE,
+                "REDIS_UNAVAILABLE",
+            ),
+            (
+                EngineError::RedisClientError(RedisClientError::Conflict("-".to_string())),
+                StatusCode::CONFLICT,
+                "PIPELINE_CONFLICT",
+            ),
+        ];
+        for (e, status, code) in cases {
+            let api_error = ApiError::from(e);
+            assert_eq!(api_error.status, status);
+            assert_eq!(api_error.code, code);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_ask_engine_errors() {
+        let (tx, mut rx) = mpsc::channel(16);
+        tokio::spawn(async move {
+            let mut held = Vec::new();
+            while let Some(msg) = rx.recv().await {
+                if let EngineMessage::GetPipeline { response_tx, .. } = msg {
+                    held.push(response_tx);
+                }
+            }
+        });
+        let result = ask_engine(&tx, Duration::from_millis(20), "Request", |response_tx| {
+            EngineMessage::GetPipeline {
+                pipeline_id: uuid::Uuid::new_v4(),
+                response_tx,
+            }
+        })
+        .await;
+        let e = result.unwrap_err();
+        assert_eq!(e.code, "ENGINE_TIMEOUT");
+        assert_eq!(e.message, "Request timed out");
+
+        let (tx, rx) = mpsc::channel(16);
+        drop(rx);
+        let result = ask_engine(&tx, Duration::from_millis(20), "Request", |response_tx| {
+            EngineMessage::GetPipeline {
+                pipeline_id: uuid::Uuid::new_v4(),
+                response_tx,
+            }
+        })
+        .await;
+        assert_eq!(result.unwrap_err().code, "ENGINE_UNAVAILABLE");
+    }
+
+    #[tokio::test]
+    async fn test_saturated_queue_is_refused() {
+        // the engine takes nothing off the queue, which holds one message
+        let (tx, _rx) = mpsc::channel(1);
+        let ask = || {
+            ask_engine(&tx, Duration::from_secs(30), "Request", |response_tx| {
+                EngineMessage::GetPipeline {
+                    pipeline_id: uuid::Uuid::new_v4(),
+                    response_tx,
+                }
+            })
+        };
+        // the first request is queued and waits for an answer, its message
+        // stays behind when it gives up
+        let queued = tokio::time::timeout(Duration::from_millis(50), ask()).await;
+        assert!(queued.is_err());
+
+        let started = std::time::Instant::now();
+        let e = ask().await.unwrap_err();
+        assert!(started.elapsed() < Duration::from_secs(1));
+        assert_eq!(e.status, StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(e.code, "ENGINE_SATURATED");
+        let response = e.error_response();
+        assert_eq!(response.headers().get(RETRY_AFTER).unwrap(), "1");
+    }
+}
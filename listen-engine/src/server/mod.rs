@@ -1,23 +1,55 @@
+pub mod access_log;
+pub mod auth;
+pub mod cors;
+pub mod error;
+pub mod openapi;
+pub mod rate_limit;
+pub mod version;
+pub mod ws;
+
 use actix_web::{
-    middleware,
+    http::StatusCode,
+    middleware::{self, from_fn},
     web::{self, Data},
-    App, HttpResponse, HttpServer, Responder,
+    App, HttpMessage, HttpRequest, HttpResponse, HttpServer, Responder,
 };
-use chrono::Utc;
+use chrono::{DateTime, Utc};
+use futures_util::future::BoxFuture;
+use futures_util::stream::{FuturesUnordered, StreamExt};
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
-use tokio::sync::mpsc;
+use std::sync::Arc;
+use std::time::Duration;
 use tokio::sync::oneshot;
+use tokio::sync::{broadcast, mpsc, watch};
 use uuid::Uuid;
 
 use crate::{
+    config::Config,
     engine::{
-        pipeline::{Pipeline, PipelineStep, Status},
+        constants::MAX_PAGE_SIZE,
+        dlq::DeadLetter,
+        evaluator::WhatIf,
+        events::PipelineEvent,
+        health::HealthReport,
+        history::ExecutionEvent,
+        jobs::{JobHandle, JobStatus, Jobs},
+        pipeline::{Pipeline, PipelinePage, PipelineStep, Status},
+        pnl::Pnl,
+        util::create_shared_http_client,
         Engine, EngineError,
     },
+    logging::LogFormat,
     metrics::metrics_handler,
 };
 
+use self::access_log::access_log;
+use self::auth::{require_admin_token, require_api_key, AdminToken, ApiKeyIdentity, ApiKeys};
+use self::cors::{cors, AllowedOrigins};
+use self::error::{ask_engine, json_error, queue_message, ApiError};
+use self::rate_limit::RateLimiter;
+use self::version::{deprecated_alias, API_V1};
+
 #[derive(Debug)]
 pub enum EngineMessage {
     AddPipeline {
@@ -28,20 +60,234 @@ pub enum EngineMessage {
         pipeline_id: Uuid,
         response_tx: oneshot::Sender<Result<Pipeline, EngineError>>,
     },
+    ListPipelines {
+        user_id: String,
+        limit: usize,
+        cursor: Option<String>,
+        response_tx: oneshot::Sender<Result<PipelinePage, EngineError>>,
+    },
+    PatchPipeline {
+        pipeline_id: Uuid,
+        patch: serde_json::Value,
+        response_tx: oneshot::Sender<Result<Pipeline, EngineError>>,
+    },
     DeletePipeline {
         pipeline_id: Uuid,
         response_tx: oneshot::Sender<Result<(), EngineError>>,
     },
+    PausePipeline {
+        pipeline_id: Uuid,
+        response_tx: oneshot::Sender<Result<Pipeline, EngineError>>,
+    },
+    ResumePipeline {
+        pipeline_id: Uuid,
+        response_tx: oneshot::Sender<Result<Pipeline, EngineError>>,
+    },
+    WhatIf {
+        pipeline_id: Uuid,
+        asset: String,
+        price: f64,
+        response_tx: oneshot::Sender<Result<WhatIf, EngineError>>,
+    },
+    Ping {
+        response_tx: oneshot::Sender<HealthReport>,
+    },
+    GetExecutionLog {
+        pipeline_id: Uuid,
+        response_tx: oneshot::Sender<Result<Vec<ExecutionEvent>, EngineError>>,
+    },
+    GetDeadLetters {
+        user_id: String,
+        response_tx: oneshot::Sender<Result<Vec<DeadLetter>, EngineError>>,
+    },
+    RetryDeadLetter {
+        user_id: String,
+        id: Uuid,
+        response_tx: oneshot::Sender<Result<DeadLetter, EngineError>>,
+    },
+    ForceTrigger {
+        pipeline_id: Uuid,
+        /// The user of the caller's key, none to force any user's pipeline
+        user_id: Option<String>,
+        response_tx: oneshot::Sender<Result<Vec<ExecutionEvent>, EngineError>>,
+    },
 }
 
+const DEFAULT_PAGE_SIZE: usize = 20;
+const DEFAULT_BIND_ADDR: &str = "0.0.0.0";
+const DEFAULT_PORT: u16 = 6966;
+pub(crate) const DEFAULT_MAX_BATCH_SIZE: usize = 500;
+pub(crate) const DEFAULT_REQUEST_TIMEOUT_MS: u64 = 5_000;
+pub(crate) const DEFAULT_ACTION_TIMEOUT_MS: u64 = 60_000;
+pub(crate) const DEFAULT_SHUTDOWN_GRACE_PERIOD_SECS: u64 = 30;
+pub(crate) const DEFAULT_MAX_PAYLOAD_BYTES: usize = 256 * 1024;
+pub(crate) const DEFAULT_RATE_LIMIT_PER_MINUTE: u32 = 120;
+// Body limit per pipeline in a batch, the JSON extractor defaults to 32KiB
+const BATCH_ITEM_PAYLOAD_LIMIT: usize = 32 * 1024;
+
 pub struct AppState {
     engine_bridge_tx: mpsc::Sender<EngineMessage>,
+    max_batch_size: usize,
+    // How long a request waits for the engine to respond
+    request_timeout: Duration,
+    // How long a request running actions waits for the engine to respond
+    action_timeout: Duration,
+    pipeline_events: broadcast::Sender<PipelineEvent>,
+    jobs: Jobs,
+    // Pipeline creations per API key, shared by the workers
+    creation_limiter: Arc<RateLimiter>,
+    // Outbound requests, shared by the workers and the engine
+    http_client: Arc<reqwest::Client>,
+}
+
+impl AppState {
+    /// Client for handlers making outbound requests, pooling connections
+    /// with the engine's actions rather than opening new ones per request
+    pub fn http_client(&self) -> Arc<reqwest::Client> {
+        self.http_client.clone()
+    }
+}
+
+/// Accepts JSON bodies of up to `limit` bytes
+fn json_config(limit: usize) -> web::JsonConfig {
+    web::JsonConfig::default()
+        .limit(limit)
+        .error_handler(json_error)
+}
+
+/// Addresses to serve on, from the comma-separated `LISTEN_BIND_ADDR` and
+/// `LISTEN_PORT`
+pub(crate) fn bind_addrs(
+    addrs: Option<&str>,
+    port: Option<&str>,
+) -> Result<Vec<(String, u16)>, String> {
+    let port = match port {
+        Some(port) => port
+            .trim()
+            .parse::<u16>()
+            .map_err(|e| format!("Invalid LISTEN_PORT {:?}: {}", port, e))?,
+        None => DEFAULT_PORT,
+    };
+    let addrs: Vec<(String, u16)> = addrs
+        .unwrap_or(DEFAULT_BIND_ADDR)
+        .split(',')
+        .map(str::trim)
+        .filter(|addr| !addr.is_empty())
+        .map(|addr| (addr.to_string(), port))
+        .collect();
+    if addrs.is_empty() {
+        return Err("LISTEN_BIND_ADDR has no addresses".to_string());
+    }
+    Ok(addrs)
+}
+
+/// Addresses to serve `/metrics` on apart from the API, from the
+/// comma-separated `host:port` of `METRICS_BIND_ADDR`, none when unset
+pub(crate) fn metrics_bind_addrs(addrs: Option<&str>) -> Result<Vec<(String, u16)>, String> {
+    addrs
+        .unwrap_or_default()
+        .split(',')
+        .map(str::trim)
+        .filter(|addr| !addr.is_empty())
+        .map(|addr| {
+            let (host, port) = addr
+                .rsplit_once(':')
+                .ok_or_else(|| format!("METRICS_BIND_ADDR {:?} has no port", addr))?;
+            let port = port
+                .parse::<u16>()
+                .map_err(|e| format!("Invalid METRICS_BIND_ADDR port {:?}: {}", addr, e))?;
+            // `[::]:9090` binds to `::`
+            let host = host.trim_start_matches('[').trim_end_matches(']');
+            Ok((host.to_string(), port))
+        })
+        .collect()
+}
+
+/// Whether the API's server serves `/metrics`, which it doesn't when they
+/// are disabled or have their own addresses
+fn metrics_on_api_server(config: &Config) -> bool {
+    config.metrics_enabled && config.metrics_bind_addrs.is_empty()
+}
+
+fn metrics_routes(cfg: &mut web::ServiceConfig) {
+    cfg.route("/metrics", web::get().to(metrics_handler));
+}
+
+/// Serves `/metrics` alone on `addrs`, e.g. on a port kept private
+fn metrics_server(addrs: &[(String, u16)]) -> std::io::Result<actix_web::dev::Server> {
+    let server = HttpServer::new(|| App::new().configure(metrics_routes))
+        .workers(1)
+        .disable_signals();
+    Ok(addrs
+        .iter()
+        .try_fold(server, |server, (addr, port)| {
+            tracing::info!("Serving metrics on {}:{}", addr, port);
+            server.bind((addr.as_str(), *port))
+        })?
+        .run())
+}
+
+/// Waits for the named tasks to finish, for at most `grace` in total.
+/// Returns the names of the tasks that were still pending.
+pub(crate) async fn drain(
+    tasks: Vec<(&'static str, BoxFuture<'_, ()>)>,
+    grace: Duration,
+) -> Vec<&'static str> {
+    let mut pending: Vec<&'static str> = tasks.iter().map(|(name, _)| *name).collect();
+    let mut running: FuturesUnordered<_> = tasks
+        .into_iter()
+        .map(|(name, task)| async move {
+            task.await;
+            name
+        })
+        .collect();
+    let _ = tokio::time::timeout(grace, async {
+        while let Some(name) = running.next().await {
+            pending.retain(|pending| *pending != name);
+        }
+    })
+    .await;
+    pending
 }
 
 pub async fn run() -> std::io::Result<()> {
+    let config = Config::from_env().map_err(|e| {
+        tracing::error!("{}", e);
+        std::io::Error::new(std::io::ErrorKind::InvalidInput, e.to_string())
+    })?;
+    tracing::info!("Loaded config: {:?}", config);
+
+    let api_keys = Data::new(ApiKeys::parse(config.api_keys.expose().unwrap_or_default()));
+    if api_keys.is_empty() {
+        tracing::warn!("API_KEYS is not set, every /api request will be rejected");
+    }
+    let admin_token = Data::new(AdminToken(config.admin_token.expose().map(str::to_string)));
+    let allowed_origins = Data::new(AllowedOrigins::parse(
+        config.allowed_origins.as_deref().unwrap_or_default(),
+    ));
+    let max_batch_size = config.max_batch_size;
+    let max_payload_bytes = config.max_payload_bytes;
+    let request_timeout = Duration::from_millis(config.request_timeout_ms);
+    let action_timeout = Duration::from_millis(config.action_timeout_ms);
+    let grace_period = Duration::from_secs(config.shutdown_grace_period_secs);
+    let creation_limiter = Arc::new(RateLimiter::per_minute(config.rate_limit_per_minute));
+    let http_client = Arc::new(create_shared_http_client().map_err(|e| {
+        tracing::error!("Failed to create HTTP client: {}", e);
+        std::io::Error::new(std::io::ErrorKind::Other, e.to_string())
+    })?);
+
     let (tx, rx) = mpsc::channel(1000);
-    let mut engine = match Engine::from_env().await {
+    let mut engine = match Engine::from_config(&config).await {
         Ok(engine) => engine,
+        Err(EngineError::Config { missing }) => {
+            for name in &missing {
+                tracing::error!("{} is missing or malformed", name);
+            }
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::Other,
+                format!("Failed to create engine, check {}", missing.join(", ")),
+            ));
+        }
         Err(e) => {
             tracing::error!("Failed to create engine: {}", e);
             return Err(std::io::Error::new(
@@ -50,9 +296,33 @@ pub async fn run() -> std::io::Result<()> {
             ));
         }
     };
+    engine = engine.with_http_client(http_client.clone());
+    let pipeline_events = engine.pipeline_events();
+    let jobs = engine.jobs();
+    let addrs = config.bind_addrs.clone();
+    let serve_metrics = metrics_on_api_server(&config);
+    let json_logs = config.log_format == LogFormat::Json;
+    if !config.metrics_enabled {
+        tracing::info!("METRICS_ENABLED is false, /metrics is not served");
+    }
+    let metrics_server = if config.metrics_enabled && !config.metrics_bind_addrs.is_empty() {
+        Some(metrics_server(&config.metrics_bind_addrs)?)
+    } else {
+        None
+    };
+    let metrics_server_handle = metrics_server.as_ref().map(|server| server.handle());
+    if let Some(server) = metrics_server {
+        tokio::spawn(async move {
+            if let Err(e) = server.await {
+                tracing::error!("Metrics server error: {}", e);
+            }
+        });
+    }
+    let config = Data::new(config);
 
     // Create a shutdown signal handler
     let (shutdown_tx, mut shutdown_rx) = mpsc::channel(1);
+    let (stop_engine_tx, stop_engine_rx) = watch::channel(false);
     let shutdown_tx_clone = shutdown_tx.clone();
 
     // Set up ctrl-c handler
@@ -62,31 +332,55 @@ pub async fn run() -> std::io::Result<()> {
         }
     });
 
-    // Main application server with metrics endpoint
+    // Main application server, with the metrics endpoint unless it has its own
     let server = HttpServer::new(move || {
         App::new()
             .app_data(Data::new(AppState {
                 engine_bridge_tx: tx.clone(),
+                max_batch_size,
+                request_timeout,
+                action_timeout,
+                pipeline_events: pipeline_events.clone(),
+                jobs: jobs.clone(),
+                creation_limiter: creation_limiter.clone(),
+                http_client: http_client.clone(),
             }))
-            .wrap(middleware::Logger::default())
-            .service(
-                web::scope("/api")
-                    .route("/healthz", web::get().to(healthz))
-                    .route("/pipeline", web::post().to(create_pipeline)),
-            )
-            .route("/metrics", web::get().to(metrics_handler))
-    })
-    .bind(("0.0.0.0", 6966))?
-    .run();
+            .app_data(api_keys.clone())
+            .app_data(admin_token.clone())
+            .app_data(allowed_origins.clone())
+            .app_data(config.clone())
+            .wrap(from_fn(cors))
+            .wrap(middleware::Condition::new(json_logs, from_fn(access_log)))
+            .wrap(middleware::Condition::new(
+                !json_logs,
+                middleware::Logger::default(),
+            ))
+            .configure(|cfg| routes(cfg, max_batch_size, max_payload_bytes))
+            .configure(|cfg| {
+                if serve_metrics {
+                    metrics_routes(cfg)
+                }
+            })
+    });
+    let server = addrs
+        .into_iter()
+        .try_fold(server, |server, (addr, port)| {
+            tracing::info!("Listening on {}:{}", addr, port);
+            server.bind((addr.as_str(), port))
+        })?
+        .run();
+    let server_handle = server.handle();
+    let mut server = server;
+    let mut engine_run = Box::pin(engine.run(rx, stop_engine_rx));
 
     tokio::select! {
-        result = server => {
+        result = &mut server => {
             let _ = shutdown_tx.send(()).await;
             if let Err(e) = result {
                 tracing::error!("Server error: {}", e);
             }
         }
-        result = engine.run(rx) => {
+        result = &mut engine_run => {
             let _ = shutdown_tx.send(()).await;
             if let Err(e) = result {
                 tracing::error!("Engine error: {}", e);
@@ -94,24 +388,317 @@ pub async fn run() -> std::io::Result<()> {
         }
         _ = shutdown_rx.recv() => {
             tracing::info!("Shutdown signal received, starting graceful shutdown");
+            // new connections are refused right away, in-flight requests
+            // are answered before the server stops, so the engine keeps
+            // taking messages until then and drains what is left after
+            let stop_server = server_handle.stop(true);
+            let pending = drain(
+                vec![
+                    (
+                        "http server",
+                        Box::pin(async move {
+                            let _ = tokio::join!(stop_server, server);
+                            let _ = stop_engine_tx.send(true);
+                        }),
+                    ),
+                    (
+                        "engine",
+                        Box::pin(async move {
+                            if let Err(e) = engine_run.await {
+                                tracing::error!("Engine error: {}", e);
+                            }
+                        }),
+                    ),
+                ],
+                grace_period,
+            )
+            .await;
+            if !pending.is_empty() {
+                tracing::warn!(
+                    ?pending,
+                    "Shutdown grace period of {:?} elapsed, exiting anyway",
+                    grace_period
+                );
+            }
         }
     }
 
+    if let Some(handle) = metrics_server_handle {
+        handle.stop(true).await;
+    }
     tracing::info!("Server shutdown complete");
     Ok(())
 }
 
-async fn healthz() -> impl Responder {
-    HttpResponse::Ok().json(serde_json::json!({
-        "status": "healthy"
+/// The API under `API_V1` and its deprecated `/api` alias
+fn routes(cfg: &mut web::ServiceConfig, max_batch_size: usize, max_payload_bytes: usize) {
+    // registered ahead of the scopes to stay unauthenticated
+    cfg.route("/api/healthz", web::get().to(healthz))
+        .route(
+            &format!("{}/openapi.json", API_V1),
+            web::get().to(openapi::openapi_json),
+        )
+        .service(
+            web::resource("/api/openapi.json")
+                .wrap(from_fn(deprecated_alias))
+                .route(web::get().to(openapi::openapi_json)),
+        )
+        // each versioned scope ahead of the unversioned alias, which
+        // would otherwise match its paths
+        .service(
+            web::scope(&format!("{}/admin", API_V1))
+                .wrap(from_fn(require_admin_token))
+                .configure(admin_routes),
+        )
+        .service(
+            web::scope(API_V1)
+                .wrap(from_fn(require_api_key))
+                .app_data(json_config(max_payload_bytes))
+                .configure(|cfg| api_routes(cfg, max_batch_size)),
+        )
+        .service(
+            web::scope("/api/admin")
+                .wrap(from_fn(require_admin_token))
+                .wrap(from_fn(deprecated_alias))
+                .configure(admin_routes),
+        )
+        .service(
+            web::scope("/api")
+                .wrap(from_fn(require_api_key))
+                .wrap(from_fn(deprecated_alias))
+                .app_data(json_config(max_payload_bytes))
+                .configure(|cfg| api_routes(cfg, max_batch_size)),
+        );
+}
+
+/// Routes of `/api/v1/admin`, behind `require_admin_token`
+fn admin_routes(cfg: &mut web::ServiceConfig) {
+    cfg.route("/config", web::get().to(get_config))
+        .route(
+            "/pipelines/bulk-delete",
+            web::post().to(bulk_delete_pipelines),
+        )
+        .route("/jobs/{id}", web::get().to(get_job))
+        .route("/jobs/{id}/cancel", web::post().to(cancel_job));
+}
+
+/// Routes of `/api/v1`, behind `require_api_key`
+fn api_routes(cfg: &mut web::ServiceConfig, max_batch_size: usize) {
+    cfg.route("/pipeline", web::post().to(create_pipeline))
+        .route("/pipelines", web::get().to(list_pipelines))
+        .service(
+            web::resource("/pipelines/batch")
+                .app_data(json_config(max_batch_size * BATCH_ITEM_PAYLOAD_LIMIT))
+                .route(web::post().to(create_pipelines_batch)),
+        )
+        .route("/pipeline/{id}", web::get().to(get_pipeline))
+        .route("/pipeline/{id}", web::patch().to(patch_pipeline))
+        .route("/pipeline/{id}", web::delete().to(delete_pipeline))
+        .route("/pipeline/{id}/pause", web::post().to(pause_pipeline))
+        .route("/pipeline/{id}/resume", web::post().to(resume_pipeline))
+        .route("/pipeline/{id}/pnl", web::get().to(get_pipeline_pnl))
+        .route(
+            "/pipeline/{id}/history",
+            web::get().to(get_pipeline_history),
+        )
+        .route("/pipeline/{id}/whatif", web::post().to(what_if_pipeline))
+        .route("/pipeline/{id}/trigger", web::post().to(force_trigger))
+        .route("/pipeline/{id}/ws", web::get().to(ws::pipeline_ws))
+        .route("/dlq", web::get().to(get_dead_letters))
+        .route("/dlq/{id}/retry", web::post().to(retry_dead_letter));
+}
+
+/// Probes Redis and the Solana RPC through the engine, which also shows the
+/// engine is still taking messages
+async fn healthz(state: Data<AppState>) -> impl Responder {
+    let check = async {
+        let (response_tx, response_rx) = oneshot::channel();
+        state
+            .engine_bridge_tx
+            .send(EngineMessage::Ping { response_tx })
+            .await
+            .ok()?;
+        response_rx.await.ok()
+    };
+
+    match tokio::time::timeout(std::time::Duration::from_secs(2), check).await {
+        Ok(Some(report)) => {
+            let failing = report.failing();
+            if failing.is_empty() {
+                HttpResponse::Ok().json(serde_json::json!({
+                    "status": "healthy",
+                    "dependencies": report
+                }))
+            } else {
+                HttpResponse::ServiceUnavailable().json(serde_json::json!({
+                    "status": "unhealthy",
+                    "failing": failing,
+                    "dependencies": report
+                }))
+            }
+        }
+        _ => HttpResponse::ServiceUnavailable().json(serde_json::json!({
+            "status": "unhealthy",
+            "failing": ["engine"]
+        })),
+    }
+}
+
+/// The loaded `Config`, secrets redacted
+async fn get_config(config: Data<Config>) -> impl Responder {
+    HttpResponse::Ok().json(config.as_ref())
+}
+
+#[derive(Debug, Deserialize)]
+pub struct BulkDeleteRequest {
+    pub user_id: String,
+}
+
+/// Starts deleting all of the user's pipelines, poll the returned job
+async fn bulk_delete_pipelines(
+    state: Data<AppState>,
+    req: web::Json<BulkDeleteRequest>,
+) -> impl Responder {
+    let engine_bridge_tx = state.engine_bridge_tx.clone();
+    let user_id = req.into_inner().user_id;
+    let job_id = state.jobs.spawn("bulk_delete_pipelines", move |job| {
+        bulk_delete(engine_bridge_tx, user_id, job)
+    });
+    HttpResponse::Accepted().json(serde_json::json!({
+        "status": "ok",
+        "job_id": job_id
     }))
 }
 
+/// Lists the user's pipelines, then deletes them one at a time through the
+/// engine, stopping early when the job is cancelled
+async fn bulk_delete(
+    engine_bridge_tx: mpsc::Sender<EngineMessage>,
+    user_id: String,
+    job: JobHandle,
+) -> Result<(), String> {
+    // collected up front, deleting while paging would shift the pages
+    let mut pipeline_ids = vec![];
+    let mut cursor = None;
+    loop {
+        if job.is_cancelled() {
+            return Ok(());
+        }
+        let (response_tx, response_rx) = oneshot::channel();
+        engine_bridge_tx
+            .send(EngineMessage::ListPipelines {
+                user_id: user_id.clone(),
+                limit: MAX_PAGE_SIZE,
+                cursor,
+                response_tx,
+            })
+            .await
+            .map_err(|e| e.to_string())?;
+        let page = response_rx
+            .await
+            .map_err(|e| e.to_string())?
+            .map_err(|e| e.to_string())?;
+        pipeline_ids.extend(page.pipelines.iter().map(|pipeline| pipeline.id));
+        cursor = match page.next_cursor {
+            Some(cursor) => Some(cursor),
+            None => break,
+        };
+    }
+
+    job.set_total(pipeline_ids.len());
+    for pipeline_id in pipeline_ids {
+        if job.is_cancelled() {
+            return Ok(());
+        }
+        let (response_tx, response_rx) = oneshot::channel();
+        engine_bridge_tx
+            .send(EngineMessage::DeletePipeline {
+                pipeline_id,
+                response_tx,
+            })
+            .await
+            .map_err(|e| e.to_string())?;
+        match response_rx.await.map_err(|e| e.to_string())? {
+            // deleted in the meantime
+            Ok(()) | Err(EngineError::GetPipelineError(_)) => job.advance(1),
+            Err(e) => return Err(e.to_string()),
+        }
+    }
+    Ok(())
+}
+
+fn job_not_found() -> ApiError {
+    ApiError::new(StatusCode::NOT_FOUND, "JOB_NOT_FOUND", "Job not found")
+}
+
+async fn get_job(state: Data<AppState>, job_id: web::Path<Uuid>) -> Result<HttpResponse, ApiError> {
+    let job = state
+        .jobs
+        .get(job_id.into_inner())
+        .ok_or_else(job_not_found)?;
+    Ok(HttpResponse::Ok().json(job))
+}
+
+async fn cancel_job(
+    state: Data<AppState>,
+    job_id: web::Path<Uuid>,
+) -> Result<HttpResponse, ApiError> {
+    match state.jobs.cancel(job_id.into_inner()) {
+        Some(job) if job.status == JobStatus::Running => Ok(HttpResponse::Ok().json(job)),
+        // the finished job is part of the answer
+        Some(job) => Ok(HttpResponse::Conflict().json(serde_json::json!({
+            "code": "JOB_FINISHED",
+            "message": "Job already finished",
+            "job": job
+        }))),
+        None => Err(job_not_found()),
+    }
+}
+
 #[derive(Debug, Deserialize, Serialize)]
 pub struct CreatePipelineRequest {
     pub user_id: String,
     pub current_steps: Vec<Uuid>,
     pub steps: HashMap<Uuid, PipelineStep>,
+    #[serde(default)]
+    pub eval_interval_ms: Option<u64>,
+    #[serde(default)]
+    pub expires_at: Option<DateTime<Utc>>,
+    #[serde(default)]
+    pub dry_run: bool,
+}
+
+impl CreatePipelineRequest {
+    /// Every step id in `current_steps` and `next_steps` must be a key of
+    /// `steps`, and a pipeline with steps must start from at least one
+    pub fn validate(&self) -> Result<(), ApiError> {
+        if self.current_steps.is_empty() && !self.steps.is_empty() {
+            return Err(invalid_pipeline(
+                "No current steps to start from".to_string(),
+            ));
+        }
+        let mut unknown: Vec<Uuid> = self
+            .current_steps
+            .iter()
+            .chain(self.steps.values().flat_map(|step| step.next_steps.iter()))
+            .filter(|id| !self.steps.contains_key(id))
+            .copied()
+            .collect();
+        if unknown.is_empty() {
+            return Ok(());
+        }
+        unknown.sort();
+        unknown.dedup();
+        let unknown: Vec<String> = unknown.iter().map(Uuid::to_string).collect();
+        Err(invalid_pipeline(format!(
+            "Unknown step ids: {}",
+            unknown.join(", ")
+        )))
+    }
+}
+
+fn invalid_pipeline(message: String) -> ApiError {
+    ApiError::new(StatusCode::BAD_REQUEST, "INVALID_PIPELINE", message)
 }
 
 impl From<CreatePipelineRequest> for Pipeline {
@@ -123,72 +710,1341 @@ impl From<CreatePipelineRequest> for Pipeline {
             steps: req.steps,
             status: Status::Pending,
             created_at: Utc::now(),
+            swap_history: vec![],
+            failure_reason: None,
+            eval_interval_ms: req.eval_interval_ms,
+            depth: 0,
+            expires_at: req.expires_at,
+            version: 0,
+            dry_run: req.dry_run,
+            dry_run_triggers: vec![],
         }
     }
 }
 
 async fn create_pipeline(
     state: Data<AppState>,
+    http_req: HttpRequest,
     req: web::Json<CreatePipelineRequest>,
-) -> impl Responder {
+) -> Result<HttpResponse, ApiError> {
     let start = std::time::Instant::now();
     metrics::counter!("pipeline_creation_attempts", 1);
 
-    let pipeline: Pipeline = req.into_inner().into();
+    let mut req = req.into_inner();
+    if let Some(user_id) = bound_user(&http_req) {
+        req.user_id = user_id;
+    }
+    if let Err(retry_after) = state
+        .creation_limiter
+        .check(&creation_caller(&http_req, &req.user_id))
+    {
+        metrics::counter!("pipeline_creation_rate_limited", 1);
+        return Err(ApiError::rate_limited(retry_after));
+    }
+    if let Err(e) = req.validate() {
+        metrics::counter!("pipeline_creation_errors", 1);
+        return Err(e);
+    }
+    let pipeline: Pipeline = req.into();
+    let result = ask_engine(
+        &state.engine_bridge_tx,
+        state.request_timeout,
+        "Pipeline creation",
+        |response_tx| EngineMessage::AddPipeline {
+            pipeline,
+            response_tx,
+        },
+    )
+    .await;
+    match &result {
+        Ok(()) => metrics::counter!("pipeline_creation_success", 1),
+        Err(e) if e.status == StatusCode::GATEWAY_TIMEOUT => {
+            metrics::counter!("pipeline_creation_timeouts", 1)
+        }
+        Err(_) => metrics::counter!("pipeline_creation_errors", 1),
+    }
+
+    metrics::histogram!("pipeline_creation_duration", start.elapsed());
+    result?;
+    Ok(HttpResponse::Created().json(serde_json::json!({
+        "status": "success",
+        "message": "Pipeline created successfully"
+    })))
+}
 
-    // Create oneshot channel for response
-    let (response_tx, response_rx) = oneshot::channel();
+/// The user the request's API key is bound to, none for a service key,
+/// which acts for any user
+fn bound_user(http_req: &HttpRequest) -> Option<String> {
+    http_req
+        .extensions()
+        .get::<ApiKeyIdentity>()
+        .and_then(|identity| identity.user_id.clone())
+}
 
-    // Send message to engine
-    if let Err(e) = state
-        .engine_bridge_tx
-        .send(EngineMessage::AddPipeline {
-            pipeline,
+/// The user a request acts for: the one its key is bound to, whatever
+/// `user_id` it gives, otherwise the one it gives
+fn acting_user(http_req: &HttpRequest, user_id: Option<String>) -> Result<String, ApiError> {
+    bound_user(http_req).or(user_id).ok_or_else(|| {
+        ApiError::new(
+            StatusCode::BAD_REQUEST,
+            "MISSING_USER_ID",
+            "A user_id is required with a service key",
+        )
+    })
+}
+
+/// Another user's pipeline is as good as missing to a key bound to a user
+fn check_owner(http_req: &HttpRequest, pipeline: &Pipeline) -> Result<(), ApiError> {
+    match bound_user(http_req) {
+        Some(user_id) if pipeline.user_id != user_id => Err(EngineError::GetPipelineError(
+            format!("Pipeline not found: {}", pipeline.id),
+        )
+        .into()),
+        _ => Ok(()),
+    }
+}
+
+/// Checks the pipeline is the caller's before acting on it, which takes
+/// asking the engine for it with a key bound to a user only
+async fn authorize_pipeline(
+    state: &AppState,
+    http_req: &HttpRequest,
+    pipeline_id: Uuid,
+) -> Result<(), ApiError> {
+    if bound_user(http_req).is_none() {
+        return Ok(());
+    }
+    let pipeline = ask_engine(
+        &state.engine_bridge_tx,
+        state.request_timeout,
+        "Pipeline request",
+        |response_tx| EngineMessage::GetPipeline {
+            pipeline_id,
             response_tx,
-        })
-        .await
-    {
-        metrics::counter!("pipeline_creation_errors", 1);
-        return HttpResponse::InternalServerError().json(serde_json::json!({
-            "status": "error",
-            "message": format!("Failed to communicate with engine: {}", e)
-        }));
-    }
-
-    // Wait for response with timeout
-    let result = match tokio::time::timeout(std::time::Duration::from_secs(5), response_rx).await {
-        Ok(response) => match response {
-            Ok(Ok(_)) => {
-                metrics::counter!("pipeline_creation_success", 1);
-                HttpResponse::Created().json(serde_json::json!({
-                    "status": "success",
-                    "message": "Pipeline created successfully"
-                }))
+        },
+    )
+    .await?;
+    check_owner(http_req, &pipeline)
+}
+
+/// Whom a pipeline creation is charged to. By API key, all of the users
+/// created through it share its limit.
+fn creation_caller(http_req: &HttpRequest, user_id: &str) -> String {
+    match http_req.extensions().get::<ApiKeyIdentity>() {
+        Some(identity) => format!("key:{}", identity.key),
+        None => format!("user:{}", user_id),
+    }
+}
+
+/// Creates each pipeline independently, answering 207 with a result per
+/// item in request order. Each pipeline takes a token of the creation
+/// limit, those past it fail with 429.
+async fn create_pipelines_batch(
+    state: Data<AppState>,
+    http_req: HttpRequest,
+    req: web::Json<Vec<CreatePipelineRequest>>,
+) -> Result<HttpResponse, ApiError> {
+    let requests = req.into_inner();
+    if requests.len() > state.max_batch_size {
+        return Err(ApiError::new(
+            StatusCode::PAYLOAD_TOO_LARGE,
+            "BATCH_TOO_LARGE",
+            format!(
+                "Batch of {} pipelines exceeds the limit of {}",
+                requests.len(),
+                state.max_batch_size
+            ),
+        ));
+    }
+    metrics::counter!("pipeline_creation_attempts", requests.len() as u64);
+    let request_timeout = state.request_timeout;
+
+    // Queue every pipeline before waiting, the engine works through them in order
+    let mut pending = Vec::with_capacity(requests.len());
+    for mut req in requests {
+        if let Some(user_id) = bound_user(&http_req) {
+            req.user_id = user_id;
+        }
+        let limited = state
+            .creation_limiter
+            .check(&creation_caller(&http_req, &req.user_id))
+            .err();
+        let invalid = match limited {
+            Some(retry_after) => {
+                metrics::counter!("pipeline_creation_rate_limited", 1);
+                Some(ApiError::rate_limited(retry_after))
             }
-            Ok(Err(e)) => {
-                metrics::counter!("pipeline_creation_errors", 1);
-                HttpResponse::InternalServerError().json(serde_json::json!({
-                    "status": "error",
-                    "message": format!("Failed to create pipeline: {}", e)
-                }))
+            None => req.validate().err(),
+        };
+        let pipeline: Pipeline = req.into();
+        let pipeline_id = pipeline.id;
+        if let Some(e) = invalid {
+            pending.push((pipeline_id, Err(e)));
+            continue;
+        }
+        let (response_tx, response_rx) = oneshot::channel();
+        let sent = queue_message(
+            &state.engine_bridge_tx,
+            EngineMessage::AddPipeline {
+                pipeline,
+                response_tx,
+            },
+        )
+        .map(|_| response_rx);
+        pending.push((pipeline_id, sent));
+    }
+
+    let results =
+        futures_util::future::join_all(pending.into_iter().map(|(pipeline_id, sent)| async move {
+            let response_rx = match sent {
+                Ok(response_rx) => response_rx,
+                Err(e) => return batch_item_error(e),
+            };
+            match tokio::time::timeout(request_timeout, response_rx).await {
+                Ok(Ok(Ok(()))) => {
+                    metrics::counter!("pipeline_creation_success", 1);
+                    serde_json::json!({
+                        "status": StatusCode::CREATED.as_u16(),
+                        "id": pipeline_id
+                    })
+                }
+                Ok(Ok(Err(e))) => batch_item_error(e.into()),
+                Ok(Err(e)) => batch_item_error(ApiError::engine_unavailable(format!(
+                    "Failed to receive response from engine: {}",
+                    e
+                ))),
+                Err(_) => {
+                    metrics::counter!("pipeline_creation_timeouts", 1);
+                    ApiError::engine_timeout("Pipeline creation timed out").to_batch_item()
+                }
             }
-            Err(e) => {
-                metrics::counter!("pipeline_creation_errors", 1);
-                HttpResponse::InternalServerError().json(serde_json::json!({
-                    "status": "error",
-                    "message": format!("Failed to receive response from engine: {}", e)
+        }))
+        .await;
+
+    Ok(HttpResponse::MultiStatus().json(serde_json::json!({ "results": results })))
+}
+
+fn batch_item_error(e: ApiError) -> serde_json::Value {
+    metrics::counter!("pipeline_creation_errors", 1);
+    e.to_batch_item()
+}
+
+async fn get_pipeline(
+    state: Data<AppState>,
+    http_req: HttpRequest,
+    pipeline_id: web::Path<Uuid>,
+) -> Result<HttpResponse, ApiError> {
+    let start = std::time::Instant::now();
+    metrics::counter!("pipeline_get_attempts", 1);
+
+    let result = ask_engine(
+        &state.engine_bridge_tx,
+        state.request_timeout,
+        "Pipeline request",
+        |response_tx| EngineMessage::GetPipeline {
+            pipeline_id: pipeline_id.into_inner(),
+            response_tx,
+        },
+    )
+    .await
+    .and_then(|pipeline| check_owner(&http_req, &pipeline).map(|()| pipeline));
+    match &result {
+        Ok(_) => metrics::counter!("pipeline_get_success", 1),
+        Err(_) => metrics::counter!("pipeline_get_errors", 1),
+    }
+
+    metrics::histogram!("pipeline_get_duration", start.elapsed());
+    Ok(HttpResponse::Ok().json(result?))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ListPipelinesQuery {
+    pub user_id: Option<String>,
+    pub limit: Option<usize>,
+    pub cursor: Option<String>,
+}
+
+async fn list_pipelines(
+    state: Data<AppState>,
+    http_req: HttpRequest,
+    query: web::Query<ListPipelinesQuery>,
+) -> Result<HttpResponse, ApiError> {
+    let query = query.into_inner();
+    let user_id = acting_user(&http_req, query.user_id)?;
+    let page = ask_engine(
+        &state.engine_bridge_tx,
+        state.request_timeout,
+        "Pipeline listing",
+        |response_tx| EngineMessage::ListPipelines {
+            user_id,
+            limit: query.limit.unwrap_or(DEFAULT_PAGE_SIZE),
+            cursor: query.cursor,
+            response_tx,
+        },
+    )
+    .await?;
+    Ok(HttpResponse::Ok().json(page))
+}
+
+/// Partial update with a JSON merge patch, see `Pipeline::patched`. Pipelines
+/// that completed or failed are rejected with 409.
+async fn patch_pipeline(
+    state: Data<AppState>,
+    http_req: HttpRequest,
+    pipeline_id: web::Path<Uuid>,
+    patch: web::Json<serde_json::Value>,
+) -> Result<HttpResponse, ApiError> {
+    let pipeline_id = pipeline_id.into_inner();
+    authorize_pipeline(&state, &http_req, pipeline_id).await?;
+    let pipeline = ask_engine(
+        &state.engine_bridge_tx,
+        state.request_timeout,
+        "Pipeline patch",
+        |response_tx| EngineMessage::PatchPipeline {
+            pipeline_id,
+            patch: patch.into_inner(),
+            response_tx,
+        },
+    )
+    .await?;
+    Ok(HttpResponse::Ok().json(pipeline))
+}
+
+/// Stops the pipeline from being evaluated, its conditions included, until
+/// resumed. Only pending pipelines can be paused, others are rejected with
+/// 409.
+async fn pause_pipeline(
+    state: Data<AppState>,
+    http_req: HttpRequest,
+    pipeline_id: web::Path<Uuid>,
+) -> Result<HttpResponse, ApiError> {
+    let pipeline_id = pipeline_id.into_inner();
+    authorize_pipeline(&state, &http_req, pipeline_id).await?;
+    let pipeline = ask_engine(
+        &state.engine_bridge_tx,
+        state.request_timeout,
+        "Pipeline pause",
+        |response_tx| EngineMessage::PausePipeline {
+            pipeline_id,
+            response_tx,
+        },
+    )
+    .await?;
+    Ok(HttpResponse::Ok().json(pipeline))
+}
+
+/// Evaluates a paused pipeline again from the next price update
+async fn resume_pipeline(
+    state: Data<AppState>,
+    http_req: HttpRequest,
+    pipeline_id: web::Path<Uuid>,
+) -> Result<HttpResponse, ApiError> {
+    let pipeline_id = pipeline_id.into_inner();
+    authorize_pipeline(&state, &http_req, pipeline_id).await?;
+    let pipeline = ask_engine(
+        &state.engine_bridge_tx,
+        state.request_timeout,
+        "Pipeline resume",
+        |response_tx| EngineMessage::ResumePipeline {
+            pipeline_id,
+            response_tx,
+        },
+    )
+    .await?;
+    Ok(HttpResponse::Ok().json(pipeline))
+}
+
+async fn delete_pipeline(
+    state: Data<AppState>,
+    http_req: HttpRequest,
+    pipeline_id: web::Path<Uuid>,
+) -> Result<HttpResponse, ApiError> {
+    let pipeline_id = pipeline_id.into_inner();
+    authorize_pipeline(&state, &http_req, pipeline_id).await?;
+    ask_engine(
+        &state.engine_bridge_tx,
+        state.request_timeout,
+        "Pipeline deletion",
+        |response_tx| EngineMessage::DeletePipeline {
+            pipeline_id,
+            response_tx,
+        },
+    )
+    .await?;
+    Ok(HttpResponse::NoContent().finish())
+}
+
+async fn get_pipeline_pnl(
+    state: Data<AppState>,
+    http_req: HttpRequest,
+    pipeline_id: web::Path<Uuid>,
+) -> Result<HttpResponse, ApiError> {
+    let pipeline = ask_engine(
+        &state.engine_bridge_tx,
+        state.request_timeout,
+        "Pipeline request",
+        |response_tx| EngineMessage::GetPipeline {
+            pipeline_id: pipeline_id.into_inner(),
+            response_tx,
+        },
+    )
+    .await?;
+    check_owner(&http_req, &pipeline)?;
+    Ok(HttpResponse::Ok().json(Pnl::from_swaps(&pipeline.swap_history)))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct WhatIfRequest {
+    pub asset: String,
+    pub price: f64,
+}
+
+/// Evaluates the pipeline's conditions at a hypothetical price for one asset
+async fn what_if_pipeline(
+    state: Data<AppState>,
+    http_req: HttpRequest,
+    pipeline_id: web::Path<Uuid>,
+    req: web::Json<WhatIfRequest>,
+) -> Result<HttpResponse, ApiError> {
+    let WhatIfRequest { asset, price } = req.into_inner();
+    if !price.is_finite() || price < 0.0 {
+        return Err(ApiError::new(
+            StatusCode::BAD_REQUEST,
+            "INVALID_PRICE",
+            format!("Invalid price: {}", price),
+        ));
+    }
+    let pipeline_id = pipeline_id.into_inner();
+    authorize_pipeline(&state, &http_req, pipeline_id).await?;
+    let what_if = ask_engine(
+        &state.engine_bridge_tx,
+        state.request_timeout,
+        "Pipeline request",
+        |response_tx| EngineMessage::WhatIf {
+            pipeline_id,
+            asset,
+            price,
+            response_tx,
+        },
+    )
+    .await?;
+    Ok(HttpResponse::Ok().json(what_if))
+}
+
+/// Runs the actions of the pipeline's current steps now, regardless of their
+/// conditions, and returns what they did. Only with `FORCE_TRIGGER_ENABLED`
+async fn force_trigger(
+    state: Data<AppState>,
+    http_req: HttpRequest,
+    pipeline_id: web::Path<Uuid>,
+) -> Result<HttpResponse, ApiError> {
+    let user_id = bound_user(&http_req);
+    // the actions are executed before the engine responds
+    let executions = ask_engine(
+        &state.engine_bridge_tx,
+        state.action_timeout,
+        "Forced trigger",
+        |response_tx| EngineMessage::ForceTrigger {
+            pipeline_id: pipeline_id.into_inner(),
+            user_id,
+            response_tx,
+        },
+    )
+    .await?;
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "status": "success",
+        "executions": executions
+    })))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct DeadLetterQuery {
+    pub user_id: Option<String>,
+}
+
+/// Steps of the pipeline that fired and the outcome of their actions, newest
+/// first and at most `EXECUTION_LOG_MAX_LEN` of them
+async fn get_pipeline_history(
+    state: Data<AppState>,
+    http_req: HttpRequest,
+    pipeline_id: web::Path<Uuid>,
+) -> Result<HttpResponse, ApiError> {
+    let pipeline_id = pipeline_id.into_inner();
+    authorize_pipeline(&state, &http_req, pipeline_id).await?;
+    let events = ask_engine(
+        &state.engine_bridge_tx,
+        state.request_timeout,
+        "Pipeline history request",
+        |response_tx| EngineMessage::GetExecutionLog {
+            pipeline_id,
+            response_tx,
+        },
+    )
+    .await?;
+    Ok(HttpResponse::Ok().json(events))
+}
+
+async fn get_dead_letters(
+    state: Data<AppState>,
+    http_req: HttpRequest,
+    query: web::Query<DeadLetterQuery>,
+) -> Result<HttpResponse, ApiError> {
+    let user_id = acting_user(&http_req, query.into_inner().user_id)?;
+    let dead_letters = ask_engine(
+        &state.engine_bridge_tx,
+        state.request_timeout,
+        "Dead letter request",
+        |response_tx| EngineMessage::GetDeadLetters {
+            user_id,
+            response_tx,
+        },
+    )
+    .await?;
+    Ok(HttpResponse::Ok().json(dead_letters))
+}
+
+async fn retry_dead_letter(
+    state: Data<AppState>,
+    http_req: HttpRequest,
+    id: web::Path<Uuid>,
+    query: web::Query<DeadLetterQuery>,
+) -> Result<HttpResponse, ApiError> {
+    let user_id = acting_user(&http_req, query.into_inner().user_id)?;
+    // the retried action is executed before the engine responds
+    let dead_letter = ask_engine(
+        &state.engine_bridge_tx,
+        state.action_timeout,
+        "Dead letter retry",
+        |response_tx| EngineMessage::RetryDeadLetter {
+            user_id,
+            id: id.into_inner(),
+            response_tx,
+        },
+    )
+    .await?;
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "status": "success",
+        "dead_letter": dead_letter
+    })))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::evaluator::Evaluator;
+    use actix_web::http::Method;
+    use std::sync::Mutex;
+
+    #[test]
+    fn test_bind_addrs() {
+        assert_eq!(
+            bind_addrs(None, None).unwrap(),
+            vec![("0.0.0.0".to_string(), 6966)]
+        );
+        assert_eq!(
+            bind_addrs(Some("0.0.0.0, ::"), Some("8080")).unwrap(),
+            vec![("0.0.0.0".to_string(), 8080), ("::".to_string(), 8080)]
+        );
+        assert!(bind_addrs(None, Some("http")).is_err());
+        assert!(bind_addrs(None, Some("70000")).is_err());
+        assert!(bind_addrs(Some(" , "), None).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_drain_bounded_by_grace_period() {
+        let started = std::time::Instant::now();
+        let pending = drain(
+            vec![
+                (
+                    "swap",
+                    Box::pin(tokio::time::sleep(Duration::from_millis(10))),
+                ),
+                ("stuck", Box::pin(std::future::pending())),
+            ],
+            Duration::from_millis(200),
+        )
+        .await;
+        assert_eq!(pending, vec!["stuck"]);
+        assert!(started.elapsed() < Duration::from_secs(1));
+
+        // nothing pending returns without waiting out the grace period
+        let started = std::time::Instant::now();
+        let pending = drain(vec![("swap", Box::pin(async {}))], Duration::from_secs(30)).await;
+        assert!(pending.is_empty());
+        assert!(started.elapsed() < Duration::from_secs(1));
+    }
+
+    #[actix_web::test]
+    async fn test_get_config() {
+        let vars = HashMap::from([
+            ("MAX_STEP_DEPTH", "8"),
+            ("EVAL_INTERVAL_MS", "10"),
+            ("ADMIN_TOKEN", "admin"),
+            ("PRIVY_APP_SECRET", "privy-secret"),
+        ]);
+        let config = Config::from_vars(|name| vars.get(name).map(|v| v.to_string())).unwrap();
+        let app = actix_web::test::init_service(
+            App::new()
+                .app_data(Data::new(AdminToken(Some("admin".to_string()))))
+                .app_data(Data::new(config))
+                .service(
+                    web::scope("/api/admin")
+                        .wrap(from_fn(require_admin_token))
+                        .route("/config", web::get().to(get_config)),
+                ),
+        )
+        .await;
+
+        let req = actix_web::test::TestRequest::get()
+            .uri("/api/admin/config")
+            .insert_header(("Authorization", "Bearer admin"))
+            .to_request();
+        let res = actix_web::test::call_service(&app, req).await;
+        assert_eq!(res.status(), StatusCode::OK);
+        let body: serde_json::Value = actix_web::test::read_body_json(res).await;
+        assert_eq!(body["bind_addrs"], serde_json::json!(["0.0.0.0:6966"]));
+        assert_eq!(body["max_batch_size"], 500);
+        assert_eq!(body["request_timeout_ms"], 5_000);
+        assert_eq!(body["action_timeout_ms"], 60_000);
+        assert_eq!(body["max_payload_bytes"], 256 * 1024);
+        assert_eq!(body["max_slippage_bps"], 1_000);
+        assert_eq!(body["max_step_depth"], 8);
+        // raised to the floor
+        assert_eq!(body["eval_interval_ms"], 50);
+        assert_eq!(body["swap_actions_enabled"], false);
+        assert_eq!(body["telegram_api_url"], "https://api.telegram.org");
+        assert_eq!(body["privy_app_secret"], "***");
+        assert_eq!(body["admin_token"], "***");
+        assert_eq!(body["redis_url"], "***");
+        assert!(body["api_keys"].is_null());
+        assert!(!body.to_string().contains("privy-secret"));
+
+        let req = actix_web::test::TestRequest::get()
+            .uri("/api/admin/config")
+            .insert_header(("Authorization", "Bearer key-a"))
+            .to_request();
+        let res = actix_web::test::call_service(&app, req).await;
+        assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+    }
+
+    #[test]
+    fn test_create_price_below_pipeline() {
+        let step_id = Uuid::new_v4();
+        let req: CreatePipelineRequest = serde_json::from_value(serde_json::json!({
+            "user_id": "user",
+            "current_steps": [step_id],
+            "steps": {
+                step_id.to_string(): {
+                    "id": step_id,
+                    "action": { "Notification": { "message": "stop loss" } },
+                    "conditions": [
+                        {
+                            "condition_type": { "PriceAbove": { "asset": "BONK", "threshold": 0.00002 } },
+                            "triggered": false,
+                            "last_evaluated": null
+                        },
+                        {
+                            "condition_type": { "PriceBelow": { "asset": "SOL", "threshold": 80.0 } },
+                            "triggered": false,
+                            "last_evaluated": null
+                        }
+                    ],
+                    "next_steps": [],
+                    "status": "Pending"
+                }
+            }
+        }))
+        .unwrap();
+        let mut pipeline: Pipeline = req.into();
+        let conditions = &mut pipeline.steps.get_mut(&step_id).unwrap().conditions;
+        let (pipelines, swaps) = (HashMap::new(), HashMap::new());
+
+        let prices = HashMap::from([("BONK".to_string(), 0.00003), ("SOL".to_string(), 100.0)]);
+        assert!(
+            !Evaluator::evaluate_conditions(conditions, &prices, &pipelines, &swaps, None).unwrap()
+        );
+        assert!(conditions[0].triggered);
+        assert!(!conditions[1].triggered);
+        assert!(conditions[1].last_evaluated.is_some());
+
+        let prices = HashMap::from([("BONK".to_string(), 0.00003), ("SOL".to_string(), 79.5)]);
+        assert!(
+            Evaluator::evaluate_conditions(conditions, &prices, &pipelines, &swaps, None).unwrap()
+        );
+        assert!(conditions[1].triggered);
+
+        // and it no longer holds once the price recovers
+        let prices = HashMap::from([("BONK".to_string(), 0.00003), ("SOL".to_string(), 85.0)]);
+        assert!(
+            !Evaluator::evaluate_conditions(conditions, &prices, &pipelines, &swaps, None).unwrap()
+        );
+        assert!(!conditions[1].triggered);
+    }
+
+    fn notification_step(id: Uuid, next_steps: &[Uuid]) -> PipelineStep {
+        serde_json::from_value(serde_json::json!({
+            "id": id,
+            "action": { "Notification": { "message": "-" } },
+            "conditions": [],
+            "next_steps": next_steps,
+            "status": "Pending"
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn test_dangling_step_references() {
+        let (first, second, missing) = (Uuid::new_v4(), Uuid::new_v4(), Uuid::new_v4());
+        let mut req = create_request("a");
+        req.steps = HashMap::from([
+            (first, notification_step(first, &[second])),
+            (second, notification_step(second, &[])),
+        ]);
+        req.current_steps = vec![first];
+        assert!(req.validate().is_ok());
+
+        // unknown current step
+        req.current_steps = vec![first, missing];
+        let e = req.validate().unwrap_err();
+        assert_eq!(
+            (e.status, e.code),
+            (StatusCode::BAD_REQUEST, "INVALID_PIPELINE")
+        );
+        assert!(e.message.contains(&missing.to_string()));
+
+        // unknown next steps, all of them listed
+        let other = Uuid::new_v4();
+        req.current_steps = vec![first];
+        req.steps
+            .insert(second, notification_step(second, &[missing, other]));
+        let e = req.validate().unwrap_err();
+        assert!(e.message.contains(&missing.to_string()));
+        assert!(e.message.contains(&other.to_string()));
+
+        // steps with nowhere to start
+        req.steps.insert(second, notification_step(second, &[]));
+        req.current_steps = vec![];
+        let e = req.validate().unwrap_err();
+        assert_eq!(e.code, "INVALID_PIPELINE");
+        assert!(e.message.contains("current steps"));
+    }
+
+    /// State of a server in front of `engine_bridge_tx`, without a rate
+    /// limit and with small batches
+    fn test_state(engine_bridge_tx: mpsc::Sender<EngineMessage>) -> AppState {
+        AppState {
+            engine_bridge_tx,
+            max_batch_size: 3,
+            request_timeout: Duration::from_secs(5),
+            action_timeout: Duration::from_secs(5),
+            pipeline_events: broadcast::channel(16).0,
+            jobs: Jobs::default(),
+            creation_limiter: Arc::new(RateLimiter::per_minute(0)),
+            http_client: Arc::new(reqwest::Client::new()),
+        }
+    }
+
+    /// Engine stand-in that rejects pipelines of the user "invalid"
+    fn fake_engine() -> mpsc::Sender<EngineMessage> {
+        let (tx, mut rx) = mpsc::channel(16);
+        tokio::spawn(async move {
+            while let Some(msg) = rx.recv().await {
+                if let EngineMessage::AddPipeline {
+                    pipeline,
+                    response_tx,
+                } = msg
+                {
+                    let result = match pipeline.user_id.as_str() {
+                        "invalid" => Err(EngineError::InvalidPipelineError("invalid".to_string())),
+                        _ => Ok(()),
+                    };
+                    let _ = response_tx.send(result);
+                }
+            }
+        });
+        tx
+    }
+
+    fn create_request(user_id: &str) -> CreatePipelineRequest {
+        CreatePipelineRequest {
+            user_id: user_id.to_string(),
+            current_steps: vec![],
+            steps: HashMap::new(),
+            eval_interval_ms: None,
+            expires_at: None,
+            dry_run: false,
+        }
+    }
+
+    #[actix_web::test]
+    async fn test_create_pipelines_batch() {
+        let app = actix_web::test::init_service(
+            App::new()
+                .app_data(Data::new(test_state(fake_engine())))
+                .route("/pipelines/batch", web::post().to(create_pipelines_batch)),
+        )
+        .await;
+
+        let req = actix_web::test::TestRequest::post()
+            .uri("/pipelines/batch")
+            .set_json(vec![
+                create_request("a"),
+                create_request("invalid"),
+                create_request("b"),
+            ])
+            .to_request();
+        let res = actix_web::test::call_service(&app, req).await;
+        assert_eq!(res.status(), StatusCode::MULTI_STATUS);
+        let body: serde_json::Value = actix_web::test::read_body_json(res).await;
+        let statuses: Vec<u64> = body["results"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|result| result["status"].as_u64().unwrap())
+            .collect();
+        assert_eq!(statuses, vec![201, 400, 201]);
+        assert!(body["results"][0]["id"].is_string());
+        assert!(body["results"][1]["error"].is_string());
+        assert_eq!(body["results"][1]["code"], "INVALID_PIPELINE");
+
+        let req = actix_web::test::TestRequest::post()
+            .uri("/pipelines/batch")
+            .set_json((0..4).map(|_| create_request("a")).collect::<Vec<_>>())
+            .to_request();
+        let res = actix_web::test::call_service(&app, req).await;
+        assert_eq!(res.status(), StatusCode::PAYLOAD_TOO_LARGE);
+        let body: serde_json::Value = actix_web::test::read_body_json(res).await;
+        assert_eq!(body["code"], "BATCH_TOO_LARGE");
+    }
+
+    #[actix_web::test]
+    async fn test_create_pipeline_times_out() {
+        // an engine that never gets round to answering
+        let (tx, mut rx) = mpsc::channel(16);
+        tokio::spawn(async move {
+            let mut held = Vec::new();
+            while let Some(msg) = rx.recv().await {
+                held.push(msg);
+            }
+        });
+        let app = actix_web::test::init_service(
+            App::new()
+                .app_data(Data::new(AppState {
+                    request_timeout: Duration::from_millis(50),
+                    ..test_state(tx)
                 }))
+                .route("/pipeline", web::post().to(create_pipeline))
+                .route("/pipelines/batch", web::post().to(create_pipelines_batch)),
+        )
+        .await;
+
+        let started = std::time::Instant::now();
+        let req = actix_web::test::TestRequest::post()
+            .uri("/pipeline")
+            .set_json(create_request("a"))
+            .to_request();
+        let res = actix_web::test::call_service(&app, req).await;
+        assert_eq!(res.status(), StatusCode::GATEWAY_TIMEOUT);
+        assert!(started.elapsed() < Duration::from_secs(5));
+        let body: serde_json::Value = actix_web::test::read_body_json(res).await;
+        assert_eq!(body["code"], "ENGINE_TIMEOUT");
+
+        let req = actix_web::test::TestRequest::post()
+            .uri("/pipelines/batch")
+            .set_json(vec![create_request("a")])
+            .to_request();
+        let res = actix_web::test::call_service(&app, req).await;
+        let body: serde_json::Value = actix_web::test::read_body_json(res).await;
+        assert_eq!(body["results"][0]["status"], 504);
+        assert_eq!(body["results"][0]["code"], "ENGINE_TIMEOUT");
+    }
+
+    #[actix_web::test]
+    async fn test_payload_limit() {
+        let app = actix_web::test::init_service(
+            App::new()
+                .app_data(Data::new(AppState {
+                    request_timeout: Duration::from_millis(50),
+                    ..test_state(mpsc::channel(16).0)
+                }))
+                .app_data(json_config(1_024))
+                .route("/pipeline", web::post().to(create_pipeline)),
+        )
+        .await;
+
+        let mut request = create_request("a");
+        request.user_id = "a".repeat(2_048);
+        let req = actix_web::test::TestRequest::post()
+            .uri("/pipeline")
+            .set_json(request)
+            .to_request();
+        let res = actix_web::test::call_service(&app, req).await;
+        assert_eq!(res.status(), StatusCode::PAYLOAD_TOO_LARGE);
+        let body: serde_json::Value = actix_web::test::read_body_json(res).await;
+        assert_eq!(body["code"], "PAYLOAD_TOO_LARGE");
+
+        let req = actix_web::test::TestRequest::post()
+            .uri("/pipeline")
+            .insert_header(("content-type", "application/json"))
+            .set_payload("{")
+            .to_request();
+        let res = actix_web::test::call_service(&app, req).await;
+        assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+        let body: serde_json::Value = actix_web::test::read_body_json(res).await;
+        assert_eq!(body["code"], "INVALID_JSON");
+    }
+
+    #[actix_web::test]
+    async fn test_creation_rate_limit() {
+        let app = actix_web::test::init_service(
+            App::new()
+                .app_data(Data::new(AppState {
+                    creation_limiter: Arc::new(RateLimiter::new(2, Duration::from_millis(400))),
+                    ..test_state(fake_engine())
+                }))
+                .route("/pipeline", web::post().to(create_pipeline)),
+        )
+        .await;
+        let create = |user_id: &str| {
+            actix_web::test::TestRequest::post()
+                .uri("/pipeline")
+                .set_json(create_request(user_id))
+                .to_request()
+        };
+
+        for _ in 0..2 {
+            let res = actix_web::test::call_service(&app, create("a")).await;
+            assert_eq!(res.status(), StatusCode::CREATED);
+        }
+        let res = actix_web::test::call_service(&app, create("a")).await;
+        assert_eq!(res.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(res.headers().get("retry-after").unwrap(), "1");
+        let body: serde_json::Value = actix_web::test::read_body_json(res).await;
+        assert_eq!(body["code"], "RATE_LIMITED");
+
+        // others aren't held up
+        let res = actix_web::test::call_service(&app, create("b")).await;
+        assert_eq!(res.status(), StatusCode::CREATED);
+
+        // a token is back after half the window
+        tokio::time::sleep(Duration::from_millis(250)).await;
+        let res = actix_web::test::call_service(&app, create("a")).await;
+        assert_eq!(res.status(), StatusCode::CREATED);
+        let res = actix_web::test::call_service(&app, create("a")).await;
+        assert_eq!(res.status(), StatusCode::TOO_MANY_REQUESTS);
+    }
+
+    #[actix_web::test]
+    async fn test_batch_creation_rate_limit() {
+        let app = actix_web::test::init_service(
+            App::new()
+                .app_data(Data::new(AppState {
+                    creation_limiter: Arc::new(RateLimiter::per_minute(2)),
+                    ..test_state(fake_engine())
+                }))
+                .route("/pipeline", web::post().to(create_pipeline))
+                .route("/pipelines/batch", web::post().to(create_pipelines_batch)),
+        )
+        .await;
+
+        // each pipeline of the batch takes a token
+        let req = actix_web::test::TestRequest::post()
+            .uri("/pipelines/batch")
+            .set_json((0..3).map(|_| create_request("a")).collect::<Vec<_>>())
+            .to_request();
+        let res = actix_web::test::call_service(&app, req).await;
+        let body: serde_json::Value = actix_web::test::read_body_json(res).await;
+        let results = body["results"].as_array().unwrap();
+        let statuses: Vec<u64> = results
+            .iter()
+            .map(|result| result["status"].as_u64().unwrap())
+            .collect();
+        assert_eq!(statuses, vec![201, 201, 429]);
+        assert_eq!(results[2]["code"], "RATE_LIMITED");
+
+        // and the single endpoint shares the limit
+        let req = actix_web::test::TestRequest::post()
+            .uri("/pipeline")
+            .set_json(create_request("a"))
+            .to_request();
+        let res = actix_web::test::call_service(&app, req).await;
+        assert_eq!(res.status(), StatusCode::TOO_MANY_REQUESTS);
+    }
+
+    #[actix_web::test]
+    async fn test_unversioned_alias_is_deprecated() {
+        let app = actix_web::test::init_service(
+            App::new()
+                .app_data(Data::new(test_state(fake_engine())))
+                .app_data(Data::new(ApiKeys::parse("key-a")))
+                .configure(|cfg| routes(cfg, 3, DEFAULT_MAX_PAYLOAD_BYTES)),
+        )
+        .await;
+        let create = |uri: &str| {
+            actix_web::test::TestRequest::post()
+                .uri(uri)
+                .insert_header(("Authorization", "Bearer key-a"))
+                .set_json(create_request("a"))
+                .to_request()
+        };
+
+        let res = actix_web::test::call_service(&app, create("/api/v1/pipeline")).await;
+        assert_eq!(res.status(), StatusCode::CREATED);
+        assert!(res.headers().get("deprecation").is_none());
+
+        let res = actix_web::test::call_service(&app, create("/api/pipeline")).await;
+        assert_eq!(res.status(), StatusCode::CREATED);
+        assert_eq!(res.headers().get("deprecation").unwrap(), "true");
+        assert_eq!(
+            res.headers().get("link").unwrap(),
+            "</api/v1/pipeline>; rel=\"successor-version\""
+        );
+
+        // rejected requests are marked all the same
+        let req = actix_web::test::TestRequest::get()
+            .uri("/api/pipelines?user_id=a")
+            .to_request();
+        let res = actix_web::test::call_service(&app, req).await;
+        assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+        assert_eq!(res.headers().get("deprecation").unwrap(), "true");
+    }
+
+    /// Engine stand-in holding one pipeline of "bob". Records the user each
+    /// pipeline is created, listed or dead letter asked for, and anything
+    /// else it is asked as "acted".
+    fn fake_user_store(bobs: Pipeline) -> (mpsc::Sender<EngineMessage>, Arc<Mutex<Vec<String>>>) {
+        let (tx, mut rx) = mpsc::channel(16);
+        let asked = Arc::new(Mutex::new(vec![]));
+        let recorded = asked.clone();
+        tokio::spawn(async move {
+            while let Some(msg) = rx.recv().await {
+                let mut recorded = recorded.lock().unwrap();
+                match msg {
+                    EngineMessage::GetPipeline {
+                        pipeline_id,
+                        response_tx,
+                    } => {
+                        let _ = response_tx.send(
+                            (pipeline_id == bobs.id)
+                                .then(|| bobs.clone())
+                                .ok_or_else(|| EngineError::GetPipelineError("-".to_string())),
+                        );
+                    }
+                    EngineMessage::AddPipeline {
+                        pipeline,
+                        response_tx,
+                    } => {
+                        recorded.push(pipeline.user_id);
+                        let _ = response_tx.send(Ok(()));
+                    }
+                    EngineMessage::ListPipelines {
+                        user_id,
+                        response_tx,
+                        ..
+                    } => {
+                        recorded.push(user_id);
+                        let _ = response_tx.send(Ok(PipelinePage {
+                            pipelines: vec![],
+                            next_cursor: None,
+                        }));
+                    }
+                    EngineMessage::GetDeadLetters {
+                        user_id,
+                        response_tx,
+                    } => {
+                        recorded.push(user_id);
+                        let _ = response_tx.send(Ok(vec![]));
+                    }
+                    EngineMessage::RetryDeadLetter {
+                        user_id,
+                        response_tx,
+                        ..
+                    } => {
+                        recorded.push(user_id);
+                        let _ =
+                            response_tx.send(Err(EngineError::GetDeadLetterError("-".to_string())));
+                    }
+                    _ => recorded.push("acted".to_string()),
+                }
             }
-        },
-        Err(_) => {
-            metrics::counter!("pipeline_creation_errors", 1);
-            HttpResponse::GatewayTimeout().json(serde_json::json!({
-                "status": "error",
-                "message": "Pipeline creation timed out"
-            }))
+        });
+        (tx, asked)
+    }
+
+    #[actix_web::test]
+    async fn test_key_bound_to_a_user_only_reaches_their_pipelines() {
+        let bobs: Pipeline = create_request("bob").into();
+        let bobs_id = bobs.id;
+        let (engine_bridge_tx, asked) = fake_user_store(bobs);
+        let app = actix_web::test::init_service(
+            App::new()
+                .app_data(Data::new(test_state(engine_bridge_tx)))
+                .app_data(Data::new(ApiKeys::parse("service-key, alice:alice-key")))
+                .configure(|cfg| routes(cfg, 3, DEFAULT_MAX_PAYLOAD_BYTES)),
+        )
+        .await;
+        let request = |method: Method, path: &str, key: &str| {
+            actix_web::test::TestRequest::default()
+                .method(method)
+                .uri(&format!("/api/v1{}", path))
+                .insert_header(("Authorization", format!("Bearer {}", key)))
+        };
+
+        // bob's pipeline is as good as missing to alice
+        let pipeline = format!("/pipeline/{}", bobs_id);
+        let acting_on_it = [
+            request(Method::GET, &pipeline, "alice-key"),
+            request(Method::PATCH, &pipeline, "alice-key").set_json(serde_json::json!({})),
+            request(Method::DELETE, &pipeline, "alice-key"),
+            request(Method::POST, &format!("{}/pause", pipeline), "alice-key"),
+            request(Method::POST, &format!("{}/resume", pipeline), "alice-key"),
+            request(Method::GET, &format!("{}/pnl", pipeline), "alice-key"),
+            request(Method::GET, &format!("{}/history", pipeline), "alice-key"),
+            request(Method::POST, &format!("{}/whatif", pipeline), "alice-key")
+                .set_json(serde_json::json!({ "asset": "SOL", "price": 1.0 })),
+            request(Method::GET, &format!("{}/ws", pipeline), "alice-key")
+                .insert_header(("Connection", "upgrade"))
+                .insert_header(("Upgrade", "websocket"))
+                .insert_header(("Sec-WebSocket-Version", "13"))
+                .insert_header(("Sec-WebSocket-Key", "dGhlIHNhbXBsZSBub25jZQ==")),
+        ];
+        for req in acting_on_it {
+            let req = req.to_request();
+            let path = format!("{} {}", req.method(), req.path());
+            let res = actix_web::test::call_service(&app, req).await;
+            assert_eq!(res.status(), StatusCode::NOT_FOUND, "{}", path);
         }
-    };
+        assert!(asked.lock().unwrap().is_empty());
 
-    metrics::histogram!("pipeline_creation_duration", start.elapsed());
-    result
+        // the user alice names is ignored, she only ever acts for herself
+        let listing_and_retrying = [
+            request(Method::POST, "/pipeline", "alice-key").set_json(create_request("bob")),
+            request(Method::GET, "/pipelines?user_id=bob", "alice-key"),
+            request(Method::GET, "/dlq?user_id=bob", "alice-key"),
+            request(
+                Method::POST,
+                &format!("/dlq/{}/retry?user_id=bob", Uuid::new_v4()),
+                "alice-key",
+            ),
+        ];
+        for req in listing_and_retrying {
+            actix_web::test::call_service(&app, req.to_request()).await;
+        }
+        assert_eq!(*asked.lock().unwrap(), vec!["alice"; 4]);
+
+        // a service key acts for anyone, once it names them
+        let res = actix_web::test::call_service(
+            &app,
+            request(Method::GET, &pipeline, "service-key").to_request(),
+        )
+        .await;
+        assert_eq!(res.status(), StatusCode::OK);
+        let res = actix_web::test::call_service(
+            &app,
+            request(Method::GET, "/pipelines", "service-key").to_request(),
+        )
+        .await;
+        assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+    }
+
+    /// Engine stand-in holding `count` pipelines of one user, slow to delete
+    fn fake_pipeline_store(count: usize) -> mpsc::Sender<EngineMessage> {
+        let (tx, mut rx) = mpsc::channel(16);
+        tokio::spawn(async move {
+            let mut pipelines: Vec<Pipeline> =
+                (0..count).map(|_| create_request("a").into()).collect();
+            while let Some(msg) = rx.recv().await {
+                match msg {
+                    EngineMessage::ListPipelines {
+                        limit,
+                        cursor,
+                        response_tx,
+                        ..
+                    } => {
+                        let offset: usize = cursor.map_or(0, |cursor| cursor.parse().unwrap());
+                        let end = pipelines.len().min(offset + limit);
+                        let _ = response_tx.send(Ok(PipelinePage {
+                            pipelines: pipelines[offset..end].to_vec(),
+                            next_cursor: (end < pipelines.len()).then(|| end.to_string()),
+                        }));
+                    }
+                    EngineMessage::DeletePipeline {
+                        pipeline_id,
+                        response_tx,
+                    } => {
+                        tokio::time::sleep(std::time::Duration::from_millis(2)).await;
+                        pipelines.retain(|pipeline| pipeline.id != pipeline_id);
+                        let _ = response_tx.send(Ok(()));
+                    }
+                    _ => {}
+                }
+            }
+        });
+        tx
+    }
+
+    #[actix_web::test]
+    async fn test_http_client_shared() {
+        async fn client_of(state: Data<AppState>) -> HttpResponse {
+            HttpResponse::Ok().body(format!("{:p}", Arc::as_ptr(&state.http_client())))
+        }
+        let http_client = Arc::new(reqwest::Client::new());
+        let shared = format!("{:p}", Arc::as_ptr(&http_client));
+
+        // each worker builds its own state, as in `run`
+        for _ in 0..2 {
+            let app = actix_web::test::init_service(
+                App::new()
+                    .app_data(Data::new(AppState {
+                        http_client: http_client.clone(),
+                        ..test_state(mpsc::channel(16).0)
+                    }))
+                    .route("/client", web::get().to(client_of)),
+            )
+            .await;
+            for _ in 0..2 {
+                let req = actix_web::test::TestRequest::get()
+                    .uri("/client")
+                    .to_request();
+                let body = actix_web::test::call_and_read_body(&app, req).await;
+                assert_eq!(body, shared.as_bytes());
+            }
+        }
+    }
+
+    #[actix_web::test]
+    async fn test_cancel_bulk_delete() {
+        let app = actix_web::test::init_service(
+            App::new()
+                .app_data(Data::new(test_state(fake_pipeline_store(250))))
+                .route(
+                    "/pipelines/bulk-delete",
+                    web::post().to(bulk_delete_pipelines),
+                )
+                .route("/jobs/{id}", web::get().to(get_job))
+                .route("/jobs/{id}/cancel", web::post().to(cancel_job)),
+        )
+        .await;
+
+        let req = actix_web::test::TestRequest::post()
+            .uri("/pipelines/bulk-delete")
+            .set_json(serde_json::json!({ "user_id": "a" }))
+            .to_request();
+        let res = actix_web::test::call_service(&app, req).await;
+        assert_eq!(res.status(), StatusCode::ACCEPTED);
+        let body: serde_json::Value = actix_web::test::read_body_json(res).await;
+        let job_uri = format!("/jobs/{}", body["job_id"].as_str().unwrap());
+
+        let job = loop {
+            let req = actix_web::test::TestRequest::get()
+                .uri(&job_uri)
+                .to_request();
+            let job: serde_json::Value = actix_web::test::call_and_read_body_json(&app, req).await;
+            if job["done"].as_u64().unwrap() >= 10 {
+                break job;
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(5)).await;
+        };
+        // listed across pages before deleting
+        assert_eq!(job["total"], 250);
+        assert_eq!(job["status"], "running");
+
+        let req = actix_web::test::TestRequest::post()
+            .uri(&format!("{}/cancel", job_uri))
+            .to_request();
+        let res = actix_web::test::call_service(&app, req).await;
+        assert_eq!(res.status(), StatusCode::OK);
+
+        let job = loop {
+            let req = actix_web::test::TestRequest::get()
+                .uri(&job_uri)
+                .to_request();
+            let job: serde_json::Value = actix_web::test::call_and_read_body_json(&app, req).await;
+            if job["status"] != "running" {
+                break job;
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(5)).await;
+        };
+        assert_eq!(job["status"], "cancelled");
+        assert!(job["done"].as_u64().unwrap() < 250);
+        assert!(job["finished_at"].is_string());
+
+        let req = actix_web::test::TestRequest::post()
+            .uri(&format!("{}/cancel", job_uri))
+            .to_request();
+        let res = actix_web::test::call_service(&app, req).await;
+        assert_eq!(res.status(), StatusCode::CONFLICT);
+
+        let req = actix_web::test::TestRequest::get()
+            .uri(&format!("/jobs/{}", Uuid::new_v4()))
+            .to_request();
+        let res = actix_web::test::call_service(&app, req).await;
+        assert_eq!(res.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[actix_web::test]
+    async fn test_metrics_on_dedicated_port() {
+        // the recorder may be installed by another test already
+        let _ = crate::metrics::setup_metrics_exporter();
+        let port = std::net::TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
+        let vars = HashMap::from([("METRICS_BIND_ADDR", format!("127.0.0.1:{}", port))]);
+        let config = Config::from_vars(|name| vars.get(name).cloned()).unwrap();
+        assert_eq!(
+            config.metrics_bind_addrs,
+            vec![("127.0.0.1".to_string(), port)]
+        );
+
+        let server = metrics_server(&config.metrics_bind_addrs).unwrap();
+        let handle = server.handle();
+        actix_web::rt::spawn(server);
+        let res = reqwest::get(format!("http://127.0.0.1:{}/metrics", port))
+            .await
+            .unwrap();
+        assert_eq!(res.status(), reqwest::StatusCode::OK);
+        let res = reqwest::get(format!("http://127.0.0.1:{}/api/healthz", port))
+            .await
+            .unwrap();
+        assert_eq!(res.status(), reqwest::StatusCode::NOT_FOUND);
+        handle.stop(true).await;
+
+        // the API's server leaves it to the dedicated one, or serves it when
+        // there is none
+        for (config, status) in [
+            (config, StatusCode::NOT_FOUND),
+            (Config::from_vars(|_| None).unwrap(), StatusCode::OK),
+            (
+                Config::from_vars(|name| (name == "METRICS_ENABLED").then(|| "false".to_string()))
+                    .unwrap(),
+                StatusCode::NOT_FOUND,
+            ),
+        ] {
+            let serve_metrics = metrics_on_api_server(&config);
+            let app = actix_web::test::init_service(
+                App::new()
+                    .configure(|cfg| routes(cfg, 3, DEFAULT_MAX_PAYLOAD_BYTES))
+                    .configure(|cfg| {
+                        if serve_metrics {
+                            metrics_routes(cfg)
+                        }
+                    }),
+            )
+            .await;
+            let req = actix_web::test::TestRequest::get()
+                .uri("/metrics")
+                .to_request();
+            let res = actix_web::test::call_service(&app, req).await;
+            assert_eq!(res.status(), status);
+        }
+
+        assert_eq!(
+            metrics_bind_addrs(Some("[::]:9090")).unwrap(),
+            vec![("::".to_string(), 9090)]
+        );
+        assert!(metrics_bind_addrs(Some("127.0.0.1")).is_err());
+        assert!(metrics_bind_addrs(None).unwrap().is_empty());
+    }
 }
@@ -0,0 +1,679 @@
+use actix_web::{HttpResponse, Responder};
+use serde_json::{json, Map, Value};
+
+/// OpenAPI 3 description of the `/api/v1` routes, served at
+/// `GET /api/v1/openapi.json`. Written out by hand, so a change to a request
+/// or response type has to be made here too; the tests check the examples
+/// still deserialize.
+pub fn spec() -> Value {
+    json!({
+        "openapi": "3.0.3",
+        "info": {
+            "title": "listen-engine",
+            "version": env!("CARGO_PKG_VERSION"),
+            "description": "Pipelines of conditions and actions, evaluated on every price update. The routes under `/api/v1` are also served under `/api`, which is deprecated and answers with a `Deprecation` header."
+        },
+        "servers": [{ "url": "/" }],
+        "security": [{ "apiKey": [] }],
+        "paths": paths(),
+        "components": {
+            "securitySchemes": {
+                "apiKey": {
+                    "type": "http",
+                    "scheme": "bearer",
+                    "description": "One of the keys in `API_KEYS`, a key bound to a user only acts on their pipelines"
+                },
+                "adminToken": {
+                    "type": "http",
+                    "scheme": "bearer",
+                    "description": "`ADMIN_TOKEN`"
+                }
+            },
+            "parameters": {
+                "pipelineId": {
+                    "name": "id",
+                    "in": "path",
+                    "required": true,
+                    "schema": { "type": "string", "format": "uuid" }
+                },
+                "userId": {
+                    "name": "user_id",
+                    "in": "query",
+                    "required": false,
+                    "description": "Required with a service key, a key bound to a user acts for that user",
+                    "schema": { "type": "string" }
+                }
+            },
+            "responses": error_responses(),
+            "schemas": schemas()
+        }
+    })
+}
+
+pub async fn openapi_json() -> impl Responder {
+    HttpResponse::Ok().json(spec())
+}
+
+fn paths() -> Value {
+    let pipeline_id = json!([{ "$ref": "#/components/parameters/pipelineId" }]);
+    let user_id = json!([{ "$ref": "#/components/parameters/userId" }]);
+    json!({
+        "/api/healthz": {
+            "get": {
+                "summary": "Health of the engine and its dependencies",
+                "security": [],
+                "responses": {
+                    "200": { "description": "Healthy" },
+                    "503": { "description": "A dependency is down or the engine doesn't answer" }
+                }
+            }
+        },
+        "/api/v1/openapi.json": {
+            "get": {
+                "summary": "This document",
+                "security": [],
+                "responses": { "200": { "description": "OpenAPI 3 document" } }
+            }
+        },
+        "/api/v1/pipeline": {
+            "post": {
+                "summary": "Create a pipeline",
+                "requestBody": json_body("CreatePipelineRequest"),
+                "responses": with_errors(
+                    json!({ "201": json_response("Created", "Status") }),
+                    &["400", "413", "429", "503", "504"],
+                )
+            }
+        },
+        "/api/v1/pipelines": {
+            "get": {
+                "summary": "The user's pipelines, a page at a time",
+                "parameters": [
+                    { "$ref": "#/components/parameters/userId" },
+                    { "name": "limit", "in": "query", "schema": { "type": "integer", "minimum": 1 } },
+                    { "name": "cursor", "in": "query", "schema": { "type": "string" } }
+                ],
+                "responses": with_errors(
+                    json!({ "200": json_response("A page of pipelines", "PipelinePage") }),
+                    &["400", "503", "504"],
+                )
+            }
+        },
+        "/api/v1/pipelines/batch": {
+            "post": {
+                "summary": "Create several pipelines, each on its own",
+                "requestBody": {
+                    "required": true,
+                    "content": { "application/json": { "schema": {
+                        "type": "array",
+                        "items": schema_ref("CreatePipelineRequest")
+                    } } }
+                },
+                "responses": with_errors(
+                    json!({ "207": {
+                        "description": "A result per pipeline, in request order; each takes a token of the creation rate limit, those past it fail with 429"
+                    } }),
+                    &["400", "413"],
+                )
+            }
+        },
+        "/api/v1/pipeline/{id}": {
+            "parameters": pipeline_id,
+            "get": {
+                "summary": "Get a pipeline",
+                "responses": with_errors(
+                    json!({ "200": json_response("The pipeline", "Pipeline") }),
+                    &["404", "503", "504"],
+                )
+            },
+            "patch": {
+                "summary": "Update a pipeline with a JSON merge patch",
+                "description": "`id`, `user_id`, `created_at`, `swap_history`, `depth` and `dry_run_triggers` can't be changed",
+                "requestBody": {
+                    "required": true,
+                    "content": { "application/json": { "schema": { "type": "object" } } }
+                },
+                "responses": with_errors(
+                    json!({ "200": json_response("The updated pipeline", "Pipeline") }),
+                    &["400", "404", "409", "503", "504"],
+                )
+            },
+            "delete": {
+                "summary": "Delete a pipeline",
+                "responses": with_errors(
+                    json!({ "204": { "description": "Deleted" } }),
+                    &["503", "504"],
+                )
+            }
+        },
+        "/api/v1/pipeline/{id}/pause": {
+            "parameters": pipeline_id,
+            "post": {
+                "summary": "Stop evaluating a pipeline until resumed",
+                "responses": with_errors(
+                    json!({ "200": json_response("The paused pipeline", "Pipeline") }),
+                    &["404", "409", "503", "504"],
+                )
+            }
+        },
+        "/api/v1/pipeline/{id}/resume": {
+            "parameters": pipeline_id,
+            "post": {
+                "summary": "Evaluate a paused pipeline again",
+                "responses": with_errors(
+                    json!({ "200": json_response("The resumed pipeline", "Pipeline") }),
+                    &["404", "409", "503", "504"],
+                )
+            }
+        },
+        "/api/v1/pipeline/{id}/pnl": {
+            "parameters": pipeline_id,
+            "get": {
+                "summary": "Profit and loss over the pipeline's swaps",
+                "responses": with_errors(
+                    json!({ "200": json_response("The PnL", "Pnl") }),
+                    &["404", "503", "504"],
+                )
+            }
+        },
+        "/api/v1/pipeline/{id}/history": {
+            "parameters": pipeline_id,
+            "get": {
+                "summary": "Steps that fired and the outcome of their actions, newest first",
+                "responses": with_errors(
+                    json!({ "200": json_array_response("The execution log", "ExecutionEvent") }),
+                    &["503", "504"],
+                )
+            }
+        },
+        "/api/v1/pipeline/{id}/whatif": {
+            "parameters": pipeline_id,
+            "post": {
+                "summary": "Evaluate the pending steps at a hypothetical price",
+                "requestBody": json_body("WhatIfRequest"),
+                "responses": with_errors(
+                    json!({ "200": json_response("Which steps would trigger", "WhatIf") }),
+                    &["400", "404", "503", "504"],
+                )
+            }
+        },
+        "/api/v1/pipeline/{id}/trigger": {
+            "parameters": pipeline_id,
+            "post": {
+                "summary": "Run the actions of the current steps now",
+                "description": "Conditions are left as they are. Only with `FORCE_TRIGGER_ENABLED=true`",
+                "responses": with_errors(
+                    json!({ "200": { "description": "What the actions did", "content": { "application/json": { "schema": {
+                        "type": "object",
+                        "properties": {
+                            "status": { "type": "string" },
+                            "executions": { "type": "array", "items": schema_ref("ExecutionEvent") }
+                        }
+                    } } } } }),
+                    &["403", "404", "409", "503", "504"],
+                )
+            }
+        },
+        "/api/v1/pipeline/{id}/ws": {
+            "parameters": pipeline_id,
+            "get": {
+                "summary": "WebSocket of the pipeline's changes",
+                "responses": { "101": { "description": "Switching protocols" } }
+            }
+        },
+        "/api/v1/dlq": {
+            "get": {
+                "summary": "The user's actions that failed for good",
+                "parameters": user_id,
+                "responses": with_errors(
+                    json!({ "200": json_array_response("Dead letters", "DeadLetter") }),
+                    &["503", "504"],
+                )
+            }
+        },
+        "/api/v1/dlq/{id}/retry": {
+            "post": {
+                "summary": "Attempt a dead letter's action again",
+                "parameters": [
+                    { "name": "id", "in": "path", "required": true, "schema": { "type": "string", "format": "uuid" } },
+                    { "$ref": "#/components/parameters/userId" }
+                ],
+                "responses": with_errors(
+                    json!({ "200": { "description": "The action succeeded" } }),
+                    &["404", "422", "503", "504"],
+                )
+            }
+        }
+    })
+}
+
+/// Responses shared by the routes, all with the `Error` body
+fn error_responses() -> Value {
+    let responses: Map<String, Value> = [
+        ("400", "The request is invalid"),
+        ("401", "Missing or invalid API key"),
+        ("403", "The operation is disabled"),
+        ("404", "Not found"),
+        ("409", "The pipeline is settled or was changed concurrently"),
+        ("413", "The body is too large"),
+        (
+            "422",
+            "The request is well formed but couldn't be carried out",
+        ),
+        ("429", "Rate limited, see `Retry-After`"),
+        (
+            "503",
+            "Redis is down or the engine is busy, see `Retry-After`",
+        ),
+        ("504", "The engine didn't answer in time"),
+    ]
+    .into_iter()
+    .map(|(status, description)| (status.to_string(), json_response(description, "Error")))
+    .collect();
+    Value::Object(responses)
+}
+
+fn with_errors(mut responses: Value, statuses: &[&str]) -> Value {
+    let responses_map = responses.as_object_mut().unwrap();
+    for status in ["401"].iter().chain(statuses) {
+        responses_map.insert(
+            status.to_string(),
+            json!({ "$ref": format!("#/components/responses/{}", status) }),
+        );
+    }
+    responses
+}
+
+fn schema_ref(name: &str) -> Value {
+    json!({ "$ref": format!("#/components/schemas/{}", name) })
+}
+
+fn json_body(schema: &str) -> Value {
+    json!({
+        "required": true,
+        "content": { "application/json": { "schema": schema_ref(schema) } }
+    })
+}
+
+fn json_response(description: &str, schema: &str) -> Value {
+    json!({
+        "description": description,
+        "content": { "application/json": { "schema": schema_ref(schema) } }
+    })
+}
+
+fn json_array_response(description: &str, schema: &str) -> Value {
+    json!({
+        "description": description,
+        "content": { "application/json": { "schema": {
+            "type": "array",
+            "items": schema_ref(schema)
+        } } }
+    })
+}
+
+/// An object with the `required` properties and optional ones
+fn object(required: &[&str], properties: Value) -> Value {
+    json!({
+        "type": "object",
+        "required": required,
+        "properties": properties
+    })
+}
+
+/// An externally tagged enum variant, `{ "<name>": <content> }`
+fn variant(name: &str, content: Value) -> Value {
+    json!({
+        "type": "object",
+        "required": [name],
+        "properties": { name: content },
+        "additionalProperties": false
+    })
+}
+
+fn schemas() -> Value {
+    let uuid = json!({ "type": "string", "format": "uuid" });
+    let date_time = json!({ "type": "string", "format": "date-time" });
+    let number = json!({ "type": "number" });
+    let string = json!({ "type": "string" });
+    let u64 = json!({ "type": "integer", "minimum": 0 });
+    let asset_price = |threshold: &str| {
+        object(
+            &["asset", threshold],
+            json!({ "asset": string, threshold: number }),
+        )
+    };
+
+    json!({
+        "Error": object(&["code", "message"], json!({
+            "code": { "type": "string", "description": "Stable, for clients to match on" },
+            "message": { "type": "string", "description": "For humans, may change" }
+        })),
+        "Status": object(&["status", "message"], json!({
+            "status": string,
+            "message": string
+        })),
+        "CreatePipelineRequest": {
+            "type": "object",
+            "required": ["user_id", "current_steps", "steps"],
+            "properties": {
+                "user_id": {
+                    "type": "string",
+                    "description": "Replaced by the user a key bound to one acts for"
+                },
+                "current_steps": { "type": "array", "items": uuid },
+                "steps": {
+                    "type": "object",
+                    "description": "By step id",
+                    "additionalProperties": schema_ref("PipelineStep")
+                },
+                "eval_interval_ms": { "type": "integer", "minimum": 0, "nullable": true },
+                "expires_at": { "type": "string", "format": "date-time", "nullable": true },
+                "dry_run": { "type": "boolean", "default": false }
+            },
+            "example": create_pipeline_example()
+        },
+        "PipelineStep": object(&["id", "action", "conditions", "next_steps", "status"], json!({
+            "id": uuid,
+            "action": schema_ref("Action"),
+            "conditions": { "type": "array", "items": schema_ref("Condition") },
+            "next_steps": { "type": "array", "items": uuid },
+            "status": schema_ref("PipelineStatus"),
+            "retry_policy": {
+                "allOf": [schema_ref("RetryPolicy")],
+                "nullable": true
+            },
+            "attempts": { "type": "integer", "minimum": 0, "default": 0 },
+            "next_attempt_at": { "type": "string", "format": "date-time", "nullable": true }
+        })),
+        "RetryPolicy": object(&["max_attempts", "base_delay_ms", "max_delay_ms"], json!({
+            "max_attempts": u64,
+            "base_delay_ms": u64,
+            "max_delay_ms": u64
+        })),
+        "PipelineStatus": {
+            "type": "string",
+            "enum": ["Pending", "Completed", "Failed", "Cancelled", "Expired", "Paused", "Retrying"]
+        },
+        "Condition": object(&["condition_type", "triggered"], json!({
+            "condition_type": schema_ref("ConditionType"),
+            "triggered": { "type": "boolean" },
+            "last_evaluated": { "type": "string", "format": "date-time", "nullable": true },
+            "cooldown_secs": { "type": "integer", "minimum": 0, "nullable": true },
+            "last_triggered": { "type": "string", "format": "date-time", "nullable": true }
+        })),
+        "ConditionType": {
+            "oneOf": [
+                variant("PriceAbove", asset_price("threshold")),
+                variant("PriceBelow", asset_price("threshold")),
+                variant("PriceBand", object(&["asset", "upper", "lower"], json!({
+                    "asset": string,
+                    "upper": number,
+                    "lower": number,
+                    "crossed": { "type": "string", "enum": ["Upper", "Lower"], "nullable": true }
+                }))),
+                variant("TrailingStop", object(&["asset", "trail_percent"], json!({
+                    "asset": string,
+                    "trail_percent": number,
+                    "peak": { "type": "number", "nullable": true }
+                }))),
+                variant("PercentageChange", object(&["asset", "change", "timeframe"], json!({
+                    "asset": string,
+                    "change": number,
+                    "timeframe": u64
+                }))),
+                variant("PercentChange", object(&["asset", "percent", "direction"], json!({
+                    "asset": string,
+                    "reference_price": { "type": "number", "nullable": true },
+                    "percent": number,
+                    "direction": { "type": "string", "enum": ["Up", "Down"] }
+                }))),
+                variant("TimeAfter", object(&["timestamp"], json!({ "timestamp": date_time }))),
+                variant("TimeWindow", object(&["start", "end"], json!({
+                    "start": { "type": "string", "description": "HH:MM:SS, UTC" },
+                    "end": { "type": "string", "description": "HH:MM:SS, UTC" }
+                }))),
+                variant("PoolTxCountAbove", object(&["amm_pool", "threshold", "window_seconds"], json!({
+                    "amm_pool": string,
+                    "threshold": u64,
+                    "window_seconds": u64
+                }))),
+                variant("VolumeAbove", object(&["asset", "window_secs", "threshold"], json!({
+                    "asset": string,
+                    "window_secs": u64,
+                    "threshold": { "type": "number", "description": "USD" },
+                    "volume": { "type": "number", "nullable": true }
+                }))),
+                variant("SwapFilled", object(&["min_amount"], json!({ "min_amount": u64 }))),
+                variant("PipelineCompleted", object(&["pipeline_id"], json!({
+                    "pipeline_id": uuid,
+                    "status": {
+                        "allOf": [schema_ref("PipelineStatus")],
+                        "nullable": true
+                    }
+                }))),
+                variant("And", json!({ "type": "array", "items": schema_ref("Condition") })),
+                variant("Or", json!({ "type": "array", "items": schema_ref("Condition") }))
+            ]
+        },
+        "Action": {
+            "oneOf": [
+                variant("Order", object(&["user_id", "address", "caip2"], json!({
+                    "user_id": string,
+                    "address": string,
+                    "caip2": string,
+                    "evm_transaction": { "type": "object", "nullable": true },
+                    "solana_transaction": { "type": "string", "format": "byte", "nullable": true }
+                }))),
+                variant("SwapOrder", object(
+                    &["amm_pool", "input_mint", "output_mint", "amount", "slippage"],
+                    json!({
+                        "amm_pool": string,
+                        "input_mint": string,
+                        "output_mint": string,
+                        "amount": { "type": "integer", "minimum": 0, "description": "In base units" },
+                        "slippage": {
+                            "oneOf": [
+                                { "type": "integer", "minimum": 0, "description": "Fixed, in bps" },
+                                object(&["base_bps", "volatility_multiplier"], json!({
+                                    "base_bps": u64,
+                                    "volatility_multiplier": number
+                                }))
+                            ]
+                        }
+                    }),
+                )),
+                variant("Notification", object(&["message"], json!({ "message": string }))),
+                variant("Webhook", object(&["url", "template"], json!({
+                    "url": {
+                        "type": "string",
+                        "description": "A public http(s) URL, local and private hosts are rejected unless `WEBHOOK_ALLOW_PRIVATE_HOSTS`"
+                    },
+                    "headers": { "type": "object", "additionalProperties": string },
+                    "template": {
+                        "type": "string",
+                        "description": "`{pipeline_id}` and `{step_id}` are filled in"
+                    }
+                }))),
+                variant("Telegram", object(&["chat_id", "bot_token_ref"], json!({
+                    "chat_id": string,
+                    "bot_token_ref": {
+                        "type": "string",
+                        "description": "The token is read from `TELEGRAM_BOT_TOKEN_<REF>`"
+                    },
+                    "template": { "type": "string", "nullable": true }
+                }))),
+                variant("Discord", object(&["webhook_url", "content_template"], json!({
+                    "webhook_url": string,
+                    "username": { "type": "string", "nullable": true },
+                    "content_template": string
+                })))
+            ]
+        },
+        "Pipeline": object(
+            &["id", "user_id", "current_steps", "steps", "status", "created_at"],
+            json!({
+                "id": uuid,
+                "user_id": string,
+                "current_steps": { "type": "array", "items": uuid },
+                "steps": { "type": "object", "additionalProperties": schema_ref("PipelineStep") },
+                "status": schema_ref("PipelineStatus"),
+                "created_at": date_time,
+                "swap_history": { "type": "array", "items": { "type": "object" } },
+                "failure_reason": { "type": "string", "nullable": true },
+                "eval_interval_ms": { "type": "integer", "minimum": 0, "nullable": true },
+                "depth": u64,
+                "expires_at": { "type": "string", "format": "date-time", "nullable": true },
+                "version": u64,
+                "dry_run": { "type": "boolean" },
+                "dry_run_triggers": { "type": "array", "items": schema_ref("ExecutionEvent") }
+            }),
+        ),
+        "PipelinePage": object(&["pipelines"], json!({
+            "pipelines": { "type": "array", "items": schema_ref("Pipeline") },
+            "next_cursor": { "type": "string", "nullable": true }
+        })),
+        "ExecutionEvent": object(
+            &["pipeline_id", "step_id", "timestamp", "conditions", "action", "outcome"],
+            json!({
+                "pipeline_id": uuid,
+                "step_id": uuid,
+                "timestamp": date_time,
+                "conditions": { "type": "array", "items": schema_ref("Condition") },
+                "action": schema_ref("Action"),
+                "outcome": { "type": "string", "enum": ["executed", "retrying", "failed", "dry_run"] },
+                "error": { "type": "string", "description": "When retrying or failed" }
+            }),
+        ),
+        "DeadLetter": object(
+            &["id", "user_id", "pipeline_id", "step_id", "action", "error", "attempts", "failed_at"],
+            json!({
+                "id": uuid,
+                "user_id": string,
+                "pipeline_id": uuid,
+                "step_id": uuid,
+                "action": schema_ref("Action"),
+                "error": string,
+                "attempts": u64,
+                "failed_at": date_time
+            }),
+        ),
+        "Pnl": object(
+            &["entry_cost", "exit_proceeds", "realized_pnl", "open_position", "open_cost_basis"],
+            json!({
+                "denomination": { "type": "string", "nullable": true },
+                "entry_cost": u64,
+                "exit_proceeds": u64,
+                "realized_pnl": { "type": "integer" },
+                "open_position": u64,
+                "open_cost_basis": u64
+            }),
+        ),
+        "WhatIfRequest": object(&["asset", "price"], json!({
+            "asset": string,
+            "price": { "type": "number", "minimum": 0 }
+        })),
+        "WhatIf": object(&["pipeline_id", "asset", "price", "steps"], json!({
+            "pipeline_id": uuid,
+            "asset": string,
+            "price": number,
+            "steps": {
+                "type": "object",
+                "additionalProperties": object(&["would_trigger", "conditions"], json!({
+                    "would_trigger": { "type": "boolean" },
+                    "conditions": { "type": "array", "items": { "type": "boolean" } }
+                }))
+            }
+        }))
+    })
+}
+
+/// Sells when SOL is above 200, the example of `CreatePipelineRequest`
+fn create_pipeline_example() -> Value {
+    let step_id = "8c1e5d2a-3f4b-4c6d-9e7f-0a1b2c3d4e5f";
+    json!({
+        "user_id": "did:privy:example",
+        "current_steps": [step_id],
+        "steps": {
+            step_id: {
+                "id": step_id,
+                "action": { "SwapOrder": {
+                    "amm_pool": "58oQChx4yWmvKdwLLZzBi4ChoCc2fqCUWBkwMihLYQo2",
+                    "input_mint": "So11111111111111111111111111111111111111112",
+                    "output_mint": "EPjFWdd5AufqSSqeM2qN1xzybapC8G4wEGGkZwyTDt1v",
+                    "amount": 1_000_000_000u64,
+                    "slippage": 50
+                } },
+                "conditions": [{
+                    "condition_type": { "PriceAbove": {
+                        "asset": "So11111111111111111111111111111111111111112",
+                        "threshold": 200.0
+                    } },
+                    "triggered": false,
+                    "last_evaluated": null
+                }],
+                "next_steps": [],
+                "status": "Pending"
+            }
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::server::CreatePipelineRequest;
+    use actix_web::{test, web, App};
+
+    /// Every `$ref` under `value`
+    fn refs<'a>(value: &'a Value, found: &mut Vec<&'a str>) {
+        match value {
+            Value::Object(map) => {
+                if let Some(Value::String(r)) = map.get("$ref") {
+                    found.push(r);
+                }
+                map.values().for_each(|v| refs(v, found));
+            }
+            Value::Array(items) => items.iter().for_each(|v| refs(v, found)),
+            _ => {}
+        }
+    }
+
+    #[actix_web::test]
+    async fn test_openapi_document() {
+        let app =
+            test::init_service(App::new().route("/api/openapi.json", web::get().to(openapi_json)))
+                .await;
+        let req = test::TestRequest::get()
+            .uri("/api/openapi.json")
+            .to_request();
+        let body: Value = test::call_and_read_body_json(&app, req).await;
+
+        assert!(body["openapi"].as_str().unwrap().starts_with("3."));
+        assert!(body["info"]["title"].is_string());
+        for path in [
+            "/api/v1/pipeline",
+            "/api/v1/pipeline/{id}",
+            "/api/v1/pipelines",
+            "/api/v1/pipeline/{id}/trigger",
+        ] {
+            assert!(body["paths"][path].is_object(), "{} is missing", path);
+        }
+        for method in ["get", "patch", "delete"] {
+            assert!(body["paths"]["/api/v1/pipeline/{id}"][method].is_object());
+        }
+        // every reference points into the document
+        let mut found = Vec::new();
+        refs(&body, &mut found);
+        assert!(!found.is_empty());
+        for r in found {
+            let pointer = r.strip_prefix('#').unwrap();
+            assert!(body.pointer(pointer).is_some(), "{} is dangling", r);
+        }
+
+        // and the example is a request the API accepts
+        let example = body["components"]["schemas"]["CreatePipelineRequest"]["example"].clone();
+        let req: CreatePipelineRequest = serde_json::from_value(example).unwrap();
+        assert!(req.validate().is_ok());
+    }
+}
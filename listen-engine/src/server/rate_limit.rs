@@ -0,0 +1,146 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Buckets kept at most. Past it the full ones, which are as good as new,
+/// are dropped, then the fullest down to half, so the next sweep is as
+/// many new keys away; a dropped bucket starts full again.
+const MAX_BUCKETS: usize = 10_000;
+
+/// Token bucket per key: each key may make `limit` requests in a burst, and
+/// regains them evenly over `window`. A limit of 0 disables it.
+pub struct RateLimiter {
+    capacity: f64,
+    // Tokens regained per second
+    refill_rate: f64,
+    buckets: Mutex<HashMap<String, Bucket>>,
+}
+
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+impl RateLimiter {
+    pub fn new(limit: u32, window: Duration) -> Self {
+        Self {
+            capacity: limit as f64,
+            refill_rate: limit as f64 / window.as_secs_f64(),
+            buckets: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// `RATE_LIMIT_PER_MINUTE`
+    pub fn per_minute(limit: u32) -> Self {
+        Self::new(limit, Duration::from_secs(60))
+    }
+
+    /// Takes a token for `key`, or returns how long until one is available
+    pub fn check(&self, key: &str) -> Result<(), Duration> {
+        self.check_at(key, Instant::now())
+    }
+
+    fn check_at(&self, key: &str, now: Instant) -> Result<(), Duration> {
+        if self.capacity == 0.0 {
+            return Ok(());
+        }
+        let mut buckets = self.buckets.lock().unwrap();
+        if buckets.len() >= MAX_BUCKETS && !buckets.contains_key(key) {
+            self.evict(&mut buckets, now);
+        }
+        let bucket = buckets.entry(key.to_string()).or_insert(Bucket {
+            tokens: self.capacity,
+            updated: now,
+        });
+        bucket.tokens = self.tokens(bucket, now);
+        bucket.updated = now;
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            Ok(())
+        } else {
+            Err(Duration::from_secs_f64(
+                (1.0 - bucket.tokens) / self.refill_rate,
+            ))
+        }
+    }
+
+    fn evict(&self, buckets: &mut HashMap<String, Bucket>, now: Instant) {
+        buckets.retain(|_, bucket| self.tokens(bucket, now) < self.capacity);
+        let excess = buckets.len().saturating_sub(MAX_BUCKETS / 2);
+        if excess == 0 {
+            return;
+        }
+        let mut fullest: Vec<(f64, String)> = buckets
+            .iter()
+            .map(|(key, bucket)| (self.tokens(bucket, now), key.clone()))
+            .collect();
+        fullest.sort_by(|a, b| b.0.total_cmp(&a.0));
+        for (_, key) in fullest.into_iter().take(excess) {
+            buckets.remove(&key);
+        }
+    }
+
+    fn tokens(&self, bucket: &Bucket, now: Instant) -> f64 {
+        let elapsed = now.saturating_duration_since(bucket.updated);
+        (bucket.tokens + elapsed.as_secs_f64() * self.refill_rate).min(self.capacity)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_token_bucket() {
+        let limiter = RateLimiter::per_minute(3);
+        let start = Instant::now();
+
+        for _ in 0..3 {
+            assert!(limiter.check_at("a", start).is_ok());
+        }
+        // a token comes back every 20s
+        let secs = |result: Result<(), Duration>| result.unwrap_err().as_secs_f64();
+        assert!((secs(limiter.check_at("a", start)) - 20.0).abs() < 1e-6);
+        // other keys have their own bucket
+        assert!(limiter.check_at("b", start).is_ok());
+
+        let later = start + Duration::from_secs(25);
+        assert!(limiter.check_at("a", later).is_ok());
+        assert!((secs(limiter.check_at("a", later)) - 15.0).abs() < 1e-6);
+
+        // refills up to the burst, no further
+        let idle = start + Duration::from_secs(600);
+        for _ in 0..3 {
+            assert!(limiter.check_at("a", idle).is_ok());
+        }
+        assert!(limiter.check_at("a", idle).is_err());
+
+        let disabled = RateLimiter::per_minute(0);
+        for _ in 0..100 {
+            assert!(disabled.check_at("a", start).is_ok());
+        }
+    }
+
+    #[test]
+    fn test_buckets_are_bounded() {
+        let limiter = RateLimiter::per_minute(1);
+        let start = Instant::now();
+        // every one drained, the later the emptier
+        for i in 0..MAX_BUCKETS {
+            let at = start + Duration::from_millis(i as u64);
+            assert!(limiter.check_at(&i.to_string(), at).is_ok());
+        }
+        let now = start + Duration::from_millis(MAX_BUCKETS as u64);
+        assert!(limiter.check_at("new", now).is_ok());
+
+        let buckets = limiter.buckets.lock().unwrap();
+        assert_eq!(buckets.len(), MAX_BUCKETS / 2 + 1);
+        // the buckets closest to full went first
+        assert!(!buckets.contains_key("0"));
+        assert!(buckets.contains_key(&(MAX_BUCKETS - 1).to_string()));
+        drop(buckets);
+        assert!(limiter
+            .check_at(&(MAX_BUCKETS - 1).to_string(), now)
+            .is_err());
+    }
+}
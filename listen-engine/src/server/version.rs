@@ -0,0 +1,39 @@
+use actix_web::{
+    body::MessageBody,
+    dev::{ServiceRequest, ServiceResponse},
+    http::header::{HeaderName, HeaderValue, LINK},
+    middleware::Next,
+    Error,
+};
+
+/// Prefix of the current version of the API. Every route but `/api/healthz`
+/// and `/metrics` lives under it, and under the unversioned `/api` as a
+/// deprecated alias.
+pub const API_V1: &str = "/api/v1";
+
+const DEPRECATION: HeaderName = HeaderName::from_static("deprecation");
+
+/// Marks responses of the unversioned `/api` alias as deprecated, linking
+/// to the same route under `API_V1`
+pub async fn deprecated_alias(
+    req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<impl MessageBody>, Error> {
+    let successor = successor(req.path());
+    let mut res = next.call(req).await?;
+    let headers = res.headers_mut();
+    headers.insert(DEPRECATION, HeaderValue::from_static("true"));
+    if let Ok(link) = HeaderValue::from_str(&format!("<{}>; rel=\"successor-version\"", successor))
+    {
+        headers.insert(LINK, link);
+    }
+    Ok(res)
+}
+
+/// `/api/...` as `/api/v1/...`
+fn successor(path: &str) -> String {
+    match path.strip_prefix("/api") {
+        Some(rest) => format!("{}{}", API_V1, rest),
+        None => path.to_string(),
+    }
+}
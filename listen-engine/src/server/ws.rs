@@ -0,0 +1,173 @@
+use std::convert::Infallible;
+use std::time::Duration;
+
+use actix_codec::{Decoder, Encoder};
+use actix_http::ws::{self, Codec, Frame, Message};
+use actix_web::{
+    http::header,
+    web::{self, Bytes, BytesMut, Data},
+    HttpRequest, HttpResponse,
+};
+use futures_util::StreamExt;
+use tokio::sync::{broadcast, mpsc};
+use uuid::Uuid;
+
+use super::{check_owner, error::ask_engine, AppState, EngineMessage};
+use crate::engine::events::PipelineEvent;
+
+const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Streams the pipeline's `PipelineEvent`s as JSON text frames, starting
+/// with its current state. The socket is closed once the pipeline completes
+/// or fails.
+pub async fn pipeline_ws(
+    state: Data<AppState>,
+    pipeline_id: web::Path<Uuid>,
+    req: HttpRequest,
+    payload: web::Payload,
+) -> Result<HttpResponse, actix_web::Error> {
+    ws::verify_handshake(req.head())?;
+    let pipeline_id = pipeline_id.into_inner();
+
+    // Subscribed before reading the pipeline so no transition is missed
+    let events = state.pipeline_events.subscribe();
+    let pipeline = ask_engine(
+        &state.engine_bridge_tx,
+        state.request_timeout,
+        "Pipeline request",
+        |response_tx| EngineMessage::GetPipeline {
+            pipeline_id,
+            response_tx,
+        },
+    )
+    .await?;
+    check_owner(&req, &pipeline)?;
+
+    let (frames_tx, frames_rx) = mpsc::channel::<Bytes>(16);
+    actix_web::rt::spawn(run_session(
+        PipelineEvent::snapshot(&pipeline),
+        events,
+        payload,
+        frames_tx,
+    ));
+
+    // `verify_handshake` checked the key is there; its hash is base64
+    let accept = req
+        .headers()
+        .get(header::SEC_WEBSOCKET_KEY)
+        .map(|key| ws::hash_key(key.as_bytes()))
+        .unwrap_or_default();
+    let accept = String::from_utf8_lossy(&accept).into_owned();
+    let frames = futures_util::stream::unfold(frames_rx, |mut frames_rx| async move {
+        let frame = frames_rx.recv().await?;
+        Some((Ok::<_, Infallible>(frame), frames_rx))
+    });
+    Ok(HttpResponse::SwitchingProtocols()
+        .upgrade("websocket")
+        .insert_header((header::SEC_WEBSOCKET_ACCEPT, accept))
+        .streaming(frames))
+}
+
+/// Writes frames for the pipeline's events and the heartbeat to
+/// `frames_tx`, and answers the client's pings and close
+async fn run_session(
+    current: PipelineEvent,
+    mut events: broadcast::Receiver<PipelineEvent>,
+    mut payload: web::Payload,
+    frames_tx: mpsc::Sender<Bytes>,
+) {
+    let pipeline_id = current.pipeline_id;
+    let mut session = Session {
+        codec: Codec::new(),
+        frames_tx,
+    };
+    if !session.send_event(&current).await {
+        return;
+    }
+    if current.status.is_terminal() {
+        session.close().await;
+        return;
+    }
+
+    let mut heartbeat = tokio::time::interval(HEARTBEAT_INTERVAL);
+    heartbeat.tick().await;
+    let mut incoming = BytesMut::new();
+    loop {
+        tokio::select! {
+            event = events.recv() => match event {
+                Ok(event) if event.pipeline_id == pipeline_id => {
+                    if !session.send_event(&event).await {
+                        return;
+                    }
+                    if event.status.is_terminal() {
+                        session.close().await;
+                        return;
+                    }
+                }
+                Ok(_) => {}
+                Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                    tracing::warn!(%pipeline_id, skipped, "Pipeline socket lagging behind");
+                }
+                Err(broadcast::error::RecvError::Closed) => {
+                    session.close().await;
+                    return;
+                }
+            },
+            chunk = payload.next() => {
+                let Some(Ok(chunk)) = chunk else {
+                    return;
+                };
+                incoming.extend_from_slice(&chunk);
+                loop {
+                    match session.codec.decode(&mut incoming) {
+                        Ok(Some(Frame::Ping(data))) => {
+                            if !session.send(Message::Pong(data)).await {
+                                return;
+                            }
+                        }
+                        Ok(Some(Frame::Close(_))) | Err(_) => {
+                            session.close().await;
+                            return;
+                        }
+                        Ok(Some(_)) => {}
+                        Ok(None) => break,
+                    }
+                }
+            },
+            _ = heartbeat.tick() => {
+                if !session.send(Message::Ping(Bytes::new())).await {
+                    return;
+                }
+            },
+        }
+    }
+}
+
+struct Session {
+    codec: Codec,
+    frames_tx: mpsc::Sender<Bytes>,
+}
+
+impl Session {
+    /// Returns whether the client is still connected
+    async fn send(&mut self, message: Message) -> bool {
+        let mut frame = BytesMut::new();
+        if self.codec.encode(message, &mut frame).is_err() {
+            return false;
+        }
+        self.frames_tx.send(frame.freeze()).await.is_ok()
+    }
+
+    async fn send_event(&mut self, event: &PipelineEvent) -> bool {
+        match serde_json::to_string(event) {
+            Ok(json) => self.send(Message::Text(json.into())).await,
+            Err(_) => false,
+        }
+    }
+
+    async fn close(&mut self) {
+        let _ = self
+            .send(Message::Close(Some(ws::CloseCode::Normal.into())))
+            .await;
+    }
+}
@@ -1,6 +1,5 @@
 use anyhow::Result;
-use bb8_redis::redis::{cmd, pipe};
-use listen_engine::redis::client::{RedisClient, RedisClientError};
+use listen_engine::redis::client::RedisClient;
 use listen_engine::server::CreatePipelineRequest;
 use listen_engine::{
     engine::{
@@ -48,17 +47,26 @@ async fn create_pipeline_via_api(
                         },
                         triggered: false,
                         last_evaluated: None,
+                        cooldown_secs: None,
+                        last_triggered: None,
                     }],
                     next_steps: vec![],
                     status: Status::Pending,
+                    retry_policy: None,
+                    attempts: 0,
+                    next_attempt_at: None,
                 },
             );
             steps
         },
+        eval_interval_ms: None,
+        expires_at: None,
+        dry_run: false,
     };
 
     let response = client
-        .post("http://localhost:6966/api/pipeline")
+        .post("http://localhost:6966/api/v1/pipeline")
+        .bearer_auth(std::env::var("API_KEY").unwrap_or_default())
         .json(&request)
         .send()
         .await?;
@@ -75,28 +83,15 @@ async fn create_pipeline_via_api(
 }
 
 async fn cleanup_test_pipelines(redis_client: &RedisClient) -> Result<(), EngineError> {
-    let mut conn = redis_client
-        .get_connection()
+    // Deleting through the client keeps the user index sets in step
+    let pipelines = redis_client
+        .get_all_pipelines()
         .await
         .map_err(EngineError::RedisClientError)?;
-
-    // Get all pipeline keys
-    let pipeline_keys: Vec<String> = cmd("KEYS")
-        .arg("pipeline:*")
-        .query_async(&mut *conn)
-        .await
-        .map_err(|e| EngineError::RedisClientError(RedisClientError::RedisError(e)))?;
-
-    // Delete all pipelines in batches
-    let mut pipe = pipe();
-    for key in pipeline_keys {
-        pipe.del(key);
-    }
-
-    let _: () = pipe
-        .query_async(&mut *conn)
+    redis_client
+        .delete_all_pipelines(&pipelines)
         .await
-        .map_err(|e| EngineError::RedisClientError(RedisClientError::RedisError(e)))?;
+        .map_err(EngineError::RedisClientError)?;
 
     Ok(())
 }
@@ -9,6 +9,8 @@ use utoipa::OpenApi;
         crate::handlers::handle_pump_buy,
         crate::handlers::handle_pump_sell,
         crate::handlers::handle_swap,
+        crate::handlers::handle_raydium_swap,
+        crate::handlers::handle_raydium_quote,
         crate::handlers::handle_get_pubkey,
         crate::handlers::handle_get_holdings
     ),
@@ -22,6 +24,11 @@ use utoipa::OpenApi;
         crate::handlers::PumpBuyRequest,
         crate::handlers::PumpSellRequest,
         crate::handlers::SwapRequest,
+        crate::handlers::RaydiumSwapRequest,
+        crate::raydium::SwapResult,
+        crate::raydium::SwapPreview,
+        crate::raydium::SwapFees,
+        crate::raydium::SimulationFailure,
         crate::handlers::HoldingsResponse,
     )),
     tags(
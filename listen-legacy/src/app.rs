@@ -158,6 +158,9 @@ pub enum Command {
         dex: Option<String>,
         #[arg(long)]
         amm_pool_id: Option<String>,
+        /// send without simulating the swap first
+        #[arg(long, action = clap::ArgAction::SetTrue)]
+        skip_preflight: Option<bool>,
 
         #[clap(short, long, action = clap::ArgAction::SetTrue)]
         yes: Option<bool>,
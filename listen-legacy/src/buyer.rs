@@ -39,8 +39,10 @@ pub async fn swap(
             *input_mint,
             *output_mint,
             wallet,
-            0,
+            raydium::Slippage::default(),
             amount,
+            true,
+            None,
         )
         .await
         {
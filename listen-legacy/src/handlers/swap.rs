@@ -1,6 +1,17 @@
+use std::str::FromStr;
+use std::sync::Arc;
+
 use crate::jup::Jupiter;
+use crate::provider::{SendAndConfirmError, SendStrategy};
+use crate::raydium::{
+    make_swap_context, make_swap_preview, ComputeBudgetConfig,
+    InsufficientBalance, JupiterOracle, OracleCheck, OracleDivergence,
+    PoolNotFound, Raydium, SimulationFailure, Slippage, SwapArgs, SwapPreview,
+    SwapResult,
+};
 use crate::state::ServiceState;
 use actix_web::{
+    error::InternalError,
     post,
     web::{Data, Json},
     Error, HttpResponse,
@@ -8,6 +19,8 @@ use actix_web::{
 use log::info;
 use serde::{Deserialize, Serialize};
 use serde_json::json;
+use solana_client::nonblocking::rpc_client::RpcClient;
+use solana_sdk::pubkey::Pubkey;
 use utoipa::ToSchema;
 
 #[derive(Debug, Clone, Deserialize, Serialize, ToSchema)]
@@ -56,3 +69,372 @@ pub async fn handle_swap(
         "result": result,
     })))
 }
+
+#[derive(Debug, Clone, Deserialize, Serialize, ToSchema)]
+pub struct RaydiumSwapRequest {
+    amm_pool: String,
+    input_mint: String,
+    output_mint: String,
+    amount: u64,
+    /// slippage in bps, at most 10000
+    slippage: u64,
+    /// swap_base_out: amount is the exact output rather than the input
+    #[serde(default)]
+    swap_base_out: bool,
+    /// compute_unit_price: micro-lamports per compute unit, defaults to 0
+    #[serde(default)]
+    compute_unit_price: Option<u64>,
+    /// compute_unit_limit: defaults to 300_000
+    #[serde(default)]
+    compute_unit_limit: Option<u32>,
+    /// use_jito: send as a tipped Jito bundle, regular submission is the
+    /// fallback
+    #[serde(default)]
+    use_jito: bool,
+    /// skip_preflight: send without simulating the swap first, a failing
+    /// swap then lands and pays its fees
+    #[serde(default)]
+    skip_preflight: bool,
+    /// confirm_timeout_ms: stop waiting on the swap after this long and
+    /// answer 504 with its signature, it may still land
+    #[serde(default)]
+    confirm_timeout_ms: Option<u64>,
+    /// oracle_max_divergence_pct: abort the swap if the pool's quote is
+    /// further than this, in percent, from Jupiter's; unchecked if unset
+    #[serde(default)]
+    oracle_max_divergence_pct: Option<f64>,
+}
+
+impl RaydiumSwapRequest {
+    fn slippage(&self) -> Result<Slippage, Error> {
+        Slippage::from_bps(self.slippage)
+            .map_err(actix_web::error::ErrorBadRequest)
+    }
+
+    fn oracle_check(&self) -> Result<Option<OracleCheck>, Error> {
+        self.oracle_max_divergence_pct
+            .map(|max_divergence_pct| {
+                OracleCheck::new(Arc::new(JupiterOracle), max_divergence_pct)
+                    .map_err(actix_web::error::ErrorBadRequest)
+            })
+            .transpose()
+    }
+
+    fn compute_budget(&self) -> Option<ComputeBudgetConfig> {
+        if self.compute_unit_price.is_none()
+            && self.compute_unit_limit.is_none()
+        {
+            return None;
+        }
+        let default = ComputeBudgetConfig::default();
+        Some(ComputeBudgetConfig {
+            unit_price: self.compute_unit_price.unwrap_or(default.unit_price),
+            unit_limit: self.compute_unit_limit.unwrap_or(default.unit_limit),
+        })
+    }
+}
+
+#[utoipa::path(
+    post,
+    path = "/raydium-swap",
+    request_body = RaydiumSwapRequest,
+    responses(
+        (status = 200, description = "Swap submitted", body = SwapResult),
+        (status = 400, description = "Invalid swap parameters"),
+        (status = 409, description = "Pool quote too far from the oracle's"),
+        (status = 410, description = "AMM pool no longer exists"),
+        (status = 422, description = "Swap simulation failed", body = SimulationFailure),
+        (status = 500, description = "Swap transaction failed"),
+        (status = 504, description = "Swap sent but not confirmed within confirm_timeout_ms")
+    ),
+    tag = "swap"
+)]
+#[post("/raydium-swap")]
+#[timed::timed(duration(printer = "info!"))]
+pub async fn handle_raydium_swap(
+    swap_request: Json<RaydiumSwapRequest>,
+    state: Data<ServiceState>,
+) -> Result<HttpResponse, Error> {
+    let swap_request = swap_request.into_inner();
+    let amm_pool = Pubkey::from_str(&swap_request.amm_pool)
+        .map_err(actix_web::error::ErrorBadRequest)?;
+    let input_token_mint = Pubkey::from_str(&swap_request.input_mint)
+        .map_err(actix_web::error::ErrorBadRequest)?;
+    let output_token_mint = Pubkey::from_str(&swap_request.output_mint)
+        .map_err(actix_web::error::ErrorBadRequest)?;
+    let slippage = swap_request.slippage()?;
+    let oracle_check = swap_request.oracle_check()?;
+
+    let wallet = state.wallet.lock().await.insecure_clone();
+    let result = Raydium::with_http_client(state.http_client.clone())
+        .swap(SwapArgs {
+            amm_pool,
+            input_token_mint,
+            output_token_mint,
+            amount: swap_request.amount,
+            slippage,
+            wallet,
+            rpc_client: RpcClient::new(state.rpc_client.url()),
+            confirmed: true,
+            no_sanity: false,
+            send_strategy: SendStrategy::Single,
+            compute_budget: swap_request.compute_budget(),
+            swap_base_in: !swap_request.swap_base_out,
+            use_jito: swap_request.use_jito,
+            retry: None,
+            address_lookup_tables: vec![],
+            skip_preflight: swap_request.skip_preflight,
+            confirm_timeout: swap_request
+                .confirm_timeout_ms
+                .map(std::time::Duration::from_millis),
+            oracle_check,
+        })
+        .await
+        .map_err(swap_error)?
+        .ok_or_else(|| {
+            actix_web::error::ErrorInternalServerError("swap not submitted")
+        })?;
+
+    Ok(HttpResponse::Ok().json(result))
+}
+
+#[utoipa::path(
+    post,
+    path = "/raydium-quote",
+    request_body = RaydiumSwapRequest,
+    responses(
+        (status = 200, description = "Swap preview", body = SwapPreview),
+        (status = 400, description = "Invalid swap parameters"),
+        (status = 410, description = "AMM pool no longer exists"),
+        (status = 500, description = "Quote failed")
+    ),
+    tag = "swap"
+)]
+#[post("/raydium-quote")]
+#[timed::timed(duration(printer = "info!"))]
+pub async fn handle_raydium_quote(
+    swap_request: Json<RaydiumSwapRequest>,
+    state: Data<ServiceState>,
+) -> Result<HttpResponse, Error> {
+    let swap_request = swap_request.into_inner();
+    let amm_pool = Pubkey::from_str(&swap_request.amm_pool)
+        .map_err(actix_web::error::ErrorBadRequest)?;
+    let input_token_mint = Pubkey::from_str(&swap_request.input_mint)
+        .map_err(actix_web::error::ErrorBadRequest)?;
+    let output_token_mint = Pubkey::from_str(&swap_request.output_mint)
+        .map_err(actix_web::error::ErrorBadRequest)?;
+    let slippage = swap_request.slippage()?;
+
+    let wallet = state.wallet.lock().await.insecure_clone();
+    let rpc_client = RpcClient::new(state.rpc_client.url());
+    let swap_context = make_swap_context(
+        &rpc_client,
+        amm_pool,
+        input_token_mint,
+        output_token_mint,
+        &wallet,
+        slippage,
+        swap_request.amount,
+        !swap_request.swap_base_out,
+        swap_request.compute_budget(),
+    )
+    .await
+    .map_err(swap_error)?;
+    let preview = make_swap_preview(&rpc_client, &wallet, &swap_context)
+        .await
+        .map_err(swap_error)?;
+
+    Ok(HttpResponse::Ok().json(preview))
+}
+
+/// swap_error returns failed simulations as 422 with the decoded reason and
+/// program logs, so that clients can tell why the swap would fail, a
+/// closed pool as 410 so that it isn't mistaken for a transient error, and
+/// a pool quote the oracle disagrees with as 409 with both outputs
+fn swap_error(e: Box<dyn std::error::Error>) -> Error {
+    let e = match e.downcast::<OracleDivergence>() {
+        Ok(divergence) => {
+            let response = HttpResponse::Conflict().json(json!({
+                "status": "error",
+                "reason": divergence.to_string(),
+                "pool_out": divergence.pool_out,
+                "oracle_out": divergence.oracle_out,
+                "divergence_pct": divergence.divergence_pct,
+            }));
+            return InternalError::from_response(
+                divergence.to_string(),
+                response,
+            )
+            .into();
+        }
+        Err(e) => e,
+    };
+    let e = match e.downcast::<PoolNotFound>() {
+        Ok(pool_not_found) => {
+            let response = HttpResponse::Gone().json(json!({
+                "status": "error",
+                "reason": pool_not_found.to_string(),
+            }));
+            return InternalError::from_response(
+                pool_not_found.to_string(),
+                response,
+            )
+            .into();
+        }
+        Err(e) => e,
+    };
+    let e = match e.downcast::<InsufficientBalance>() {
+        Ok(insufficient) => {
+            return actix_web::error::ErrorBadRequest(insufficient.to_string())
+        }
+        Err(e) => e,
+    };
+    let e = match e.downcast::<SimulationFailure>() {
+        Ok(failure) => {
+            let response = HttpResponse::UnprocessableEntity().json(json!({
+                "status": "error",
+                "reason": failure.reason,
+                "simulation": failure,
+            }));
+            return InternalError::from_response(
+                failure.to_string(),
+                response,
+            )
+            .into();
+        }
+        Err(e) => e,
+    };
+    match e.downcast::<SendAndConfirmError>() {
+        Ok(e) => match e.as_ref() {
+            SendAndConfirmError::Timeout { signature, .. } => {
+                let response = HttpResponse::GatewayTimeout().json(json!({
+                    "status": "error",
+                    "reason": e.to_string(),
+                    "signature": signature.to_string(),
+                }));
+                InternalError::from_response(e.to_string(), response).into()
+            }
+            _ => actix_web::error::ErrorInternalServerError(e.to_string()),
+        },
+        Err(e) => actix_web::error::ErrorInternalServerError(e.to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{constants, seller_service::load_amm_keys};
+    use actix_web::{body::to_bytes, http::StatusCode};
+    use solana_client::{
+        rpc_request::RpcRequest, rpc_response::RpcSimulateTransactionResult,
+    };
+    use std::collections::HashMap;
+
+    #[tokio::test]
+    async fn test_failed_simulation_response() {
+        let sim_res: RpcSimulateTransactionResult =
+            serde_json::from_value(json!({
+                "err": { "InstructionError": [2, { "Custom": 30 }] },
+                "logs": [
+                    "Program 675kPX9MHTjS2zt1qfr1NYHuzeLXfQM9H24wFSUt1Mp8 invoke [1]",
+                    "Program log: Error: exceeds desired slippage limit",
+                    "Program 675kPX9MHTjS2zt1qfr1NYHuzeLXfQM9H24wFSUt1Mp8 failed: custom program error: 0x1e",
+                ],
+            }))
+            .unwrap();
+        let failure = SimulationFailure::from_result(&sim_res).unwrap();
+        assert_eq!(failure.reason, "exceeds desired slippage limit");
+
+        let response = swap_error(failure.into()).error_response();
+        assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
+        let body: serde_json::Value = serde_json::from_slice(
+            &to_bytes(response.into_body()).await.unwrap(),
+        )
+        .unwrap();
+        assert_eq!(body["reason"], "exceeds desired slippage limit");
+        assert_eq!(body["simulation"]["logs"].as_array().unwrap().len(), 3);
+    }
+
+    #[tokio::test]
+    async fn test_missing_pool_response() {
+        let amm_pool = Pubkey::new_unique();
+        let rpc_client = RpcClient::new_mock_with_mocks(
+            "succeeds".to_string(),
+            HashMap::from([(
+                RpcRequest::GetAccountInfo,
+                json!({ "context": { "slot": 1 }, "value": null }),
+            )]),
+        );
+        let err = load_amm_keys(
+            &rpc_client,
+            &constants::RAYDIUM_LIQUIDITY_POOL_V4_PUBKEY,
+            &amm_pool,
+        )
+        .await
+        .unwrap_err();
+        assert!(err.is::<PoolNotFound>());
+
+        let response = swap_error(err).error_response();
+        assert_eq!(response.status(), StatusCode::GONE);
+        let body: serde_json::Value = serde_json::from_slice(
+            &to_bytes(response.into_body()).await.unwrap(),
+        )
+        .unwrap();
+        assert_eq!(
+            body["reason"],
+            format!("amm pool {} does not exist", amm_pool)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_oracle_divergence_response() {
+        let divergence = OracleDivergence {
+            pool_out: 1_000_000,
+            oracle_out: 1_500_000,
+            divergence_pct: 33.33,
+            max_divergence_pct: 5.,
+        };
+        let response = swap_error(divergence.into()).error_response();
+        assert_eq!(response.status(), StatusCode::CONFLICT);
+        let body: serde_json::Value = serde_json::from_slice(
+            &to_bytes(response.into_body()).await.unwrap(),
+        )
+        .unwrap();
+        assert_eq!(body["pool_out"], 1_000_000);
+        assert_eq!(body["oracle_out"], 1_500_000);
+    }
+
+    #[test]
+    fn test_oracle_divergence_bound() {
+        let request = |max_divergence_pct: serde_json::Value| {
+            serde_json::from_value::<RaydiumSwapRequest>(json!({
+                "amm_pool": Pubkey::new_unique().to_string(),
+                "input_mint": Pubkey::new_unique().to_string(),
+                "output_mint": Pubkey::new_unique().to_string(),
+                "amount": 1_000,
+                "slippage": 50,
+                "oracle_max_divergence_pct": max_divergence_pct,
+            }))
+            .unwrap()
+        };
+        assert!(request(json!(null)).oracle_check().unwrap().is_none());
+        assert!(request(json!(2.5)).oracle_check().unwrap().is_some());
+        let response = request(json!(-1.)).oracle_check().unwrap_err();
+        assert_eq!(
+            response.error_response().status(),
+            StatusCode::BAD_REQUEST
+        );
+        // NaN can't be sent as JSON, but it is refused all the same
+        let mut nan = request(json!(null));
+        nan.oracle_max_divergence_pct = Some(f64::NAN);
+        assert!(nan.oracle_check().is_err());
+    }
+
+    #[test]
+    fn test_successful_simulation_is_not_a_failure() {
+        let sim_res: RpcSimulateTransactionResult =
+            serde_json::from_value(json!({ "err": null, "logs": [] }))
+                .unwrap();
+        assert!(SimulationFailure::from_result(&sim_res).is_none());
+    }
+}
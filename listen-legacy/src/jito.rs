@@ -12,16 +12,15 @@ use log::{error, info};
 use serde::Deserialize;
 use serde_json::json;
 use solana_client::nonblocking::rpc_client::RpcClient;
+use solana_sdk::pubkey::Pubkey;
 use solana_sdk::signature::Keypair;
 use solana_sdk::signer::Signer;
-use solana_sdk::system_instruction::transfer;
+use solana_sdk::system_instruction::{transfer, SystemInstruction};
+use solana_sdk::system_program;
 use solana_sdk::transaction::Transaction;
 use solana_sdk::{
     instruction::Instruction, transaction::VersionedTransaction,
 };
-use solana_transaction_status::{
-    Encodable, EncodedTransaction, UiTransactionEncoding,
-};
 use tonic::{codegen::InterceptedService, transport::Channel};
 
 use crate::constants;
@@ -58,6 +57,38 @@ pub async fn wait_leader(
     Ok(true)
 }
 
+/// jito_tip_ix pays the tip account, the block engine only forwards bundles
+/// that tip
+pub fn jito_tip_ix(payer: &Pubkey, tip_lamports: u64) -> Instruction {
+    transfer(payer, &constants::JITO_TIP_PUBKEY, tip_lamports)
+}
+
+/// bundle_tip is the total the bundle's transactions transfer to the tip
+/// account
+pub fn bundle_tip(txs: &[VersionedTransaction]) -> u64 {
+    txs.iter()
+        .flat_map(|tx| {
+            let keys = tx.message.static_account_keys();
+            tx.message.instructions().iter().filter_map(move |ix| {
+                if keys.get(ix.program_id_index as usize)
+                    != Some(&system_program::id())
+                {
+                    return None;
+                }
+                let to = keys.get(*ix.accounts.get(1)? as usize)?;
+                match bincode::deserialize(&ix.data).ok()? {
+                    SystemInstruction::Transfer { lamports }
+                        if *to == constants::JITO_TIP_PUBKEY =>
+                    {
+                        Some(lamports)
+                    }
+                    _ => None,
+                }
+            })
+        })
+        .sum()
+}
+
 #[timed::timed(duration(printer = "info!"))]
 pub async fn send_swap_tx(
     ixs: &mut Vec<Instruction>,
@@ -136,14 +167,13 @@ pub struct JitoResponse {
 
 #[timed::timed(duration(printer = "info!"))]
 pub async fn send_jito_tx(
-    tx: Transaction,
+    tx: impl Into<VersionedTransaction>,
 ) -> Result<String, Box<dyn std::error::Error>> {
     let client = reqwest::Client::new();
 
-    let encoded_tx = match tx.encode(UiTransactionEncoding::Binary) {
-        EncodedTransaction::LegacyBinary(b) => b,
-        _ => return Err("Failed to encode transaction".into()),
-    };
+    // base58 of the wire format, legacy or v0
+    let encoded_tx =
+        bs58::encode(bincode::serialize(&tx.into())?).into_string();
 
     let res = client
         .post("https://mainnet.block-engine.jito.wtf/api/v1/transactions")
@@ -178,6 +208,27 @@ mod tests {
 
     use crate::util::env;
 
+    #[test]
+    fn test_bundle_tip() {
+        let payer = Keypair::new();
+        let tx = |ixs: &[_]| {
+            super::VersionedTransaction::from(Transaction::new_with_payer(
+                ixs,
+                Some(&payer.pubkey()),
+            ))
+        };
+        let elsewhere =
+            system_instruction::transfer(&payer.pubkey(), &payer.pubkey(), 7);
+        assert_eq!(super::bundle_tip(&[tx(&[elsewhere.clone()])]), 0);
+        assert_eq!(
+            super::bundle_tip(&[
+                tx(&[elsewhere, super::jito_tip_ix(&payer.pubkey(), 1_000)]),
+                tx(&[super::jito_tip_ix(&payer.pubkey(), 500)]),
+            ]),
+            1_500
+        );
+    }
+
     #[tokio::test]
     async fn test_send_jito_tx() {
         dotenv::dotenv().ok();
@@ -222,6 +222,7 @@ pub mod provider;
 pub mod pump;
 pub mod pump_service;
 pub mod raydium;
+pub mod rate_limit;
 pub mod rpc;
 pub mod seller;
 pub mod seller_service;
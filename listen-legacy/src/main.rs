@@ -19,10 +19,11 @@ use listen::{
     listener_service, prometheus,
     pump::{self},
     pump_service,
-    raydium::{self, Raydium, SwapArgs},
+    raydium::{self, Raydium, Slippage, SwapArgs},
     rpc, seller, seller_service,
     service::run_listen_service,
     tx_parser, util, BlockAndProgramSubscribable, Listener, Provider,
+    SendStrategy,
 };
 use solana_client::{
     nonblocking::{self, rpc_client::RpcClient},
@@ -485,6 +486,7 @@ async fn main() -> Result<(), Box<dyn Error>> {
             yes,
             dex,
             amm_pool_id,
+            skip_preflight,
         } => {
             let rpc_client = RpcClient::new(env("RPC_URL"));
             let raydium = Raydium::new();
@@ -508,7 +510,9 @@ async fn main() -> Result<(), Box<dyn Error>> {
                 let input_token_mint = Pubkey::from_str(input_mint.as_str())?;
                 let output_token_mint =
                     Pubkey::from_str(output_mint.as_str())?;
-                let slippage_bps = slippage.unwrap_or(800) as u64; // 8%
+                // 8% by default
+                let slippage =
+                    Slippage::from_bps(slippage.unwrap_or(800) as u64)?;
                 let wallet = Keypair::read_from_file(path)?;
                 info!("Wallet: {}", wallet.pubkey());
                 info!(
@@ -532,11 +536,20 @@ async fn main() -> Result<(), Box<dyn Error>> {
                         input_token_mint,
                         output_token_mint,
                         amount: amount_specified,
-                        slippage: slippage_bps,
+                        slippage,
                         wallet,
                         rpc_client,
                         confirmed: yes.unwrap_or(false),
                         no_sanity: true,
+                        send_strategy: SendStrategy::default(),
+                        compute_budget: None,
+                        swap_base_in: true,
+                        use_jito: false,
+                        retry: None,
+                        address_lookup_tables: vec![],
+                        skip_preflight: skip_preflight.unwrap_or(false),
+                        confirm_timeout: None,
+                        oracle_check: None,
                     })
                     .await?;
                 return Ok(());
@@ -1,11 +1,57 @@
 use log::info;
-use prometheus::{Encoder, IntCounter, Registry, TextEncoder};
-use std::sync::Arc;
+use prometheus::{
+    Encoder, Histogram, HistogramOpts, IntCounter, Registry, TextEncoder,
+};
+use std::sync::{Arc, OnceLock};
 use warp::Filter;
 
 static TRANSACTIONS_RECEIVED: &str = "transactions_received";
 static TRANSACTIONS_PROCESSED: &str = "transactions_processed";
 static REQUESTS_SENT: &str = "requests_sent";
+static RPC_RATE_LIMIT_WAIT: &str = "rpc_rate_limit_wait_seconds";
+static SWAP_CONFIRMATION_DURATION: &str = "swap_confirmation_duration";
+static SWAP_CONFIRMATIONS_TIMED_OUT: &str = "swap_confirmations_timed_out";
+
+/// rpc_rate_limit_wait is how long RPC calls were held back by `RPC_MAX_RPS`
+pub fn rpc_rate_limit_wait() -> &'static Histogram {
+    static HISTOGRAM: OnceLock<Histogram> = OnceLock::new();
+    HISTOGRAM.get_or_init(|| {
+        Histogram::with_opts(HistogramOpts::new(
+            RPC_RATE_LIMIT_WAIT,
+            "Time RPC calls waited for the rate limiter",
+        ))
+        .unwrap()
+    })
+}
+
+/// swap_confirmation_duration is how long sent swaps took to be confirmed,
+/// fail or drop, in seconds
+pub fn swap_confirmation_duration() -> &'static Histogram {
+    static HISTOGRAM: OnceLock<Histogram> = OnceLock::new();
+    HISTOGRAM.get_or_init(|| {
+        Histogram::with_opts(
+            HistogramOpts::new(
+                SWAP_CONFIRMATION_DURATION,
+                "Time from sending a swap to its outcome, in seconds",
+            )
+            .buckets(vec![0.5, 1.0, 2.0, 5.0, 10.0, 20.0, 30.0, 60.0, 90.0]),
+        )
+        .unwrap()
+    })
+}
+
+/// swap_confirmations_timed_out counts swaps given up on before an outcome,
+/// which may still land
+pub fn swap_confirmations_timed_out() -> &'static IntCounter {
+    static COUNTER: OnceLock<IntCounter> = OnceLock::new();
+    COUNTER.get_or_init(|| {
+        IntCounter::new(
+            SWAP_CONFIRMATIONS_TIMED_OUT,
+            "Swaps that were not confirmed within their timeout",
+        )
+        .unwrap()
+    })
+}
 
 pub fn setup_metrics(
 ) -> (Arc<IntCounter>, Arc<IntCounter>, Arc<IntCounter>, Registry) {
@@ -34,6 +80,15 @@ pub fn setup_metrics(
         .register(Box::new(transactions_processed.clone()))
         .unwrap();
     registry.register(Box::new(requests_sent.clone())).unwrap();
+    registry
+        .register(Box::new(rpc_rate_limit_wait().clone()))
+        .unwrap();
+    registry
+        .register(Box::new(swap_confirmation_duration().clone()))
+        .unwrap();
+    registry
+        .register(Box::new(swap_confirmations_timed_out().clone()))
+        .unwrap();
 
     (
         Arc::new(transactions_received),
@@ -1,21 +1,39 @@
 use crate::{
+    jito::bundle_tip,
+    rate_limit::throttle_rpc,
     raydium::{parse_holding, Holding},
     types,
     util::env,
 };
 use std::str::FromStr;
 
+use base64::Engine;
+use futures_util::future::BoxFuture;
+use futures_util::StreamExt;
 use log::{debug, info, warn};
+use serde::{Deserialize, Serialize};
 use solana_client::{
-    nonblocking::rpc_client::RpcClient, rpc_client::SerializableTransaction,
-    rpc_config::RpcTransactionConfig, rpc_request::TokenAccountsFilter,
+    client_error::{ClientError, ClientErrorKind, Result as ClientResult},
+    nonblocking::rpc_client::RpcClient,
+    rpc_client::SerializableTransaction,
+    rpc_config::{
+        RpcProgramAccountsConfig, RpcSendTransactionConfig,
+        RpcTransactionConfig,
+    },
+    rpc_request::TokenAccountsFilter,
+    rpc_response::{Response, RpcSimulateTransactionResult},
 };
 use solana_sdk::{
-    commitment_config::CommitmentConfig, program_pack::Pack, pubkey::Pubkey,
+    commitment_config::CommitmentConfig,
+    hash::Hash,
+    program_pack::Pack,
+    pubkey::Pubkey,
     signature::Signature,
+    transaction::{Transaction, TransactionError, VersionedTransaction},
 };
 use solana_transaction_status::{
-    EncodedConfirmedTransactionWithStatusMeta, UiTransactionEncoding,
+    EncodedConfirmedTransactionWithStatusMeta, TransactionConfirmationStatus,
+    UiTransactionEncoding,
 };
 use spl_token_2022::{
     extension::StateWithExtensionsOwned,
@@ -32,17 +50,525 @@ pub fn get_client(url: &str) -> Result<RpcClient, Box<dyn std::error::Error>> {
     Ok(rpc_client)
 }
 
+/// how often the signature status is polled while waiting for a transaction
+/// to land
+const CONFIRM_POLL_INTERVAL_MS: u64 = 500;
+
+/// confirm_tx_with_timeout starts polling at CONFIRM_POLL_INTERVAL_MS and
+/// backs off, doubling the interval up to this
+const MAX_CONFIRM_POLL_INTERVAL_MS: u64 = 4_000;
+
+/// SwapOutcome is the terminal state of a submitted transaction
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum SwapOutcome {
+    /// landed and executed successfully
+    Confirmed,
+    /// landed but execution failed, resubmitting the same swap won't help
+    Failed(TransactionError),
+    /// never landed before the blockhash expired, safe to retry
+    Dropped,
+}
+
+impl SwapOutcome {
+    pub fn is_retryable(&self) -> bool {
+        matches!(self, SwapOutcome::Dropped)
+    }
+}
+
+/// SendAndConfirmError is a transaction that was not seen succeeding at the
+/// commitment it was sent for
+#[derive(Debug, thiserror::Error)]
+pub enum SendAndConfirmError {
+    #[error("rpc error: {0}")]
+    Rpc(#[from] ClientError),
+    /// reached the commitment, but execution failed
+    #[error("{signature} failed: {error}")]
+    Failed {
+        signature: Signature,
+        error: TransactionError,
+    },
+    /// `last_status` is None if no endpoint had seen the transaction
+    #[error("{signature} not {commitment:?} after {timeout:?}, last status: {last_status:?}")]
+    Timeout {
+        signature: Signature,
+        commitment: CommitmentConfig,
+        timeout: std::time::Duration,
+        last_status: Option<TransactionConfirmationStatus>,
+    },
+}
+
+/// SendStrategy controls how many endpoints a signed transaction is sent to
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum SendStrategy {
+    /// send once, conserves rate limits
+    #[default]
+    Single,
+    /// broadcast the same transaction to every rpc to maximize the chance of
+    /// landing
+    Fanout { rpcs: Vec<String> },
+}
+
+/// Submission is the outcome of a sent transaction along with the endpoint
+/// which observed it first
+#[derive(Debug, Clone)]
+pub struct Submission {
+    pub signature: Signature,
+    pub outcome: SwapOutcome,
+    pub endpoint: String,
+    /// endpoints that accepted the transaction
+    pub accepted: Vec<String>,
+    /// endpoints that reported the transaction as confirmed
+    pub confirmed_by: Vec<String>,
+}
+
+/// QuorumConfirmation is the outcome agreed on by the queried endpoints
+#[derive(Debug, Clone)]
+pub struct QuorumConfirmation {
+    pub outcome: SwapOutcome,
+    pub confirmed_by: Vec<String>,
+}
+
+/// confirmation_quorum is the number of independent RPCs that have to report
+/// a swap as confirmed before it counts as landed, set with
+/// `CONFIRMATION_QUORUM`, defaults to 1
+pub fn confirmation_quorum() -> usize {
+    std::env::var("CONFIRMATION_QUORUM")
+        .ok()
+        .and_then(|quorum| quorum.parse().ok())
+        .filter(|quorum| *quorum > 0)
+        .unwrap_or(1)
+}
+
+/// how long the block engine is polled for the status of a bundle
+const BUNDLE_STATUS_TIMEOUT_SECS: u64 = 30;
+
+const DEFAULT_BLOCK_ENGINE_URL: &str = "https://mainnet.block-engine.jito.wtf";
+
+const DEFAULT_JITO_TIP_LAMPORTS: u64 = 100_000;
+
+/// jito_tip_lamports is the tip paid with every bundle, set with
+/// `JITO_TIP_LAMPORTS`, defaults to 100_000
+pub fn jito_tip_lamports() -> u64 {
+    std::env::var("JITO_TIP_LAMPORTS")
+        .ok()
+        .and_then(|tip| tip.parse().ok())
+        .unwrap_or(DEFAULT_JITO_TIP_LAMPORTS)
+}
+
+/// jito_bundles_url is the bundle endpoint of the block engine set with
+/// `BLOCK_ENGINE_URL`, defaults to the mainnet one
+pub fn jito_bundles_url() -> String {
+    let block_engine_url = std::env::var("BLOCK_ENGINE_URL")
+        .unwrap_or_else(|_| DEFAULT_BLOCK_ENGINE_URL.to_string());
+    format!("{}/api/v1/bundles", block_engine_url.trim_end_matches('/'))
+}
+
+/// BundleStatus is the state of a recently sent bundle as reported by the
+/// block engine, Invalid until it has seen the bundle
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub enum BundleStatus {
+    Invalid,
+    Pending,
+    Failed,
+    Landed,
+}
+
+/// parse_bundle_status reads the status out of a getInflightBundleStatuses
+/// response for a single bundle
+fn parse_bundle_status(
+    response: &serde_json::Value,
+) -> Result<BundleStatus, Box<dyn std::error::Error>> {
+    if let Some(error) = response.get("error") {
+        return Err(error.to_string().into());
+    }
+    let status = response["result"]["value"]
+        .get(0)
+        .and_then(|value| value.get("status"))
+        .ok_or_else(|| format!("no bundle status in {}", response))?;
+    Ok(serde_json::from_value(status.clone())?)
+}
+
+/// BundleRejected is a bundle the block engine refused or never received,
+/// none of its transactions went out
+#[derive(Debug, Clone, thiserror::Error)]
+#[error("bundle rejected: {0}")]
+pub struct BundleRejected(pub String);
+
+async fn get_bundle_status(
+    client: &reqwest::Client,
+    url: &str,
+    bundle_id: &str,
+) -> Result<BundleStatus, Box<dyn std::error::Error>> {
+    let response = client
+        .post(url)
+        .json(&serde_json::json!({
+            "jsonrpc": "2.0",
+            "id": 1,
+            "method": "getInflightBundleStatuses",
+            "params": [[bundle_id]]
+        }))
+        .send()
+        .await?
+        .json::<serde_json::Value>()
+        .await?;
+    parse_bundle_status(&response)
+}
+
+/// how long to wait before trying the next endpoint, grows with every
+/// endpoint tried
+const FAILOVER_BACKOFF_MS: u64 = 100;
+
+/// is_timeout is whether `err` is a confirmation that timed out
+pub fn is_timeout(err: &(dyn std::error::Error + 'static)) -> bool {
+    matches!(
+        err.downcast_ref::<SendAndConfirmError>(),
+        Some(SendAndConfirmError::Timeout { .. })
+    )
+}
+
+/// record_confirmation times an outcome of waiting on a swap since `start`,
+/// or counts it timed out
+fn record_confirmation<T>(
+    start: tokio::time::Instant,
+    confirmation: &Result<T, Box<dyn std::error::Error>>,
+) {
+    match confirmation {
+        Ok(_) => crate::prometheus::swap_confirmation_duration()
+            .observe(start.elapsed().as_secs_f64()),
+        Err(e) if is_timeout(e.as_ref()) => {
+            crate::prometheus::swap_confirmations_timed_out().inc();
+            warn!("{}", e);
+        }
+        Err(_) => {}
+    }
+}
+
+/// is_failover_error is true for the errors another endpoint might not run
+/// into, as opposed to errors like a failing preflight that every endpoint
+/// would return
+fn is_failover_error(err: &ClientError) -> bool {
+    match err.kind() {
+        ClientErrorKind::Io(_) => true,
+        // no status means the request never got a response
+        ClientErrorKind::Reqwest(e) => e
+            .status()
+            .map_or(true, |status| matches!(status.as_u16(), 429 | 503)),
+        _ => false,
+    }
+}
+
+type RpcAccount = solana_sdk::account::Account;
+
+/// RpcOps are the rpc calls a swap is simulated and submitted with, so the
+/// swap path runs the same against an RpcClient, a Provider failing over
+/// through its endpoints or canned responses in tests.
+/// get_multiple_accounts loads the accounts the pool vault amounts are
+/// calculated from
+pub trait RpcOps: Sync {
+    /// get_latest_blockhash returns the blockhash at the rpc's commitment
+    /// along with the last block height it is valid for
+    fn get_latest_blockhash(&self)
+        -> BoxFuture<'_, ClientResult<(Hash, u64)>>;
+
+    fn simulate_transaction<'a>(
+        &'a self,
+        tx: &'a VersionedTransaction,
+    ) -> BoxFuture<'a, ClientResult<Response<RpcSimulateTransactionResult>>>;
+
+    fn send_tx<'a>(
+        &'a self,
+        tx: &'a VersionedTransaction,
+    ) -> BoxFuture<'a, ClientResult<Signature>>;
+
+    fn get_multiple_accounts<'a>(
+        &'a self,
+        pubkeys: &'a [Pubkey],
+    ) -> BoxFuture<'a, ClientResult<Vec<Option<RpcAccount>>>>;
+}
+
+impl RpcOps for RpcClient {
+    fn get_latest_blockhash(
+        &self,
+    ) -> BoxFuture<'_, ClientResult<(Hash, u64)>> {
+        Box::pin(self.get_latest_blockhash_with_commitment(self.commitment()))
+    }
+
+    fn simulate_transaction<'a>(
+        &'a self,
+        tx: &'a VersionedTransaction,
+    ) -> BoxFuture<'a, ClientResult<Response<RpcSimulateTransactionResult>>>
+    {
+        Box::pin(RpcClient::simulate_transaction(self, tx))
+    }
+
+    fn send_tx<'a>(
+        &'a self,
+        tx: &'a VersionedTransaction,
+    ) -> BoxFuture<'a, ClientResult<Signature>> {
+        Box::pin(RpcClient::send_transaction(self, tx))
+    }
+
+    fn get_multiple_accounts<'a>(
+        &'a self,
+        pubkeys: &'a [Pubkey],
+    ) -> BoxFuture<'a, ClientResult<Vec<Option<RpcAccount>>>> {
+        Box::pin(RpcClient::get_multiple_accounts(self, pubkeys))
+    }
+}
+
+impl RpcOps for Provider {
+    fn get_latest_blockhash(
+        &self,
+    ) -> BoxFuture<'_, ClientResult<(Hash, u64)>> {
+        Box::pin(Provider::get_latest_blockhash(self))
+    }
+
+    fn simulate_transaction<'a>(
+        &'a self,
+        tx: &'a VersionedTransaction,
+    ) -> BoxFuture<'a, ClientResult<Response<RpcSimulateTransactionResult>>>
+    {
+        Box::pin(self.with_failover(
+            "simulateTransaction",
+            move |rpc_client| {
+                let tx = tx.clone();
+                Box::pin(
+                    async move { rpc_client.simulate_transaction(&tx).await },
+                )
+            },
+        ))
+    }
+
+    fn send_tx<'a>(
+        &'a self,
+        tx: &'a VersionedTransaction,
+    ) -> BoxFuture<'a, ClientResult<Signature>> {
+        Box::pin(self.with_failover("sendTransaction", move |rpc_client| {
+            let tx = tx.clone();
+            Box::pin(async move { rpc_client.send_transaction(&tx).await })
+        }))
+    }
+
+    fn get_multiple_accounts<'a>(
+        &'a self,
+        pubkeys: &'a [Pubkey],
+    ) -> BoxFuture<'a, ClientResult<Vec<Option<RpcAccount>>>> {
+        Box::pin(self.with_failover(
+            "getMultipleAccounts",
+            move |rpc_client| {
+                let pubkeys = pubkeys.to_vec();
+                Box::pin(async move {
+                    rpc_client.get_multiple_accounts(&pubkeys).await
+                })
+            },
+        ))
+    }
+}
+
 // Provider provides the data, contains both RPC client that can
 // communicate over the REST interface and utilities like getting
 // the pricing data from Jupiter
-pub struct Provider {}
+pub struct Provider {
+    rpc_clients: Vec<RpcClient>,
+}
 
 impl Provider {
+    /// new builds a provider over the endpoints, its methods try them in
+    /// order and fail over to the next on transport errors and 429/503s
+    pub fn new(
+        rpc_urls: Vec<String>,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        if rpc_urls.is_empty() {
+            return Err("provider needs at least one rpc url".into());
+        }
+        Ok(Self {
+            rpc_clients: rpc_urls
+                .into_iter()
+                .map(|url| {
+                    RpcClient::new_with_commitment(
+                        url,
+                        CommitmentConfig::processed(),
+                    )
+                })
+                .collect(),
+        })
+    }
+
+    /// from_env reads the endpoints from `RPC_URLS`, comma separated in
+    /// order of preference, or falls back to `RPC_URL`
+    pub fn from_env() -> Result<Self, Box<dyn std::error::Error>> {
+        let rpc_urls = std::env::var("RPC_URLS")
+            .or_else(|_| std::env::var("RPC_URL"))
+            .map_err(|_| "RPC_URLS or RPC_URL env var not set")?;
+        Self::new(
+            rpc_urls
+                .split(',')
+                .map(str::trim)
+                .filter(|url| !url.is_empty())
+                .map(String::from)
+                .collect(),
+        )
+    }
+
+    pub fn rpc_urls(&self) -> Vec<String> {
+        self.rpc_clients.iter().map(|c| c.url()).collect()
+    }
+
+    /// with_failover makes the call against each endpoint in turn until one
+    /// answers, the last error is returned only once all of them failed
+    async fn with_failover<T, F>(
+        &self,
+        method: &str,
+        call: F,
+    ) -> ClientResult<T>
+    where
+        F: for<'a> Fn(&'a RpcClient) -> BoxFuture<'a, ClientResult<T>>,
+    {
+        let mut last_err = None;
+        for (i, rpc_client) in self.rpc_clients.iter().enumerate() {
+            if i > 0 {
+                tokio::time::sleep(std::time::Duration::from_millis(
+                    FAILOVER_BACKOFF_MS * i as u64,
+                ))
+                .await;
+            }
+            throttle_rpc().await;
+            match call(rpc_client).await {
+                Err(e) if is_failover_error(&e) => {
+                    warn!(
+                        "{} {} failed, failing over: {}",
+                        rpc_client.url(),
+                        method,
+                        e
+                    );
+                    last_err = Some(e);
+                }
+                res => return res,
+            }
+        }
+        Err(last_err.expect("provider has at least one rpc"))
+    }
+
+    /// get_latest_blockhash returns the blockhash along with the last block
+    /// height it is valid for
+    pub async fn get_latest_blockhash(&self) -> ClientResult<(Hash, u64)> {
+        self.with_failover("getLatestBlockhash", |rpc_client| {
+            Box::pin(
+                rpc_client.get_latest_blockhash_with_commitment(
+                    rpc_client.commitment(),
+                ),
+            )
+        })
+        .await
+    }
+
+    pub async fn simulate_transaction(
+        &self,
+        tx: &Transaction,
+    ) -> ClientResult<Response<RpcSimulateTransactionResult>> {
+        self.with_failover("simulateTransaction", |rpc_client| {
+            let tx = tx.clone();
+            Box::pin(async move { rpc_client.simulate_transaction(&tx).await })
+        })
+        .await
+    }
+
+    /// send_transaction is send_tx failing over through the endpoints, an
+    /// endpoint that fails to respond might still have forwarded the
+    /// transaction, which is harmless as every copy has the same signature
+    pub async fn send_transaction(
+        &self,
+        tx: &Transaction,
+    ) -> ClientResult<Signature> {
+        self.with_failover("sendTransaction", |rpc_client| {
+            let tx = tx.clone();
+            Box::pin(async move { rpc_client.send_transaction(&tx).await })
+        })
+        .await
+    }
+
+    /// send_and_confirm sends the transaction and polls its status until it
+    /// reaches `commitment`, returning the signature only if it succeeded.
+    /// Gives up after `timeout` with the last status seen, the transaction
+    /// may still land until its blockhash expires
+    pub async fn send_and_confirm<T>(
+        &self,
+        tx: &T,
+        commitment: CommitmentConfig,
+        timeout: std::time::Duration,
+    ) -> Result<Signature, SendAndConfirmError>
+    where
+        T: SerializableTransaction + Clone + Send + Sync + 'static,
+    {
+        let signature = self
+            .with_failover("sendTransaction", |rpc_client| {
+                let tx = tx.clone();
+                Box::pin(async move { rpc_client.send_transaction(&tx).await })
+            })
+            .await?;
+        let deadline = tokio::time::Instant::now() + timeout;
+        let mut last_status = None;
+        loop {
+            let status = self
+                .with_failover("getSignatureStatuses", |rpc_client| {
+                    Box::pin(async move {
+                        rpc_client.get_signature_statuses(&[signature]).await
+                    })
+                })
+                .await?
+                .value
+                .into_iter()
+                .next()
+                .flatten();
+            if let Some(status) = status {
+                if status.satisfies_commitment(commitment) {
+                    return match status.err {
+                        Some(error) => Err(SendAndConfirmError::Failed {
+                            signature,
+                            error,
+                        }),
+                        None => Ok(signature),
+                    };
+                }
+                last_status = status.confirmation_status;
+            }
+            if tokio::time::Instant::now() >= deadline {
+                return Err(SendAndConfirmError::Timeout {
+                    signature,
+                    commitment,
+                    timeout,
+                    last_status,
+                });
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(
+                CONFIRM_POLL_INTERVAL_MS,
+            ))
+            .await;
+        }
+    }
+
+    pub async fn get_program_accounts_with_config(
+        &self,
+        program_id: &Pubkey,
+        config: RpcProgramAccountsConfig,
+    ) -> ClientResult<Vec<(Pubkey, solana_sdk::account::Account)>> {
+        self.with_failover("getProgramAccounts", |rpc_client| {
+            let (program_id, config) = (*program_id, config.clone());
+            Box::pin(async move {
+                rpc_client
+                    .get_program_accounts_with_config(&program_id, config)
+                    .await
+            })
+        })
+        .await
+    }
+
     #[timed(duration(printer = "info!"))]
     pub async fn get_holdings(
         rpc_client: &RpcClient,
         owner: &Pubkey,
     ) -> Result<Vec<Holding>, Box<dyn std::error::Error>> {
+        throttle_rpc().await;
         let atas = rpc_client
             .get_token_accounts_by_owner(
                 owner,
@@ -64,6 +590,7 @@ impl Provider {
         rpc_client: &RpcClient,
         pubkey: &Pubkey,
     ) -> Result<u64, Box<dyn std::error::Error>> {
+        throttle_rpc().await;
         let balance = rpc_client.get_balance(pubkey).await?;
         Ok(balance)
     }
@@ -74,6 +601,7 @@ impl Provider {
         pubkey: &Pubkey,
         mint: &Pubkey,
     ) -> Result<u64, Box<dyn std::error::Error>> {
+        throttle_rpc().await;
         let token_accounts = rpc_client
             .get_token_accounts_by_owner(
                 pubkey,
@@ -82,6 +610,7 @@ impl Provider {
             .await?;
         match token_accounts.first() {
             Some(token_account) => {
+                throttle_rpc().await;
                 let acount_info = rpc_client
                     .get_account(&Pubkey::from_str(
                         token_account.pubkey.as_str(),
@@ -107,6 +636,7 @@ impl Provider {
         let mut backoff = 100;
         let retries = 5;
         for _ in 0..retries {
+            throttle_rpc().await;
             match rpc_client
                 .get_transaction_with_config(
                     &sig,
@@ -152,6 +682,7 @@ impl Provider {
         tx: &impl SerializableTransaction,
         _skip_preflight: bool,
     ) -> Result<String, Box<dyn std::error::Error>> {
+        throttle_rpc().await;
         let start = std::time::Instant::now();
         match rpc_client
             .send_transaction(
@@ -172,6 +703,420 @@ impl Provider {
         }
     }
 
+    /// send_jito_bundle sends the transactions as a bundle through the block
+    /// engine and polls its status until it lands. The bundle has to pay at
+    /// least `tip_lamports` to the tip account, see jito::jito_tip_ix. A
+    /// bundle that never reached the block engine or that it refused is a
+    /// BundleRejected error; any other error leaves the bundle possibly
+    /// submitted. One still pending after BUNDLE_STATUS_TIMEOUT_SECS isn't
+    /// an error, as it can land until its blockhash expires. Returns the
+    /// bundle id
+    #[timed(duration(printer = "info!"))]
+    pub async fn send_jito_bundle(
+        client: &reqwest::Client,
+        txs: &[VersionedTransaction],
+        tip_lamports: u64,
+    ) -> Result<String, Box<dyn std::error::Error>> {
+        if txs.is_empty() || txs.len() > 5 {
+            return Err(BundleRejected(format!(
+                "a bundle holds 1 to 5 transactions, got {}",
+                txs.len()
+            ))
+            .into());
+        }
+        let tip = bundle_tip(txs);
+        if tip < tip_lamports {
+            return Err(BundleRejected(format!(
+                "bundle tips {} lamports, {} required",
+                tip, tip_lamports
+            ))
+            .into());
+        }
+        let encoded = txs
+            .iter()
+            .map(|tx| {
+                Ok(base64::prelude::BASE64_STANDARD
+                    .encode(bincode::serialize(tx)?))
+            })
+            .collect::<Result<Vec<_>, Box<dyn std::error::Error>>>()?;
+
+        let url = jito_bundles_url();
+        let response = client
+            .post(&url)
+            .json(&serde_json::json!({
+                "jsonrpc": "2.0",
+                "id": 1,
+                "method": "sendBundle",
+                "params": [encoded, { "encoding": "base64" }]
+            }))
+            .send()
+            .await
+            .map_err(|e| -> Box<dyn std::error::Error> {
+                if e.is_connect() {
+                    BundleRejected(e.to_string()).into()
+                } else {
+                    e.into()
+                }
+            })?
+            .json::<serde_json::Value>()
+            .await?;
+        if let Some(error) = response.get("error") {
+            return Err(BundleRejected(error.to_string()).into());
+        }
+        let bundle_id = response["result"]
+            .as_str()
+            .ok_or_else(|| format!("no bundle id in {}", response))?
+            .to_string();
+        info!("sent bundle {}", bundle_id);
+
+        let deadline = tokio::time::Instant::now()
+            + std::time::Duration::from_secs(BUNDLE_STATUS_TIMEOUT_SECS);
+        while tokio::time::Instant::now() < deadline {
+            tokio::time::sleep(std::time::Duration::from_millis(
+                CONFIRM_POLL_INTERVAL_MS,
+            ))
+            .await;
+            // the bundle is out, a failed poll must not look like a rejection
+            match get_bundle_status(&client, &url, &bundle_id).await {
+                Ok(BundleStatus::Landed) => return Ok(bundle_id),
+                Ok(BundleStatus::Failed) => {
+                    return Err(format!("bundle {} failed", bundle_id).into())
+                }
+                Ok(status) => debug!("bundle {}: {:?}", bundle_id, status),
+                Err(e) => warn!("bundle {} status: {}", bundle_id, e),
+            }
+        }
+        warn!(
+            "bundle {} not landed after {}s",
+            bundle_id, BUNDLE_STATUS_TIMEOUT_SECS
+        );
+        Ok(bundle_id)
+    }
+
+    /// confirm_tx polls the signature status until the transaction lands or
+    /// the block height moves past last_valid_block_height, after which the
+    /// transaction can no longer be included and is considered dropped
+    pub async fn confirm_tx(
+        rpc_client: &RpcClient,
+        signature: &Signature,
+        last_valid_block_height: u64,
+    ) -> Result<SwapOutcome, Box<dyn std::error::Error>> {
+        Provider::confirm_tx_with_timeout(
+            rpc_client,
+            signature,
+            last_valid_block_height,
+            None,
+        )
+        .await
+    }
+
+    /// confirm_tx_with_timeout is confirm_tx giving up after `timeout`, if
+    /// any, with a SendAndConfirmError::Timeout that carries the signature:
+    /// the transaction can still land until its blockhash expires, so check
+    /// on it later rather than take it as failed. The poll interval backs
+    /// off exponentially. Outcomes are timed in swap_confirmation_duration,
+    /// timeouts counted in swap_confirmations_timed_out
+    #[timed(duration(printer = "info!"))]
+    pub async fn confirm_tx_with_timeout(
+        rpc_client: &RpcClient,
+        signature: &Signature,
+        last_valid_block_height: u64,
+        timeout: Option<std::time::Duration>,
+    ) -> Result<SwapOutcome, Box<dyn std::error::Error>> {
+        let start = tokio::time::Instant::now();
+        let outcome = Provider::confirm_tx_until(
+            rpc_client,
+            signature,
+            last_valid_block_height,
+            start,
+            timeout,
+        )
+        .await;
+        record_confirmation(start, &outcome);
+        outcome
+    }
+
+    /// confirm_tx_until polls for the outcome of the transaction until
+    /// `timeout` after `start`, if any, without recording metrics, for
+    /// callers polling several endpoints for one swap
+    async fn confirm_tx_until(
+        rpc_client: &RpcClient,
+        signature: &Signature,
+        last_valid_block_height: u64,
+        start: tokio::time::Instant,
+        timeout: Option<std::time::Duration>,
+    ) -> Result<SwapOutcome, Box<dyn std::error::Error>> {
+        let outcome = Provider::poll_tx_status(
+            rpc_client,
+            signature,
+            last_valid_block_height,
+            timeout.map(|timeout| start + timeout),
+        )
+        .await?;
+        outcome.ok_or_else(|| {
+            SendAndConfirmError::Timeout {
+                signature: *signature,
+                commitment: CommitmentConfig::confirmed(),
+                timeout: timeout.unwrap_or_default(),
+                last_status: None,
+            }
+            .into()
+        })
+    }
+
+    /// poll_tx_status is the outcome of the transaction, None if there is
+    /// none by `deadline`
+    async fn poll_tx_status(
+        rpc_client: &RpcClient,
+        signature: &Signature,
+        last_valid_block_height: u64,
+        deadline: Option<tokio::time::Instant>,
+    ) -> Result<Option<SwapOutcome>, Box<dyn std::error::Error>> {
+        let mut interval =
+            std::time::Duration::from_millis(CONFIRM_POLL_INTERVAL_MS);
+        loop {
+            throttle_rpc().await;
+            let status = rpc_client
+                .get_signature_statuses(&[*signature])
+                .await?
+                .value
+                .into_iter()
+                .next()
+                .flatten();
+            match status {
+                Some(status) => {
+                    if let Some(err) = status.err {
+                        return Ok(Some(SwapOutcome::Failed(err)));
+                    }
+                    if status
+                        .satisfies_commitment(CommitmentConfig::confirmed())
+                    {
+                        return Ok(Some(SwapOutcome::Confirmed));
+                    }
+                    // landed, waiting for the cluster to confirm it
+                }
+                None => {
+                    throttle_rpc().await;
+                    let block_height = rpc_client.get_block_height().await?;
+                    if block_height > last_valid_block_height {
+                        debug!(
+                            "{} not found at block height {}, dropped",
+                            signature, block_height
+                        );
+                        return Ok(Some(SwapOutcome::Dropped));
+                    }
+                }
+            }
+            let wake = tokio::time::Instant::now() + interval;
+            match deadline {
+                Some(deadline) if deadline <= tokio::time::Instant::now() => {
+                    return Ok(None);
+                }
+                Some(deadline) => {
+                    tokio::time::sleep_until(wake.min(deadline)).await
+                }
+                None => tokio::time::sleep_until(wake).await,
+            }
+            interval = (interval * 2).min(std::time::Duration::from_millis(
+                MAX_CONFIRM_POLL_INTERVAL_MS,
+            ));
+        }
+    }
+
+    /// confirm_tx_quorum waits for the transaction on every client and only
+    /// reports it confirmed once `quorum` of them agree, so that a single
+    /// lagging or misbehaving RPC can't produce a false confirmation. With a
+    /// `timeout` it gives up like confirm_tx_with_timeout when no outcome was
+    /// reached in time
+    #[timed(duration(printer = "info!"))]
+    pub async fn confirm_tx_quorum(
+        rpc_clients: &[RpcClient],
+        signature: &Signature,
+        last_valid_block_height: u64,
+        quorum: usize,
+        timeout: Option<std::time::Duration>,
+    ) -> Result<QuorumConfirmation, Box<dyn std::error::Error>> {
+        if quorum == 0 || quorum > rpc_clients.len() {
+            return Err(format!(
+                "confirmation quorum of {} needs as many rpcs, got {}",
+                quorum,
+                rpc_clients.len()
+            )
+            .into());
+        }
+        let start = tokio::time::Instant::now();
+        let mut pending = rpc_clients
+            .iter()
+            .map(|rpc_client| async move {
+                let outcome = Provider::confirm_tx_until(
+                    rpc_client,
+                    signature,
+                    last_valid_block_height,
+                    start,
+                    timeout,
+                )
+                .await;
+                (rpc_client.url(), outcome)
+            })
+            .collect::<futures_util::stream::FuturesUnordered<_>>();
+
+        let mut remaining = rpc_clients.len();
+        let mut confirmed_by = vec![];
+        let mut failed = None;
+        let mut dropped = 0;
+        let mut timed_out = None;
+        while let Some((endpoint, outcome)) = pending.next().await {
+            remaining -= 1;
+            match outcome {
+                Ok(SwapOutcome::Confirmed) => {
+                    confirmed_by.push(endpoint);
+                    if confirmed_by.len() >= quorum {
+                        return Ok(QuorumConfirmation {
+                            outcome: SwapOutcome::Confirmed,
+                            confirmed_by,
+                        });
+                    }
+                }
+                Ok(SwapOutcome::Failed(err)) => failed = Some(err),
+                Ok(SwapOutcome::Dropped) => dropped += 1,
+                Err(e) if is_timeout(e.as_ref()) => timed_out = Some(e),
+                Err(e) => warn!("{} could not confirm tx: {}", endpoint, e),
+            }
+            if confirmed_by.len() + remaining < quorum {
+                break;
+            }
+        }
+
+        let outcome = match failed {
+            Some(err) => SwapOutcome::Failed(err),
+            None if dropped > 0 => SwapOutcome::Dropped,
+            None => {
+                let short = format!(
+                    "{} confirmed by {} of {} required rpcs",
+                    signature,
+                    confirmed_by.len(),
+                    quorum
+                );
+                // the rest may still confirm it, so it isn't safe to retry
+                if let Some(timed_out) = timed_out {
+                    warn!("{}, the others timed out", short);
+                    return Err(timed_out);
+                }
+                return Err(short.into());
+            }
+        };
+        warn!(
+            "{} confirmed by {} of {} required rpcs: {:?}",
+            signature,
+            confirmed_by.len(),
+            quorum,
+            outcome
+        );
+        Ok(QuorumConfirmation {
+            outcome,
+            confirmed_by,
+        })
+    }
+
+    /// send_tx_fanout sends the same signed transaction through every client
+    /// and returns the outcome reported first, or with a quorum above 1 the
+    /// outcome agreed on by the clients; all copies share a signature so the
+    /// transaction can land at most once. With `skip_preflight` the
+    /// endpoints send it on without simulating it first; `confirm_timeout`
+    /// is as in confirm_tx_with_timeout
+    #[timed(duration(printer = "info!"))]
+    pub async fn send_tx_fanout(
+        rpc_clients: &[RpcClient],
+        tx: &impl SerializableTransaction,
+        last_valid_block_height: u64,
+        quorum: usize,
+        skip_preflight: bool,
+        confirm_timeout: Option<std::time::Duration>,
+    ) -> Result<Submission, Box<dyn std::error::Error>> {
+        let config = RpcSendTransactionConfig {
+            skip_preflight,
+            ..RpcSendTransactionConfig::default()
+        };
+        let sends = futures_util::future::join_all(rpc_clients.iter().map(
+            |rpc_client| async move {
+                throttle_rpc().await;
+                rpc_client.send_transaction_with_config(tx, config).await
+            },
+        ))
+        .await;
+        let mut accepted = vec![];
+        let mut last_err = None;
+        for (rpc_client, res) in rpc_clients.iter().zip(sends) {
+            match res {
+                Ok(_) => accepted.push(rpc_client),
+                Err(e) => {
+                    warn!("{} rejected tx: {}", rpc_client.url(), e);
+                    last_err = Some(e);
+                }
+            }
+        }
+        if accepted.is_empty() {
+            return Err(match last_err {
+                Some(e) => e.into(),
+                None => "no rpc endpoints to send to".into(),
+            });
+        }
+
+        let signature = *tx.get_signature();
+        let accepted_urls = accepted.iter().map(|c| c.url()).collect();
+        let start = tokio::time::Instant::now();
+        if quorum > 1 {
+            // any endpoint can observe the status, not just the accepting ones
+            let confirmation = Provider::confirm_tx_quorum(
+                rpc_clients,
+                &signature,
+                last_valid_block_height,
+                quorum,
+                confirm_timeout,
+            )
+            .await;
+            record_confirmation(start, &confirmation);
+            let confirmation = confirmation?;
+            return Ok(Submission {
+                signature,
+                outcome: confirmation.outcome,
+                endpoint: confirmation.confirmed_by.join(","),
+                accepted: accepted_urls,
+                confirmed_by: confirmation.confirmed_by,
+            });
+        }
+
+        let confirmations = accepted.iter().map(|rpc_client| {
+            Box::pin(async move {
+                Provider::confirm_tx_until(
+                    rpc_client,
+                    &signature,
+                    last_valid_block_height,
+                    start,
+                    confirm_timeout,
+                )
+                .await
+                .map(|outcome| (outcome, rpc_client.url()))
+            })
+        });
+        let confirmation = futures_util::future::select_ok(confirmations)
+            .await
+            .map(|(confirmation, _)| confirmation);
+        record_confirmation(start, &confirmation);
+        let (outcome, endpoint) = confirmation?;
+
+        Ok(Submission {
+            signature,
+            confirmed_by: match outcome {
+                SwapOutcome::Confirmed => vec![endpoint.clone()],
+                _ => vec![],
+            },
+            outcome,
+            endpoint,
+            accepted: accepted_urls,
+        })
+    }
+
     /// sanity_check is for mint_authority and freeze_authority, for non
     /// pump.fun tokens is crucial, mint authority enables minting any amount of
     /// the token and freeze authority can renounce the ability to trade the
@@ -181,6 +1126,7 @@ impl Provider {
         rpc_client: &RpcClient,
         mint: &Pubkey,
     ) -> Result<(bool, String), Box<dyn std::error::Error>> {
+        throttle_rpc().await;
         let account = rpc_client.get_account(mint).await?;
         // recommended approach
         // get the token account mint based on the account too to confirm
@@ -215,6 +1161,7 @@ pub async fn get_tx_async_with_client(
     let sig = Signature::from_str(signature)?;
     let mut backoff = 100;
     for _ in 0..retries {
+        throttle_rpc().await;
         match rpc_client
             .get_transaction_with_config(
                 &sig,
@@ -248,6 +1195,7 @@ pub async fn get_tx_async(
     let mut backoff = 100;
     let retries = 5;
     for _ in 0..retries {
+        throttle_rpc().await;
         match rpc_client
             .get_transaction_with_config(
                 &sig,
@@ -269,3 +1217,518 @@ pub async fn get_tx_async(
     }
     Err(format!("could not fetch {}", signature).into())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    use solana_client::rpc_request::RpcRequest;
+    use solana_sdk::{
+        hash::Hash, instruction::InstructionError, signature::Keypair,
+        signer::Signer, system_instruction,
+    };
+    use solana_transaction_status::{
+        TransactionConfirmationStatus, TransactionStatus,
+    };
+    use std::collections::HashMap;
+
+    fn mock_statuses(status: Option<TransactionStatus>) -> serde_json::Value {
+        json!({
+            "context": { "slot": 1 },
+            "value": [status],
+        })
+    }
+
+    fn landed(err: Option<TransactionError>) -> TransactionStatus {
+        TransactionStatus {
+            slot: 1,
+            confirmations: None,
+            status: match &err {
+                Some(err) => Err(err.clone()),
+                None => Ok(()),
+            },
+            err,
+            confirmation_status: Some(
+                TransactionConfirmationStatus::Confirmed,
+            ),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_confirm_tx_confirmed() {
+        let rpc_client = RpcClient::new_mock_with_mocks(
+            "succeeds",
+            HashMap::from([(
+                RpcRequest::GetSignatureStatuses,
+                mock_statuses(Some(landed(None))),
+            )]),
+        );
+        let outcome =
+            Provider::confirm_tx(&rpc_client, &Signature::default(), 100)
+                .await
+                .unwrap();
+        assert_eq!(outcome, SwapOutcome::Confirmed);
+    }
+
+    #[tokio::test]
+    async fn test_confirm_tx_failed() {
+        let err = TransactionError::InstructionError(
+            0,
+            InstructionError::Custom(30),
+        );
+        let rpc_client = RpcClient::new_mock_with_mocks(
+            "succeeds",
+            HashMap::from([(
+                RpcRequest::GetSignatureStatuses,
+                mock_statuses(Some(landed(Some(err.clone())))),
+            )]),
+        );
+        let outcome =
+            Provider::confirm_tx(&rpc_client, &Signature::default(), 100)
+                .await
+                .unwrap();
+        assert_eq!(outcome, SwapOutcome::Failed(err));
+        assert!(!outcome.is_retryable());
+    }
+
+    #[tokio::test]
+    async fn test_confirm_tx_dropped() {
+        let rpc_client = RpcClient::new_mock_with_mocks(
+            "succeeds",
+            HashMap::from([
+                (RpcRequest::GetSignatureStatuses, mock_statuses(None)),
+                (RpcRequest::GetBlockHeight, json!(101)),
+            ]),
+        );
+        let outcome =
+            Provider::confirm_tx(&rpc_client, &Signature::default(), 100)
+                .await
+                .unwrap();
+        assert_eq!(outcome, SwapOutcome::Dropped);
+        assert!(outcome.is_retryable());
+    }
+
+    #[tokio::test]
+    async fn test_confirm_tx_timeout_returns_signature() {
+        // never found, while the blockhash stays valid
+        let rpc_client = RpcClient::new_mock("sig_not_found");
+        let signature = Signature::new_unique();
+        let timeout = std::time::Duration::from_millis(1_200);
+        let timed_out_before =
+            crate::prometheus::swap_confirmations_timed_out().get();
+        let err = Provider::confirm_tx_with_timeout(
+            &rpc_client,
+            &signature,
+            u64::MAX,
+            Some(timeout),
+        )
+        .await
+        .unwrap_err();
+        assert!(is_timeout(err.as_ref()));
+        match err.downcast_ref::<SendAndConfirmError>() {
+            Some(SendAndConfirmError::Timeout {
+                signature: timed_out,
+                timeout: after,
+                ..
+            }) => {
+                assert_eq!(*timed_out, signature);
+                assert_eq!(*after, timeout);
+            }
+            other => panic!("expected a timeout, got {:?}", other),
+        }
+        assert!(
+            crate::prometheus::swap_confirmations_timed_out().get()
+                > timed_out_before
+        );
+    }
+
+    #[tokio::test]
+    async fn test_send_tx_fanout() {
+        let payer = Keypair::new();
+        let tx = Transaction::new_signed_with_payer(
+            &[system_instruction::transfer(
+                &payer.pubkey(),
+                &payer.pubkey(),
+                1,
+            )],
+            Some(&payer.pubkey()),
+            &[&payer],
+            Hash::default(),
+        );
+        // "fails" mocks an endpoint that rejects every request
+        let rpc_clients = [
+            RpcClient::new_mock("succeeds"),
+            RpcClient::new_mock("fails"),
+            RpcClient::new_mock("succeeds"),
+        ];
+        let submission =
+            Provider::send_tx_fanout(&rpc_clients, &tx, 100, 1, false, None)
+                .await
+                .unwrap();
+        assert_eq!(submission.signature, tx.signatures[0]);
+        assert_eq!(submission.outcome, SwapOutcome::Confirmed);
+        assert_eq!(submission.endpoint, "succeeds");
+        assert_eq!(submission.accepted, vec!["succeeds", "succeeds"]);
+        assert_eq!(submission.confirmed_by, vec!["succeeds"]);
+    }
+
+    fn confirming_client(url: &str) -> RpcClient {
+        RpcClient::new_mock_with_mocks(
+            url.to_string(),
+            HashMap::from([(
+                RpcRequest::GetSignatureStatuses,
+                mock_statuses(Some(landed(None))),
+            )]),
+        )
+    }
+
+    fn dropping_client() -> RpcClient {
+        RpcClient::new_mock_with_mocks(
+            "succeeds".to_string(),
+            HashMap::from([
+                (RpcRequest::GetSignatureStatuses, mock_statuses(None)),
+                (RpcRequest::GetBlockHeight, json!(101)),
+            ]),
+        )
+    }
+
+    #[tokio::test]
+    async fn test_confirm_tx_quorum() {
+        // mocks answer once, so each check gets fresh clients; one of the
+        // three rpcs never sees the transaction
+        let rpc_clients = || {
+            [
+                confirming_client("succeeds-a"),
+                dropping_client(),
+                confirming_client("succeeds-b"),
+            ]
+        };
+        let signature = Signature::default();
+
+        let confirmation = Provider::confirm_tx_quorum(
+            &rpc_clients(),
+            &signature,
+            100,
+            2,
+            None,
+        )
+        .await
+        .unwrap();
+        assert_eq!(confirmation.outcome, SwapOutcome::Confirmed);
+        let mut confirmed_by = confirmation.confirmed_by;
+        confirmed_by.sort();
+        assert_eq!(confirmed_by, vec!["succeeds-a", "succeeds-b"]);
+
+        let confirmation = Provider::confirm_tx_quorum(
+            &rpc_clients(),
+            &signature,
+            100,
+            3,
+            None,
+        )
+        .await
+        .unwrap();
+        assert_eq!(confirmation.outcome, SwapOutcome::Dropped);
+        assert_eq!(confirmation.confirmed_by.len(), 2);
+
+        assert!(Provider::confirm_tx_quorum(
+            &rpc_clients(),
+            &signature,
+            100,
+            4,
+            None
+        )
+        .await
+        .is_err());
+
+        // short of the quorum in time, with the signature to keep watching
+        let err = Provider::confirm_tx_quorum(
+            &[
+                confirming_client("succeeds-a"),
+                RpcClient::new_mock("sig_not_found"),
+            ],
+            &signature,
+            u64::MAX,
+            2,
+            Some(std::time::Duration::from_millis(1_200)),
+        )
+        .await
+        .unwrap_err();
+        match err.downcast_ref::<SendAndConfirmError>() {
+            Some(SendAndConfirmError::Timeout {
+                signature: timed_out,
+                ..
+            }) => assert_eq!(*timed_out, signature),
+            other => panic!("expected a timeout, got {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_send_jito_bundle_requires_tip() {
+        let payer = Keypair::new();
+        let tx =
+            VersionedTransaction::from(Transaction::new_signed_with_payer(
+                &[system_instruction::transfer(
+                    &payer.pubkey(),
+                    &payer.pubkey(),
+                    1,
+                )],
+                Some(&payer.pubkey()),
+                &[&payer],
+                Hash::default(),
+            ));
+        // rejected before anything is sent to the block engine
+        let client = reqwest::Client::new();
+        let err = Provider::send_jito_bundle(&client, &[tx], 1_000)
+            .await
+            .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "bundle rejected: bundle tips 0 lamports, 1000 required"
+        );
+        assert!(err.is::<BundleRejected>());
+        assert!(Provider::send_jito_bundle(&client, &[], 0)
+            .await
+            .unwrap_err()
+            .is::<BundleRejected>());
+    }
+
+    #[test]
+    fn test_parse_bundle_status() {
+        let response = |status: &str| {
+            json!({
+                "jsonrpc": "2.0",
+                "id": 1,
+                "result": {
+                    "context": { "slot": 1 },
+                    "value": [{ "bundle_id": "b", "status": status }],
+                },
+            })
+        };
+        assert_eq!(
+            parse_bundle_status(&response("Landed")).unwrap(),
+            BundleStatus::Landed
+        );
+        assert_eq!(
+            parse_bundle_status(&response("Invalid")).unwrap(),
+            BundleStatus::Invalid
+        );
+        assert!(parse_bundle_status(&json!({
+            "jsonrpc": "2.0",
+            "id": 1,
+            "error": { "code": -32602, "message": "bad params" },
+        }))
+        .is_err());
+    }
+
+    #[tokio::test]
+    async fn test_provider_fails_over() {
+        // nothing listens on the discard port, so the first endpoint is a
+        // transport error
+        let provider = Provider {
+            rpc_clients: vec![
+                RpcClient::new("http://127.0.0.1:9".to_string()),
+                RpcClient::new_mock("succeeds".to_string()),
+            ],
+        };
+        assert!(provider.get_latest_blockhash().await.is_ok());
+
+        let provider = Provider {
+            rpc_clients: vec![
+                RpcClient::new("http://127.0.0.1:9".to_string()),
+                RpcClient::new("http://127.0.0.1:9".to_string()),
+            ],
+        };
+        let err = provider.get_latest_blockhash().await.unwrap_err();
+        assert!(is_failover_error(&err));
+
+        assert!(Provider::new(vec![]).is_err());
+    }
+
+    /// serve_rpc answers json rpc over http, sendTransaction with
+    /// `signature` and each getSignatureStatuses with the next of
+    /// `statuses`, repeating the last one; returns the url and the number of
+    /// status polls
+    async fn serve_rpc(
+        signature: Signature,
+        statuses: Vec<Option<TransactionStatus>>,
+    ) -> (String, std::sync::Arc<std::sync::atomic::AtomicUsize>) {
+        use std::sync::atomic::Ordering;
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let listener =
+            tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let polls =
+            std::sync::Arc::new(std::sync::atomic::AtomicUsize::new(0));
+        let served = polls.clone();
+        tokio::spawn(async move {
+            loop {
+                let (mut stream, _) = listener.accept().await.unwrap();
+                let (polls, statuses) = (served.clone(), statuses.clone());
+                tokio::spawn(async move {
+                    let mut buf = Vec::new();
+                    let mut chunk = [0u8; 4096];
+                    loop {
+                        // a request is complete once its body is in
+                        let request = loop {
+                            let text = String::from_utf8_lossy(&buf);
+                            if let Some((head, body)) =
+                                text.split_once("\r\n\r\n")
+                            {
+                                let len = head
+                                    .lines()
+                                    .find_map(|line| {
+                                        let (name, value) =
+                                            line.split_once(':')?;
+                                        name.eq_ignore_ascii_case(
+                                            "content-length",
+                                        )
+                                        .then(|| value.trim().parse().ok())?
+                                    })
+                                    .unwrap_or(0usize);
+                                if body.len() >= len {
+                                    let request: serde_json::Value =
+                                        serde_json::from_str(&body[..len])
+                                            .unwrap();
+                                    buf.drain(..head.len() + 4 + len);
+                                    break request;
+                                }
+                            }
+                            match stream.read(&mut chunk).await {
+                                Ok(0) | Err(_) => return,
+                                Ok(n) => buf.extend_from_slice(&chunk[..n]),
+                            }
+                        };
+                        let result = match request["method"].as_str() {
+                            Some("getVersion") => json!({
+                                "solana-core": "1.16.27",
+                                "feature-set": 0,
+                            }),
+                            Some("sendTransaction") => {
+                                json!(signature.to_string())
+                            }
+                            Some("getSignatureStatuses") => {
+                                let poll =
+                                    polls.fetch_add(1, Ordering::SeqCst);
+                                mock_statuses(
+                                    statuses[poll.min(statuses.len() - 1)]
+                                        .clone(),
+                                )
+                            }
+                            method => panic!("unexpected {:?}", method),
+                        };
+                        let body = json!({
+                            "jsonrpc": "2.0",
+                            "id": request["id"],
+                            "result": result,
+                        })
+                        .to_string();
+                        let response = format!(
+                            "HTTP/1.1 200 OK\r\ncontent-type: application/json\r\ncontent-length: {}\r\n\r\n{}",
+                            body.len(),
+                            body
+                        );
+                        if stream.write_all(response.as_bytes()).await.is_err()
+                        {
+                            return;
+                        }
+                    }
+                });
+            }
+        });
+        (url, polls)
+    }
+
+    fn at(
+        confirmation_status: TransactionConfirmationStatus,
+        err: Option<TransactionError>,
+    ) -> Option<TransactionStatus> {
+        Some(TransactionStatus {
+            confirmation_status: Some(confirmation_status),
+            ..landed(err)
+        })
+    }
+
+    #[tokio::test]
+    async fn test_send_and_confirm() {
+        let payer = Keypair::new();
+        let tx = Transaction::new_signed_with_payer(
+            &[system_instruction::transfer(
+                &payer.pubkey(),
+                &payer.pubkey(),
+                1,
+            )],
+            Some(&payer.pubkey()),
+            &[&payer],
+            Hash::default(),
+        );
+        let signature = tx.signatures[0];
+        let timeout = std::time::Duration::from_secs(5);
+
+        // processed on the first poll, confirmed on the second
+        let (url, polls) = serve_rpc(
+            signature,
+            vec![
+                at(TransactionConfirmationStatus::Processed, None),
+                at(TransactionConfirmationStatus::Confirmed, None),
+            ],
+        )
+        .await;
+        let provider = Provider::new(vec![url]).unwrap();
+        let confirmed = provider
+            .send_and_confirm(&tx, CommitmentConfig::confirmed(), timeout)
+            .await
+            .unwrap();
+        assert_eq!(confirmed, signature);
+        assert_eq!(polls.load(std::sync::atomic::Ordering::SeqCst), 2);
+
+        // confirmed, but the transaction itself failed
+        let err = TransactionError::InstructionError(
+            0,
+            InstructionError::Custom(30),
+        );
+        let (url, _) = serve_rpc(
+            signature,
+            vec![at(
+                TransactionConfirmationStatus::Confirmed,
+                Some(err.clone()),
+            )],
+        )
+        .await;
+        let provider = Provider::new(vec![url]).unwrap();
+        match provider
+            .send_and_confirm(&tx, CommitmentConfig::confirmed(), timeout)
+            .await
+        {
+            Err(SendAndConfirmError::Failed { error, .. }) => {
+                assert_eq!(error, err)
+            }
+            res => panic!("expected a failed transaction, got {:?}", res),
+        }
+
+        // never finalized, the last status seen comes with the timeout
+        let (url, _) = serve_rpc(
+            signature,
+            vec![None, at(TransactionConfirmationStatus::Confirmed, None)],
+        )
+        .await;
+        let provider = Provider::new(vec![url]).unwrap();
+        match provider
+            .send_and_confirm(
+                &tx,
+                CommitmentConfig::finalized(),
+                std::time::Duration::from_secs(1),
+            )
+            .await
+        {
+            Err(SendAndConfirmError::Timeout { last_status, .. }) => {
+                assert_eq!(
+                    last_status,
+                    Some(TransactionConfirmationStatus::Confirmed)
+                )
+            }
+            res => panic!("expected a timeout, got {:?}", res),
+        }
+    }
+}
@@ -0,0 +1,110 @@
+use std::sync::OnceLock;
+use std::time::{Duration, Instant};
+
+use log::debug;
+use tokio::sync::Mutex;
+
+/// RateLimiter is a token bucket that refills at `rps` tokens per second and
+/// holds at most a second worth of tokens; callers that find the bucket empty
+/// are queued behind the ones already waiting instead of being rejected
+pub struct RateLimiter {
+    rps: f64,
+    bucket: Mutex<Bucket>,
+}
+
+struct Bucket {
+    /// negative when calls are queued for tokens that are yet to refill
+    tokens: f64,
+    last_refill: Instant,
+}
+
+impl RateLimiter {
+    pub fn new(rps: u32) -> Self {
+        let rps = rps.max(1) as f64;
+        RateLimiter {
+            rps,
+            bucket: Mutex::new(Bucket {
+                tokens: rps,
+                last_refill: Instant::now(),
+            }),
+        }
+    }
+
+    /// acquire takes a token, waiting for it if the bucket is empty, and
+    /// returns how long the call was held back
+    pub async fn acquire(&self) -> Duration {
+        let wait = {
+            let mut bucket = self.bucket.lock().await;
+            let now = Instant::now();
+            let elapsed = now.duration_since(bucket.last_refill).as_secs_f64();
+            bucket.tokens = (bucket.tokens + elapsed * self.rps).min(self.rps);
+            bucket.last_refill = now;
+            // reserve the token now so that later callers queue behind
+            bucket.tokens -= 1.0;
+            if bucket.tokens >= 0.0 {
+                Duration::ZERO
+            } else {
+                Duration::from_secs_f64(-bucket.tokens / self.rps)
+            }
+        };
+        if !wait.is_zero() {
+            tokio::time::sleep(wait).await;
+        }
+        wait
+    }
+}
+
+/// rpc_rate_limiter is the limiter shared by all RPC calls made through
+/// `Provider`, configured with `RPC_MAX_RPS`; unset or 0 means no limit
+pub fn rpc_rate_limiter() -> Option<&'static RateLimiter> {
+    static LIMITER: OnceLock<Option<RateLimiter>> = OnceLock::new();
+    LIMITER
+        .get_or_init(|| {
+            std::env::var("RPC_MAX_RPS")
+                .ok()
+                .and_then(|rps| rps.parse::<u32>().ok())
+                .filter(|rps| *rps > 0)
+                .map(RateLimiter::new)
+        })
+        .as_ref()
+}
+
+/// throttle_rpc waits for the RPC rate limit, if any, before a call goes out
+pub async fn throttle_rpc() {
+    if let Some(limiter) = rpc_rate_limiter() {
+        let wait = limiter.acquire().await;
+        crate::prometheus::rpc_rate_limit_wait().observe(wait.as_secs_f64());
+        if !wait.is_zero() {
+            debug!("rpc call rate limited for {:?}", wait);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+
+    #[tokio::test]
+    async fn test_burst_is_spread_out() {
+        let limiter = Arc::new(RateLimiter::new(10));
+        let start = Instant::now();
+
+        // the first 10 go out at once, the other 10 at 10 per second
+        let calls = (0..20).map(|_| {
+            let limiter = limiter.clone();
+            tokio::spawn(async move {
+                limiter.acquire().await;
+                start.elapsed()
+            })
+        });
+        let mut sent_at =
+            futures_util::future::try_join_all(calls).await.unwrap();
+        sent_at.sort();
+
+        assert!(sent_at[9] < Duration::from_millis(100));
+        assert!(sent_at[10] >= Duration::from_millis(90));
+        assert!(sent_at[19] >= Duration::from_millis(950));
+        assert!(sent_at[19] - sent_at[10] >= Duration::from_millis(850));
+    }
+}
@@ -1,18 +1,22 @@
 use std::collections::HashMap;
 use std::str::FromStr;
+use std::sync::{Arc, Mutex, OnceLock};
 
 use log::{debug, info, warn};
 use raydium_library::amm;
 use raydium_library::amm::AmmKeys;
 use raydium_library::amm::MarketPubkeys;
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
 use serde_json::Value;
 use solana_account_decoder::parse_account_data::ParsedAccount;
+use solana_account_decoder::parse_token::UiTokenAmount;
 use solana_account_decoder::UiAccountData;
 use solana_client::nonblocking::rpc_client::RpcClient;
 use solana_client::rpc_config::RpcSendTransactionConfig;
 use solana_client::rpc_request::TokenAccountsFilter;
-use solana_client::rpc_response::RpcKeyedAccount;
+use solana_client::rpc_response::{
+    RpcKeyedAccount, RpcSimulateTransactionResult,
+};
 use solana_sdk::signer::EncodableKey;
 use spl_token::instruction::burn;
 use spl_token::state::Mint;
@@ -20,9 +24,14 @@ use std::error::Error;
 use timed::timed;
 use utoipa::ToSchema;
 
-use crate::jito::send_jito_tx;
+use crate::jito::{jito_tip_ix, send_jito_tx};
 use crate::seller_service::load_amm_keys;
-use crate::{constants, Provider};
+use crate::util::lamports_to_sol;
+use crate::{
+    confirmation_quorum, constants, jito_bundles_url, jito_tip_lamports,
+    BundleRejected, Provider, RpcOps, SendStrategy, Submission, SwapOutcome,
+};
+use futures_util::future::BoxFuture;
 use futures_util::StreamExt;
 use indicatif::{ProgressBar, ProgressStyle};
 use raydium_library::common;
@@ -37,8 +46,13 @@ use solana_client::rpc_filter::RpcFilterType;
 use solana_sdk::instruction::Instruction;
 use solana_sdk::program_pack::Pack;
 use solana_sdk::{
-    pubkey::Pubkey, signature::Keypair, signer::Signer,
-    transaction::Transaction,
+    address_lookup_table_account::AddressLookupTableAccount,
+    hash::Hash,
+    message::{v0, VersionedMessage},
+    pubkey::Pubkey,
+    signature::{Keypair, Signature},
+    signer::Signer,
+    transaction::{Transaction, TransactionError, VersionedTransaction},
 };
 use std::fs::File;
 use std::io::Write;
@@ -205,24 +219,112 @@ pub async fn download_raydium_json(
     Ok(())
 }
 
-pub struct Raydium {}
+pub struct Raydium {
+    /// shared by the requests the swaps make, e.g. to the block engine
+    http_client: Client,
+}
+
+/// Slippage is the tolerance of a swap in basis points, from 0 up to
+/// 10_000 (100%)
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Slippage(u64);
+
+/// SlippageOutOfRange is returned for slippage over 10_000 bps
+#[derive(Debug, Clone, thiserror::Error)]
+#[error("slippage of {0} bps is out of range, expected 0 to 10000")]
+pub struct SlippageOutOfRange(pub u64);
+
+impl Slippage {
+    pub const MAX_BPS: u64 = 10_000;
+
+    pub fn from_bps(bps: u64) -> Result<Self, SlippageOutOfRange> {
+        if bps > Self::MAX_BPS {
+            return Err(SlippageOutOfRange(bps));
+        }
+        Ok(Self(bps))
+    }
+
+    /// bps is also the raw value swap_with_slippage expects
+    pub fn bps(&self) -> u64 {
+        self.0
+    }
+}
 
 pub struct SwapArgs {
     pub amm_pool: Pubkey,
     pub input_token_mint: Pubkey,
     pub output_token_mint: Pubkey,
     pub amount: u64,
-    pub slippage: u64,
+    pub slippage: Slippage,
     pub wallet: Keypair,
     pub rpc_client: RpcClient,
     pub confirmed: bool,
     /// no_sanity: skip sanity checks
     pub no_sanity: bool,
+    pub send_strategy: SendStrategy,
+    /// compute_budget: defaults to ComputeBudgetConfig::default()
+    pub compute_budget: Option<ComputeBudgetConfig>,
+    /// swap_base_in: amount is the input, otherwise the exact output
+    pub swap_base_in: bool,
+    /// use_jito: send as a tipped Jito bundle, falling back to send_strategy
+    /// if the block engine rejects it
+    pub use_jito: bool,
+    /// retry: defaults to SubmitRetry::default()
+    pub retry: Option<SubmitRetry>,
+    /// address_lookup_tables: send as a v0 transaction loading accounts
+    /// through these tables, a legacy transaction if empty
+    pub address_lookup_tables: Vec<AddressLookupTableAccount>,
+    /// skip_preflight: send without simulating the swap first, neither
+    /// here nor by the rpc, saving a round trip; a failing swap then lands
+    /// and pays its fees
+    pub skip_preflight: bool,
+    /// confirm_timeout: stop waiting on the swap after this long with a
+    /// SendAndConfirmError::Timeout holding its signature, wait until it
+    /// lands or its blockhash expires if None
+    pub confirm_timeout: Option<std::time::Duration>,
+    /// oracle_check: abort before sending if the pool's quote strays too far
+    /// from an independent one, None skips the check
+    pub oracle_check: Option<OracleCheck>,
 }
 
 pub struct Swap {
     pre_swap_instructions: Vec<Instruction>,
     post_swap_instructions: Vec<Instruction>,
+    wsol: WsolFlow,
+}
+
+/// WsolFlow is the SOL that passes through the temporary WSOL accounts of a
+/// swap, in lamports
+#[derive(
+    Debug, Default, Clone, Copy, PartialEq, Serialize, Deserialize, ToSchema,
+)]
+pub struct WsolFlow {
+    /// SOL wrapped to be swapped
+    pub wrapped: u64,
+    /// SOL unwrapped from the output when its account is closed, as quoted
+    pub unwrapped: u64,
+    /// rent paid to open the accounts, returned when they are closed
+    pub rent_reclaimed: u64,
+}
+
+impl Swap {
+    /// wsol_flow is the SOL wrapped and unwrapped by the instructions built
+    /// in `handle_token_account`, given the quote; swapping base out from
+    /// SOL wraps up to the quoted max input
+    pub fn wsol_flow(
+        &self,
+        (input_token_mint, output_token_mint): (&Pubkey, &Pubkey),
+        quote: &SwapQuote,
+    ) -> WsolFlow {
+        let mut flow = self.wsol;
+        if *input_token_mint == constants::SOLANA_PROGRAM_ID {
+            flow.wrapped = flow.wrapped.max(quote.max_in);
+        }
+        if *output_token_mint == constants::SOLANA_PROGRAM_ID {
+            flow.unwrapped = quote.expected_out;
+        }
+        flow
+    }
 }
 
 pub struct SwapContext {
@@ -236,8 +338,571 @@ pub struct SwapContext {
     pub amount: u64,
     pub input_token_mint: Pubkey,
     pub output_token_mint: Pubkey,
-    pub slippage: u64,
+    pub slippage: Slippage,
     pub swap_base_in: bool,
+    pub compute_budget: ComputeBudgetConfig,
+    /// address_lookup_tables: see make_swap_tx, empty unless set
+    pub address_lookup_tables: Vec<AddressLookupTableAccount>,
+}
+
+impl SwapContext {
+    /// prefetch_pool_state starts the pool vault calculation in the
+    /// background, so that it overlaps with whatever comes before the swap
+    /// is built, e.g. fetching the blockhash or waiting for the JITO leader
+    pub fn prefetch_pool_state(
+        &self,
+        rpc_client: &RpcClient,
+        payer: &Pubkey,
+    ) -> PoolState {
+        let rpc_client = RpcClient::new_with_commitment(
+            rpc_client.url(),
+            rpc_client.commitment(),
+        );
+        let (amm_program, amm_pool, payer) =
+            (self.amm_program, self.amm_pool, *payer);
+        let (amm_keys, market_keys) =
+            (self.amm_keys.clone(), self.market_keys.clone());
+        PoolState(tokio::spawn(async move {
+            raydium_library::amm::calculate_pool_vault_amounts(
+                &rpc_client,
+                &amm_program,
+                &amm_pool,
+                &amm_keys,
+                &market_keys,
+                amm::utils::CalculateMethod::Simulate(payer),
+            )
+            .await
+            .map_err(|e| e.to_string())
+        }))
+    }
+}
+
+/// PoolState is a pool vault calculation running in the background, see
+/// SwapContext::prefetch_pool_state
+pub struct PoolState(
+    tokio::task::JoinHandle<Result<amm::CalculateResult, String>>,
+);
+
+impl PoolState {
+    pub async fn get(self) -> Result<amm::CalculateResult, Box<dyn Error>> {
+        Ok(self.0.await??)
+    }
+}
+
+/// default compute budget of the swap transaction, the price is in
+/// micro-lamports
+const SWAP_COMPUTE_UNIT_PRICE: u64 = 0;
+const SWAP_COMPUTE_UNIT_LIMIT: u32 = 300_000;
+
+/// how many times a swap is submitted with a fresh blockhash before it counts
+/// as dropped
+const SWAP_SUBMIT_ATTEMPTS: usize = 3;
+
+/// SubmitRetry controls resubmitting a swap whose blockhash expired before
+/// it landed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SubmitRetry {
+    /// attempts: submissions in total, each signed with a fresh blockhash
+    pub attempts: usize,
+    /// resimulate: simulate every resubmission, not only the first
+    pub resimulate: bool,
+}
+
+impl Default for SubmitRetry {
+    fn default() -> Self {
+        Self {
+            attempts: SWAP_SUBMIT_ATTEMPTS,
+            resimulate: false,
+        }
+    }
+}
+
+/// ComputeBudgetConfig is the compute budget set on the swap transaction,
+/// raising unit_price pays for priority during congestion
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ComputeBudgetConfig {
+    /// unit_price: micro-lamports per compute unit
+    pub unit_price: u64,
+    pub unit_limit: u32,
+}
+
+impl Default for ComputeBudgetConfig {
+    fn default() -> Self {
+        Self {
+            unit_price: SWAP_COMPUTE_UNIT_PRICE,
+            unit_limit: SWAP_COMPUTE_UNIT_LIMIT,
+        }
+    }
+}
+
+impl ComputeBudgetConfig {
+    /// priority_fee is the most the budget pays on top of the base fee, in
+    /// lamports
+    pub fn priority_fee(&self) -> u64 {
+        self.unit_price * self.unit_limit as u64 / 1_000_000
+    }
+}
+
+/// compute unit price used when recent prioritization fees are unavailable
+pub const FALLBACK_PRIORITY_FEE: u64 = 25_000;
+pub const PRIORITY_COMPUTE_UNIT_LIMIT: u32 = 300_000;
+
+/// fee per signature, the swap is only signed by the wallet
+const BASE_FEE_LAMPORTS: u64 = 5_000;
+
+/// SOL swap_percent keeps back when selling SOL on top of the fees and the
+/// rent of the WSOL and output accounts, e.g. for a Token-2022 output
+/// account being larger than a classic one
+const NATIVE_SWAP_BUFFER_LAMPORTS: u64 = 1_000_000;
+
+/// PercentOutOfRange is returned for a share of a balance outside 1 to 100
+#[derive(Debug, Clone, thiserror::Error)]
+#[error("percent of {0} is out of range, expected 1 to 100")]
+pub struct PercentOutOfRange(pub u8);
+
+/// percent_of_balance is `percent` of `balance`, floored to whole base units
+pub fn percent_of_balance(
+    balance: u64,
+    percent: u8,
+) -> Result<u64, PercentOutOfRange> {
+    if !(1..=100).contains(&percent) {
+        return Err(PercentOutOfRange(percent));
+    }
+    Ok((balance as u128 * percent as u128 / 100) as u64)
+}
+
+/// token_amount_of_percent is `percent` of a token account balance in base
+/// units; the raw amount is used rather than ui_amount so that nothing finer
+/// than the mint's decimals is made up by float rounding
+pub fn token_amount_of_percent(
+    balance: &UiTokenAmount,
+    percent: u8,
+) -> Result<u64, Box<dyn Error>> {
+    let amount = balance.amount.parse::<u64>()?;
+    Ok(percent_of_balance(amount, percent)?)
+}
+
+/// SwapQuote is what the pool vaults imply about the amounts of a swap at the
+/// time the instructions were built; the specified side is exact
+#[derive(Debug, Default, Clone, Copy)]
+pub struct SwapQuote {
+    /// input at zero slippage
+    pub expected_in: u64,
+    /// input after applying the slippage, the other_amount_threshold when
+    /// swapping base out
+    pub max_in: u64,
+    /// output at zero slippage
+    pub expected_out: u64,
+    /// output after applying the slippage, the other_amount_threshold when
+    /// swapping base in
+    pub min_out: u64,
+    /// taken by the pool from the input
+    pub pool_fee: u64,
+    /// output at the pool price before the swap, i.e. without price impact
+    pub spot_out: u64,
+}
+
+impl SwapQuote {
+    /// other_amount_threshold bounds the side of the swap that isn't
+    /// specified: the least output for base in, the most input for base out
+    pub fn other_amount_threshold(&self, swap_base_in: bool) -> u64 {
+        if swap_base_in {
+            self.min_out
+        } else {
+            self.max_in
+        }
+    }
+}
+
+/// PriceOracle quotes swaps independently of the pool being swapped on, so
+/// that a pool whose vaults were skewed, e.g. to sandwich the swap, is
+/// caught before its quote becomes the slippage threshold
+pub trait PriceOracle: Send + Sync {
+    /// expected_out is the output the oracle expects for `amount` of the
+    /// input
+    fn expected_out<'a>(
+        &'a self,
+        input_mint: &'a Pubkey,
+        output_mint: &'a Pubkey,
+        amount: u64,
+    ) -> BoxFuture<'a, Result<u64, Box<dyn Error>>>;
+}
+
+/// JupiterOracle quotes through Jupiter's aggregator
+pub struct JupiterOracle;
+
+impl PriceOracle for JupiterOracle {
+    fn expected_out<'a>(
+        &'a self,
+        input_mint: &'a Pubkey,
+        output_mint: &'a Pubkey,
+        amount: u64,
+    ) -> BoxFuture<'a, Result<u64, Box<dyn Error>>> {
+        Box::pin(async move {
+            let quote = crate::jup::Jupiter::fetch_quote(
+                &input_mint.to_string(),
+                &output_mint.to_string(),
+                amount,
+                0,
+            )
+            .await?;
+            Ok(quote.out_amount.parse()?)
+        })
+    }
+}
+
+/// OracleCheck bounds how far, in percent of the oracle's output, the
+/// pool's expected output may be from it
+#[derive(Clone)]
+pub struct OracleCheck {
+    pub oracle: Arc<dyn PriceOracle>,
+    pub max_divergence_pct: f64,
+}
+
+/// DivergenceOutOfRange is returned for a divergence bound that is negative
+/// or not a number
+#[derive(Debug, Clone, thiserror::Error)]
+#[error("oracle divergence of {0}% is out of range, expected 0 or more")]
+pub struct DivergenceOutOfRange(pub f64);
+
+impl OracleCheck {
+    pub fn new(
+        oracle: Arc<dyn PriceOracle>,
+        max_divergence_pct: f64,
+    ) -> Result<Self, DivergenceOutOfRange> {
+        if max_divergence_pct.is_nan() || max_divergence_pct < 0. {
+            return Err(DivergenceOutOfRange(max_divergence_pct));
+        }
+        Ok(Self {
+            oracle,
+            max_divergence_pct,
+        })
+    }
+}
+
+/// OracleDivergence is a pool quoting too far from the oracle to be trusted
+/// with the swap
+#[derive(Debug, Clone, thiserror::Error)]
+#[error(
+    "pool quotes {pool_out} out, {divergence_pct:.2}% off the oracle's \
+     {oracle_out}, more than the {max_divergence_pct}% allowed"
+)]
+pub struct OracleDivergence {
+    pub pool_out: u64,
+    pub oracle_out: u64,
+    pub divergence_pct: f64,
+    pub max_divergence_pct: f64,
+}
+
+/// check_oracle asks the oracle for the output of the quoted input and
+/// fails with OracleDivergence if the pool's expected output is further
+/// from it than allowed. Quick swaps have no quote to check, so fail it.
+pub async fn check_oracle(
+    check: &OracleCheck,
+    input_mint: &Pubkey,
+    output_mint: &Pubkey,
+    quote: &SwapQuote,
+) -> Result<(), Box<dyn Error>> {
+    if quote.expected_in == 0 || quote.expected_out == 0 {
+        return Err("no pool quote to check against the oracle".into());
+    }
+    let oracle_out = check
+        .oracle
+        .expected_out(input_mint, output_mint, quote.expected_in)
+        .await?;
+    if oracle_out == 0 {
+        return Err(format!(
+            "oracle has no quote for {} to {}",
+            input_mint, output_mint
+        )
+        .into());
+    }
+    let divergence_pct = (quote.expected_out as f64 - oracle_out as f64).abs()
+        / oracle_out as f64
+        * 100.;
+    debug!(
+        "pool quotes {} out, oracle {}: {:.2}% apart",
+        quote.expected_out, oracle_out, divergence_pct
+    );
+    if divergence_pct > check.max_divergence_pct {
+        return Err(OracleDivergence {
+            pool_out: quote.expected_out,
+            oracle_out,
+            divergence_pct,
+            max_divergence_pct: check.max_divergence_pct,
+        }
+        .into());
+    }
+    Ok(())
+}
+
+/// quote_swap prices a swap of `amount` against the pool vaults, `amount` is
+/// the input when swapping base in and the exact output otherwise
+fn quote_swap(
+    (pool_pc, pool_coin): (u64, u64),
+    (fee_numerator, fee_denominator): (u64, u64),
+    direction: amm::utils::SwapDirection,
+    amount: u64,
+    swap_base_in: bool,
+    slippage: u64,
+) -> SwapQuote {
+    let swap = |slippage| {
+        amm::swap_with_slippage(
+            pool_pc,
+            pool_coin,
+            fee_numerator,
+            fee_denominator,
+            direction,
+            amount,
+            swap_base_in,
+            slippage,
+        )
+        .unwrap_or(0)
+    };
+    let (expected, threshold) = (swap(0), swap(slippage));
+    let (expected_in, max_in, expected_out, min_out) = if swap_base_in {
+        (amount, amount, expected, threshold)
+    } else {
+        (expected, threshold, amount, amount)
+    };
+    let (reserve_in, reserve_out) = match direction {
+        amm::utils::SwapDirection::Coin2PC => (pool_coin, pool_pc),
+        amm::utils::SwapDirection::PC2Coin => (pool_pc, pool_coin),
+    };
+    let (pool_fee, spot_out) = spot_quote(
+        expected_in,
+        reserve_in,
+        reserve_out,
+        fee_numerator,
+        fee_denominator,
+    );
+    SwapQuote {
+        expected_in,
+        max_in,
+        expected_out,
+        min_out,
+        pool_fee,
+        spot_out,
+    }
+}
+
+/// spot_quote is the pool fee on the input and what the rest of the input
+/// would get at the current pool price, as (pool_fee, spot_out)
+fn spot_quote(
+    amount: u64,
+    reserve_in: u64,
+    reserve_out: u64,
+    fee_numerator: u64,
+    fee_denominator: u64,
+) -> (u64, u64) {
+    if reserve_in == 0 || fee_denominator == 0 {
+        return (0, 0);
+    }
+    let pool_fee = (amount as u128 * fee_numerator as u128
+        / fee_denominator as u128) as u64;
+    let spot_out = ((amount - pool_fee) as u128 * reserve_out as u128
+        / reserve_in as u128) as u64;
+    (pool_fee, spot_out)
+}
+
+/// SwapFees is what a swap costs on top of the input
+#[derive(Debug, Default, Clone, Copy, PartialEq, Serialize, ToSchema)]
+pub struct SwapFees {
+    /// taken by the pool, in input token units
+    pub pool_fee: f64,
+    /// compute unit price times the compute unit limit, in SOL
+    pub priority_fee: f64,
+    /// signature fee, in SOL
+    pub base_fee: f64,
+    /// rent of the temporary WSOL accounts, in SOL, returned when they are
+    /// closed at the end of the swap
+    pub wsol_rent: f64,
+}
+
+/// SwapPreview is a quoted swap with everything needed to confirm it before
+/// it is sent, amounts are in UI units
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct SwapPreview {
+    pub venue: String,
+    /// pools the swap goes through, in order
+    pub route: Vec<String>,
+    pub input_mint: String,
+    pub output_mint: String,
+    pub in_amount: f64,
+    /// output at zero slippage
+    pub expected_out: f64,
+    /// least output accepted at the slippage, the swap fails below it
+    pub min_out: f64,
+    pub slippage_bps: u64,
+    /// how much less the output is than at the pool price, in percent
+    pub price_impact_pct: f64,
+    pub fees: SwapFees,
+}
+
+impl SwapPreview {
+    pub fn new(
+        amm_pool: &Pubkey,
+        (input_mint, output_mint): (&Pubkey, &Pubkey),
+        slippage: u64,
+        quote: &SwapQuote,
+        wsol: &WsolFlow,
+        (input_decimals, output_decimals): (u8, u8),
+        compute_budget: &ComputeBudgetConfig,
+    ) -> Self {
+        let price_impact_pct = if quote.spot_out > 0 {
+            quote.spot_out.saturating_sub(quote.expected_out) as f64
+                / quote.spot_out as f64
+                * 100.
+        } else {
+            0.
+        };
+        let priority_fee = compute_budget.priority_fee();
+        Self {
+            venue: "raydium-amm-v4".to_string(),
+            route: vec![amm_pool.to_string()],
+            input_mint: input_mint.to_string(),
+            output_mint: output_mint.to_string(),
+            in_amount: ui_amount(quote.expected_in, input_decimals),
+            expected_out: ui_amount(quote.expected_out, output_decimals),
+            min_out: ui_amount(quote.min_out, output_decimals),
+            slippage_bps: slippage,
+            price_impact_pct,
+            fees: SwapFees {
+                pool_fee: ui_amount(quote.pool_fee, input_decimals),
+                priority_fee: lamports_to_sol(priority_fee),
+                base_fee: lamports_to_sol(BASE_FEE_LAMPORTS),
+                wsol_rent: lamports_to_sol(wsol.rent_reclaimed),
+            },
+        }
+    }
+}
+
+fn ui_amount(amount: u64, decimals: u8) -> f64 {
+    amount as f64 / 10f64.powi(decimals as i32)
+}
+
+/// SwapResult is the record of a submitted swap, amounts are in base units
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct SwapResult {
+    pub signature: String,
+    pub input_mint: String,
+    pub output_mint: String,
+    pub in_amount: u64,
+    /// quoted from the pool vaults, 0 if the pool calculation was skipped
+    pub out_amount: u64,
+    #[schema(value_type = Object)]
+    pub outcome: SwapOutcome,
+    /// the rpc which reported the outcome
+    pub endpoint: String,
+    /// the rpcs which reported the swap as confirmed, as many as
+    /// `CONFIRMATION_QUORUM` requires
+    #[serde(default)]
+    pub confirmed_by: Vec<String>,
+    /// SOL moved through WSOL, zeroed if neither side is SOL
+    #[serde(default)]
+    pub wsol: WsolFlow,
+}
+
+/// SimulationFailure is a failed pre-send simulation, with the reason decoded
+/// from the program logs where possible
+#[derive(Debug, Clone, Serialize, ToSchema, thiserror::Error)]
+#[error("swap would fail: {reason}")]
+pub struct SimulationFailure {
+    pub reason: String,
+    #[schema(value_type = Object)]
+    pub error: TransactionError,
+    pub logs: Vec<String>,
+}
+
+impl SimulationFailure {
+    pub fn from_result(result: &RpcSimulateTransactionResult) -> Option<Self> {
+        let error = result.err.clone()?;
+        let logs = result.logs.clone().unwrap_or_default();
+        // programs log the human readable reason as "Program log: Error: ..."
+        let reason = logs
+            .iter()
+            .rev()
+            .find_map(|log| log.split_once("Error: ").map(|(_, r)| r))
+            .map(str::to_string)
+            .unwrap_or_else(|| error.to_string());
+        Some(Self {
+            reason,
+            error,
+            logs,
+        })
+    }
+}
+
+/// layout of raydium_amm::state::AmmInfo, the account of an AMM v4 pool
+const AMM_INFO_LEN: usize = 752;
+const AMM_COIN_VAULT_OFFSET: usize = 336;
+const AMM_PC_VAULT_OFFSET: usize = 368;
+const AMM_COIN_MINT_OFFSET: usize = 400;
+const AMM_PC_MINT_OFFSET: usize = 432;
+
+/// pool_cache holds the pools resolved by get_amm_pool_id, keyed by
+/// pool_cache_key
+fn pool_cache() -> &'static Mutex<HashMap<(Pubkey, Pubkey), Pubkey>> {
+    static CACHE: OnceLock<Mutex<HashMap<(Pubkey, Pubkey), Pubkey>>> =
+        OnceLock::new();
+    CACHE.get_or_init(Default::default)
+}
+
+/// pool_cache_key is the mint pair in order, so either direction of a swap
+/// finds the same pool
+fn pool_cache_key(mint_a: &Pubkey, mint_b: &Pubkey) -> (Pubkey, Pubkey) {
+    if mint_a <= mint_b {
+        (*mint_a, *mint_b)
+    } else {
+        (*mint_b, *mint_a)
+    }
+}
+
+/// pool_vaults reads the (coin, pc) vaults of an AMM v4 pool account
+fn pool_vaults(data: &[u8]) -> Option<(Pubkey, Pubkey)> {
+    let pubkey_at = |offset: usize| {
+        Some(Pubkey::new_from_array(
+            data.get(offset..offset + 32)?.try_into().ok()?,
+        ))
+    };
+    Some((
+        pubkey_at(AMM_COIN_VAULT_OFFSET)?,
+        pubkey_at(AMM_PC_VAULT_OFFSET)?,
+    ))
+}
+
+/// PoolNotFound is returned when the amm pool account doesn't exist, e.g. the
+/// pool has been closed; unlike RPC errors retrying won't help
+#[derive(Debug, Clone, thiserror::Error)]
+#[error("amm pool {0} does not exist")]
+pub struct PoolNotFound(pub Pubkey);
+
+/// InsufficientBalance is a wallet that can't fund the SOL to be wrapped
+/// along with the rent of its account and the fee buffer
+#[derive(Debug, Clone, thiserror::Error)]
+#[error(
+    "wallet {wallet} has {available} lamports, wrapping needs {needed} \
+     (rent {rent}, amount {amount}, fee buffer {fee_buffer})"
+)]
+pub struct InsufficientBalance {
+    pub wallet: Pubkey,
+    pub available: u64,
+    pub needed: u64,
+    pub rent: u64,
+    pub amount: u64,
+    pub fee_buffer: u64,
+}
+
+const DEFAULT_FEE_BUFFER_LAMPORTS: u64 = 5_000_000;
+
+/// fee_buffer_lamports is kept in the wallet when wrapping SOL so the swap
+/// can still pay its fees, set with `FEE_BUFFER_LAMPORTS`, defaults to
+/// 5_000_000 (0.005 SOL)
+pub fn fee_buffer_lamports() -> u64 {
+    std::env::var("FEE_BUFFER_LAMPORTS")
+        .ok()
+        .and_then(|buffer| buffer.parse().ok())
+        .unwrap_or(DEFAULT_FEE_BUFFER_LAMPORTS)
 }
 
 pub async fn get_calc_result(
@@ -361,8 +1026,10 @@ pub async fn make_swap_context(
     input_token_mint: Pubkey,
     output_token_mint: Pubkey,
     wallet: &Keypair,
-    slippage: u64,
+    slippage: Slippage,
     amount: u64,
+    swap_base_in: bool,
+    compute_budget: Option<ComputeBudgetConfig>,
 ) -> Result<SwapContext, Box<dyn Error>> {
     let amm_program = constants::RAYDIUM_LIQUIDITY_POOL_V4_PUBKEY;
     // load amm keys
@@ -377,14 +1044,20 @@ pub async fn make_swap_context(
     let mut swap = Swap {
         pre_swap_instructions: vec![],
         post_swap_instructions: vec![],
+        wsol: WsolFlow::default(),
     };
+    // swapping base out, the input is only known once quoted, any WSOL it
+    // needs is wrapped with the swap instruction
+    let fee_buffer = fee_buffer_lamports();
     let user_source = handle_token_account(
         &mut swap,
         rpc_client,
         &input_token_mint,
-        amount,
+        if swap_base_in { amount } else { 0 },
         &wallet.pubkey(),
         &wallet.pubkey(),
+        fee_buffer,
+        None,
     )
     .await?;
     let user_destination = handle_token_account(
@@ -394,6 +1067,8 @@ pub async fn make_swap_context(
         0,
         &wallet.pubkey(),
         &wallet.pubkey(),
+        fee_buffer,
+        None,
     )
     .await?;
     Ok(SwapContext {
@@ -408,29 +1083,55 @@ pub async fn make_swap_context(
         input_token_mint,
         output_token_mint,
         slippage,
-        swap_base_in: true,
+        swap_base_in,
+        compute_budget: compute_budget.unwrap_or_default(),
+        address_lookup_tables: vec![],
     })
 }
 
-#[timed(duration(printer = "info!"))]
 pub async fn make_swap_ixs(
     rpc_client: &RpcClient,
     wallet: &Keypair,
     swap_context: &SwapContext,
     quick: bool,
 ) -> Result<Vec<Instruction>, Box<dyn Error>> {
-    // calculate amm pool vault with load data at the same time or use simulate to calculate
-    // this step adds some latency, could be pre-calculated while waiting for the JITO leader
-    let other_amount_threshold = if !quick {
-        let result = raydium_library::amm::calculate_pool_vault_amounts(
-            rpc_client,
-            &swap_context.amm_program,
-            &swap_context.amm_pool,
-            &swap_context.amm_keys,
-            &swap_context.market_keys,
-            amm::utils::CalculateMethod::Simulate(wallet.pubkey()),
-        )
-        .await?;
+    let (ixs, _) =
+        make_swap_ixs_with_quote(rpc_client, wallet, swap_context, quick)
+            .await?;
+    Ok(ixs)
+}
+
+/// make_swap_ixs_with_quote is make_swap_ixs that also returns the quote the
+/// slippage threshold was derived from, the quote is zeroed when quick
+pub async fn make_swap_ixs_with_quote(
+    rpc_client: &RpcClient,
+    wallet: &Keypair,
+    swap_context: &SwapContext,
+    quick: bool,
+) -> Result<(Vec<Instruction>, SwapQuote), Box<dyn Error>> {
+    let pool_state = (!quick).then(|| {
+        swap_context.prefetch_pool_state(rpc_client, &wallet.pubkey())
+    });
+    make_swap_ixs_with_pool_state(rpc_client, wallet, swap_context, pool_state)
+        .await
+}
+
+/// make_swap_ixs_with_pool_state is make_swap_ixs_with_quote with the pool
+/// vault calculation already under way, None skips it like quick
+#[timed(duration(printer = "info!"))]
+pub async fn make_swap_ixs_with_pool_state(
+    rpc_client: &RpcClient,
+    wallet: &Keypair,
+    swap_context: &SwapContext,
+    pool_state: Option<PoolState>,
+) -> Result<(Vec<Instruction>, SwapQuote), Box<dyn Error>> {
+    let quote = if let Some(pool_state) = pool_state {
+        // the mint is fetched while the calculation finishes
+        let (result, mint_account) = tokio::join!(
+            pool_state.get(),
+            rpc_client.get_account(&swap_context.output_token_mint)
+        );
+        let (result, mint_account) = (result?, mint_account?);
         self::calc_result_to_financials(
             swap_context.market_keys.coin_mint.to_string()
                 == constants::SOLANA_PROGRAM_ID.to_string(),
@@ -451,21 +1152,15 @@ pub async fn make_swap_ixs(
         } else {
             amm::utils::SwapDirection::PC2Coin
         };
-        let other_amount_threshold = amm::swap_with_slippage(
-            result.pool_pc_vault_amount,
-            result.pool_coin_vault_amount,
-            result.swap_fee_numerator,
-            result.swap_fee_denominator,
+        let quote = quote_swap(
+            (result.pool_pc_vault_amount, result.pool_coin_vault_amount),
+            (result.swap_fee_numerator, result.swap_fee_denominator),
             direction,
             swap_context.amount,
             swap_context.swap_base_in,
-            swap_context.slippage,
-        )
-        .unwrap_or(0);
+            swap_context.slippage.bps(),
+        );
 
-        let mint_account = rpc_client
-            .get_account(&swap_context.output_token_mint)
-            .await?;
         let mint_data = Mint::unpack(&mint_account.data)?;
         let burn_pct =
             self::get_burn_pct(mint_data, result).expect("get burn pct");
@@ -479,7 +1174,8 @@ pub async fn make_swap_ixs(
                 "pool_lp_amount": result.pool_lp_amount,
                 "swap_fee_numerator": result.swap_fee_numerator,
                 "swap_fee_denominator": result.swap_fee_denominator,
-                "other_amount_threshold": other_amount_threshold,
+                "other_amount_threshold": quote
+                    .other_amount_threshold(swap_context.swap_base_in),
                 "liquidity_burn_pct": burn_pct,
             }))?
         );
@@ -487,11 +1183,16 @@ pub async fn make_swap_ixs(
             return Err(format!("LP is only {} burnt", burn_pct).into());
         }
 
-        other_amount_threshold
+        quote
     } else {
         info!("Quick swap, skipping pool vault calculation");
-        0
+        SwapQuote::default()
     };
+    if !swap_context.swap_base_in && quote.max_in == 0 {
+        return Err(
+            "swapping base out needs the pool vault calculation".into()
+        );
+    }
     // let market_cap = util::lamports_to_sol(result.pool_coin_vault_amount);
     // info!("market cap: {}", market_cap);
     // if market_cap < 50. {
@@ -505,9 +1206,23 @@ pub async fn make_swap_ixs(
         &swap_context.user_source,
         &swap_context.user_destination,
         swap_context.amount,
-        other_amount_threshold,
+        quote.other_amount_threshold(swap_context.swap_base_in),
         swap_context.swap_base_in,
     )?;
+    let mut swap_ixs = vec![];
+    if !swap_context.swap_base_in
+        && swap_context.input_token_mint == constants::SOLANA_PROGRAM_ID
+    {
+        swap_ixs.push(solana_sdk::system_instruction::transfer(
+            &wallet.pubkey(),
+            &swap_context.user_source,
+            quote.max_in,
+        ));
+        swap_ixs.push(spl_token::instruction::sync_native(
+            &spl_token::id(),
+            &swap_context.user_source,
+        )?);
+    }
     debug!(
         "swap_ix program_id: {:?}, accounts: {} ",
         swap_ix.program_id,
@@ -519,13 +1234,75 @@ pub async fn make_swap_ixs(
                 .collect::<Vec<String>>()
         )?,
     );
-    let ixs = [
-        make_compute_budget_ixs(0, 300_000),
-        swap_context.swap.pre_swap_instructions.clone(),
-        vec![swap_ix],
-        swap_context.swap.post_swap_instructions.clone(),
-    ];
-    Ok(ixs.concat())
+    Ok((
+        assemble_swap_ixs(
+            &swap_context.compute_budget,
+            &swap_context.swap,
+            [swap_ixs, vec![swap_ix]].concat(),
+        ),
+        quote,
+    ))
+}
+
+/// assemble_swap_ixs orders the swap transaction: compute budget, token
+/// account setup, the swap itself and the cleanup
+pub fn assemble_swap_ixs(
+    compute_budget: &ComputeBudgetConfig,
+    swap: &Swap,
+    swap_ixs: Vec<Instruction>,
+) -> Vec<Instruction> {
+    [
+        make_compute_budget_ixs(
+            compute_budget.unit_price,
+            compute_budget.unit_limit,
+        ),
+        swap.pre_swap_instructions.clone(),
+        swap_ixs,
+        swap.post_swap_instructions.clone(),
+    ]
+    .concat()
+}
+
+/// make_swap_preview quotes the swap without sending it, see SwapPreview
+pub async fn make_swap_preview(
+    rpc_client: &RpcClient,
+    wallet: &Keypair,
+    swap_context: &SwapContext,
+) -> Result<SwapPreview, Box<dyn Error>> {
+    let (_, quote) =
+        make_swap_ixs_with_quote(rpc_client, wallet, swap_context, false)
+            .await?;
+    let mints = rpc_client
+        .get_multiple_accounts(&[
+            swap_context.input_token_mint,
+            swap_context.output_token_mint,
+        ])
+        .await?;
+    let decimals = mints
+        .iter()
+        .map(|account| -> Result<u8, Box<dyn Error>> {
+            let account = account.as_ref().ok_or("mint account not found")?;
+            Ok(Mint::unpack(&account.data)?.decimals)
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    Ok(SwapPreview::new(
+        &swap_context.amm_pool,
+        (
+            &swap_context.input_token_mint,
+            &swap_context.output_token_mint,
+        ),
+        swap_context.slippage.bps(),
+        &quote,
+        &swap_context.swap.wsol_flow(
+            (
+                &swap_context.input_token_mint,
+                &swap_context.output_token_mint,
+            ),
+            &quote,
+        ),
+        (decimals[0], decimals[1]),
+        &swap_context.compute_budget,
+    ))
 }
 
 impl Default for Raydium {
@@ -535,61 +1312,251 @@ impl Default for Raydium {
 }
 
 impl Raydium {
-    pub const fn new() -> Self {
-        Raydium {}
+    pub fn new() -> Self {
+        Self::with_http_client(Client::new())
+    }
+
+    /// with_http_client makes the swaps send their requests through
+    /// `http_client`, so that its connections are reused
+    pub fn with_http_client(http_client: Client) -> Self {
+        Raydium { http_client }
     }
 
-    #[deprecated = "slow and not production required"]
+    /// get_amm_pool_id finds the Raydium AMM v4 pool of the mint pair,
+    /// whichever way round the mints are. If there are several it is the one
+    /// holding the most of `mint_a`. The lookup scans the pool program's
+    /// accounts, which takes seconds on most RPCs, so results are cached for
+    /// the life of the process
     pub async fn get_amm_pool_id(
         &self,
         rpc_client: &RpcClient,
-        input_mint: &Pubkey,
-        output_mint: &Pubkey,
-    ) -> Pubkey {
-        // this is obtained from LIQUIDITY_LAYOUT_V4 from TypeScript Raydium SDK
-        const INPUT_MINT_OFFSET: usize = 53;
-        const OUTPUT_MINT_OFFSET: usize = 85;
-
-        let _accounts =
-            rpc_client
-            .get_program_accounts_with_config(
-                &constants::OPENBOOK_PROGRAM_ID,
-                RpcProgramAccountsConfig {
-                    filters: Some(vec![
-                        RpcFilterType::Memcmp(Memcmp::new(
-                            INPUT_MINT_OFFSET,
-                            MemcmpEncodedBytes::Base64(input_mint.to_string()),
-                        )),
-                        RpcFilterType::Memcmp(Memcmp::new(
-                            OUTPUT_MINT_OFFSET,
-                            MemcmpEncodedBytes::Base64(output_mint.to_string()),
-                        )),
-                    ]),
-                    account_config: RpcAccountInfoConfig {
-                        encoding: Some(UiAccountEncoding::Base64),
-                        commitment: Some(
-                            solana_sdk::commitment_config::CommitmentConfig::processed(),
-                        ),
-                        data_slice: None,
-                        min_context_slot: None,
+        mint_a: &Pubkey,
+        mint_b: &Pubkey,
+    ) -> Result<Pubkey, Box<dyn Error>> {
+        let key = pool_cache_key(mint_a, mint_b);
+        if let Some(amm_pool) = pool_cache().lock().unwrap().get(&key) {
+            return Ok(*amm_pool);
+        }
+
+        let mut candidates = vec![];
+        for (coin_mint, pc_mint) in [(mint_a, mint_b), (mint_b, mint_a)] {
+            let accounts = rpc_client
+                .get_program_accounts_with_config(
+                    &constants::RAYDIUM_LIQUIDITY_POOL_V4_PUBKEY,
+                    RpcProgramAccountsConfig {
+                        filters: Some(vec![
+                            RpcFilterType::DataSize(AMM_INFO_LEN as u64),
+                            RpcFilterType::Memcmp(Memcmp::new(
+                                AMM_COIN_MINT_OFFSET,
+                                MemcmpEncodedBytes::Base58(
+                                    coin_mint.to_string(),
+                                ),
+                            )),
+                            RpcFilterType::Memcmp(Memcmp::new(
+                                AMM_PC_MINT_OFFSET,
+                                MemcmpEncodedBytes::Base58(
+                                    pc_mint.to_string(),
+                                ),
+                            )),
+                        ]),
+                        account_config: RpcAccountInfoConfig {
+                            encoding: Some(UiAccountEncoding::Base64),
+                            ..Default::default()
+                        },
+                        ..Default::default()
                     },
-                    ..Default::default()
-                },
+                )
+                .await?;
+            for (amm_pool, account) in accounts {
+                let vaults = pool_vaults(&account.data)
+                    .ok_or("amm pool account too short")?;
+                let vault = if coin_mint == mint_a {
+                    vaults.0
+                } else {
+                    vaults.1
+                };
+                candidates.push((amm_pool, vault));
+            }
+        }
+        let vaults = candidates
+            .iter()
+            .map(|(_, vault)| *vault)
+            .collect::<Vec<_>>();
+        let balances = rpc_client
+            .get_multiple_accounts(&vaults)
+            .await?
+            .iter()
+            .map(|account| {
+                account
+                    .as_ref()
+                    .and_then(|a| {
+                        spl_token::state::Account::unpack(&a.data).ok()
+                    })
+                    .map_or(0, |a| a.amount)
+            })
+            .collect::<Vec<_>>();
+        let amm_pool = candidates
+            .iter()
+            .zip(balances)
+            .max_by_key(|(_, balance)| *balance)
+            .map(|((amm_pool, _), _)| *amm_pool)
+            .ok_or_else(|| {
+                format!("no raydium pool for {} / {}", mint_a, mint_b)
+            })?;
+        debug!(
+            "resolved {} / {} to {} out of {} pools",
+            mint_a,
+            mint_b,
+            amm_pool,
+            candidates.len()
+        );
+        pool_cache().lock().unwrap().insert(key, amm_pool);
+        Ok(amm_pool)
+    }
+
+    /// swap_simple buys `output_token_mint` with `sol_amount` lamports,
+    /// wrapped as WSOL. Without `amm_pool` the pool is looked up with
+    /// get_amm_pool_id, which takes seconds the first time a token is swapped
+    pub async fn swap_simple(
+        &self,
+        rpc_client: RpcClient,
+        wallet: Keypair,
+        output_token_mint: Pubkey,
+        sol_amount: u64,
+        slippage: Slippage,
+        amm_pool: Option<Pubkey>,
+    ) -> Result<Option<SwapResult>, Box<dyn Error>> {
+        let amm_pool = match amm_pool {
+            Some(amm_pool) => amm_pool,
+            None => {
+                self.get_amm_pool_id(
+                    &rpc_client,
+                    &constants::SOLANA_PROGRAM_ID,
+                    &output_token_mint,
+                )
+                .await?
+            }
+        };
+        self.swap(SwapArgs {
+            amm_pool,
+            input_token_mint: constants::SOLANA_PROGRAM_ID,
+            output_token_mint,
+            amount: sol_amount,
+            slippage,
+            wallet,
+            rpc_client,
+            confirmed: true,
+            no_sanity: false,
+            send_strategy: SendStrategy::Single,
+            compute_budget: None,
+            swap_base_in: true,
+            use_jito: false,
+            retry: None,
+            address_lookup_tables: vec![],
+            skip_preflight: false,
+            confirm_timeout: None,
+            oracle_check: None,
+        })
+        .await
+    }
+
+    /// swap_percent swaps `percent` (1 to 100) of the wallet's balance of the
+    /// input token in place of `swap_args.amount`. Selling SOL keeps back
+    /// what the swap costs in fees and rent, see NATIVE_SWAP_BUFFER_LAMPORTS
+    pub async fn swap_percent(
+        &self,
+        mut swap_args: SwapArgs,
+        percent: u8,
+    ) -> Result<Option<SwapResult>, Box<dyn Error>> {
+        if !swap_args.swap_base_in {
+            return Err(
+                "swap_percent sells a share of the input, it needs swap_base_in"
+                    .into(),
+            );
+        }
+        // checked before any rpc call
+        percent_of_balance(0, percent)?;
+        let rpc_client = &swap_args.rpc_client;
+        let owner = swap_args.wallet.pubkey();
+        let mint = swap_args.input_token_mint;
+        swap_args.amount = if mint == constants::SOLANA_PROGRAM_ID {
+            let lamports = rpc_client.get_balance(&owner).await?;
+            let rent = rpc_client
+                .get_minimum_balance_for_rent_exemption(
+                    spl_token::state::Account::LEN,
+                )
+                .await?;
+            let mut reserved = 2 * rent
+                + BASE_FEE_LAMPORTS
+                + NATIVE_SWAP_BUFFER_LAMPORTS
+                + swap_args.compute_budget.unwrap_or_default().priority_fee();
+            if swap_args.use_jito {
+                reserved += jito_tip_lamports();
+            }
+            percent_of_balance(lamports.saturating_sub(reserved), percent)?
+        } else {
+            let token_program = token_program_of(rpc_client, &mint).await?;
+            let token = spl_associated_token_account::get_associated_token_address_with_program_id(
+                &owner,
+                &mint,
+                &token_program,
+            );
+            let balance = rpc_client.get_token_account_balance(&token).await?;
+            token_amount_of_percent(&balance, percent)?
+        };
+        if swap_args.amount == 0 {
+            return Err(format!(
+                "nothing to swap, {}% of the {} balance is 0",
+                percent, mint
             )
-            .await
-            .unwrap();
-        Pubkey::default()
+            .into());
+        }
+        info!("swapping {}% of {}: {}", percent, mint, swap_args.amount);
+        self.swap(swap_args).await
     }
 
-    // swap_simple is a wrapper around swap that requires only the token mint
-    pub fn swap_simple(&self, _output_token_mint: Pubkey, _sol_amount: u64) {
-        // need to fetch amm pool by input/output first, not critical but useful
+    /// simulate builds and signs the swap like `swap` and simulates it
+    /// without ever sending it, whether or not the simulation fails; only
+    /// the swap parameters of the args are used
+    pub async fn simulate(
+        &self,
+        swap_args: SwapArgs,
+    ) -> Result<RpcSimulateTransactionResult, Box<dyn Error>> {
+        let mut swap_context = self::make_swap_context(
+            &swap_args.rpc_client,
+            swap_args.amm_pool,
+            swap_args.input_token_mint,
+            swap_args.output_token_mint,
+            &swap_args.wallet,
+            swap_args.slippage,
+            swap_args.amount,
+            swap_args.swap_base_in,
+            swap_args.compute_budget,
+        )
+        .await?;
+        swap_context.address_lookup_tables = swap_args.address_lookup_tables;
+        let ixs = self::make_swap_ixs(
+            &swap_args.rpc_client,
+            &swap_args.wallet,
+            &swap_context,
+            swap_args.no_sanity,
+        )
+        .await?;
+        self::simulate_ixs(
+            &swap_args.rpc_client,
+            &swap_args.wallet,
+            &ixs,
+            &swap_context.address_lookup_tables,
+        )
+        .await
     }
 
+    /// swap builds, signs and submits the swap, then waits for it to land;
+    /// returns None if the swap was not confirmed at the prompt
     pub async fn swap(
         &self,
         swap_args: SwapArgs,
-    ) -> Result<(), Box<dyn Error>> {
+    ) -> Result<Option<SwapResult>, Box<dyn Error>> {
         let SwapArgs {
             amm_pool,
             input_token_mint,
@@ -600,8 +1567,22 @@ impl Raydium {
             rpc_client,
             confirmed,
             no_sanity,
+            send_strategy,
+            compute_budget,
+            swap_base_in,
+            use_jito,
+            retry,
+            address_lookup_tables,
+            skip_preflight,
+            confirm_timeout,
+            oracle_check,
         } = swap_args;
-        let swap_context = self::make_swap_context(
+        if no_sanity && oracle_check.is_some() {
+            return Err("an oracle check needs the pool quote, which a \
+                        quick swap skips"
+                .into());
+        }
+        let mut swap_context = self::make_swap_context(
             &rpc_client,
             amm_pool,
             input_token_mint,
@@ -609,9 +1590,12 @@ impl Raydium {
             &wallet,
             slippage,
             amount,
+            swap_base_in,
+            compute_budget,
         )
         .await?;
-        let ixs = self::make_swap_ixs(
+        swap_context.address_lookup_tables = address_lookup_tables;
+        let (ixs, quote) = self::make_swap_ixs_with_quote(
             &rpc_client,
             &wallet,
             &swap_context,
@@ -625,29 +1609,331 @@ impl Raydium {
                 "input": input_token_mint.to_string(),
                 "output": output_token_mint.to_string(),
                 "funder": wallet.pubkey().to_string(),
-                "slippage": slippage,
+                "slippage": slippage.bps(),
             }))?
         );
+        if let Some(oracle_check) = &oracle_check {
+            self::check_oracle(
+                oracle_check,
+                &input_token_mint,
+                &output_token_mint,
+                &quote,
+            )
+            .await?;
+        }
+        let quorum = confirmation_quorum();
+        if quorum > 1 && matches!(send_strategy, SendStrategy::Single) {
+            return Err(format!(
+                "confirmation quorum of {} requires the fanout send strategy",
+                quorum
+            )
+            .into());
+        }
         if !confirmed
             && !dialoguer::Confirm::new()
                 .with_prompt("Go for it?")
                 .interact()?
         {
-            return Ok(());
+            return Ok(None);
         }
-        let tx = Transaction::new_signed_with_payer(
-            ixs.as_slice(),
-            Some(&wallet.pubkey()),
-            &[&wallet],
-            rpc_client.get_latest_blockhash().await?,
-        );
-        let sim_res = rpc_client.simulate_transaction(&tx).await?;
-        info!("Simulation: {}", serde_json::to_string_pretty(&sim_res)?);
-        send_jito_tx(tx).await?;
-        Ok(())
-    }
-}
-
+        let rpc_clients = match &send_strategy {
+            SendStrategy::Single => vec![],
+            SendStrategy::Fanout { rpcs } => rpcs
+                .iter()
+                .map(|url| {
+                    RpcClient::new_with_commitment(
+                        url.clone(),
+                        rpc_client.commitment(),
+                    )
+                })
+                .collect::<Vec<_>>(),
+        };
+        let submission = self::submit_with_retry(
+            &rpc_client,
+            &wallet,
+            &ixs,
+            &swap_context.address_lookup_tables,
+            &retry.unwrap_or_default(),
+            skip_preflight,
+            |tx, blockhash, last_valid_block_height| {
+                let (rpc_client, wallet, ixs) = (&rpc_client, &wallet, &ixs);
+                let http_client = &self.http_client;
+                let address_lookup_tables =
+                    &swap_context.address_lookup_tables;
+                let (send_strategy, rpc_clients) =
+                    (&send_strategy, &rpc_clients);
+                async move {
+                    if use_jito {
+                        if let Some((signature, outcome)) =
+                            self::send_swap_bundle(
+                                http_client,
+                                rpc_client,
+                                wallet,
+                                ixs,
+                                address_lookup_tables,
+                                blockhash,
+                                last_valid_block_height,
+                                confirm_timeout,
+                            )
+                            .await?
+                        {
+                            return Ok::<_, Box<dyn Error>>(Submission {
+                                signature,
+                                confirmed_by: match outcome {
+                                    SwapOutcome::Confirmed => {
+                                        vec![rpc_client.url()]
+                                    }
+                                    _ => vec![],
+                                },
+                                outcome,
+                                endpoint: jito_bundles_url(),
+                                accepted: vec![jito_bundles_url()],
+                            });
+                        }
+                    }
+                    match send_strategy {
+                        SendStrategy::Single => {
+                            let signature = tx.signatures[0];
+                            send_jito_tx(tx).await?;
+                            let outcome = Provider::confirm_tx_with_timeout(
+                                rpc_client,
+                                &signature,
+                                last_valid_block_height,
+                                confirm_timeout,
+                            )
+                            .await?;
+                            Ok(Submission {
+                                signature,
+                                confirmed_by: match outcome {
+                                    SwapOutcome::Confirmed => {
+                                        vec![rpc_client.url()]
+                                    }
+                                    _ => vec![],
+                                },
+                                outcome,
+                                endpoint: rpc_client.url(),
+                                accepted: vec![rpc_client.url()],
+                            })
+                        }
+                        SendStrategy::Fanout { .. } => {
+                            let submission = Provider::send_tx_fanout(
+                                rpc_clients,
+                                &tx,
+                                last_valid_block_height,
+                                quorum,
+                                skip_preflight,
+                                confirm_timeout,
+                            )
+                            .await?;
+                            info!(
+                                "sent to {} endpoints, confirmed by {:?}",
+                                submission.accepted.len(),
+                                submission.confirmed_by
+                            );
+                            Ok(submission)
+                        }
+                    }
+                }
+            },
+        )
+        .await?;
+        let Submission {
+            signature,
+            outcome,
+            endpoint,
+            confirmed_by,
+            ..
+        } = submission;
+        info!("{}: {:?}", signature, outcome);
+        Ok(Some(SwapResult {
+            signature: signature.to_string(),
+            input_mint: input_token_mint.to_string(),
+            output_mint: output_token_mint.to_string(),
+            in_amount: if swap_base_in {
+                amount
+            } else {
+                quote.expected_in
+            },
+            out_amount: quote.expected_out,
+            outcome,
+            endpoint,
+            confirmed_by,
+            wsol: swap_context
+                .swap
+                .wsol_flow((&input_token_mint, &output_token_mint), &quote),
+        }))
+    }
+}
+
+/// submit_with_retry signs the swap with a fresh blockhash and hands it to
+/// `send`, then signs and sends it again with a new blockhash for as long as
+/// the blockhash expires before the swap lands, up to `retry.attempts`
+/// submissions. Only the first transaction is simulated unless
+/// `retry.resimulate` is set, none with `skip_preflight`
+pub async fn submit_with_retry<R, F, Fut>(
+    rpc_client: &R,
+    wallet: &Keypair,
+    ixs: &[Instruction],
+    address_lookup_tables: &[AddressLookupTableAccount],
+    retry: &SubmitRetry,
+    skip_preflight: bool,
+    mut send: F,
+) -> Result<Submission, Box<dyn Error>>
+where
+    R: RpcOps + ?Sized,
+    F: FnMut(VersionedTransaction, Hash, u64) -> Fut,
+    Fut: std::future::Future<Output = Result<Submission, Box<dyn Error>>>,
+{
+    let attempts = retry.attempts.max(1);
+    if skip_preflight {
+        warn!("preflight skipped, the swap is sent without simulating it");
+    }
+    for attempt in 1..=attempts {
+        let (blockhash, last_valid_block_height) =
+            rpc_client.get_latest_blockhash().await?;
+        let tx = make_swap_tx(wallet, ixs, address_lookup_tables, blockhash)?;
+        if !skip_preflight && (attempt == 1 || retry.resimulate) {
+            simulate_swap(rpc_client, &tx).await?;
+        }
+        match send(tx, blockhash, last_valid_block_height).await {
+            Ok(submission)
+                if submission.outcome == SwapOutcome::Dropped
+                    && attempt < attempts =>
+            {
+                warn!(
+                    "{} dropped, resubmitting ({}/{})",
+                    submission.signature, attempt, attempts
+                );
+            }
+            Err(e)
+                if is_blockhash_expired(e.as_ref()) && attempt < attempts =>
+            {
+                warn!("{}, resubmitting ({}/{})", e, attempt, attempts);
+            }
+            res => return res,
+        }
+    }
+    unreachable!("the last attempt returns")
+}
+
+/// is_blockhash_expired is true for the errors of a transaction whose
+/// blockhash is too old to be included, signing it again can succeed
+pub fn is_blockhash_expired(err: &(dyn Error + 'static)) -> bool {
+    let err = err.to_string();
+    err.contains("Blockhash not found") || err.contains("BlockhashNotFound")
+}
+
+/// send_swap_bundle sends the swap with a tip to the block engine and waits
+/// for it to land, up to `confirm_timeout`, None if the bundle was rejected
+/// before it went out and the swap has to be sent the regular way. Any
+/// other failure is returned, as the bundle may still land
+#[allow(clippy::too_many_arguments)]
+pub async fn send_swap_bundle(
+    http_client: &Client,
+    rpc_client: &RpcClient,
+    wallet: &Keypair,
+    ixs: &[Instruction],
+    address_lookup_tables: &[AddressLookupTableAccount],
+    blockhash: Hash,
+    last_valid_block_height: u64,
+    confirm_timeout: Option<std::time::Duration>,
+) -> Result<Option<(Signature, SwapOutcome)>, Box<dyn Error>> {
+    let tip = jito_tip_lamports();
+    let tx = make_swap_tx(
+        wallet,
+        &[ixs, &[jito_tip_ix(&wallet.pubkey(), tip)]].concat(),
+        address_lookup_tables,
+        blockhash,
+    )?;
+    let signature = tx.signatures[0];
+    match Provider::send_jito_bundle(http_client, &[tx], tip).await {
+        Ok(_) => {}
+        Err(e) if e.is::<BundleRejected>() => {
+            warn!("{}, sending the swap without jito", e);
+            return Ok(None);
+        }
+        Err(e) => return Err(e),
+    }
+    let outcome = Provider::confirm_tx_with_timeout(
+        rpc_client,
+        &signature,
+        last_valid_block_height,
+        confirm_timeout,
+    )
+    .await?;
+    Ok(Some((signature, outcome)))
+}
+
+/// simulate_swap runs the transaction against the current state without
+/// sending it, a failing simulation is a SimulationFailure error
+pub async fn simulate_swap(
+    rpc_client: &(impl RpcOps + ?Sized),
+    tx: &VersionedTransaction,
+) -> Result<(), Box<dyn Error>> {
+    let sim_res = rpc_client.simulate_transaction(tx).await?;
+    info!("Simulation: {}", serde_json::to_string_pretty(&sim_res)?);
+    if let Some(failure) = SimulationFailure::from_result(&sim_res.value) {
+        warn!("{}", failure);
+        return Err(failure.into());
+    }
+    Ok(())
+}
+
+/// make_swap_tx signs the instructions as a v0 transaction that loads the
+/// accounts found in the lookup tables through them, which keeps swaps with
+/// extra instructions within the size limit; a legacy transaction without
+/// any tables
+pub fn make_swap_tx(
+    wallet: &Keypair,
+    ixs: &[Instruction],
+    address_lookup_tables: &[AddressLookupTableAccount],
+    blockhash: Hash,
+) -> Result<VersionedTransaction, Box<dyn Error>> {
+    if address_lookup_tables.is_empty() {
+        return Ok(VersionedTransaction::from(
+            Transaction::new_signed_with_payer(
+                ixs,
+                Some(&wallet.pubkey()),
+                &[wallet],
+                blockhash,
+            ),
+        ));
+    }
+    let message = v0::Message::try_compile(
+        &wallet.pubkey(),
+        ixs,
+        address_lookup_tables,
+        blockhash,
+    )?;
+    Ok(VersionedTransaction::try_new(
+        VersionedMessage::V0(message),
+        &[wallet],
+    )?)
+}
+
+/// simulate_ixs signs the instructions with the latest blockhash and
+/// simulates them, returning the result with the compute units consumed and
+/// the logs; nothing is sent
+pub async fn simulate_ixs(
+    rpc_client: &(impl RpcOps + ?Sized),
+    wallet: &Keypair,
+    ixs: &[Instruction],
+    address_lookup_tables: &[AddressLookupTableAccount],
+) -> Result<RpcSimulateTransactionResult, Box<dyn Error>> {
+    let (blockhash, _) = rpc_client.get_latest_blockhash().await?;
+    let tx = make_swap_tx(wallet, ixs, address_lookup_tables, blockhash)?;
+    let sim_res = rpc_client.simulate_transaction(&tx).await?;
+    info!("Simulation: {}", serde_json::to_string_pretty(&sim_res)?);
+    Ok(sim_res.value)
+}
+
+/// handle_token_account adds the instructions opening the owner's account
+/// for the mint. SOL is wrapped into a fresh native account funded with
+/// `amount` from `funding`, which has to keep `fee_buffer_lamports` on top
+/// to pay for the transaction, an InsufficientBalance error otherwise. The
+/// native account is closed after the swap, sending its lamports to
+/// `close_destination`, the owner if None
+#[allow(clippy::too_many_arguments)]
 pub async fn handle_token_account(
     swap: &mut Swap,
     rpc_client: &RpcClient,
@@ -655,6 +1941,8 @@ pub async fn handle_token_account(
     amount: u64,
     owner: &Pubkey,
     funding: &Pubkey,
+    fee_buffer_lamports: u64,
+    close_destination: Option<Pubkey>,
 ) -> Result<Pubkey, Box<dyn Error>> {
     // two cases - an account is a token account or a native account (WSOL)
     if mint.eq(&constants::SOLANA_PROGRAM_ID) {
@@ -664,27 +1952,82 @@ pub async fn handle_token_account(
             )
             .await?;
         let lamports = rent + amount;
+        if amount > 0 {
+            let available = rpc_client.get_balance(funding).await?;
+            let needed = lamports.saturating_add(fee_buffer_lamports);
+            if available < needed {
+                return Err(InsufficientBalance {
+                    wallet: *funding,
+                    available,
+                    needed,
+                    rent,
+                    amount,
+                    fee_buffer: fee_buffer_lamports,
+                }
+                .into());
+            }
+        }
         let seed = &Keypair::new().pubkey().to_string()[0..32];
-        let token = generate_pub_key(owner, seed);
-        let mut init_ixs =
-            create_init_token(&token, seed, mint, owner, funding, lamports);
-        let mut close_ixs = common::close_account(&token, owner, owner);
+        // the native mint is a classic SPL mint
+        let token_program = spl_token::id();
+        let token = generate_pub_key(owner, seed, &token_program);
+        let mut init_ixs = create_init_token(
+            &token,
+            seed,
+            mint,
+            owner,
+            funding,
+            lamports,
+            &token_program,
+        );
+        let mut close_ixs = common::close_account(
+            &token,
+            &close_destination.unwrap_or(*owner),
+            owner,
+        );
         // swap.signers.push(token);
         swap.pre_swap_instructions.append(&mut init_ixs);
         swap.post_swap_instructions.append(&mut close_ixs);
+        // the close returns the rent along with whatever SOL is left
+        swap.wsol.wrapped += amount;
+        swap.wsol.rent_reclaimed += rent;
         Ok(token)
     } else {
-        let token =
-            &spl_associated_token_account::get_associated_token_address(
-                owner, mint,
-            );
-        let mut ata_ixs =
-            common::create_ata_token_or_not(funding, mint, owner);
-        swap.pre_swap_instructions.append(&mut ata_ixs);
-        Ok(*token)
+        let token_program = token_program_of(rpc_client, mint).await?;
+        let token = spl_associated_token_account::get_associated_token_address_with_program_id(
+            owner,
+            mint,
+            &token_program,
+        );
+        swap.pre_swap_instructions.push(
+            spl_associated_token_account::instruction::create_associated_token_account_idempotent(
+                funding,
+                owner,
+                mint,
+                &token_program,
+            ),
+        );
+        Ok(token)
     }
 }
 
+/// token_program_of is the program owning the mint, classic SPL or
+/// Token-2022
+pub async fn token_program_of(
+    rpc_client: &RpcClient,
+    mint: &Pubkey,
+) -> Result<Pubkey, Box<dyn Error>> {
+    let owner = rpc_client.get_account(mint).await?.owner;
+    if owner != spl_token::id() && owner != spl_token_2022::id() {
+        return Err(format!(
+            "mint {} is owned by {}, not a token program",
+            mint, owner
+        )
+        .into());
+    }
+    Ok(owner)
+}
+
 pub fn create_init_token(
     token: &Pubkey,
     seed: &str,
@@ -692,7 +2035,9 @@ pub fn create_init_token(
     owner: &Pubkey,
     funding: &Pubkey,
     lamports: u64,
+    token_program: &Pubkey,
 ) -> Vec<Instruction> {
+    // a Token-2022 account without extensions is the size of a classic one
     vec![
         solana_sdk::system_instruction::create_account_with_seed(
             funding,
@@ -701,10 +2046,10 @@ pub fn create_init_token(
             seed,
             lamports,
             spl_token::state::Account::LEN as u64,
-            &spl_token::id(),
+            token_program,
         ),
-        spl_token::instruction::initialize_account(
-            &spl_token::id(),
+        spl_token_2022::instruction::initialize_account(
+            token_program,
             token,
             mint,
             owner,
@@ -713,8 +2058,12 @@ pub fn create_init_token(
     ]
 }
 
-pub fn generate_pub_key(from: &Pubkey, seed: &str) -> Pubkey {
-    Pubkey::create_with_seed(from, seed, &spl_token::id()).unwrap()
+pub fn generate_pub_key(
+    from: &Pubkey,
+    seed: &str,
+    token_program: &Pubkey,
+) -> Pubkey {
+    Pubkey::create_with_seed(from, seed, token_program).unwrap()
 }
 
 pub fn make_compute_budget_ixs(
@@ -727,10 +2076,1008 @@ pub fn make_compute_budget_ixs(
     ]
 }
 
-pub fn make_priority_compute_budget_ixs(
-    _provider: &Provider,
-    _addressess: &[Pubkey],
+/// make_priority_compute_budget_ixs prices compute units at the given
+/// percentile (0-100) of the recent prioritization fees paid for
+/// transactions locking `addresses`, falling back to
+/// `FALLBACK_PRIORITY_FEE` if the RPC has none or fails
+pub async fn make_priority_compute_budget_ixs(
+    rpc_client: &RpcClient,
+    addresses: &[Pubkey],
+    percentile: u8,
 ) -> Vec<Instruction> {
-    // let res = provider.rpc_client.get_recent_prioritization_fees(addresses).unwrap();
-    vec![]
+    let price =
+        match rpc_client.get_recent_prioritization_fees(addresses).await {
+            Ok(fees) => {
+                let fees: Vec<u64> =
+                    fees.iter().map(|fee| fee.prioritization_fee).collect();
+                fee_percentile(&fees, percentile).unwrap_or_else(|| {
+                    warn!("no recent prioritization fees, using fallback");
+                    FALLBACK_PRIORITY_FEE
+                })
+            }
+            Err(e) => {
+                warn!("failed to get recent prioritization fees: {}", e);
+                FALLBACK_PRIORITY_FEE
+            }
+        };
+    debug!("priority fee p{}: {} micro-lamports", percentile, price);
+    make_compute_budget_ixs(price, PRIORITY_COMPUTE_UNIT_LIMIT)
+}
+
+/// fee_percentile is the nearest-rank percentile of the fees, None if empty
+pub fn fee_percentile(fees: &[u64], percentile: u8) -> Option<u64> {
+    if fees.is_empty() {
+        return None;
+    }
+    let mut fees = fees.to_vec();
+    fees.sort_unstable();
+    let rank = (percentile.min(100) as usize * fees.len()).div_ceil(100);
+    Some(fees[rank.saturating_sub(1)])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use borsh::BorshDeserialize;
+    use futures_util::future::BoxFuture;
+    use solana_client::client_error::Result as ClientResult;
+    use solana_client::rpc_request::RpcRequest;
+    use solana_client::rpc_response::Response;
+    use solana_sdk::compute_budget::ComputeBudgetInstruction;
+    use solana_sdk::system_instruction::SystemInstruction;
+
+    const RENT: u64 = 2_039_280;
+
+    fn rent_rpc() -> RpcClient {
+        wallet_rpc(100_000_000_000)
+    }
+
+    /// wallet_rpc reports the rent of a token account and a wallet holding
+    /// `balance` lamports
+    fn wallet_rpc(balance: u64) -> RpcClient {
+        let mut mocks = HashMap::new();
+        mocks.insert(
+            RpcRequest::GetMinimumBalanceForRentExemption,
+            json!(RENT),
+        );
+        mocks.insert(
+            RpcRequest::GetBalance,
+            json!({ "context": { "slot": 1 }, "value": balance }),
+        );
+        RpcClient::new_mock_with_mocks("succeeds".to_string(), mocks)
+    }
+
+    fn new_swap() -> Swap {
+        Swap {
+            pre_swap_instructions: vec![],
+            post_swap_instructions: vec![],
+            wsol: WsolFlow::default(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_wsol_flow_wrapping_input() {
+        let owner = Keypair::new().pubkey();
+        let mut swap = new_swap();
+        let amount = 1_000_000_000;
+        handle_token_account(
+            &mut swap,
+            &rent_rpc(),
+            &constants::SOLANA_PROGRAM_ID,
+            amount,
+            &owner,
+            &owner,
+            0,
+            None,
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(
+            swap.wsol,
+            WsolFlow {
+                wrapped: amount,
+                unwrapped: 0,
+                rent_reclaimed: RENT,
+            }
+        );
+        // the account is funded with exactly what is reported
+        let funded = swap
+            .pre_swap_instructions
+            .iter()
+            .find_map(|ix| match bincode::deserialize(&ix.data) {
+                Ok(SystemInstruction::CreateAccountWithSeed {
+                    lamports,
+                    ..
+                }) => Some(lamports),
+                _ => None,
+            })
+            .unwrap();
+        assert_eq!(funded, amount + RENT);
+    }
+
+    #[tokio::test]
+    async fn test_wrapping_rejects_insufficient_balance() {
+        let owner = Keypair::new().pubkey();
+        let (amount, fee_buffer) = (1_000_000_000, 5_000_000);
+
+        // one lamport short of the amount, its rent and the fee buffer
+        let needed = RENT + amount + fee_buffer;
+        let mut swap = new_swap();
+        let err = handle_token_account(
+            &mut swap,
+            &wallet_rpc(needed - 1),
+            &constants::SOLANA_PROGRAM_ID,
+            amount,
+            &owner,
+            &owner,
+            fee_buffer,
+            None,
+        )
+        .await
+        .unwrap_err();
+        let err = err.downcast::<InsufficientBalance>().unwrap();
+        assert_eq!(err.available, needed - 1);
+        assert_eq!(err.needed, needed);
+        assert!(swap.pre_swap_instructions.is_empty());
+        assert_eq!(swap.wsol, WsolFlow::default());
+
+        handle_token_account(
+            &mut new_swap(),
+            &wallet_rpc(needed),
+            &constants::SOLANA_PROGRAM_ID,
+            amount,
+            &owner,
+            &owner,
+            fee_buffer,
+            None,
+        )
+        .await
+        .unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_close_to_destination() {
+        let owner = Keypair::new().pubkey();
+        let destination = Keypair::new().pubkey();
+        let closed_to = |swap: &Swap| {
+            let close_ix = swap
+                .post_swap_instructions
+                .iter()
+                .find(|ix| {
+                    matches!(
+                        spl_token::instruction::TokenInstruction::unpack(
+                            &ix.data
+                        ),
+                        Ok(spl_token::instruction::TokenInstruction::CloseAccount)
+                    )
+                })
+                .unwrap();
+            close_ix.accounts[1].pubkey
+        };
+
+        let mut swap = new_swap();
+        handle_token_account(
+            &mut swap,
+            &rent_rpc(),
+            &constants::SOLANA_PROGRAM_ID,
+            0,
+            &owner,
+            &owner,
+            0,
+            Some(destination),
+        )
+        .await
+        .unwrap();
+        assert_eq!(closed_to(&swap), destination);
+        // the account is closed once the swap is done with it
+        let swap_ix = Instruction::new_with_bytes(
+            constants::RAYDIUM_LIQUIDITY_POOL_V4_PUBKEY,
+            &[],
+            vec![],
+        );
+        let ixs = assemble_swap_ixs(
+            &ComputeBudgetConfig::default(),
+            &swap,
+            vec![swap_ix.clone()],
+        );
+        let position =
+            |ix: &Instruction| ixs.iter().position(|i| i == ix).unwrap();
+        assert!(
+            position(&swap_ix) < position(&swap.post_swap_instructions[0])
+        );
+
+        let mut swap = new_swap();
+        handle_token_account(
+            &mut swap,
+            &rent_rpc(),
+            &constants::SOLANA_PROGRAM_ID,
+            0,
+            &owner,
+            &owner,
+            0,
+            None,
+        )
+        .await
+        .unwrap();
+        assert_eq!(closed_to(&swap), owner);
+    }
+
+    struct FixedOracle(u64);
+
+    impl PriceOracle for FixedOracle {
+        fn expected_out<'a>(
+            &'a self,
+            _input_mint: &'a Pubkey,
+            _output_mint: &'a Pubkey,
+            _amount: u64,
+        ) -> BoxFuture<'a, Result<u64, Box<dyn Error>>> {
+            Box::pin(async move { Ok(self.0) })
+        }
+    }
+
+    #[tokio::test]
+    async fn test_oracle_divergence_aborts() {
+        let (input, output) =
+            (Keypair::new().pubkey(), Keypair::new().pubkey());
+        let quote = SwapQuote {
+            expected_in: 1_000_000_000,
+            expected_out: 1_000_000,
+            ..Default::default()
+        };
+        let check = |oracle_out| OracleCheck {
+            oracle: Arc::new(FixedOracle(oracle_out)),
+            max_divergence_pct: 5.,
+        };
+
+        // the pool gives a third less than the oracle, as if skewed
+        let err = check_oracle(&check(1_500_000), &input, &output, &quote)
+            .await
+            .unwrap_err();
+        let divergence = err.downcast::<OracleDivergence>().unwrap();
+        assert_eq!(divergence.pool_out, 1_000_000);
+        assert_eq!(divergence.oracle_out, 1_500_000);
+        assert!(divergence.divergence_pct > 33.);
+
+        // 1% apart is within the bound
+        check_oracle(&check(1_010_000), &input, &output, &quote)
+            .await
+            .unwrap();
+        // a quick swap has nothing to compare, which fails the check
+        check_oracle(
+            &check(1_500_000),
+            &input,
+            &output,
+            &SwapQuote::default(),
+        )
+        .await
+        .unwrap_err();
+
+        for max_divergence_pct in [-1., f64::NAN] {
+            let oracle = Arc::new(FixedOracle(1_000_000));
+            assert!(OracleCheck::new(oracle, max_divergence_pct).is_err());
+        }
+        assert!(OracleCheck::new(Arc::new(FixedOracle(1_000_000)), 0.).is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_wsol_flow_unwrapping_output() {
+        let owner = Keypair::new().pubkey();
+        let mut swap = new_swap();
+        handle_token_account(
+            &mut swap,
+            &rent_rpc(),
+            &constants::SOLANA_PROGRAM_ID,
+            0,
+            &owner,
+            &owner,
+            0,
+            None,
+        )
+        .await
+        .unwrap();
+        assert_eq!(swap.wsol.wrapped, 0);
+        assert_eq!(swap.wsol.rent_reclaimed, RENT);
+
+        let quote = SwapQuote {
+            expected_out: 420_000_000,
+            min_out: 400_000_000,
+            ..Default::default()
+        };
+        let token = Keypair::new().pubkey();
+        let flow =
+            swap.wsol_flow((&token, &constants::SOLANA_PROGRAM_ID), &quote);
+        assert_eq!(flow.unwrapped, quote.expected_out);
+        assert_eq!(flow.rent_reclaimed, RENT);
+        // only a SOL output is unwrapped
+        let other = Keypair::new().pubkey();
+        assert_eq!(swap.wsol_flow((&token, &other), &quote).unwrapped, 0);
+    }
+
+    #[tokio::test]
+    async fn test_token_account_of_mint_program() {
+        let mint_rpc = |token_program: Pubkey| {
+            let mut mocks = HashMap::new();
+            mocks.insert(
+                RpcRequest::GetAccountInfo,
+                json!({
+                    "context": { "slot": 1 },
+                    "value": {
+                        "data": ["", "base64"],
+                        "executable": false,
+                        "lamports": 1_461_600,
+                        "owner": token_program.to_string(),
+                        "rentEpoch": 0,
+                    },
+                }),
+            );
+            RpcClient::new_mock_with_mocks("succeeds".to_string(), mocks)
+        };
+        let (owner, mint) = (Keypair::new().pubkey(), Keypair::new().pubkey());
+
+        for token_program in [spl_token::id(), spl_token_2022::id()] {
+            let mut swap = new_swap();
+            let token = handle_token_account(
+                &mut swap,
+                &mint_rpc(token_program),
+                &mint,
+                0,
+                &owner,
+                &owner,
+                0,
+                None,
+            )
+            .await
+            .unwrap();
+            assert_eq!(
+                token,
+                spl_associated_token_account::get_associated_token_address_with_program_id(
+                    &owner,
+                    &mint,
+                    &token_program,
+                )
+            );
+            let ata_ix = &swap.pre_swap_instructions[0];
+            assert_eq!(ata_ix.program_id, spl_associated_token_account::id());
+            assert!(ata_ix
+                .accounts
+                .iter()
+                .any(|meta| meta.pubkey == token_program));
+        }
+
+        // the WSOL account is always a classic SPL account
+        let mut swap = new_swap();
+        handle_token_account(
+            &mut swap,
+            &rent_rpc(),
+            &constants::SOLANA_PROGRAM_ID,
+            0,
+            &owner,
+            &owner,
+            0,
+            None,
+        )
+        .await
+        .unwrap();
+        assert!(swap
+            .pre_swap_instructions
+            .iter()
+            .skip(1)
+            .all(|ix| ix.program_id == spl_token::id()));
+
+        let err = handle_token_account(
+            &mut new_swap(),
+            &mint_rpc(solana_sdk::system_program::id()),
+            &mint,
+            0,
+            &owner,
+            &owner,
+            0,
+            None,
+        )
+        .await
+        .unwrap_err();
+        assert!(err.to_string().contains("not a token program"));
+    }
+
+    #[test]
+    fn test_swap_preview_is_consistent() {
+        // 1 SOL into a 100 SOL / 1M token pool at raydium's 0.25% fee
+        let (reserve_in, reserve_out) = (100_000_000_000, 1_000_000_000_000);
+        let amount = 1_000_000_000;
+        let (pool_fee, spot_out) =
+            spot_quote(amount, reserve_in, reserve_out, 25, 10_000);
+        let after_fee = (amount - pool_fee) as u128;
+        let expected_out = (reserve_out as u128 * after_fee
+            / (reserve_in as u128 + after_fee))
+            as u64;
+        let quote = SwapQuote {
+            expected_in: amount,
+            max_in: amount,
+            expected_out,
+            min_out: expected_out / 10_000 * (10_000 - 100),
+            pool_fee,
+            spot_out,
+        };
+        let wsol = WsolFlow {
+            wrapped: amount,
+            unwrapped: 0,
+            rent_reclaimed: RENT,
+        };
+        let token = Keypair::new().pubkey();
+        let preview = SwapPreview::new(
+            &Keypair::new().pubkey(),
+            (&constants::SOLANA_PROGRAM_ID, &token),
+            100,
+            &quote,
+            &wsol,
+            (9, 6),
+            &ComputeBudgetConfig::default(),
+        );
+
+        assert!(preview.expected_out >= preview.min_out);
+        assert!(preview.min_out > 0.);
+        // a 1% deposit into the pool moves the price by about 1%
+        assert!(preview.price_impact_pct >= 0.);
+        assert!((0.9..1.1).contains(&preview.price_impact_pct));
+        assert_eq!(preview.in_amount, 1.);
+        assert_eq!(preview.fees.pool_fee, 0.0025);
+        assert_eq!(preview.fees.base_fee, 0.000005);
+        assert_eq!(preview.fees.wsol_rent, lamports_to_sol(RENT));
+        assert_eq!(preview.route.len(), 1);
+    }
+
+    #[test]
+    fn test_fee_percentile() {
+        let fees: Vec<u64> = (1..=100).rev().collect();
+        assert_eq!(fee_percentile(&fees, 75), Some(75));
+        assert_eq!(fee_percentile(&fees, 100), Some(100));
+        assert_eq!(fee_percentile(&fees, 0), Some(1));
+        assert_eq!(fee_percentile(&[0, 0, 40_000], 75), Some(40_000));
+        assert_eq!(fee_percentile(&[], 75), None);
+    }
+
+    #[tokio::test]
+    async fn test_priority_fee_fallback() {
+        let compute_unit_price = |ixs: &[Instruction]| {
+            ixs.iter().find_map(|ix| {
+                match ComputeBudgetInstruction::try_from_slice(&ix.data) {
+                    Ok(ComputeBudgetInstruction::SetComputeUnitPrice(
+                        price,
+                    )) => Some(price),
+                    _ => None,
+                }
+            })
+        };
+
+        let mut mocks = HashMap::new();
+        mocks.insert(
+            RpcRequest::GetRecentPrioritizationFees,
+            json!([
+                { "slot": 1, "prioritizationFee": 10_000 },
+                { "slot": 2, "prioritizationFee": 30_000 },
+                { "slot": 3, "prioritizationFee": 20_000 },
+                { "slot": 4, "prioritizationFee": 0 },
+            ]),
+        );
+        let rpc_client =
+            RpcClient::new_mock_with_mocks("succeeds".to_string(), mocks);
+        let ixs = make_priority_compute_budget_ixs(
+            &rpc_client,
+            &[constants::RAYDIUM_LIQUIDITY_POOL_V4_PUBKEY],
+            75,
+        )
+        .await;
+        assert_eq!(compute_unit_price(&ixs), Some(20_000));
+
+        let mut mocks = HashMap::new();
+        mocks.insert(RpcRequest::GetRecentPrioritizationFees, json!([]));
+        let rpc_client =
+            RpcClient::new_mock_with_mocks("succeeds".to_string(), mocks);
+        let ixs = make_priority_compute_budget_ixs(&rpc_client, &[], 75).await;
+        assert_eq!(compute_unit_price(&ixs), Some(FALLBACK_PRIORITY_FEE));
+    }
+
+    #[test]
+    fn test_custom_compute_budget() {
+        let compute_budget = ComputeBudgetConfig {
+            unit_price: 100_000,
+            unit_limit: 450_000,
+        };
+        let swap_ix = Instruction::new_with_bytes(
+            constants::RAYDIUM_LIQUIDITY_POOL_V4_PUBKEY,
+            &[9],
+            vec![],
+        );
+        let ixs =
+            assemble_swap_ixs(&compute_budget, &new_swap(), vec![swap_ix]);
+
+        let budget: Vec<ComputeBudgetInstruction> = ixs
+            .iter()
+            .filter(|ix| ix.program_id == solana_sdk::compute_budget::id())
+            .map(|ix| {
+                ComputeBudgetInstruction::try_from_slice(&ix.data).unwrap()
+            })
+            .collect();
+        assert_eq!(
+            budget,
+            vec![
+                ComputeBudgetInstruction::SetComputeUnitPrice(100_000),
+                ComputeBudgetInstruction::SetComputeUnitLimit(450_000),
+            ]
+        );
+        assert_eq!(ixs.last().unwrap().data, vec![9]);
+        // 100_000 micro-lamports for 450_000 units
+        assert_eq!(compute_budget.priority_fee(), 45_000);
+    }
+
+    #[tokio::test]
+    async fn test_failed_simulation_is_err() {
+        let simulation = |err: Value| {
+            let mut mocks = HashMap::new();
+            mocks.insert(
+                RpcRequest::SimulateTransaction,
+                json!({
+                    "context": { "slot": 1 },
+                    "value": {
+                        "err": err,
+                        "logs": [
+                            "Program log: Error: exceeds desired slippage limit"
+                        ],
+                    },
+                }),
+            );
+            RpcClient::new_mock_with_mocks("succeeds".to_string(), mocks)
+        };
+        let wallet = Keypair::new();
+        let tx =
+            VersionedTransaction::from(Transaction::new_signed_with_payer(
+                &make_compute_budget_ixs(0, 300_000),
+                Some(&wallet.pubkey()),
+                &[&wallet],
+                Hash::default(),
+            ));
+
+        let err = simulate_swap(
+            &simulation(json!({ "InstructionError": [2, { "Custom": 30 }] })),
+            &tx,
+        )
+        .await
+        .unwrap_err();
+        let failure = err.downcast_ref::<SimulationFailure>().unwrap();
+        assert_eq!(failure.reason, "exceeds desired slippage limit");
+
+        assert!(simulate_swap(&simulation(Value::Null), &tx).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_simulate_ixs_never_sends() {
+        let mut mocks = HashMap::new();
+        mocks.insert(
+            RpcRequest::SimulateTransaction,
+            json!({
+                "context": { "slot": 1 },
+                "value": {
+                    "err": null,
+                    "logs": ["Program log: Instruction: SwapBaseIn"],
+                    "unitsConsumed": 42_000,
+                },
+            }),
+        );
+        // sending would fail to parse the signature
+        mocks.insert(RpcRequest::SendTransaction, Value::Null);
+        let rpc_client =
+            RpcClient::new_mock_with_mocks("succeeds".to_string(), mocks);
+        let wallet = Keypair::new();
+
+        let simulation = simulate_ixs(
+            &rpc_client,
+            &wallet,
+            &make_compute_budget_ixs(0, 300_000),
+            &[],
+        )
+        .await
+        .unwrap();
+        assert!(simulation.err.is_none());
+        assert_eq!(simulation.units_consumed, Some(42_000));
+        assert_eq!(
+            simulation.logs.unwrap(),
+            vec!["Program log: Instruction: SwapBaseIn"]
+        );
+    }
+
+    #[test]
+    fn test_lookup_table_shrinks_swap_tx() {
+        let wallet = Keypair::new();
+        let accounts: Vec<Pubkey> =
+            (0..16).map(|_| Keypair::new().pubkey()).collect();
+        let ix = Instruction::new_with_bytes(
+            constants::RAYDIUM_LIQUIDITY_POOL_V4_PUBKEY,
+            &[9],
+            accounts
+                .iter()
+                .map(|account| {
+                    solana_sdk::instruction::AccountMeta::new(*account, false)
+                })
+                .collect(),
+        );
+        let ixs = [make_compute_budget_ixs(0, 300_000), vec![ix]].concat();
+
+        let legacy =
+            make_swap_tx(&wallet, &ixs, &[], Hash::default()).unwrap();
+        assert!(matches!(legacy.message, VersionedMessage::Legacy(_)));
+
+        let table = AddressLookupTableAccount {
+            key: Keypair::new().pubkey(),
+            addresses: accounts.clone(),
+        };
+        let versioned =
+            make_swap_tx(&wallet, &ixs, &[table], Hash::default()).unwrap();
+        let VersionedMessage::V0(message) = &versioned.message else {
+            panic!("expected a v0 message");
+        };
+        assert_eq!(message.address_table_lookups.len(), 1);
+        assert_eq!(
+            message.address_table_lookups[0].writable_indexes.len(),
+            accounts.len()
+        );
+        assert_eq!(
+            versioned.message.static_account_keys().len() + accounts.len(),
+            legacy.message.static_account_keys().len()
+        );
+        assert!(versioned.verify_with_results().iter().all(|ok| *ok));
+        assert!(
+            bincode::serialize(&versioned).unwrap().len()
+                < bincode::serialize(&legacy).unwrap().len()
+        );
+    }
+
+    #[test]
+    fn test_slippage_bounds() {
+        assert_eq!(Slippage::from_bps(0).unwrap().bps(), 0);
+        assert_eq!(Slippage::from_bps(10_000).unwrap().bps(), 10_000);
+        let err = Slippage::from_bps(10_001).unwrap_err();
+        assert_eq!(err.0, 10_001);
+        assert!(err.to_string().contains("10001 bps"));
+
+        // 0 bps accepts only the quote, 10000 bps any output
+        let vaults = (100_000_000_000, 1_000_000_000_000);
+        let direction = amm::utils::SwapDirection::PC2Coin;
+        let quote = |slippage: Slippage| {
+            quote_swap(
+                vaults,
+                (25, 10_000),
+                direction,
+                1_000_000_000,
+                true,
+                slippage.bps(),
+            )
+        };
+        let exact = quote(Slippage::from_bps(0).unwrap());
+        assert_eq!(exact.min_out, exact.expected_out);
+        assert_eq!(quote(Slippage::from_bps(10_000).unwrap()).min_out, 0);
+    }
+
+    #[test]
+    fn test_percent_of_balance() {
+        let balance = |amount: &str, decimals: u8| -> UiTokenAmount {
+            serde_json::from_value(json!({
+                "amount": amount,
+                "decimals": decimals,
+                "uiAmount": amount.parse::<f64>().unwrap()
+                    / 10f64.powi(decimals as i32),
+                "uiAmountString": "-",
+            }))
+            .unwrap()
+        };
+
+        // 1.234567 at 6 decimals, halves floor to the smallest unit
+        let token = balance("1234567", 6);
+        assert_eq!(token_amount_of_percent(&token, 50).unwrap(), 617_283);
+        assert_eq!(token_amount_of_percent(&token, 33).unwrap(), 407_407);
+        assert_eq!(token_amount_of_percent(&token, 1).unwrap(), 12_345);
+
+        // 100% is the whole balance, even past what a f64 holds exactly
+        let token = balance("18446744073709551615", 9);
+        assert_eq!(token_amount_of_percent(&token, 100).unwrap(), u64::MAX);
+        assert_eq!(token_amount_of_percent(&balance("1", 0), 100).unwrap(), 1);
+        assert_eq!(token_amount_of_percent(&balance("1", 0), 99).unwrap(), 0);
+
+        assert_eq!(percent_of_balance(0, 100).unwrap(), 0);
+        for percent in [0, 101, u8::MAX] {
+            let err = percent_of_balance(1_000, percent).unwrap_err();
+            assert_eq!(err.0, percent);
+        }
+    }
+
+    #[test]
+    fn test_quote_swap_base_in_and_out() {
+        // 100 SOL / 1M token pool at raydium's 0.25% fee, SOL is the pc side
+        let vaults = (100_000_000_000, 1_000_000_000_000);
+        let fee = (25, 10_000);
+        let direction = amm::utils::SwapDirection::PC2Coin;
+
+        // 1 SOL in, the output is bounded from below
+        let base_in =
+            quote_swap(vaults, fee, direction, 1_000_000_000, true, 100);
+        assert_eq!(base_in.expected_in, 1_000_000_000);
+        assert_eq!(base_in.max_in, 1_000_000_000);
+        assert!(base_in.min_out < base_in.expected_out);
+        assert_eq!(base_in.other_amount_threshold(true), base_in.min_out);
+
+        // exactly what 1 SOL gets out, the input is bounded from above
+        let base_out = quote_swap(
+            vaults,
+            fee,
+            direction,
+            base_in.expected_out,
+            false,
+            100,
+        );
+        assert_eq!(base_out.expected_out, base_in.expected_out);
+        assert_eq!(base_out.min_out, base_in.expected_out);
+        assert!(base_out.max_in > base_out.expected_in);
+        assert_eq!(base_out.other_amount_threshold(false), base_out.max_in);
+        // within rounding of the input that gets that output
+        assert!(base_out.expected_in.abs_diff(1_000_000_000) <= 1);
+
+        // a SOL input is wrapped up to the max input
+        let swap = new_swap();
+        let token = Keypair::new().pubkey();
+        let flow =
+            swap.wsol_flow((&constants::SOLANA_PROGRAM_ID, &token), &base_out);
+        assert_eq!(flow.wrapped, base_out.max_in);
+    }
+
+    #[test]
+    fn test_pool_vaults() {
+        let (coin_vault, pc_vault) =
+            (Pubkey::new_unique(), Pubkey::new_unique());
+        let mut data = vec![0; AMM_INFO_LEN];
+        data[AMM_COIN_VAULT_OFFSET..AMM_COIN_VAULT_OFFSET + 32]
+            .copy_from_slice(coin_vault.as_ref());
+        data[AMM_PC_VAULT_OFFSET..AMM_PC_VAULT_OFFSET + 32]
+            .copy_from_slice(pc_vault.as_ref());
+        assert_eq!(pool_vaults(&data), Some((coin_vault, pc_vault)));
+        assert_eq!(pool_vaults(&data[..AMM_PC_VAULT_OFFSET]), None);
+    }
+
+    #[tokio::test]
+    async fn test_amm_pool_id_is_cached_for_either_order() {
+        let (mint_a, mint_b) = (Pubkey::new_unique(), Pubkey::new_unique());
+        let amm_pool = Pubkey::new_unique();
+        pool_cache()
+            .lock()
+            .unwrap()
+            .insert(pool_cache_key(&mint_b, &mint_a), amm_pool);
+
+        // any request to the rpc fails, so these are answered by the cache
+        let rpc_client = RpcClient::new_mock("fails".to_string());
+        let raydium = Raydium::new();
+        for (a, b) in [(&mint_a, &mint_b), (&mint_b, &mint_a)] {
+            let found =
+                raydium.get_amm_pool_id(&rpc_client, a, b).await.unwrap();
+            assert_eq!(found, amm_pool);
+        }
+        assert!(raydium
+            .get_amm_pool_id(&rpc_client, &mint_a, &Pubkey::new_unique())
+            .await
+            .is_err());
+    }
+
+    #[tokio::test]
+    async fn test_submit_with_retry_after_expired_blockhash() {
+        let wallet = Keypair::new();
+        let ixs = [solana_sdk::system_instruction::transfer(
+            &wallet.pubkey(),
+            &wallet.pubkey(),
+            1,
+        )];
+        let sent = std::sync::Mutex::new(vec![]);
+        let send = |tx: VersionedTransaction, _: Hash, _: u64| {
+            let sent = &sent;
+            async move {
+                let signature = tx.signatures[0];
+                let mut sent = sent.lock().unwrap();
+                sent.push(signature);
+                if sent.len() == 1 {
+                    return Err::<Submission, Box<dyn Error>>(
+                        "Transaction simulation failed: Blockhash not found"
+                            .into(),
+                    );
+                }
+                Ok(Submission {
+                    signature,
+                    outcome: SwapOutcome::Confirmed,
+                    endpoint: "succeeds".to_string(),
+                    accepted: vec!["succeeds".to_string()],
+                    confirmed_by: vec!["succeeds".to_string()],
+                })
+            }
+        };
+
+        let submission = submit_with_retry(
+            &RpcClient::new_mock("succeeds".to_string()),
+            &wallet,
+            &ixs,
+            &[],
+            &SubmitRetry::default(),
+            false,
+            send,
+        )
+        .await
+        .unwrap();
+        assert_eq!(submission.outcome, SwapOutcome::Confirmed);
+        assert_eq!(sent.lock().unwrap().len(), 2);
+        assert_eq!(submission.signature, sent.lock().unwrap()[1]);
+
+        // without another attempt the expired blockhash is the outcome
+        sent.lock().unwrap().clear();
+        let err = submit_with_retry(
+            &RpcClient::new_mock("succeeds".to_string()),
+            &wallet,
+            &ixs,
+            &[],
+            &SubmitRetry {
+                attempts: 1,
+                resimulate: false,
+            },
+            false,
+            send,
+        )
+        .await
+        .unwrap_err();
+        assert!(is_blockhash_expired(err.as_ref()));
+    }
+
+    /// MockRpc answers the swap's rpc calls with canned responses and keeps
+    /// the transactions it was handed
+    #[derive(Default)]
+    struct MockRpc {
+        blockhash: Hash,
+        simulated: std::sync::Mutex<Vec<VersionedTransaction>>,
+        sent: std::sync::Mutex<Vec<VersionedTransaction>>,
+    }
+
+    impl RpcOps for MockRpc {
+        fn get_latest_blockhash(
+            &self,
+        ) -> BoxFuture<'_, ClientResult<(Hash, u64)>> {
+            Box::pin(async move { Ok((self.blockhash, 100)) })
+        }
+
+        fn simulate_transaction<'a>(
+            &'a self,
+            tx: &'a VersionedTransaction,
+        ) -> BoxFuture<'a, ClientResult<Response<RpcSimulateTransactionResult>>>
+        {
+            self.simulated.lock().unwrap().push(tx.clone());
+            Box::pin(async move {
+                Ok(serde_json::from_value(json!({
+                    "context": { "slot": 1 },
+                    "value": {
+                        "err": null,
+                        "logs": ["Program log: Instruction: SwapBaseIn"],
+                        "unitsConsumed": 42_000,
+                    },
+                }))
+                .unwrap())
+            })
+        }
+
+        fn send_tx<'a>(
+            &'a self,
+            tx: &'a VersionedTransaction,
+        ) -> BoxFuture<'a, ClientResult<Signature>> {
+            self.sent.lock().unwrap().push(tx.clone());
+            Box::pin(async move { Ok(tx.signatures[0]) })
+        }
+
+        fn get_multiple_accounts<'a>(
+            &'a self,
+            pubkeys: &'a [Pubkey],
+        ) -> BoxFuture<
+            'a,
+            ClientResult<Vec<Option<solana_sdk::account::Account>>>,
+        > {
+            Box::pin(async move { Ok(vec![None; pubkeys.len()]) })
+        }
+    }
+
+    #[tokio::test]
+    async fn test_submit_with_mock_rpc() {
+        let rpc = MockRpc {
+            blockhash: Hash::new_unique(),
+            ..Default::default()
+        };
+        let wallet = Keypair::new();
+        let ixs = assemble_swap_ixs(
+            &ComputeBudgetConfig::default(),
+            &new_swap(),
+            vec![solana_sdk::system_instruction::transfer(
+                &wallet.pubkey(),
+                &wallet.pubkey(),
+                1,
+            )],
+        );
+
+        let submission = submit_with_retry(
+            &rpc,
+            &wallet,
+            &ixs,
+            &[],
+            &SubmitRetry::default(),
+            false,
+            |tx, blockhash, _| {
+                let rpc = &rpc;
+                async move {
+                    assert_eq!(blockhash, rpc.blockhash);
+                    let signature = rpc.send_tx(&tx).await?;
+                    Ok(Submission {
+                        signature,
+                        outcome: SwapOutcome::Confirmed,
+                        endpoint: "mock".to_string(),
+                        accepted: vec!["mock".to_string()],
+                        confirmed_by: vec!["mock".to_string()],
+                    })
+                }
+            },
+        )
+        .await
+        .unwrap();
+        assert_eq!(submission.outcome, SwapOutcome::Confirmed);
+
+        // simulated once, then sent as signed with the canned blockhash
+        assert_eq!(rpc.simulated.lock().unwrap().len(), 1);
+        let sent = rpc.sent.lock().unwrap();
+        assert_eq!(sent.len(), 1);
+        assert_eq!(sent[0].signatures[0], submission.signature);
+        assert_eq!(sent[0].message.recent_blockhash(), &rpc.blockhash);
+        assert_eq!(sent[0].message.instructions().len(), ixs.len());
+    }
+
+    #[tokio::test]
+    async fn test_skip_preflight_does_not_simulate() {
+        let rpc = MockRpc {
+            blockhash: Hash::new_unique(),
+            ..Default::default()
+        };
+        let wallet = Keypair::new();
+        let ixs = [solana_sdk::system_instruction::transfer(
+            &wallet.pubkey(),
+            &wallet.pubkey(),
+            1,
+        )];
+
+        let submission = submit_with_retry(
+            &rpc,
+            &wallet,
+            &ixs,
+            &[],
+            &SubmitRetry {
+                attempts: 1,
+                resimulate: true,
+            },
+            true,
+            |tx, _, _| {
+                let rpc = &rpc;
+                async move {
+                    let signature = rpc.send_tx(&tx).await?;
+                    Ok(Submission {
+                        signature,
+                        outcome: SwapOutcome::Confirmed,
+                        endpoint: "mock".to_string(),
+                        accepted: vec!["mock".to_string()],
+                        confirmed_by: vec!["mock".to_string()],
+                    })
+                }
+            },
+        )
+        .await
+        .unwrap();
+
+        assert!(rpc.simulated.lock().unwrap().is_empty());
+        assert_eq!(rpc.sent.lock().unwrap().len(), 1);
+        assert_eq!(submission.outcome, SwapOutcome::Confirmed);
+    }
 }
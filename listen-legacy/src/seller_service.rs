@@ -3,6 +3,7 @@ use std::sync::Arc;
 
 use crate::execute::Executor;
 use crate::http_client::HttpClient;
+use crate::raydium::PoolNotFound;
 use crate::util::healthz;
 use crate::{
     buyer,
@@ -313,7 +314,7 @@ pub async fn load_amm_keys(
     )
     .await
     .map_err(|_| "Failed to get account")?
-    .ok_or("Account not found")?;
+    .ok_or(PoolNotFound(*amm_pool))?;
 
     Ok(amm::AmmKeys {
         amm_pool: *amm_pool,
@@ -349,13 +350,14 @@ where
             .await
         {
             Ok(res) => {
-                if let Some(account) = res.value {
-                    let account_data = account.data.as_slice();
-                    let ret = unsafe {
-                        &*(&account_data[0] as *const u8 as *const T)
-                    };
-                    return Ok(Some(ret.clone()));
-                }
+                // the account doesn't exist, only errors are retried
+                let Some(account) = res.value else {
+                    return Ok(None);
+                };
+                let account_data = account.data.as_slice();
+                let ret =
+                    unsafe { &*(&account_data[0] as *const u8 as *const T) };
+                return Ok(Some(ret.clone()));
             }
             Err(e) => {
                 warn!("could not get account: {}", e);
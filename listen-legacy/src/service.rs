@@ -2,7 +2,8 @@ use crate::api_docs::ApiDocs;
 use crate::blockhash::update_latest_blockhash;
 use crate::handlers::{
     handle_balance, handle_get_holdings, handle_get_pubkey, handle_pump_buy,
-    handle_pump_sell, handle_swap, handle_token_balance,
+    handle_pump_sell, handle_raydium_quote, handle_raydium_swap, handle_swap,
+    handle_token_balance,
 };
 use crate::state::ServiceState;
 use crate::util::{env, healthz};
@@ -54,6 +55,7 @@ impl ListenService {
             wallet,
             rpc_client,
             latest_blockhash: Arc::new(Mutex::new(Hash::default())),
+            http_client: reqwest::Client::new(),
         });
 
         Ok(Self { port, state })
@@ -83,6 +85,8 @@ impl ListenService {
                 ))
                 .app_data(state.clone())
                 .service(handle_swap)
+                .service(handle_raydium_swap)
+                .service(handle_raydium_quote)
                 .service(handle_get_pubkey)
                 .service(handle_get_holdings)
                 .service(handle_balance)
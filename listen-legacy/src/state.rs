@@ -8,4 +8,6 @@ pub struct ServiceState {
     pub wallet: Arc<Mutex<Keypair>>,
     pub rpc_client: Arc<RpcClient>,
     pub latest_blockhash: Arc<Mutex<Hash>>,
+    /// shared by the outgoing requests of the handlers
+    pub http_client: reqwest::Client,
 }
@@ -84,3 +84,36 @@ async fn test_gets_top_holders() {
             .unwrap();
     assert!(ok);
 }
+
+#[tokio::test]
+#[ignore = "integration test"]
+async fn test_prefetch_pool_state() {
+    use solana_sdk::{signature::Keypair, signer::Signer};
+
+    let rpc_client = RpcClient::new(env("RPC_URL"));
+    let wallet = Keypair::new();
+    // SOL-USDC
+    let swap_context = crate::raydium::make_swap_context(
+        &rpc_client,
+        Pubkey::from_str("58oQChx4yWmvKdwLLZzBi4ChoCc2fqCUWBkwMihLYQo2")
+            .unwrap(),
+        constants::SOLANA_PROGRAM_ID,
+        Pubkey::from_str("EPjFWJd5AufqSSqeM2qN1xzybapC8G4wEGGkZwyTDt1v")
+            .unwrap(),
+        &wallet,
+        crate::raydium::Slippage::default(),
+        1_000_000,
+        true,
+        None,
+    )
+    .await
+    .unwrap();
+
+    // calculated while the blockhash is fetched
+    let pool_state =
+        swap_context.prefetch_pool_state(&rpc_client, &wallet.pubkey());
+    rpc_client.get_latest_blockhash().await.unwrap();
+    let prefetched = pool_state.get().await.unwrap();
+    assert!(prefetched.pool_pc_vault_amount > 0);
+    assert!(prefetched.pool_coin_vault_amount > 0);
+}